@@ -0,0 +1,257 @@
+//! Local compute benchmark
+//!
+//! Measures the integer and floating-point throughput of all CPU threads
+//! and the memory copy bandwidth of the node. The three results are
+//! combined into one compute score, normalized so that the reference
+//! machine (a mid-range eight-thread desktop) scores [`REFERENCE_SCORE`].
+//! The score is stored in the [`NodeProfile`], advertised to peers in
+//! dispatch bids and used to rank them.
+
+use chrono::Utc;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ui_api::{ComputeBenchmark, NodeProfile};
+use crate::util::write_atomic;
+
+/// Score of the reference machine
+pub const REFERENCE_SCORE: f64 = 1000.0;
+
+/// Integer throughput of the reference machine in million operations per
+/// second
+const REFERENCE_INTEGER_MOPS: f64 = 20_000.0;
+
+/// Floating-point throughput of the reference machine in GFLOPS
+const REFERENCE_FLOAT_GFLOPS: f64 = 40.0;
+
+/// Memory copy bandwidth of the reference machine in GB/s
+const REFERENCE_MEMORY_GBPS: f64 = 20.0;
+
+/// Iterations between two checks of the clock
+const BATCH: u64 = 1 << 16;
+
+/// Benchmark parameters
+#[derive(Debug, Clone)]
+pub struct BenchmarkConfig {
+    /// How long each of the three tests runs
+    pub duration_per_test: Duration,
+    /// Number of threads (defaults to the logical CPUs)
+    pub threads: usize,
+    /// Total size of the memory copied by the bandwidth test; must be well
+    /// above the CPU caches
+    pub memory_bytes: usize,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            duration_per_test: Duration::from_secs(2),
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            memory_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Runs the benchmark suite; blocks the calling thread for about three
+/// times [`BenchmarkConfig::duration_per_test`]
+pub fn run(config: &BenchmarkConfig) -> ComputeBenchmark {
+    let threads = config.threads.max(1);
+    log::info!("Running compute benchmark on {} threads", threads);
+
+    let integer_ops = on_all_threads(threads, |_| integer_test(config.duration_per_test));
+    let float_ops = on_all_threads(threads, |_| float_test(config.duration_per_test));
+    let chunk = (config.memory_bytes / threads / 2).max(1);
+    let memory_bytes = on_all_threads(threads, |_| memory_test(chunk, config.duration_per_test));
+
+    let seconds = config.duration_per_test.as_secs_f64();
+    let integer_mops = integer_ops / seconds / 1e6;
+    let float_gflops = float_ops / seconds / 1e9;
+    let memory_bandwidth_gbps = memory_bytes / seconds / 1e9;
+    let compute_score = score(integer_mops, float_gflops, memory_bandwidth_gbps);
+    log::info!(
+        "Compute benchmark: {:.0} MOPS, {:.1} GFLOPS, {:.1} GB/s, score {:.0}",
+        integer_mops, float_gflops, memory_bandwidth_gbps, compute_score
+    );
+
+    ComputeBenchmark {
+        integer_mops,
+        float_gflops,
+        memory_bandwidth_gbps,
+        threads,
+        compute_score,
+        measured_at: Utc::now(),
+    }
+}
+
+/// Combines the test results into the normalized score: the geometric
+/// mean of the results relative to the reference machine
+pub fn score(integer_mops: f64, float_gflops: f64, memory_bandwidth_gbps: f64) -> f64 {
+    let ratios = [
+        integer_mops / REFERENCE_INTEGER_MOPS,
+        float_gflops / REFERENCE_FLOAT_GFLOPS,
+        memory_bandwidth_gbps / REFERENCE_MEMORY_GBPS,
+    ];
+    if ratios.iter().any(|ratio| !ratio.is_finite() || *ratio <= 0.0) {
+        return 0.0;
+    }
+    REFERENCE_SCORE * ratios.iter().product::<f64>().cbrt()
+}
+
+/// Gets the compute power in TFLOPS measured by a benchmark
+pub fn tflops(benchmark: &ComputeBenchmark) -> f64 {
+    benchmark.float_gflops / 1000.0
+}
+
+/// Runs `test` on `threads` threads at once and sums the results
+fn on_all_threads(threads: usize, test: impl Fn(usize) -> f64 + Sync) -> f64 {
+    let test = &test;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|thread| scope.spawn(move || test(thread))).collect();
+        handles.into_iter().filter_map(|handle| handle.join().ok()).sum()
+    })
+}
+
+/// Counts integer operations (xorshift, multiply, add) done in `duration`
+fn integer_test(duration: Duration) -> f64 {
+    let started = Instant::now();
+    let mut state = black_box(0x9E37_79B9_7F4A_7C15u64);
+    let mut batches = 0u64;
+    while started.elapsed() < duration {
+        for _ in 0..BATCH {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state = state.wrapping_mul(0x2545_F491_4F6C_DD1D).wrapping_add(1);
+        }
+        batches += 1;
+    }
+    black_box(state);
+    // Three shift-xor pairs, a multiply and an add
+    (batches * BATCH * 8) as f64
+}
+
+/// Counts floating-point operations (independent multiply-add chains)
+/// done in `duration`
+fn float_test(duration: Duration) -> f64 {
+    let started = Instant::now();
+    let mut acc = black_box([1.0f64, 1.1, 1.2, 1.3, 1.4, 1.5, 1.6, 1.7]);
+    let (factor, offset) = black_box((0.999_999_9f64, 1e-7f64));
+    let mut batches = 0u64;
+    while started.elapsed() < duration {
+        for _ in 0..BATCH {
+            for value in acc.iter_mut() {
+                *value = *value * factor + offset;
+            }
+        }
+        batches += 1;
+    }
+    black_box(acc);
+    (batches * BATCH * acc.len() as u64 * 2) as f64
+}
+
+/// Counts bytes moved (read and written) by copying a `bytes` buffer back
+/// and forth during `duration`
+fn memory_test(bytes: usize, duration: Duration) -> f64 {
+    let mut source = vec![1u8; bytes];
+    let mut target = vec![0u8; bytes];
+    let started = Instant::now();
+    let mut copies = 0u64;
+    while started.elapsed() < duration {
+        target.copy_from_slice(&source);
+        std::mem::swap(&mut source, &mut target);
+        black_box(&mut source);
+        copies += 1;
+    }
+    (copies * bytes as u64 * 2) as f64
+}
+
+/// Persistent profile of this node
+pub struct NodeProfileStore {
+    path: PathBuf,
+    profile: Mutex<NodeProfile>,
+}
+
+impl NodeProfileStore {
+    /// Loads the profile stored at `path`, starting an empty one for
+    /// `peer_id` on first use
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile exists but cannot be read
+    pub fn open(path: &Path, peer_id: &str) -> io::Result<Self> {
+        let mut profile: NodeProfile = if path.exists() {
+            serde_json::from_slice(&fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            NodeProfile::default()
+        };
+        profile.peer_id = peer_id.to_string();
+        Ok(Self { path: path.to_path_buf(), profile: Mutex::new(profile) })
+    }
+
+    /// Gets a copy of the profile
+    pub fn profile(&self) -> NodeProfile {
+        self.profile.lock().map(|profile| profile.clone()).unwrap_or_default()
+    }
+
+    /// Stores the result of a benchmark run in the profile
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile cannot be written
+    pub fn set_compute(&self, benchmark: ComputeBenchmark) -> io::Result<()> {
+        let mut profile = self
+            .profile
+            .lock()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        profile.compute = Some(benchmark);
+        let bytes = serde_json::to_vec_pretty(&*profile).map_err(io::Error::other)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&self.path, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_is_normalized_to_reference() {
+        let reference = score(REFERENCE_INTEGER_MOPS, REFERENCE_FLOAT_GFLOPS, REFERENCE_MEMORY_GBPS);
+        assert!((reference - REFERENCE_SCORE).abs() < 1e-9);
+        let twice = score(2.0 * REFERENCE_INTEGER_MOPS, 2.0 * REFERENCE_FLOAT_GFLOPS, 2.0 * REFERENCE_MEMORY_GBPS);
+        assert!((twice - 2.0 * REFERENCE_SCORE).abs() < 1e-9);
+        assert_eq!(score(0.0, REFERENCE_FLOAT_GFLOPS, REFERENCE_MEMORY_GBPS), 0.0);
+    }
+
+    #[test]
+    fn test_benchmark_is_stored_in_profile() {
+        let config = BenchmarkConfig {
+            duration_per_test: Duration::from_millis(50),
+            threads: 2,
+            memory_bytes: 4 * 1024 * 1024,
+        };
+        let benchmark = run(&config);
+        assert!(benchmark.integer_mops > 0.0);
+        assert!(benchmark.float_gflops > 0.0);
+        assert!(benchmark.memory_bandwidth_gbps > 0.0);
+        assert!(benchmark.compute_score > 0.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node_profile.json");
+        let store = NodeProfileStore::open(&path, "peer").unwrap();
+        assert!(store.profile().compute.is_none());
+        store.set_compute(benchmark.clone()).unwrap();
+
+        // JSON may round the last digit of a float
+        let stored = NodeProfileStore::open(&path, "peer").unwrap().profile().compute.unwrap();
+        assert!((stored.compute_score - benchmark.compute_score).abs() < 1e-9 * benchmark.compute_score);
+        assert_eq!(stored.measured_at, benchmark.measured_at);
+    }
+}
@@ -0,0 +1,231 @@
+//! Client-side encryption of stored objects
+//!
+//! Every object is encrypted with AES-256-GCM under a fresh random data
+//! key before it leaves the node. The data key is in turn encrypted
+//! ("wrapped") with the key of the object's owner and kept next to the
+//! object, so re-keying an owner only means rewrapping data keys, and an
+//! object shared by several owners carries a wrapped key for each. Owner
+//! keys never leave the node: they are kept in the [`KeyStore`] in the
+//! data directory, next to the node identity.
+//!
+//! GCM authenticates what it encrypts, so a modified ciphertext, wrapped
+//! key or owner is detected on decryption.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{ChronicleError, ChronicleResult};
+use crate::identity::write_secret;
+
+/// Name of the cipher
+pub const ALGORITHM: &str = "AES-256-GCM";
+
+/// Key size in bits
+pub const KEY_BITS: u32 = 256;
+
+const KEY_BYTES: usize = 32;
+const NONCE_BYTES: usize = 12;
+
+/// Associated data of encrypted objects
+const DATA_DOMAIN: &[u8] = b"mycelium/chronicle/v1/data";
+
+/// Associated data prefix of wrapped data keys
+const KEY_WRAP_DOMAIN: &[u8] = b"mycelium/chronicle/v1/key/";
+
+/// Key of an object owner, used to wrap data keys
+pub type OwnerKey = [u8; KEY_BYTES];
+
+/// Encryption parameters stored with an object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encryption {
+    /// Cipher of the object and its wrapped key
+    pub algorithm: String,
+    /// Owner whose key wraps the data key
+    pub owner: String,
+    /// Data key encrypted with the owner key
+    pub wrapped_key: Vec<u8>,
+    /// Nonce used to wrap the data key
+    pub key_nonce: Vec<u8>,
+    /// Nonce used to encrypt the object
+    pub nonce: Vec<u8>,
+}
+
+/// Encrypts `plaintext` with a fresh data key wrapped by `owner_key`
+///
+/// Returns the encryption parameters and the ciphertext (with its
+/// authentication tag).
+pub fn seal(owner: &str, owner_key: &OwnerKey, plaintext: &[u8]) -> ChronicleResult<(Encryption, Vec<u8>)> {
+    let mut data_key = [0u8; KEY_BYTES];
+    OsRng.fill_bytes(&mut data_key);
+    let nonce = random_nonce();
+    let key_nonce = random_nonce();
+
+    let ciphertext = encrypt(&data_key, &nonce, plaintext, DATA_DOMAIN)?;
+    let wrapped_key = encrypt(owner_key, &key_nonce, &data_key, &key_wrap_aad(owner))?;
+    let encryption = Encryption {
+        algorithm: ALGORITHM.to_string(),
+        owner: owner.to_string(),
+        wrapped_key,
+        key_nonce: key_nonce.to_vec(),
+        nonce: nonce.to_vec(),
+    };
+    Ok((encryption, ciphertext))
+}
+
+/// Unwraps the data key with `owner_key` and decrypts `ciphertext`
+///
+/// # Errors
+///
+/// Returns [`ChronicleError::Decryption`] if the key is wrong or the
+/// ciphertext or parameters were modified
+pub fn open(encryption: &Encryption, owner_key: &OwnerKey, ciphertext: &[u8]) -> ChronicleResult<Vec<u8>> {
+    decrypt(&unwrap_key(encryption, owner_key)?, &encryption.nonce, ciphertext, DATA_DOMAIN)
+}
+
+/// Wraps the data key of an object for another owner
+///
+/// Returns the parameters with which `owner` reads the same ciphertext
+/// using its own key.
+pub fn rewrap(encryption: &Encryption, owner_key: &OwnerKey, owner: &str, new_owner_key: &OwnerKey) -> ChronicleResult<Encryption> {
+    let data_key = unwrap_key(encryption, owner_key)?;
+    let key_nonce = random_nonce();
+    let wrapped_key = encrypt(new_owner_key, &key_nonce, &data_key, &key_wrap_aad(owner))?;
+    Ok(Encryption {
+        owner: owner.to_string(),
+        wrapped_key,
+        key_nonce: key_nonce.to_vec(),
+        ..encryption.clone()
+    })
+}
+
+fn unwrap_key(encryption: &Encryption, owner_key: &OwnerKey) -> ChronicleResult<[u8; KEY_BYTES]> {
+    if encryption.algorithm != ALGORITHM {
+        return Err(ChronicleError::Decryption(format!("unsupported cipher {}", encryption.algorithm)));
+    }
+    decrypt(owner_key, &encryption.key_nonce, &encryption.wrapped_key, &key_wrap_aad(&encryption.owner))?
+        .try_into()
+        .map_err(|_| ChronicleError::Decryption("wrapped key has the wrong size".to_string()))
+}
+
+fn encrypt(key: &[u8; KEY_BYTES], nonce: &[u8], msg: &[u8], aad: &[u8]) -> ChronicleResult<Vec<u8>> {
+    Aes256Gcm::new(key.into())
+        .encrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| ChronicleError::Decryption("encryption failed".to_string()))
+}
+
+fn decrypt(key: &[u8; KEY_BYTES], nonce: &[u8], msg: &[u8], aad: &[u8]) -> ChronicleResult<Vec<u8>> {
+    if nonce.len() != NONCE_BYTES {
+        return Err(ChronicleError::Decryption("nonce has the wrong size".to_string()));
+    }
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| ChronicleError::Decryption("authentication failed".to_string()))
+}
+
+fn random_nonce() -> [u8; NONCE_BYTES] {
+    let mut nonce = [0u8; NONCE_BYTES];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+fn key_wrap_aad(owner: &str) -> Vec<u8> {
+    [KEY_WRAP_DOMAIN, owner.as_bytes()].concat()
+}
+
+/// Owner keys of this node, kept in a file only the user can read
+pub struct KeyStore {
+    path: PathBuf,
+    keys: Mutex<HashMap<String, OwnerKey>>,
+}
+
+impl KeyStore {
+    /// Loads the keystore at `path` (missing means empty)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore exists but cannot be read
+    pub fn open(path: &Path) -> ChronicleResult<Self> {
+        let mut keys = HashMap::new();
+        if path.exists() {
+            let stored: HashMap<String, String> = serde_json::from_slice(&fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for (owner, key) in stored {
+                let key = hex::decode(&key)
+                    .ok()
+                    .and_then(|key| OwnerKey::try_from(key.as_slice()).ok())
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid key of owner {}", owner)))?;
+                keys.insert(owner, key);
+            }
+        }
+        Ok(Self { path: path.to_path_buf(), keys: Mutex::new(keys) })
+    }
+
+    /// Gets the key of `owner`, generating it on first use
+    pub fn owner_key(&self, owner: &str) -> ChronicleResult<OwnerKey> {
+        let mut keys = self.keys.lock().map_err(|e| ChronicleError::StateUnavailable(e.to_string()))?;
+        if let Some(key) = keys.get(owner) {
+            return Ok(*key);
+        }
+        let mut key = [0u8; KEY_BYTES];
+        OsRng.fill_bytes(&mut key);
+        keys.insert(owner.to_string(), key);
+        if let Err(e) = self.save(&keys) {
+            keys.remove(owner);
+            return Err(e);
+        }
+        log::info!("Generated storage key for owner {}", owner);
+        Ok(key)
+    }
+
+    fn save(&self, keys: &HashMap<String, OwnerKey>) -> ChronicleResult<()> {
+        let stored: HashMap<&String, String> = keys.iter().map(|(owner, key)| (owner, hex::encode(key))).collect();
+        let bytes = serde_json::to_vec_pretty(&stored).map_err(io::Error::other)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_secret(&self.path, &bytes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keystore.json");
+        let key = KeyStore::open(&path).unwrap().owner_key("aibox").unwrap();
+        assert_eq!(KeyStore::open(&path).unwrap().owner_key("aibox").unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let (encryption, ciphertext) = seal("aibox", &key, b"private dataset").unwrap();
+        assert_ne!(&ciphertext[..15], b"private dataset");
+        assert_eq!(open(&encryption, &key, &ciphertext).unwrap(), b"private dataset");
+
+        let mut flipped = ciphertext.clone();
+        flipped[3] ^= 1;
+        assert!(matches!(open(&encryption, &key, &flipped), Err(ChronicleError::Decryption(_))));
+        let stolen = Encryption { owner: "intruder".to_string(), ..encryption.clone() };
+        assert!(open(&stolen, &key, &ciphertext).is_err());
+        let other_key = KeyStore::open(&path).unwrap().owner_key("other").unwrap();
+        assert!(open(&encryption, &other_key, &ciphertext).is_err());
+
+        let shared = rewrap(&encryption, &key, "other", &other_key).unwrap();
+        assert_eq!(open(&shared, &other_key, &ciphertext).unwrap(), b"private dataset");
+        assert!(open(&shared, &key, &ciphertext).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Reed-Solomon erasure coding of stored objects
+//!
+//! An object is split into `k` equally sized data shards (the last one
+//! padded with zeros) and `m` parity shards are computed from them. Each
+//! shard is stored as a fragment under its own content ID, and any `k` of
+//! the `k + m` shards are enough to rebuild the object. The
+//! [`ObjectManifest`] lists the shards in order and is all a reader needs
+//! to find and check them.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+
+/// Largest number of shards of one object (the size of the Galois field)
+const MAX_SHARDS: usize = 256;
+
+/// Shard counts of an erasure-coded object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureConfig {
+    /// Number of data shards (`k`), the minimum needed for recovery
+    pub data_shards: usize,
+    /// Number of parity shards (`m`), the number of shards that may be lost
+    pub parity_shards: usize,
+}
+
+impl Default for ErasureConfig {
+    fn default() -> Self {
+        Self { data_shards: 4, parity_shards: 2 }
+    }
+}
+
+impl fmt::Display for ErasureConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reed-Solomon ({}+{})", self.data_shards, self.parity_shards)
+    }
+}
+
+impl ErasureConfig {
+    /// Gets the total number of shards
+    pub fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    /// Gets the stored bytes per byte of data
+    pub fn redundancy_factor(&self) -> f32 {
+        self.total_shards() as f32 / self.data_shards.max(1) as f32
+    }
+
+    /// Checks that objects can be coded with these shard counts
+    pub fn validate(&self) -> ChronicleResult<()> {
+        if self.data_shards == 0 || self.parity_shards == 0 || self.total_shards() > MAX_SHARDS {
+            return Err(ChronicleError::Erasure(format!(
+                "{} data and {} parity shards are not supported (at least one of each, at most {} in total)",
+                self.data_shards, self.parity_shards, MAX_SHARDS
+            )));
+        }
+        Ok(())
+    }
+
+    fn codec(&self) -> ChronicleResult<ReedSolomon> {
+        self.validate()?;
+        ReedSolomon::new(self.data_shards, self.parity_shards).map_err(|e| ChronicleError::Erasure(e.to_string()))
+    }
+}
+
+/// Description of an erasure-coded object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectManifest {
+    /// Content ID of the whole object
+    pub content_id: ContentId,
+    /// Object size in bytes
+    pub size: u64,
+    /// Shard counts
+    pub config: ErasureConfig,
+    /// Content IDs of the shards: data shards first, then parity shards
+    pub shards: Vec<ContentId>,
+}
+
+impl ObjectManifest {
+    /// Gets the size of every shard in bytes
+    pub fn shard_size(&self) -> u64 {
+        self.size.div_ceil(self.config.data_shards.max(1) as u64).max(1)
+    }
+}
+
+/// Splits `data` into data and parity shards
+///
+/// Returns the manifest of the object and the shards in manifest order.
+pub fn encode(data: &[u8], config: ErasureConfig) -> ChronicleResult<(ObjectManifest, Vec<Vec<u8>>)> {
+    let codec = config.codec()?;
+    let shard_size = data.len().div_ceil(config.data_shards).max(1);
+    let mut shards: Vec<Vec<u8>> = (0..config.total_shards())
+        .map(|index| {
+            let start = (index * shard_size).min(data.len());
+            let end = ((index + 1) * shard_size).min(data.len());
+            let mut shard = if index < config.data_shards { data[start..end].to_vec() } else { Vec::new() };
+            shard.resize(shard_size, 0);
+            shard
+        })
+        .collect();
+    codec.encode(&mut shards).map_err(|e| ChronicleError::Erasure(e.to_string()))?;
+
+    let manifest = ObjectManifest {
+        content_id: ContentId::for_bytes(data),
+        size: data.len() as u64,
+        config,
+        shards: shards.iter().map(|shard| ContentId::for_bytes(shard)).collect(),
+    };
+    Ok((manifest, shards))
+}
+
+/// Rebuilds an object from its shards (`None` for lost ones)
+///
+/// Shards that do not match their content ID count as lost.
+///
+/// # Errors
+///
+/// Returns an error if fewer than `k` intact shards are left or the
+/// rebuilt object does not match its content ID
+pub fn decode(manifest: &ObjectManifest, shards: Vec<Option<Vec<u8>>>) -> ChronicleResult<Vec<u8>> {
+    let config = manifest.config;
+    let codec = config.codec()?;
+    if shards.len() != config.total_shards() || manifest.shards.len() != config.total_shards() {
+        return Err(ChronicleError::Erasure(format!(
+            "object {} needs {} shards", manifest.content_id, config.total_shards()
+        )));
+    }
+    let mut shards: Vec<Option<Vec<u8>>> = shards
+        .into_iter()
+        .zip(&manifest.shards)
+        .map(|(shard, id)| shard.filter(|shard| id.verify(shard).is_ok()))
+        .collect();
+    let available = shards.iter().filter(|shard| shard.is_some()).count();
+    if available < config.data_shards {
+        return Err(ChronicleError::NotEnoughShards { available, required: config.data_shards });
+    }
+    codec.reconstruct_data(&mut shards).map_err(|e| ChronicleError::Erasure(e.to_string()))?;
+
+    let mut data: Vec<u8> = shards.into_iter().take(config.data_shards).flatten().flatten().collect();
+    data.truncate(manifest.size as usize);
+    manifest.content_id.verify(&data)?;
+    Ok(data)
+}
+
+/// Erasure codes `data` and stores every shard in `store`
+pub fn put_object(store: &dyn ContentStore, data: &[u8], config: ErasureConfig) -> ChronicleResult<ObjectManifest> {
+    let (manifest, shards) = encode(data, config)?;
+    for shard in &shards {
+        store.put(shard)?;
+    }
+    Ok(manifest)
+}
+
+/// Reads the shards of an object from `store` and rebuilds it
+///
+/// Missing and damaged shards are tolerated as long as `k` intact ones
+/// remain.
+pub fn get_object(store: &dyn ContentStore, manifest: &ObjectManifest) -> ChronicleResult<Vec<u8>> {
+    let mut shards = Vec::with_capacity(manifest.shards.len());
+    for id in &manifest.shards {
+        shards.push(match store.get(id) {
+            Ok(shard) => Some(shard),
+            Err(ChronicleError::NotFound(_) | ChronicleError::IntegrityMismatch { .. }) => None,
+            Err(e) => return Err(e),
+        });
+    }
+    decode(manifest, shards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::fragments::FragmentStore;
+
+    #[test]
+    fn test_object_survives_losing_parity_count_shards() {
+        let data: Vec<u8> = (0..10_007u32).map(|i| (i * 31 % 251) as u8).collect();
+        let config = ErasureConfig { data_shards: 5, parity_shards: 3 };
+        let (manifest, shards) = encode(&data, config).unwrap();
+        assert_eq!(shards.len(), 8);
+        assert_eq!(config.redundancy_factor(), 1.6);
+
+        // Every way of losing m consecutive shards, data and parity alike
+        for first_lost in 0..config.total_shards() {
+            let mut damaged: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+            for lost in 0..config.parity_shards {
+                damaged[(first_lost + lost) % config.total_shards()] = None;
+            }
+            assert_eq!(decode(&manifest, damaged).unwrap(), data);
+        }
+
+        let mut too_few: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        too_few[..4].iter_mut().for_each(|shard| *shard = None);
+        assert!(matches!(
+            decode(&manifest, too_few),
+            Err(ChronicleError::NotEnoughShards { available: 4, required: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_stored_object_is_rebuilt_from_remaining_fragments() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FragmentStore::open(dir.path(), u64::MAX).unwrap();
+        let data: Vec<u8> = (0..1300u32).flat_map(|i| i.to_le_bytes()).collect();
+        let manifest = put_object(&store, &data, ErasureConfig::default()).unwrap();
+
+        store.remove(&manifest.shards[0]).unwrap();
+        store.remove(&manifest.shards[5]).unwrap();
+        assert_eq!(get_object(&store, &manifest).unwrap(), data);
+
+        store.remove(&manifest.shards[3]).unwrap();
+        assert!(get_object(&store, &manifest).is_err());
+        assert!(encode(b"", ErasureConfig { data_shards: 3, parity_shards: 0 }).is_err());
+    }
+}
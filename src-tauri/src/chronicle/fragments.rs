@@ -0,0 +1,568 @@
+//! On-disk fragment store
+//!
+//! Fragments are kept as one file each, named by their content ID and
+//! sharded by the first byte of the ID so that no directory grows too
+//! large:
+//!
+//! ```text
+//! <dir>/objects/3f/3fa9…c2
+//! <dir>/objects/3f/3fa9…c2.held
+//! <dir>/index.json
+//! <dir>/index.log
+//! ```
+//!
+//! A fragment is written to a temporary file and renamed into place once
+//! it is on disk, so a crash never leaves a partial fragment under its
+//! ID. The index records the size and storage time of every fragment.
+//! Each change to it is appended to `index.log` as one JSON line, and the
+//! log is folded into `index.json` once it grows long and whenever the
+//! store is opened. If the index is lost or damaged it is rebuilt by
+//! scanning the shards. The peer a fragment is held for and the end of
+//! its lease are also written next to it (`.held`), so a rebuilt index
+//! still tells held fragments from the node's own.
+//!
+//! The store never grows beyond the space allocated to it: a fragment that
+//! does not fit is refused with [`ChronicleError::QuotaExceeded`], and a
+//! write that fails because the disk is full leaves no trace behind.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+use crate::identity::PeerId;
+
+/// Storage allocated to Chronicle until the Covenant settings are applied
+pub const DEFAULT_ALLOCATED_BYTES: u64 = 50 * 1024 * 1024 * 1024;
+
+const INDEX_FILE: &str = "index.json";
+const INDEX_LOG_FILE: &str = "index.log";
+
+/// Number of logged changes after which the log is folded into the index
+const COMPACTION_THRESHOLD: u64 = 1000;
+
+/// Index entry of a stored fragment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FragmentEntry {
+    /// Fragment size in bytes
+    pub size: u64,
+    /// When the fragment was stored
+    pub stored_at: DateTime<Utc>,
+    /// Peer the fragment is held for and its lease (`None` for shards of
+    /// the node's own objects)
+    pub held: Option<Hold>,
+}
+
+/// Lease of the peer a fragment is held for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hold {
+    /// Peer the fragment is held for
+    pub peer: PeerId,
+    /// End of the peer's lease, after which the fragment may be deleted
+    pub until: DateTime<Utc>,
+}
+
+/// Change to the index, logged as one JSON line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum IndexChange {
+    Added { id: ContentId, entry: FragmentEntry },
+    Removed { id: ContentId },
+}
+
+/// Log of the index changes made since the index was last written
+struct IndexLog {
+    file: File,
+    /// Changes logged since the index was written
+    len: u64,
+    /// Length of the log up to its last complete change
+    bytes: u64,
+}
+
+/// Space used by the store
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StoreStats {
+    /// Number of stored fragments
+    pub fragment_count: usize,
+    /// Bytes taken by the fragments
+    pub used_bytes: u64,
+    /// Bytes the node allocated to Chronicle
+    pub allocated_bytes: u64,
+}
+
+/// Content-addressed fragment store in a local directory
+pub struct FragmentStore {
+    dir: PathBuf,
+    index: Mutex<HashMap<ContentId, FragmentEntry>>,
+    /// Locked after `index`, never before it
+    log: Mutex<IndexLog>,
+    allocated_bytes: AtomicU64,
+    /// Bytes of fragments being written
+    reserved_bytes: AtomicU64,
+    /// Counter making temporary file names unique
+    writes: AtomicU64,
+}
+
+impl FragmentStore {
+    /// Opens the store in `dir`, allowing it to grow to `allocated_bytes`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or scanned
+    pub fn open(dir: impl AsRef<Path>, allocated_bytes: u64) -> ChronicleResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("objects"))?;
+        // Leftovers of writes interrupted by a crash
+        let _ = fs::remove_dir_all(dir.join("tmp"));
+        fs::create_dir_all(dir.join("tmp"))?;
+
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(INDEX_LOG_FILE))?;
+        let store = Self {
+            index: Mutex::new(HashMap::new()),
+            log: Mutex::new(IndexLog { file: log, len: 0, bytes: 0 }),
+            allocated_bytes: AtomicU64::new(allocated_bytes),
+            reserved_bytes: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            dir,
+        };
+        let index = match fs::read(store.dir.join(INDEX_FILE)) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(mut index) => {
+                    store.replay(&mut index)?;
+                    index
+                }
+                Err(e) => {
+                    log::warn!("Chronicle index is damaged ({}); rebuilding it", e);
+                    store.scan()?
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => store.scan()?,
+            Err(e) => return Err(e.into()),
+        };
+        let mut locked = store.lock_index()?;
+        *locked = index;
+        store.compact(&locked)?;
+        drop(locked);
+        Ok(store)
+    }
+
+    /// Sets the space the store may use
+    ///
+    /// Lowering it below the space in use only refuses new fragments; the
+    /// excess is freed by migrating fragments to other peers (see
+    /// [`Replicator::evict`](super::replication::Replicator::evict)).
+    pub fn set_allocated_bytes(&self, allocated_bytes: u64) {
+        self.allocated_bytes.store(allocated_bytes, Ordering::Relaxed);
+    }
+
+    /// Gets the space used by the store
+    pub fn stats(&self) -> ChronicleResult<StoreStats> {
+        let index = self.lock_index()?;
+        Ok(StoreStats {
+            fragment_count: index.len(),
+            used_bytes: index.values().map(|entry| entry.size).sum(),
+            allocated_bytes: self.allocated_bytes.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Gets the space left for new fragments
+    pub fn free_bytes(&self) -> ChronicleResult<u64> {
+        let stats = self.stats()?;
+        Ok(stats.allocated_bytes.saturating_sub(stats.used_bytes))
+    }
+
+    /// Gets the bytes stored beyond the allocated space
+    pub fn excess_bytes(&self) -> ChronicleResult<u64> {
+        let stats = self.stats()?;
+        Ok(stats.used_bytes.saturating_sub(stats.allocated_bytes))
+    }
+
+    /// Gets the fragments held for other peers, oldest first
+    pub fn held(&self) -> ChronicleResult<Vec<(ContentId, PeerId)>> {
+        let index = self.lock_index()?;
+        let mut held: Vec<(&ContentId, &FragmentEntry)> =
+            index.iter().filter(|(_, entry)| entry.held.is_some()).collect();
+        held.sort_by(|a, b| a.1.stored_at.cmp(&b.1.stored_at).then_with(|| a.0.cmp(b.0)));
+        Ok(held
+            .into_iter()
+            .filter_map(|(id, entry)| Some((id.clone(), entry.held.as_ref()?.peer.clone())))
+            .collect())
+    }
+
+    /// Stores a fragment on behalf of `peer`, whose lease on it ends at
+    /// `until`
+    ///
+    /// A fragment that is already stored keeps its entry; if it is held
+    /// for `peer`, its lease is extended to `until`.
+    pub fn put_held(&self, data: &[u8], peer: &PeerId, until: DateTime<Utc>) -> ChronicleResult<ContentId> {
+        let id = self.store(data, Some(&Hold { peer: peer.clone(), until }))?;
+        self.renew_held(&id, peer, until)?;
+        Ok(id)
+    }
+
+    /// Extends the lease of `peer` on a fragment held for it to `until`
+    /// unless it already lasts longer
+    ///
+    /// Returns whether the fragment is held for `peer`.
+    pub fn renew_held(&self, id: &ContentId, peer: &PeerId, until: DateTime<Utc>) -> ChronicleResult<bool> {
+        let mut index = self.lock_index()?;
+        let Some(entry) = index.get(id) else {
+            return Ok(false);
+        };
+        match &entry.held {
+            Some(hold) if hold.peer != *peer => return Ok(false),
+            Some(hold) if hold.until >= until => return Ok(true),
+            Some(_) => {}
+            None => return Ok(false),
+        }
+        let hold = Hold { peer: peer.clone(), until };
+        self.write_hold(id, &hold).map_err(storage_error)?;
+        let entry = FragmentEntry { held: Some(hold), ..entry.clone() };
+        index.insert(id.clone(), entry.clone());
+        self.log_change(&index, IndexChange::Added { id: id.clone(), entry })?;
+        Ok(true)
+    }
+
+    /// Gets the index entry of a fragment
+    pub fn entry(&self, id: &ContentId) -> ChronicleResult<Option<FragmentEntry>> {
+        Ok(self.lock_index()?.get(id).cloned())
+    }
+
+    /// Gets the IDs of all stored fragments
+    pub fn ids(&self) -> ChronicleResult<Vec<ContentId>> {
+        let mut ids: Vec<ContentId> = self.lock_index()?.keys().cloned().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Deletes a fragment; returns whether it was stored
+    pub fn remove(&self, id: &ContentId) -> ChronicleResult<bool> {
+        let mut index = self.lock_index()?;
+        let removed = index.remove(id).is_some();
+        for path in [self.path(id), self.holder_path(id)] {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if removed {
+            self.log_change(&index, IndexChange::Removed { id: id.clone() })?;
+        }
+        Ok(removed)
+    }
+
+    /// Stores a fragment if it fits into the allocated space
+    fn store(&self, data: &[u8], held: Option<&Hold>) -> ChronicleResult<ContentId> {
+        let id = ContentId::for_bytes(data);
+        let size = data.len() as u64;
+        {
+            let index = self.lock_index()?;
+            if index.contains_key(&id) {
+                return Ok(id);
+            }
+            let used = index.values().map(|entry| entry.size).sum::<u64>() + self.reserved_bytes.load(Ordering::Relaxed);
+            let free = self.allocated_bytes.load(Ordering::Relaxed).saturating_sub(used);
+            if size > free {
+                return Err(ChronicleError::QuotaExceeded { requested: size, free });
+            }
+            self.reserved_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+        let written = self.write(&id, data, held);
+        self.reserved_bytes.fetch_sub(size, Ordering::Relaxed);
+        written.map(|()| id)
+    }
+
+    /// Writes a fragment and adds it to the index, undoing the write if
+    /// any step fails
+    fn write(&self, id: &ContentId, data: &[u8], held: Option<&Hold>) -> ChronicleResult<()> {
+        // Written outside the index lock; concurrent writers of the same
+        // fragment rename identical files.
+        let path = self.path(id);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            // The holder goes first: a held fragment is never on disk
+            // without it
+            .and_then(|()| held.map_or(Ok(()), |hold| self.write_hold(id, hold)))
+            .and_then(|()| self.write_file(&path, data));
+        if let Err(e) = written {
+            if held.is_some() {
+                let _ = fs::remove_file(self.holder_path(id));
+            }
+            return Err(storage_error(e));
+        }
+
+        let mut index = self.lock_index()?;
+        if index.contains_key(id) {
+            return Ok(());
+        }
+        let entry = FragmentEntry { size: data.len() as u64, stored_at: Utc::now(), held: held.cloned() };
+        index.insert(id.clone(), entry.clone());
+        if let Err(e) = self.log_change(&index, IndexChange::Added { id: id.clone(), entry }) {
+            index.remove(id);
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(self.holder_path(id));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to a temporary file and renames it to `path` once it
+    /// is on disk
+    fn write_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let tmp = self.dir.join("tmp").join(format!("{}.{}", name, self.writes.fetch_add(1, Ordering::Relaxed)));
+        let written = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+
+    /// Writes the lease of the peer a fragment is held for next to it
+    fn write_hold(&self, id: &ContentId, hold: &Hold) -> io::Result<()> {
+        let bytes = serde_json::to_vec(hold).map_err(io::Error::other)?;
+        self.write_file(&self.holder_path(id), &bytes)
+    }
+
+    /// Gets the file holding a fragment
+    fn path(&self, id: &ContentId) -> PathBuf {
+        self.dir.join("objects").join(&id.as_str()[..2]).join(id.as_str())
+    }
+
+    /// Gets the file holding the lease of the peer a fragment is held for
+    fn holder_path(&self, id: &ContentId) -> PathBuf {
+        self.path(id).with_extension("held")
+    }
+
+    /// Rebuilds the index from the fragment files
+    ///
+    /// Fragments are not verified here; a file whose content does not match
+    /// its name is detected when it is read. Holders left behind by a write
+    /// that did not finish are deleted.
+    fn scan(&self) -> ChronicleResult<HashMap<ContentId, FragmentEntry>> {
+        let mut index = HashMap::new();
+        for shard in fs::read_dir(self.dir.join("objects"))? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(shard.path())? {
+                let file = file?;
+                let name = file.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                if let Some(id) = name.strip_suffix(".held").and_then(|id| ContentId::parse(id).ok()) {
+                    if !self.path(&id).exists() {
+                        fs::remove_file(file.path())?;
+                    }
+                    continue;
+                }
+                let Ok(id) = ContentId::parse(name) else {
+                    continue;
+                };
+                let metadata = file.metadata()?;
+                let stored_at = metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+                let held = match fs::read(self.holder_path(&id)) {
+                    Ok(bytes) => {
+                        Some(serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e.into()),
+                };
+                index.insert(id, FragmentEntry { size: metadata.len(), stored_at, held });
+            }
+        }
+        log::info!("Chronicle index rebuilt with {} fragments", index.len());
+        Ok(index)
+    }
+
+    /// Applies the logged changes to `index`
+    ///
+    /// A torn change at the end of the log (left by a crash mid-write) is
+    /// discarded together with anything after it.
+    fn replay(&self, index: &mut HashMap<ContentId, FragmentEntry>) -> ChronicleResult<()> {
+        let reader = BufReader::new(File::open(self.dir.join(INDEX_LOG_FILE))?);
+        for line in reader.split(b'\n') {
+            match serde_json::from_slice(&line?) {
+                Ok(IndexChange::Added { id, entry }) => {
+                    index.insert(id, entry);
+                }
+                Ok(IndexChange::Removed { id }) => {
+                    index.remove(&id);
+                }
+                Err(e) => {
+                    log::warn!("Discarding torn Chronicle index log tail: {}", e);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a change already made to `index` to the log, folding the
+    /// log into the index once it grows long
+    fn log_change(&self, index: &HashMap<ContentId, FragmentEntry>, change: IndexChange) -> ChronicleResult<()> {
+        let mut bytes = serde_json::to_vec(&change).map_err(io::Error::other)?;
+        bytes.push(b'\n');
+        let mut log = self.lock_log()?;
+        let written = log.file.write_all(&bytes).and_then(|()| log.file.sync_data());
+        if let Err(e) = written {
+            // A partial line would hide every later change from replay
+            if let Err(truncate) = log.file.set_len(log.bytes) {
+                log::error!("Failed to cut a torn change off the Chronicle index log: {}", truncate);
+            }
+            return Err(storage_error(e));
+        }
+        log.bytes += bytes.len() as u64;
+        log.len += 1;
+        let full = log.len >= COMPACTION_THRESHOLD;
+        drop(log);
+
+        // The change is safe in the log either way
+        if full {
+            if let Err(e) = self.compact(index) {
+                log::warn!("Failed to fold the Chronicle index log: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `index` atomically and empties the log
+    ///
+    /// Replaying changes already in the index leaves it as it is, so a
+    /// crash before the log is emptied loses nothing.
+    fn compact(&self, index: &HashMap<ContentId, FragmentEntry>) -> ChronicleResult<()> {
+        let bytes = serde_json::to_vec(index).map_err(io::Error::other)?;
+        self.write_file(&self.dir.join(INDEX_FILE), &bytes).map_err(storage_error)?;
+        let mut log = self.lock_log()?;
+        log.file.set_len(0)?;
+        log.file.sync_all()?;
+        log.len = 0;
+        log.bytes = 0;
+        Ok(())
+    }
+
+    fn lock_index(&self) -> ChronicleResult<MutexGuard<'_, HashMap<ContentId, FragmentEntry>>> {
+        self.index
+            .lock()
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))
+    }
+
+    fn lock_log(&self) -> ChronicleResult<MutexGuard<'_, IndexLog>> {
+        self.log
+            .lock()
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))
+    }
+}
+
+/// Converts a failed write, singling out a full disk
+fn storage_error(e: io::Error) -> ChronicleError {
+    if e.kind() == io::ErrorKind::StorageFull {
+        ChronicleError::DiskFull
+    } else {
+        e.into()
+    }
+}
+
+impl ContentStore for FragmentStore {
+    fn put(&self, data: &[u8]) -> ChronicleResult<ContentId> {
+        self.store(data, None)
+    }
+
+    fn get(&self, id: &ContentId) -> ChronicleResult<Vec<u8>> {
+        let data = match fs::read(self.path(id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ChronicleError::NotFound(id.clone())),
+            Err(e) => return Err(e.into()),
+        };
+        id.verify(&data)?;
+        Ok(data)
+    }
+
+    fn contains(&self, id: &ContentId) -> ChronicleResult<bool> {
+        Ok(self.lock_index()?.contains_key(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragments_survive_reopen_and_lost_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = {
+            let store = FragmentStore::open(dir.path(), 1024).unwrap();
+            let first = store.put(b"first fragment").unwrap();
+            let second = store.put(b"second").unwrap();
+            assert_eq!(store.put(b"second").unwrap(), second);
+            assert!(dir.path().join("objects").join(&first.as_str()[..2]).join(first.as_str()).is_file());
+            (first, second)
+        };
+
+        let store = FragmentStore::open(dir.path(), 2048).unwrap();
+        assert_eq!(store.get(&first).unwrap(), b"first fragment");
+        assert_eq!(store.stats().unwrap(), StoreStats { fragment_count: 2, used_bytes: 20, allocated_bytes: 2048 });
+        assert!(store.remove(&second).unwrap());
+        assert!(!store.contains(&second).unwrap());
+        assert!(matches!(store.get(&second), Err(ChronicleError::NotFound(_))));
+        let third = store.put(b"third").unwrap();
+        drop(store);
+
+        // Changes since the last open are only in the log, whose torn tail
+        // is dropped
+        let mut log = OpenOptions::new().append(true).open(dir.path().join("index.log")).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("index.log")).unwrap().lines().count(), 2);
+        log.write_all(br#"{"change":"removed","id":"#).unwrap();
+        let store = FragmentStore::open(dir.path(), 2048).unwrap();
+        let mut ids = vec![first.clone(), third.clone()];
+        ids.sort();
+        assert_eq!(store.ids().unwrap(), ids);
+        assert!(store.remove(&third).unwrap());
+        drop(store);
+
+        fs::write(dir.path().join("index.json"), b"{ damaged").unwrap();
+        let store = FragmentStore::open(dir.path(), 2048).unwrap();
+        assert_eq!(store.ids().unwrap(), vec![first]);
+    }
+
+    #[test]
+    fn test_quota_is_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FragmentStore::open(dir.path(), 1024).unwrap();
+        store.put(&[1; 1000]).unwrap();
+        assert!(matches!(
+            store.put(&[2; 100]),
+            Err(ChronicleError::QuotaExceeded { requested: 100, free: 24 })
+        ));
+        assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+        let held = store.put_held(&[3; 24], &"peer".to_string(), Utc::now()).unwrap();
+        assert_eq!(store.held().unwrap(), vec![(held, "peer".to_string())]);
+
+        store.set_allocated_bytes(512);
+        assert_eq!(store.excess_bytes().unwrap(), 512);
+        assert!(matches!(store.put(&[4; 1]), Err(ChronicleError::QuotaExceeded { free: 0, .. })));
+    }
+
+    #[test]
+    fn test_tampered_fragment_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FragmentStore::open(dir.path(), 1024).unwrap();
+        let id = store.put(b"original").unwrap();
+        fs::write(store.path(&id), b"tampered").unwrap();
+        assert!(matches!(store.get(&id), Err(ChronicleError::IntegrityMismatch { .. })));
+    }
+}
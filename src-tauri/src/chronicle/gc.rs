@@ -0,0 +1,295 @@
+//! Retention and garbage collection
+//!
+//! Every object is kept until the retention leases of all its owners end
+//! (see [`ObjectStore::renew`]). The [`GarbageCollector`] regularly ends
+//! expired leases, deletes the objects left without one, tells their
+//! holders to drop the shards and deletes fragments that no object
+//! references any more, such as the leftovers of an interrupted write or
+//! of an object stored again in encrypted form. Fragments held for peers
+//! are kept until the lease their owner granted with them ends (see
+//! [`Replicator::renew_holders`]), and deleted once the owner stops
+//! renewing it.
+//!
+//! Streamed objects hold a lease of their own. Before leases are ended,
+//! the chunks of every listed stream are held until the stream's lease
+//! ends, and streams whose lease ended are forgotten, leaving their chunks
+//! to be collected as objects.
+//!
+//! Unreferenced fragments are only deleted once they are older than a
+//! grace period, so that the shards of an object being stored are not
+//! mistaken for garbage before it enters the catalog.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::objects::ObjectStore;
+use super::replication::Replicator;
+use super::stream::StreamStore;
+use super::{ChronicleResult, ContentId};
+
+/// Interval between two collections
+pub const DEFAULT_GC_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Age below which unreferenced fragments are kept
+const ORPHAN_GRACE: chrono::Duration = chrono::Duration::hours(1);
+
+/// Number of deletions buffered for slow subscribers
+const EVENT_BUFFER: usize = 64;
+
+/// Object or fragment deleted by the collector
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deletion {
+    /// ID of the deleted object, or of the fragment if it belonged to none
+    pub content_id: ContentId,
+    /// Owner of the deleted object or of the held fragment (`None` for
+    /// unreferenced fragments)
+    pub owner: Option<String>,
+    /// Local bytes freed
+    pub freed_bytes: u64,
+}
+
+/// Deletes expired objects, expired held fragments and unreferenced
+/// fragments
+#[derive(Clone)]
+pub struct GarbageCollector {
+    objects: Arc<ObjectStore>,
+    replicator: Option<Replicator>,
+    streams: Option<Arc<StreamStore>>,
+    interval: Duration,
+    events: broadcast::Sender<Deletion>,
+}
+
+impl GarbageCollector {
+    /// Creates a collector for `objects`, running every `interval`
+    ///
+    /// Without a `replicator`, holders of deleted objects are not told to
+    /// drop their copies. The chunks of the streams in `streams` are kept
+    /// as long as their stream.
+    pub fn new(
+        objects: Arc<ObjectStore>,
+        replicator: Option<Replicator>,
+        streams: Option<Arc<StreamStore>>,
+        interval: Duration,
+    ) -> Self {
+        Self { objects, replicator, streams, interval, events: broadcast::channel(EVENT_BUFFER).0 }
+    }
+
+    /// Subscribes to deletions
+    pub fn subscribe(&self) -> broadcast::Receiver<Deletion> {
+        self.events.subscribe()
+    }
+
+    /// Runs a collection right away and then every interval
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.collect(Utc::now()).await {
+                log::error!("Chronicle garbage collection failed: {}", e);
+            }
+        }
+    }
+
+    /// Deletes the objects whose last lease ended by `now`, the fragments
+    /// held for peers whose lease ended by `now` and the unreferenced
+    /// fragments older than the grace period
+    ///
+    /// Returns the deletions, which are also published to subscribers.
+    pub async fn collect(&self, now: DateTime<Utc>) -> ChronicleResult<Vec<Deletion>> {
+        if let Some(streams) = &self.streams {
+            for id in streams.end_expired_streams(now)? {
+                log::info!("Forgot expired stream {}", id);
+            }
+            streams.hold_all_chunks()?;
+        }
+
+        let mut deletions = Vec::new();
+        for id in self.objects.end_expired_leases(now)? {
+            let Some((object, freed_bytes)) = self.objects.delete_object(&id)? else {
+                continue;
+            };
+            if let Some(replicator) = &self.replicator {
+                replicator.forget(&object).await;
+            }
+            log::info!("Deleted expired object {} of {} ({} bytes freed)", id, object.owner, freed_bytes);
+            deletions.push(Deletion { content_id: id, owner: Some(object.owner), freed_bytes });
+        }
+
+        let referenced: HashSet<ContentId> = self
+            .objects
+            .objects()?
+            .into_iter()
+            .flat_map(|(_, object)| object.manifest.shards)
+            .collect();
+        let fragments = self.objects.fragments();
+        for id in fragments.ids()? {
+            let Some(entry) = fragments.entry(&id)? else {
+                continue;
+            };
+            if referenced.contains(&id) {
+                continue;
+            }
+            match entry.held {
+                Some(hold) if hold.until > now => {}
+                Some(hold) => {
+                    if fragments.remove(&id)? {
+                        let freed = entry.size;
+                        log::info!("Deleted fragment {} held for peer {} past its lease ({} bytes freed)", id, hold.peer, freed);
+                        deletions.push(Deletion { content_id: id, owner: Some(hold.peer), freed_bytes: freed });
+                    }
+                }
+                None if now - entry.stored_at < ORPHAN_GRACE => {}
+                None => {
+                    if fragments.remove(&id)? {
+                        log::info!("Deleted unreferenced fragment {} ({} bytes freed)", id, entry.size);
+                        deletions.push(Deletion { content_id: id, owner: None, freed_bytes: entry.size });
+                    }
+                }
+            }
+        }
+
+        for deletion in &deletions {
+            let _ = self.events.send(deletion.clone());
+        }
+        Ok(deletions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::replication::tests::{open_objects, start_node, start_peers};
+    use crate::chronicle::{ChronicleError, ContentStore};
+    use crate::rpc::LoopbackNetwork;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_expired_objects_and_orphans_are_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let fragments = objects.fragments();
+        let collector = GarbageCollector::new(Arc::clone(&objects), None, None, DEFAULT_GC_INTERVAL);
+        let mut events = collector.subscribe();
+
+        let kept = objects.put(b"notes the aibox still needs").unwrap();
+        objects.set_retention(Duration::ZERO);
+        let expired = objects.put(b"scratch data of a finished task").unwrap();
+        let orphan = fragments.put(b"shard of an interrupted write").unwrap();
+        assert!(matches!(objects.renew(&kept, "intruder"), Err(ChronicleError::NotOwner(_))));
+        let used = fragments.stats().unwrap().used_bytes;
+
+        // Young orphans are left alone
+        let deletions = collector.collect(Utc::now()).await.unwrap();
+        assert_eq!(deletions.len(), 1);
+        assert_eq!((&deletions[0].content_id, deletions[0].owner.as_deref()), (&expired, Some("node")));
+        assert!(!objects.contains(&expired).unwrap() && objects.contains(&kept).unwrap());
+        assert!(fragments.contains(&orphan).unwrap());
+        assert_eq!(events.try_recv().unwrap(), deletions[0]);
+        let expired_bytes = deletions[0].freed_bytes;
+
+        let deletions = collector.collect(Utc::now() + chrono::Duration::hours(2)).await.unwrap();
+        assert_eq!(deletions, vec![Deletion { content_id: orphan, owner: None, freed_bytes: 29 }]);
+        assert_eq!(used - fragments.stats().unwrap().used_bytes, expired_bytes + 29);
+        assert_eq!(objects.get(&kept).unwrap(), b"notes the aibox still needs");
+    }
+
+    #[tokio::test]
+    async fn test_holders_drop_shards_of_deleted_objects() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let peers = start_peers(&network, 6);
+        let collector = GarbageCollector::new(Arc::clone(&owner.objects), Some(owner.replicator.clone()), None, DEFAULT_GC_INTERVAL);
+
+        owner.objects.set_retention(Duration::ZERO);
+        let id = owner.objects.put(&b"intermediate results".repeat(200)).unwrap();
+        owner.replicator.replicate(&id).await.unwrap();
+        assert!(peers.iter().all(|peer| peer.objects.fragments().stats().unwrap().fragment_count == 1));
+
+        assert_eq!(collector.collect(Utc::now()).await.unwrap().len(), 1);
+        assert_eq!(owner.objects.fragments().stats().unwrap().fragment_count, 0);
+        assert!(peers.iter().all(|peer| peer.objects.fragments().stats().unwrap().fragment_count == 0));
+    }
+
+    #[tokio::test]
+    async fn test_held_fragments_are_kept_while_their_owner_renews() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let peers = start_peers(&network, 6);
+        let id = owner.objects.put(&b"checkpoint of a long task".repeat(200)).unwrap();
+        owner.replicator.replicate(&id).await.unwrap();
+        let lease = owner.objects.object(&id).unwrap().unwrap().expires_at().unwrap();
+        let held = || peers.iter().map(|peer| peer.objects.fragments().stats().unwrap().fragment_count).sum::<usize>();
+        let collect_all = |at: DateTime<Utc>| {
+            let collectors: Vec<GarbageCollector> = peers
+                .iter()
+                .map(|peer| GarbageCollector::new(Arc::clone(&peer.objects), None, None, DEFAULT_GC_INTERVAL))
+                .collect();
+            async move {
+                for collector in collectors {
+                    collector.collect(at).await.unwrap();
+                }
+            }
+        };
+
+        // Renewing the owner's lease renews the holders' leases with it
+        owner.objects.extend_lease(&id, "owner", lease + chrono::Duration::days(30)).unwrap();
+        owner.replicator.renew_holders(&id).await.unwrap();
+        collect_all(lease + chrono::Duration::days(1)).await;
+        assert_eq!(held(), 6);
+
+        // The owner stops renewing
+        collect_all(lease + chrono::Duration::days(31)).await;
+        assert_eq!(held(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_live_as_long_as_their_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let fragments = objects.fragments();
+        let streams = Arc::new(StreamStore::open(dir.path(), Arc::clone(&objects), None).unwrap());
+        let collector = GarbageCollector::new(Arc::clone(&objects), None, Some(Arc::clone(&streams)), DEFAULT_GC_INTERVAL);
+
+        objects.set_retention(Duration::from_secs(3600));
+        let data = b"tokenized training corpus".repeat(1000);
+        let id = streams.put_stream("corpus", "aibox", data.as_slice()).await.unwrap();
+        let chunks = streams.stream(&id).unwrap().unwrap().chunks;
+
+        // Renewing the stream renews its chunks
+        objects.set_retention(Duration::from_secs(10 * 24 * 3600));
+        streams.renew(&id, "aibox").unwrap();
+        assert!(matches!(streams.renew(&id, "intruder"), Err(ChronicleError::NotOwner(_))));
+        assert!(collector.collect(Utc::now() + chrono::Duration::hours(2)).await.unwrap().is_empty());
+        let mut copy = Vec::new();
+        streams.get_stream("copy", &id, "aibox", 0, &mut copy).await.unwrap();
+        assert_eq!(copy, data);
+
+        let deletions = collector.collect(Utc::now() + chrono::Duration::days(11)).await.unwrap();
+        let deleted: Vec<ContentId> = deletions.into_iter().map(|deletion| deletion.content_id).collect();
+        assert_eq!(deleted, chunks);
+        assert!(streams.stream(&id).unwrap().is_none());
+        assert_eq!(fragments.stats().unwrap().fragment_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_held_fragments_survive_a_lost_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let (held, orphan) = {
+            let objects = open_objects(dir.path());
+            let until = Utc::now() + chrono::Duration::days(1);
+            let held = objects.fragments().put_held(b"shard of a peer's object", &"peer".to_string(), until).unwrap();
+            let orphan = objects.fragments().put(b"shard of an interrupted write").unwrap();
+            (held, orphan)
+        };
+        fs::remove_file(dir.path().join("index.json")).unwrap();
+
+        let objects = open_objects(dir.path());
+        let collector = GarbageCollector::new(Arc::clone(&objects), None, None, DEFAULT_GC_INTERVAL);
+        let deletions = collector.collect(Utc::now() + chrono::Duration::hours(2)).await.unwrap();
+        assert_eq!(deletions, vec![Deletion { content_id: orphan, owner: None, freed_bytes: 29 }]);
+        assert_eq!(objects.fragments().held().unwrap(), vec![(held, "peer".to_string())]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Chronicle protocol: content-addressed storage
+//!
+//! Data handed to Chronicle is addressed by the BLAKE3 hash of its bytes,
+//! so any reader can verify what it gets back. Other protocols depend only
+//! on the [`ContentStore`] trait, not on a particular storage backend; the
+//! node keeps its share of the network's data in a
+//! [`FragmentStore`](fragments::FragmentStore). Objects are
+//! [erasure coded](erasure) into shards so that they survive the loss of
+//! some of their fragments, and [encrypted](crypto) before they are coded
+//! so that fragments reveal nothing to the nodes holding them. The
+//! [`ObjectStore`](objects::ObjectStore) combines both, and the
+//! [`Replicator`](replication::Replicator) spreads the shards over other
+//! peers and makes them [prove](proofs) that they still hold them. Damaged
+//! and lost shards are found and rebuilt by the [scrubber](scrub), and
+//! objects whose retention lease ran out are [collected](gc). Objects too
+//! large for memory are [streamed](stream) in chunks.
+
+pub mod crypto;
+pub mod erasure;
+pub mod fragments;
+pub mod gc;
+pub mod objects;
+pub mod placement;
+pub mod proofs;
+pub mod replication;
+pub mod scrub;
+pub mod stream;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+/// Content identifier: lowercase hex BLAKE3 hash of the content
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ContentId(String);
+
+impl ContentId {
+    /// Computes the content ID of `data`
+    pub fn for_bytes(data: &[u8]) -> Self {
+        Self(blake3::hash(data).to_hex().to_string())
+    }
+
+    /// Computes the content ID of everything `reader` yields without
+    /// holding it in memory
+    pub fn for_reader(reader: impl Read) -> std::io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(reader)?;
+        Ok(Self(hasher.finalize().to_hex().to_string()))
+    }
+
+    /// Parses a content ID from its hex representation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `hex` is not a 64 character hex string
+    pub fn parse(hex: &str) -> ChronicleResult<Self> {
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ChronicleError::InvalidContentId(hex.to_string()));
+        }
+        Ok(Self(hex.to_ascii_lowercase()))
+    }
+
+    /// Gets the hex representation
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Checks that `data` hashes to this content ID
+    pub fn verify(&self, data: &[u8]) -> ChronicleResult<()> {
+        let actual = Self::for_bytes(data);
+        if &actual != self {
+            return Err(ChronicleError::IntegrityMismatch {
+                expected: self.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ContentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Storage that addresses data by content ID
+///
+/// Implementations must verify content against its ID before returning it.
+pub trait ContentStore: Send + Sync {
+    /// Stores `data` and returns its content ID
+    fn put(&self, data: &[u8]) -> ChronicleResult<ContentId>;
+
+    /// Gets the data stored under `id`
+    fn get(&self, id: &ContentId) -> ChronicleResult<Vec<u8>>;
+
+    /// Checks whether `id` is stored
+    fn contains(&self, id: &ContentId) -> ChronicleResult<bool>;
+
+    /// Stores everything `reader` yields and returns its content ID
+    ///
+    /// The default implementation collects the data in memory first;
+    /// stores that can take it piece by piece override it.
+    fn put_reader(&self, reader: &mut dyn Read) -> ChronicleResult<ContentId> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.put(&data)
+    }
+
+    /// Writes the verified data stored under `id` to `writer` and returns
+    /// its size
+    ///
+    /// The default implementation gets the whole data first.
+    fn get_into(&self, id: &ContentId, writer: &mut dyn Write) -> ChronicleResult<u64> {
+        let data = self.get(id)?;
+        writer.write_all(&data)?;
+        Ok(data.len() as u64)
+    }
+}
+
+/// Content store kept in memory
+#[derive(Default)]
+pub struct MemoryContentStore {
+    objects: Mutex<HashMap<ContentId, Vec<u8>>>,
+}
+
+impl ContentStore for MemoryContentStore {
+    fn put(&self, data: &[u8]) -> ChronicleResult<ContentId> {
+        let id = ContentId::for_bytes(data);
+        let mut objects = self.objects.lock().map_err(|e| ChronicleError::StateUnavailable(e.to_string()))?;
+        objects.entry(id.clone()).or_insert_with(|| data.to_vec());
+        Ok(id)
+    }
+
+    fn get(&self, id: &ContentId) -> ChronicleResult<Vec<u8>> {
+        let objects = self.objects.lock().map_err(|e| ChronicleError::StateUnavailable(e.to_string()))?;
+        let data = objects.get(id).cloned().ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        id.verify(&data)?;
+        Ok(data)
+    }
+
+    fn contains(&self, id: &ContentId) -> ChronicleResult<bool> {
+        let objects = self.objects.lock().map_err(|e| ChronicleError::StateUnavailable(e.to_string()))?;
+        Ok(objects.contains_key(id))
+    }
+}
+
+/// Chronicle error types
+#[derive(Debug, thiserror::Error)]
+pub enum ChronicleError {
+    #[error("Storage I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Content not found: {0}")]
+    NotFound(ContentId),
+    #[error("Invalid content ID: {0}")]
+    InvalidContentId(String),
+    #[error("Integrity check failed: expected {expected}, got {actual}")]
+    IntegrityMismatch { expected: ContentId, actual: ContentId },
+    #[error("Erasure coding failed: {0}")]
+    Erasure(String),
+    #[error("Not enough shards to rebuild the object: {available} of {required}")]
+    NotEnoughShards { available: usize, required: usize },
+    #[error("Decryption failed: {0}")]
+    Decryption(String),
+    #[error("Encryption is required but object {0} is not encrypted")]
+    EncryptionRequired(ContentId),
+    #[error("Object {0} belongs to another owner")]
+    NotOwner(ContentId),
+    #[error("Storage quota exceeded: {requested} bytes requested, {free} bytes free")]
+    QuotaExceeded { requested: u64, free: u64 },
+    #[error("Disk is full")]
+    DiskFull,
+    #[error("Chronicle state is unavailable: {0}")]
+    StateUnavailable(String),
+}
+
+/// Result type for Chronicle operations
+pub type ChronicleResult<T> = Result<T, ChronicleError>;
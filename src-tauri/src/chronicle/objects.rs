@@ -0,0 +1,589 @@
+//! Encrypted, erasure-coded objects
+//!
+//! The object store is what the rest of the node hands its data to. An
+//! object is encrypted for its owner, the ciphertext is erasure coded and
+//! the shards are kept in the [`FragmentStore`]. Objects stay addressed by
+//! the content ID of their plaintext, so callers never see ciphertext; the
+//! catalog maps that ID to the manifest of the shards and the encryption
+//! parameters needed to read it back:
+//!
+//! ```text
+//! <dir>/catalog.json
+//! ```
+//!
+//! Objects are kept for a retention period: storing an object grants its
+//! owner a lease that the owner renews by storing it again or through
+//! [`ObjectStore::renew`]. Owners storing the same data share one copy of
+//! the shards, but each holds a lease and a wrapped data key of its own,
+//! so no owner depends on another; only owners holding a lease can read
+//! the object. Objects whose leases all ended are deleted by the
+//! [garbage collector](super::gc).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::crypto::{self, Encryption, KeyStore};
+use super::erasure::{self, ErasureConfig, ObjectManifest};
+use super::fragments::FragmentStore;
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+use crate::identity::PeerId;
+use crate::ui_api::NodeFragmentInfo;
+use crate::util::write_atomic;
+
+/// Retention period of new objects until the Covenant settings are applied
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Share of one owner in a stored object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    /// End of the retention lease
+    pub expires_at: DateTime<Utc>,
+    /// Encryption parameters with the data key wrapped for the owner
+    /// (`None` for objects stored in plaintext)
+    pub encryption: Option<Encryption>,
+}
+
+/// Catalog entry of a stored object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredObject {
+    /// Owner that first stored the object
+    pub owner: String,
+    /// Plaintext size in bytes
+    pub size: u64,
+    /// Shards of the stored (possibly encrypted) bytes
+    pub manifest: ObjectManifest,
+    /// Encryption parameters of the first owner (`None` for objects
+    /// stored in plaintext)
+    pub encryption: Option<Encryption>,
+    /// When the object was stored
+    pub stored_at: DateTime<Utc>,
+    /// Lease of every owner, including the first
+    pub leases: BTreeMap<String, Lease>,
+    /// Peers holding a copy of each shard, in manifest order (empty until
+    /// the object is replicated)
+    #[serde(default)]
+    pub holders: Vec<Vec<PeerId>>,
+    /// End of the lease the holders were last granted on their shards
+    /// (`None` until the object is replicated)
+    pub holders_until: Option<DateTime<Utc>>,
+    /// Shards whose local copy was dropped on purpose to stay within the
+    /// quota, leaving them to their holders
+    #[serde(default)]
+    pub evicted: BTreeSet<usize>,
+}
+
+/// End of a lease of `period` starting at `start`
+pub(super) fn lease_until(start: DateTime<Utc>, period: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(period)
+        .ok()
+        .and_then(|period| start.checked_add_signed(period))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+impl StoredObject {
+    /// Gets the peers holding a copy of a shard
+    pub fn holders_of(&self, shard: usize) -> &[PeerId] {
+        self.holders.get(shard).map_or(&[], Vec::as_slice)
+    }
+
+    /// Gets the end of the last lease on the object
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.leases.values().map(|lease| lease.expires_at).max()
+    }
+
+    /// Whether `owner` holds a lease on the object
+    pub fn is_owned_by(&self, owner: &str) -> bool {
+        self.leases.contains_key(owner)
+    }
+
+    /// Gets the encryption parameters `owner` reads the object with
+    fn encryption_of(&self, id: &ContentId, owner: &str) -> ChronicleResult<Option<&Encryption>> {
+        let lease = self.leases.get(owner).ok_or_else(|| ChronicleError::NotOwner(id.clone()))?;
+        Ok(lease.encryption.as_ref())
+    }
+}
+
+/// Object store on top of the node's fragment store
+pub struct ObjectStore {
+    fragments: Arc<FragmentStore>,
+    keys: KeyStore,
+    /// Owner of objects stored through [`ContentStore`]
+    owner: String,
+    erasure: ErasureConfig,
+    encryption_required: AtomicBool,
+    /// Retention period of new objects in seconds
+    retention_secs: AtomicU64,
+    catalog_path: PathBuf,
+    catalog: Mutex<HashMap<ContentId, StoredObject>>,
+}
+
+impl ObjectStore {
+    /// Opens the object store whose catalog is in `dir`
+    ///
+    /// Objects stored through [`ContentStore`] belong to `owner` (the
+    /// node itself).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the catalog exists but cannot be read
+    pub fn open(dir: impl AsRef<Path>, fragments: Arc<FragmentStore>, keys: KeyStore, owner: &str) -> ChronicleResult<Self> {
+        let catalog_path = dir.as_ref().join("catalog.json");
+        let catalog: HashMap<ContentId, StoredObject> = match fs::read(&catalog_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            fragments,
+            keys,
+            owner: owner.to_string(),
+            erasure: ErasureConfig::default(),
+            encryption_required: AtomicBool::new(false),
+            retention_secs: AtomicU64::new(DEFAULT_RETENTION.as_secs()),
+            catalog_path,
+            catalog: Mutex::new(catalog),
+        })
+    }
+
+    /// Gets the owner of objects stored through [`ContentStore`]
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Gets the fragment store holding the shards
+    pub fn fragments(&self) -> &Arc<FragmentStore> {
+        &self.fragments
+    }
+
+    /// Gets the erasure coding of new objects
+    pub fn erasure(&self) -> ErasureConfig {
+        self.erasure
+    }
+
+    /// Sets whether unencrypted objects are refused
+    ///
+    /// While set, objects can neither be stored nor read in plaintext.
+    pub fn set_encryption_required(&self, required: bool) {
+        self.encryption_required.store(required, Ordering::Relaxed);
+    }
+
+    /// Gets whether unencrypted objects are refused
+    pub fn encryption_required(&self) -> bool {
+        self.encryption_required.load(Ordering::Relaxed)
+    }
+
+    /// Sets the retention period of objects stored or renewed from now on
+    pub fn set_retention(&self, retention: Duration) {
+        self.retention_secs.store(retention.as_secs(), Ordering::Relaxed);
+    }
+
+    /// Gets the retention period of new objects
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_secs.load(Ordering::Relaxed))
+    }
+
+    /// Extends the lease of `owner` on an object to a retention period
+    /// from now
+    ///
+    /// Returns the end of the lease, which never moves backwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the object is unknown or `owner` holds no lease
+    /// on it
+    pub fn renew(&self, id: &ContentId, owner: &str) -> ChronicleResult<DateTime<Utc>> {
+        self.extend_lease(id, owner, lease_until(Utc::now(), self.retention()))
+    }
+
+    /// Extends the lease of `owner` on an object to `until` unless it
+    /// already lasts longer
+    ///
+    /// Returns the end of the lease.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the object is unknown or `owner` holds no lease
+    /// on it
+    pub fn extend_lease(&self, id: &ContentId, owner: &str, until: DateTime<Utc>) -> ChronicleResult<DateTime<Utc>> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let lease = object.leases.get_mut(owner).ok_or_else(|| ChronicleError::NotOwner(id.clone()))?;
+        let expires_at = lease.expires_at.max(until);
+        if expires_at != lease.expires_at {
+            lease.expires_at = expires_at;
+            self.save_catalog(&catalog)?;
+        }
+        Ok(expires_at)
+    }
+
+    /// Ends the leases that ran out by `now`
+    ///
+    /// Returns the objects no owner holds a lease on any more, ordered by
+    /// content ID. They are left for the caller to delete.
+    pub fn end_expired_leases(&self, now: DateTime<Utc>) -> ChronicleResult<Vec<ContentId>> {
+        let mut catalog = self.lock_catalog()?;
+        let mut ended = false;
+        let mut unowned = Vec::new();
+        for (id, object) in catalog.iter_mut() {
+            let leases = object.leases.len();
+            object.leases.retain(|_, lease| lease.expires_at > now);
+            ended |= object.leases.len() != leases;
+            if object.leases.is_empty() {
+                unowned.push(id.clone());
+            }
+        }
+        if ended {
+            self.save_catalog(&catalog)?;
+        }
+        unowned.sort();
+        Ok(unowned)
+    }
+
+    /// Grants `owner` a lease on an object stored by other owners, with
+    /// the data key wrapped for it
+    fn share(&self, id: &ContentId, owner: &str) -> ChronicleResult<()> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let encryption = match &object.encryption {
+            Some(encryption) => Some(self.rewrap(encryption, owner)?),
+            None => None,
+        };
+        let expires_at = lease_until(Utc::now(), self.retention());
+        object.leases.insert(owner.to_string(), Lease { expires_at, encryption });
+        self.save_catalog(&catalog)
+    }
+
+    fn rewrap(&self, encryption: &Encryption, owner: &str) -> ChronicleResult<Encryption> {
+        let key = self.keys.owner_key(&encryption.owner)?;
+        crypto::rewrap(encryption, &key, owner, &self.keys.owner_key(owner)?)
+    }
+
+    /// Deletes an object from the catalog together with the local copies
+    /// of its shards that no other object shares
+    ///
+    /// Returns the deleted entry and the bytes freed, or `None` if the
+    /// object is unknown. Copies held by peers are left to the caller.
+    pub fn delete_object(&self, id: &ContentId) -> ChronicleResult<Option<(StoredObject, u64)>> {
+        let mut catalog = self.lock_catalog()?;
+        let Some(object) = catalog.remove(id) else {
+            return Ok(None);
+        };
+        self.save_catalog(&catalog)?;
+
+        let mut freed = 0;
+        for shard in &object.manifest.shards {
+            if catalog.values().any(|other| other.manifest.shards.contains(shard)) {
+                continue;
+            }
+            let size = self.fragments.entry(shard)?.map_or(0, |entry| entry.size);
+            if self.fragments.remove(shard)? {
+                freed += size;
+            }
+        }
+        Ok(Some((object, freed)))
+    }
+
+    /// Gets the catalog entry of an object
+    pub fn object(&self, id: &ContentId) -> ChronicleResult<Option<StoredObject>> {
+        Ok(self.lock_catalog()?.get(id).cloned())
+    }
+
+    /// Gets all stored objects, ordered by content ID
+    pub fn objects(&self) -> ChronicleResult<Vec<(ContentId, StoredObject)>> {
+        let mut objects: Vec<(ContentId, StoredObject)> =
+            self.lock_catalog()?.iter().map(|(id, object)| (id.clone(), object.clone())).collect();
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(objects)
+    }
+
+    /// Records that `peer` holds a copy of shard `shard` of an object
+    pub fn add_holder(&self, id: &ContentId, shard: usize, peer: &PeerId) -> ChronicleResult<()> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let shards = object.manifest.shards.len();
+        if shard >= shards {
+            return Err(ChronicleError::Erasure(format!("object {} has no shard {}", id, shard)));
+        }
+        object.holders.resize(shards, Vec::new());
+        if object.holders[shard].contains(peer) {
+            return Ok(());
+        }
+        object.holders[shard].push(peer.clone());
+        self.save_catalog(&catalog)
+    }
+
+    /// Records that the holders of an object's shards were granted a lease
+    /// until `until`
+    pub fn set_holders_until(&self, id: &ContentId, until: DateTime<Utc>) -> ChronicleResult<()> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        object.holders_until = Some(until);
+        self.save_catalog(&catalog)
+    }
+
+    /// Forgets that `peer` holds a copy of shard `shard` of an object
+    pub fn remove_holder(&self, id: &ContentId, shard: usize, peer: &PeerId) -> ChronicleResult<()> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        match object.holders.get_mut(shard) {
+            Some(holders) if holders.contains(peer) => holders.retain(|holder| holder != peer),
+            _ => return Ok(()),
+        }
+        self.save_catalog(&catalog)
+    }
+
+    /// Records whether the local copy of shard `shard` of an object was
+    /// dropped on purpose
+    pub fn set_evicted(&self, id: &ContentId, shard: usize, evicted: bool) -> ChronicleResult<()> {
+        let mut catalog = self.lock_catalog()?;
+        let object = catalog.get_mut(id).ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let changed = if evicted { object.evicted.insert(shard) } else { object.evicted.remove(&shard) };
+        if !changed {
+            return Ok(());
+        }
+        self.save_catalog(&catalog)
+    }
+
+    /// Gets the number of shards of an object on each node, this node
+    /// (`local_peer`) first
+    pub fn node_distribution(&self, id: &ContentId, local_peer: &PeerId) -> ChronicleResult<Vec<NodeFragmentInfo>> {
+        let object = self.object(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let mut local = 0;
+        for content_id in &object.manifest.shards {
+            local += u32::from(self.fragments.contains(content_id)?);
+        }
+        let mut remote: BTreeMap<&PeerId, u32> = BTreeMap::new();
+        for peer in object.holders.iter().flatten() {
+            *remote.entry(peer).or_default() += 1;
+        }
+
+        let mut nodes = vec![NodeFragmentInfo {
+            node_id: local_peer.clone(),
+            is_your_node: true,
+            fragment_count: local,
+        }];
+        nodes.extend(remote.into_iter().map(|(peer, fragment_count)| NodeFragmentInfo {
+            node_id: peer.clone(),
+            is_your_node: false,
+            fragment_count,
+        }));
+        Ok(nodes)
+    }
+
+    /// Stores `data` for `owner`, encrypted unless `encrypt` is false
+    ///
+    /// Returns the content ID of the plaintext. Storing an object that is
+    /// already stored (at least as well protected) only renews or grants
+    /// the lease of `owner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ChronicleError::EncryptionRequired`] if `encrypt` is false
+    /// while encryption is required, and [`ChronicleError::QuotaExceeded`]
+    /// if the shards do not fit into the fragment store
+    pub fn put_object(&self, data: &[u8], owner: &str, encrypt: bool) -> ChronicleResult<ContentId> {
+        let id = ContentId::for_bytes(data);
+        if !encrypt && self.encryption_required() {
+            return Err(ChronicleError::EncryptionRequired(id));
+        }
+        let existing = self.object(&id)?;
+        if let Some(existing) = &existing {
+            if existing.encryption.is_some() || !encrypt {
+                if existing.is_owned_by(owner) {
+                    self.renew(&id, owner)?;
+                } else {
+                    self.share(&id, owner)?;
+                }
+                return Ok(id);
+            }
+        }
+
+        let (encryption, stored) = if encrypt {
+            let (encryption, ciphertext) = crypto::seal(owner, &self.keys.owner_key(owner)?, data)?;
+            (Some(encryption), ciphertext)
+        } else {
+            (None, data.to_vec())
+        };
+        // Checked up front so that a refused object leaves no shards behind
+        let (manifest, shards) = erasure::encode(&stored, self.erasure)?;
+        let requested = shards.iter().map(|shard| shard.len() as u64).sum();
+        let free = self.fragments.free_bytes()?;
+        if requested > free {
+            return Err(ChronicleError::QuotaExceeded { requested, free });
+        }
+        for shard in &shards {
+            self.fragments.put(shard)?;
+        }
+
+        let now = Utc::now();
+        let expires_at = lease_until(now, self.retention());
+        // Owners of a plaintext copy keep their leases on the encrypted one
+        let mut leases = BTreeMap::new();
+        for (other, lease) in existing.map(|existing| existing.leases).unwrap_or_default() {
+            let encryption = match &encryption {
+                Some(encryption) => Some(self.rewrap(encryption, &other)?),
+                None => None,
+            };
+            leases.insert(other, Lease { expires_at: lease.expires_at, encryption });
+        }
+        let lease = Lease { expires_at, encryption: encryption.clone() };
+        leases.insert(owner.to_string(), lease);
+        let mut catalog = self.lock_catalog()?;
+        catalog.insert(
+            id.clone(),
+            StoredObject {
+                owner: owner.to_string(),
+                size: data.len() as u64,
+                manifest,
+                encryption,
+                stored_at: now,
+                leases,
+                holders: Vec::new(),
+                holders_until: None,
+                evicted: BTreeSet::new(),
+            },
+        );
+        self.save_catalog(&catalog)?;
+        Ok(id)
+    }
+
+    /// Reads, decrypts and verifies an object for `owner`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the object is unknown, `owner` holds no lease on
+    /// it, too many of its shards are lost, it was tampered with, or it is
+    /// stored in plaintext while encryption is required
+    pub fn get_object(&self, id: &ContentId, owner: &str) -> ChronicleResult<Vec<u8>> {
+        let object = self.object(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let encryption = object.encryption_of(id, owner)?;
+        let stored = erasure::get_object(self.fragments.as_ref(), &object.manifest)?;
+        self.open_object(id, encryption, stored)
+    }
+
+    /// Rebuilds an object from shards gathered by the caller (`None` for
+    /// lost ones), then decrypts and verifies it for `owner` like
+    /// [`get_object`](Self::get_object)
+    pub fn decode_object(&self, id: &ContentId, object: &StoredObject, owner: &str, shards: Vec<Option<Vec<u8>>>) -> ChronicleResult<Vec<u8>> {
+        let encryption = object.encryption_of(id, owner)?;
+        let stored = erasure::decode(&object.manifest, shards)?;
+        self.open_object(id, encryption, stored)
+    }
+
+    fn open_object(&self, id: &ContentId, encryption: Option<&Encryption>, stored: Vec<u8>) -> ChronicleResult<Vec<u8>> {
+        let data = match encryption {
+            Some(encryption) => crypto::open(encryption, &self.keys.owner_key(&encryption.owner)?, &stored)?,
+            None if self.encryption_required() => return Err(ChronicleError::EncryptionRequired(id.clone())),
+            None => stored,
+        };
+        id.verify(&data)?;
+        Ok(data)
+    }
+
+    fn save_catalog(&self, catalog: &HashMap<ContentId, StoredObject>) -> ChronicleResult<()> {
+        let bytes = serde_json::to_vec(catalog).map_err(io::Error::other)?;
+        write_atomic(&self.catalog_path, &bytes)?;
+        Ok(())
+    }
+
+    fn lock_catalog(&self) -> ChronicleResult<MutexGuard<'_, HashMap<ContentId, StoredObject>>> {
+        self.catalog
+            .lock()
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))
+    }
+}
+
+impl ContentStore for ObjectStore {
+    fn put(&self, data: &[u8]) -> ChronicleResult<ContentId> {
+        self.put_object(data, &self.owner, true)
+    }
+
+    fn get(&self, id: &ContentId) -> ChronicleResult<Vec<u8>> {
+        self.get_object(id, &self.owner)
+    }
+
+    fn contains(&self, id: &ContentId) -> ChronicleResult<bool> {
+        Ok(self.lock_catalog()?.get(id).is_some_and(|object| object.is_owned_by(&self.owner)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_store(dir: &Path) -> ObjectStore {
+        let fragments = Arc::new(FragmentStore::open(dir, u64::MAX).unwrap());
+        let keys = KeyStore::open(&dir.join("keystore.json")).unwrap();
+        ObjectStore::open(dir, fragments, keys, "node").unwrap()
+    }
+
+    #[test]
+    fn test_objects_are_stored_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"the aibox diary, entry one: nobody may read this".repeat(20);
+        let id = {
+            let store = open_store(dir.path());
+            let id = store.put(&data).unwrap();
+            for shard in &store.object(&id).unwrap().unwrap().manifest.shards {
+                let fragment = store.fragments().get(shard).unwrap();
+                assert!(!fragment.windows(12).any(|window| window == &data[..12]));
+            }
+            id
+        };
+
+        let store = open_store(dir.path());
+        assert!(store.contains(&id).unwrap());
+        assert_eq!(store.get(&id).unwrap(), data);
+        assert_eq!(store.object(&id).unwrap().unwrap().encryption.unwrap().owner, "node");
+    }
+
+    #[test]
+    fn test_required_encryption_refuses_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let plain = store.put_object(b"public dataset", "aibox", false).unwrap();
+        assert_eq!(store.get_object(&plain, "aibox").unwrap(), b"public dataset");
+
+        store.set_encryption_required(true);
+        assert!(matches!(store.get_object(&plain, "aibox"), Err(ChronicleError::EncryptionRequired(_))));
+        assert!(matches!(
+            store.put_object(b"another dataset", "aibox", false),
+            Err(ChronicleError::EncryptionRequired(_))
+        ));
+
+        // Storing it again encrypted upgrades the stored copy
+        assert_eq!(store.put_object(b"public dataset", "aibox", true).unwrap(), plain);
+        assert_eq!(store.get_object(&plain, "aibox").unwrap(), b"public dataset");
+    }
+
+    #[test]
+    fn test_owners_share_objects_but_not_leases() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let data = b"dataset two aiboxes train on".repeat(10);
+        store.set_retention(Duration::ZERO);
+        let id = store.put_object(&data, "aibox", true).unwrap();
+        store.set_retention(DEFAULT_RETENTION);
+        assert_eq!(store.put_object(&data, "other", true).unwrap(), id);
+        assert!(matches!(store.get_object(&id, "intruder"), Err(ChronicleError::NotOwner(_))));
+        assert!(matches!(store.renew(&id, "intruder"), Err(ChronicleError::NotOwner(_))));
+
+        // The first owner's lease ends; the other owner reads with its own key
+        assert!(store.end_expired_leases(Utc::now()).unwrap().is_empty());
+        let object = store.object(&id).unwrap().unwrap();
+        assert_eq!(object.leases.keys().collect::<Vec<_>>(), ["other"]);
+        assert_eq!(object.leases["other"].encryption.as_ref().unwrap().owner, "other");
+        assert!(matches!(store.get_object(&id, "aibox"), Err(ChronicleError::NotOwner(_))));
+        assert_eq!(store.get_object(&id, "other").unwrap(), data);
+
+        let later = Utc::now() + chrono::Duration::from_std(DEFAULT_RETENTION).unwrap() + chrono::Duration::days(1);
+        assert_eq!(store.end_expired_leases(later).unwrap(), vec![id]);
+    }
+}
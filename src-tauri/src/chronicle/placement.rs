@@ -0,0 +1,136 @@
+//! Placement of shards on peers
+//!
+//! Peers answer a storage offer with their free space, the storage quota
+//! they advertise and optionally their region. Shards are placed one at a
+//! time on the candidate with the highest weight, which grows with the
+//! peer's reputation and free share of its quota and shrinks with the
+//! number of shards of the same object the peer (or its region) already
+//! holds. Spreading an object over as many peers and regions as possible
+//! means that losing one of them costs as few shards as possible.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::identity::PeerId;
+
+/// Storage a peer offers to hold shards
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageOffer {
+    /// Free space in bytes
+    pub free_bytes: u64,
+    /// Storage the peer allocated to Chronicle in bytes
+    pub quota_bytes: u64,
+    /// Region the peer is located in, if it advertises one
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// Peer that may receive shards
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Peer ID
+    pub peer: PeerId,
+    /// Storage offered by the peer
+    pub offer: StorageOffer,
+    /// Reputation score of the peer between 0 and 1
+    pub reputation: f64,
+}
+
+impl Candidate {
+    /// Weight of the candidate before spreading is taken into account
+    fn weight(&self, free_bytes: u64) -> f64 {
+        let free_share = free_bytes as f64 / self.offer.quota_bytes.max(free_bytes).max(1) as f64;
+        self.reputation * (0.5 + 0.5 * free_share)
+    }
+}
+
+/// Picks a peer for each of `count` shards of `shard_size` bytes
+///
+/// `holders` are the peers already holding other shards of the object.
+/// Returns fewer peers than shards if the candidates run out of space.
+pub fn place(count: usize, shard_size: u64, candidates: &[Candidate], holders: &[PeerId]) -> Vec<PeerId> {
+    let mut free: Vec<u64> = candidates.iter().map(|candidate| candidate.offer.free_bytes).collect();
+    let mut peer_shards: HashMap<&str, usize> = HashMap::new();
+    let mut region_shards: HashMap<&str, usize> = HashMap::new();
+    let region_of = |peer: &str| {
+        candidates
+            .iter()
+            .find(|candidate| candidate.peer == peer)
+            .and_then(|candidate| candidate.offer.region.as_deref())
+    };
+    for holder in holders {
+        *peer_shards.entry(holder).or_default() += 1;
+        if let Some(region) = region_of(holder) {
+            *region_shards.entry(region).or_default() += 1;
+        }
+    }
+
+    let mut placed = Vec::with_capacity(count);
+    for _ in 0..count {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| free[*index] >= shard_size)
+            .map(|(index, candidate)| {
+                let on_peer = peer_shards.get(candidate.peer.as_str()).copied().unwrap_or(0);
+                let in_region = candidate
+                    .offer
+                    .region
+                    .as_deref()
+                    .and_then(|region| region_shards.get(region))
+                    .copied()
+                    .unwrap_or(0);
+                let weight = candidate.weight(free[index]) / (1 + on_peer) as f64 / (1 + in_region) as f64;
+                (index, weight)
+            })
+            .max_by(|a, b| {
+                a.1.total_cmp(&b.1)
+                    .then_with(|| candidates[b.0].peer.cmp(&candidates[a.0].peer))
+            });
+        let Some((index, _)) = best else {
+            break;
+        };
+
+        let candidate = &candidates[index];
+        free[index] -= shard_size;
+        *peer_shards.entry(&candidate.peer).or_default() += 1;
+        if let Some(region) = candidate.offer.region.as_deref() {
+            *region_shards.entry(region).or_default() += 1;
+        }
+        placed.push(candidate.peer.clone());
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(peer: &str, free_bytes: u64, region: &str, reputation: f64) -> Candidate {
+        Candidate {
+            peer: peer.to_string(),
+            offer: StorageOffer { free_bytes, quota_bytes: 1000, region: Some(region.to_string()) },
+            reputation,
+        }
+    }
+
+    #[test]
+    fn test_shards_are_spread_over_peers_and_regions() {
+        let candidates = [
+            candidate("a", 1000, "eu", 0.9),
+            candidate("b", 1000, "eu", 0.5),
+            candidate("c", 1000, "asia", 0.5),
+            candidate("d", 150, "america", 0.5),
+        ];
+        let placed = place(4, 100, &candidates, &[]);
+        // The best peer first, then the other regions before its neighbour
+        assert_eq!(placed, ["a", "c", "d", "b"]);
+        assert_eq!(place(6, 100, &candidates, &[]).iter().filter(|peer| *peer == "d").count(), 1);
+
+        // Peers that already hold shards of the object come last; full ones
+        // are skipped
+        let placed = place(3, 100, &candidates, &["a".to_string(), "c".to_string()]);
+        assert_eq!(placed[0], "d");
+        assert!(place(2, 2000, &candidates, &[]).is_empty());
+    }
+}
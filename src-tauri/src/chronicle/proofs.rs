@@ -0,0 +1,266 @@
+//! Proofs of storage
+//!
+//! Holders are paid to keep shards, so the owner regularly checks that
+//! they still do. A [`Challenge`] names a random byte range of a shard and
+//! a fresh nonce; the holder answers with the hash of the nonce followed
+//! by that range. The answer cannot be precomputed or derived from the
+//! content ID, so only a holder with the bytes at hand can give it. The
+//! owner checks the answer against its own copy of the shard. Before it
+//! drops its copy to stay within its quota, it prepares challenges with
+//! their answers and keeps checking the holders with those, each used
+//! once.
+//!
+//! The outcome of the latest challenge of every shard copy is kept in the
+//! [`ProofBook`] and shown as the status of the fragment.
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{ChronicleError, ChronicleResult, ContentId};
+use crate::identity::PeerId;
+use crate::ui_api::FragmentStatus;
+use crate::util::write_atomic;
+
+/// Largest byte range a challenge asks for
+const CHALLENGE_BYTES: u64 = 4096;
+
+/// Challenges prepared for a shard the owner drops: about a week of
+/// challenges at the default proof interval
+pub const PREPARED_CHALLENGES: usize = 32;
+
+/// Request to prove possession of part of a shard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Challenge {
+    /// Shard to prove
+    pub content_id: ContentId,
+    /// Start of the byte range
+    pub offset: u64,
+    /// Length of the byte range
+    pub length: u64,
+    /// Fresh random value mixed into the answer
+    pub nonce: Vec<u8>,
+}
+
+impl Challenge {
+    /// Creates a challenge over a random range of a shard of `size` bytes
+    pub fn random(content_id: ContentId, size: u64, rng: &mut impl Rng) -> Self {
+        let length = size.min(CHALLENGE_BYTES);
+        let offset = rng.gen_range(0..=size - length);
+        let mut nonce = vec![0u8; 32];
+        rng.fill(nonce.as_mut_slice());
+        Self { content_id, offset, length, nonce }
+    }
+
+    /// Computes the answer from the shard content
+    ///
+    /// Returns `None` if the range lies outside the shard.
+    pub fn answer(&self, shard: &[u8]) -> Option<ContentId> {
+        let end = self.offset.checked_add(self.length)?;
+        let range = shard.get(usize::try_from(self.offset).ok()?..usize::try_from(end).ok()?)?;
+        Some(ContentId::for_bytes(&[self.nonce.as_slice(), range].concat()))
+    }
+
+    /// Creates `count` random challenges of a shard together with their
+    /// answers, to check its holders once the shard is gone
+    pub fn prepare(content_id: &ContentId, shard: &[u8], count: usize, rng: &mut impl Rng) -> Vec<PreparedChallenge> {
+        (0..count)
+            .filter_map(|_| {
+                let challenge = Self::random(content_id.clone(), shard.len() as u64, rng);
+                let answer = challenge.answer(shard)?;
+                Some(PreparedChallenge { challenge, answer })
+            })
+            .collect()
+    }
+}
+
+/// Challenge prepared while the owner still had the shard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreparedChallenge {
+    /// Challenge to send
+    pub challenge: Challenge,
+    /// Answer of an intact copy
+    pub answer: ContentId,
+}
+
+/// Outcome of the latest challenges of one shard copy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProofRecord {
+    /// Status derived from the latest challenge
+    pub status: FragmentStatus,
+    /// When the holder was last challenged
+    pub last_challenge: DateTime<Utc>,
+    /// When the holder last answered correctly
+    pub last_proof: Option<DateTime<Utc>>,
+    /// Challenges failed since the last correct answer
+    pub failures: u32,
+}
+
+/// Contents of the proof book file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Book {
+    records: HashMap<ContentId, HashMap<PeerId, ProofRecord>>,
+    /// Unused challenges of shards the owner no longer keeps
+    prepared: HashMap<ContentId, Vec<PreparedChallenge>>,
+}
+
+/// Persistent proof outcomes by shard and holder
+pub struct ProofBook {
+    path: PathBuf,
+    book: Mutex<Book>,
+}
+
+impl ProofBook {
+    /// Opens the proof book stored at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed
+    pub fn open(path: impl AsRef<Path>) -> ChronicleResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let book = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Book::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, book: Mutex::new(book) })
+    }
+
+    /// Gets the record of a shard copy (`None` if it was never challenged)
+    pub fn get(&self, shard: &ContentId, holder: &str) -> ChronicleResult<Option<ProofRecord>> {
+        Ok(self.lock_book()?.records.get(shard).and_then(|holders| holders.get(holder)).cloned())
+    }
+
+    /// Keeps challenges prepared for a shard, replacing those left over
+    pub fn prepare(&self, shard: &ContentId, challenges: Vec<PreparedChallenge>) -> ChronicleResult<()> {
+        let mut book = self.lock_book()?;
+        book.prepared.insert(shard.clone(), challenges);
+        self.save(&book)
+    }
+
+    /// Takes one of the challenges prepared for a shard (`None` once they
+    /// are used up)
+    pub fn take_prepared(&self, shard: &ContentId) -> ChronicleResult<Option<PreparedChallenge>> {
+        let mut book = self.lock_book()?;
+        let Some(prepared) = book.prepared.get_mut(shard) else {
+            return Ok(None);
+        };
+        let challenge = prepared.pop();
+        if prepared.is_empty() {
+            book.prepared.remove(shard);
+        }
+        self.save(&book)?;
+        Ok(challenge)
+    }
+
+    /// Drops the challenges prepared for a shard the owner keeps again
+    pub fn discard_prepared(&self, shard: &ContentId) -> ChronicleResult<()> {
+        let mut book = self.lock_book()?;
+        if book.prepared.remove(shard).is_none() {
+            return Ok(());
+        }
+        self.save(&book)
+    }
+
+    /// Whether a shard copy was not challenged within `interval`
+    pub fn is_due(&self, shard: &ContentId, holder: &str, interval: Duration, now: DateTime<Utc>) -> ChronicleResult<bool> {
+        Ok(self.get(shard, holder)?.is_none_or(|record| {
+            (now - record.last_challenge).to_std().is_ok_and(|elapsed| elapsed >= interval)
+        }))
+    }
+
+    /// Records the outcome of a challenge; `Active` means it was answered
+    /// correctly
+    pub fn record(&self, shard: &ContentId, holder: &PeerId, status: FragmentStatus) -> ChronicleResult<ProofRecord> {
+        let mut book = self.lock_book()?;
+        let now = Utc::now();
+        let record = book
+            .records
+            .entry(shard.clone())
+            .or_default()
+            .entry(holder.clone())
+            .or_insert_with(|| ProofRecord {
+                status: FragmentStatus::Active,
+                last_challenge: now,
+                last_proof: None,
+                failures: 0,
+            });
+        record.last_challenge = now;
+        if status == FragmentStatus::Active {
+            record.last_proof = Some(now);
+            record.failures = 0;
+        } else {
+            record.failures += 1;
+        }
+        record.status = status;
+        let record = record.clone();
+        self.save(&book)?;
+        Ok(record)
+    }
+
+    fn save(&self, book: &Book) -> ChronicleResult<()> {
+        let bytes = serde_json::to_vec(book).map_err(io::Error::other)?;
+        write_atomic(&self.path, &bytes)?;
+        Ok(())
+    }
+
+    fn lock_book(&self) -> ChronicleResult<MutexGuard<'_, Book>> {
+        self.book
+            .lock()
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_depends_on_range_and_nonce() {
+        let shard: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let id = ContentId::for_bytes(&shard);
+        let mut rng = rand::thread_rng();
+        let challenge = Challenge::random(id.clone(), shard.len() as u64, &mut rng);
+        assert_eq!(challenge.length, CHALLENGE_BYTES);
+        let answer = challenge.answer(&shard).unwrap();
+
+        let mut damaged = shard.clone();
+        damaged[(challenge.offset + challenge.length / 2) as usize] ^= 1;
+        assert_ne!(challenge.answer(&damaged), Some(answer.clone()));
+        let renewed = Challenge { nonce: vec![0; 32], ..challenge.clone() };
+        assert_ne!(renewed.answer(&shard), Some(answer));
+        assert_eq!(challenge.answer(&shard[..100]), None);
+
+        let small = Challenge::random(id.clone(), 10, &mut rng);
+        assert_eq!((small.offset, small.length), (0, 10));
+
+        let prepared = Challenge::prepare(&id, &shard, 3, &mut rng);
+        assert_eq!(prepared.len(), 3);
+        assert!(prepared.iter().all(|prepared| prepared.challenge.answer(&shard) == Some(prepared.answer.clone())));
+    }
+
+    #[test]
+    fn test_prepared_challenges_are_used_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proofs.json");
+        let shard = b"shard the owner drops".repeat(10);
+        let id = ContentId::for_bytes(&shard);
+
+        let book = ProofBook::open(&path).unwrap();
+        book.prepare(&id, Challenge::prepare(&id, &shard, 2, &mut rand::thread_rng())).unwrap();
+        book.record(&id, &"holder".to_string(), FragmentStatus::Active).unwrap();
+
+        let book = ProofBook::open(&path).unwrap();
+        assert!(book.get(&id, "holder").unwrap().is_some());
+        let first = book.take_prepared(&id).unwrap().unwrap();
+        let second = book.take_prepared(&id).unwrap().unwrap();
+        assert_ne!(first.challenge.nonce, second.challenge.nonce);
+        assert!(book.take_prepared(&id).unwrap().is_none());
+    }
+}
@@ -0,0 +1,852 @@
+//! Replication of shards to peers
+//!
+//! The [`Replicator`] distributes the shards of the node's objects over
+//! other peers. For every object with shards that no peer holds yet it
+//! asks the reachable peers for a [`StorageOffer`], [places](super::placement)
+//! the shards on the best of them, pushes each shard and records the peer
+//! as its holder in the object catalog. The local copies are kept, so the
+//! node reads its objects from disk as long as it can; shards lost locally
+//! are fetched back from their holders.
+//!
+//! Holders are [challenged](super::proofs) to prove that they still hold
+//! their shards; failed proofs mark the copy as missing or corrupted and
+//! cost the holder reputation.
+//!
+//! The same replicator serves the holder side: it answers offers from the
+//! space left in the local fragment store, stores pushed shards, answers
+//! challenges and hands shards out again.
+//!
+//! Holders keep a shard until the lease its owner granted with it ends.
+//! Whenever the owner's own lease on an object is extended, the next pass
+//! extends the leases of its holders to match; shards of an owner that
+//! stops renewing are [collected](super::gc) by their holders.
+//!
+//! When the node stores more than its quota allows, it
+//! [evicts](Replicator::evict) shards: shards held for peers are released
+//! to their owners, which place them on other peers first, and local
+//! copies of the node's own shards are dropped once a peer proved to hold
+//! them. Challenges are prepared before, so that the holders of dropped
+//! shards are still checked.
+
+use chrono::{DateTime, Utc};
+use futures::future::{join_all, BoxFuture};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::objects::{ObjectStore, StoredObject};
+use super::placement::{self, Candidate, StorageOffer};
+use super::proofs::{Challenge, ProofBook, PREPARED_CHALLENGES};
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+use crate::identity::PeerId;
+use crate::rpc::{self, RpcHandler, RpcResult, Transport};
+use crate::synapse::verification::ReputationBook;
+use crate::ui_api::{FragmentStatus, NodeFragmentInfo};
+
+/// Protocol name of shard replication messages
+pub const REPLICATION_PROTOCOL: &str = "/mycelium/chronicle/replication/1";
+
+/// Replication timeouts and settings
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// How long to wait for storage offers
+    pub offer_timeout: Duration,
+    /// How long the transfer of one shard may take
+    pub transfer_timeout: Duration,
+    /// Interval between replication passes
+    pub interval: Duration,
+    /// Interval between two challenges of the same shard copy
+    pub proof_interval: Duration,
+    /// How long a holder may take to answer a challenge
+    pub proof_deadline: Duration,
+    /// Region advertised in this node's offers
+    pub region: Option<String>,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            offer_timeout: Duration::from_secs(5),
+            transfer_timeout: Duration::from_secs(60),
+            interval: Duration::from_secs(60),
+            proof_interval: Duration::from_secs(6 * 3600),
+            proof_deadline: Duration::from_secs(10),
+            region: None,
+        }
+    }
+}
+
+/// Request of the replication protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationRequest {
+    /// Asks a peer how much it can store for shards of `shard_size` bytes
+    Offer { shard_size: u64 },
+    /// Pushes a shard to a peer, to hold until `until`
+    Store { data: Vec<u8>, until: DateTime<Utc> },
+    /// Extends the lease of a holder on a shard to `until`
+    Renew { content_id: ContentId, until: DateTime<Utc> },
+    /// Asks a holder for a shard
+    Fetch { content_id: ContentId },
+    /// Asks a holder to prove that it holds a shard
+    Challenge(Challenge),
+    /// Asks the owner of a shard to take it back from the holder
+    Release { content_id: ContentId },
+    /// Tells a holder that the owner deleted the shard
+    Drop { content_id: ContentId },
+}
+
+/// Response of the replication protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationResponse {
+    /// Storage the peer offers
+    Offer(StorageOffer),
+    /// The shard is stored under `content_id`
+    Stored { content_id: ContentId },
+    /// The lease on the shard was extended
+    Renewed,
+    /// Content of a requested shard
+    Shard { data: Vec<u8> },
+    /// Answer to a challenge
+    Proof { answer: ContentId },
+    /// The holder may delete its copy of the shard
+    Released,
+    /// The holder deleted its copy of the shard
+    Dropped,
+    /// The peer refused the request
+    Declined { reason: String },
+}
+
+/// Distributes the node's shards and holds shards of other peers
+#[derive(Clone)]
+pub struct Replicator {
+    objects: Arc<ObjectStore>,
+    transport: Arc<dyn Transport>,
+    reputation: Arc<ReputationBook>,
+    proofs: Arc<ProofBook>,
+    config: ReplicationConfig,
+}
+
+impl Replicator {
+    /// Creates a replicator for the objects of `objects`
+    ///
+    /// The replicator must also be registered as the
+    /// [`REPLICATION_PROTOCOL`] handler of the node to hold shards of its
+    /// peers. `reputation` records how reliably peers hold shards and
+    /// `proofs` the outcome of their challenges.
+    pub fn new(
+        objects: Arc<ObjectStore>,
+        transport: Arc<dyn Transport>,
+        reputation: Arc<ReputationBook>,
+        proofs: Arc<ProofBook>,
+        config: ReplicationConfig,
+    ) -> Self {
+        Self { objects, transport, reputation, proofs, config }
+    }
+
+    /// Gets the outcomes of the challenges of shard holders
+    pub fn proofs(&self) -> &ProofBook {
+        &self.proofs
+    }
+
+    /// Runs the replicator: frees space beyond the quota, places shards
+    /// without holders and challenges holders that are due every interval
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.evict().await {
+                log::error!("Failed to free storage beyond the quota: {}", e);
+            }
+            let objects = match self.objects.objects() {
+                Ok(objects) => objects,
+                Err(e) => {
+                    log::error!("Failed to list stored objects: {}", e);
+                    continue;
+                }
+            };
+            for (id, _) in objects {
+                if let Err(e) = self.replicate(&id).await {
+                    log::warn!("Replication of object {} failed: {}", id, e);
+                }
+                if let Err(e) = self.renew_holders(&id).await {
+                    log::warn!("Failed to renew the holders of object {}: {}", id, e);
+                }
+            }
+            if let Err(e) = self.challenge_holders().await {
+                log::error!("Failed to challenge shard holders: {}", e);
+            }
+        }
+    }
+
+    /// Places the shards of an object that no peer holds yet
+    ///
+    /// Returns the number of shards pushed to peers. Shards that found no
+    /// place are left for the next pass; shards lost locally are left to
+    /// the [scrubber](super::scrub).
+    pub async fn replicate(&self, id: &ContentId) -> ChronicleResult<usize> {
+        let object = self.objects.object(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let mut unplaced = Vec::new();
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            if !object.holders_of(shard).is_empty() {
+                continue;
+            }
+            match self.objects.fragments().get(content_id) {
+                Ok(data) => unplaced.push((shard, data)),
+                Err(ChronicleError::NotFound(_) | ChronicleError::IntegrityMismatch { .. }) => {
+                    log::debug!("Shard {} of object {} is lost locally and cannot be placed", shard, id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.place(id, &object, unplaced).await
+    }
+
+    /// Pushes shards of an object, given with their index, to the best
+    /// peers offering room for them
+    ///
+    /// Returns the number of shards placed.
+    async fn place(&self, id: &ContentId, object: &StoredObject, shards: Vec<(usize, Vec<u8>)>) -> ChronicleResult<usize> {
+        if shards.is_empty() {
+            return Ok(0);
+        }
+        let manifest = &object.manifest;
+        let shard_size = manifest.size.div_ceil(manifest.config.data_shards as u64).max(1);
+        let candidates = self.collect_offers(shard_size).await;
+        let holders: Vec<PeerId> = object.holders.iter().flatten().cloned().collect();
+        let peers = placement::place(shards.len(), shard_size, &candidates, &holders);
+        let until = object.expires_at().unwrap_or_else(Utc::now);
+
+        // Shards go to different peers, so they are pushed in parallel
+        let transfers = shards.into_iter().zip(peers).map(|((shard, data), peer)| async move {
+            let request = ReplicationRequest::Store { data, until };
+            let answer = self.request(&peer, &request, self.config.transfer_timeout).await;
+            (shard, peer, answer)
+        });
+        let mut placed = 0;
+        for (shard, peer, answer) in join_all(transfers).await {
+            let content_id = &manifest.shards[shard];
+            match answer {
+                Ok(ReplicationResponse::Stored { content_id: stored }) if stored == *content_id => {
+                    self.objects.add_holder(id, shard, &peer)?;
+                    placed += 1;
+                }
+                Ok(ReplicationResponse::Declined { reason }) => {
+                    log::info!("Peer {} declined shard {} of object {}: {}", peer, shard, id, reason);
+                }
+                Ok(other) => log::warn!("Unexpected answer from peer {} to shard transfer: {:?}", peer, other),
+                Err(e) => log::warn!("Failed to push shard {} of object {} to peer {}: {}", shard, id, peer, e),
+            }
+        }
+        if placed > 0 {
+            log::info!("Replicated {} shards of object {}", placed, id);
+            if object.holders_until.is_none() {
+                self.objects.set_holders_until(id, until)?;
+            }
+        }
+        Ok(placed)
+    }
+
+    /// Extends the leases of the holders of an object's shards to the end
+    /// of the owners' last lease on it, if they were granted less
+    ///
+    /// Holders that cannot be reached are tried again on the next call.
+    pub async fn renew_holders(&self, id: &ContentId) -> ChronicleResult<()> {
+        let Some(object) = self.objects.object(id)? else {
+            return Ok(());
+        };
+        let Some(until) = object.expires_at() else {
+            return Ok(());
+        };
+        if object.holders_until.is_none_or(|granted| granted >= until) {
+            return Ok(());
+        }
+        let mut renewed = true;
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            for holder in object.holders_of(shard) {
+                let request = ReplicationRequest::Renew { content_id: content_id.clone(), until };
+                match self.request(holder, &request, self.config.transfer_timeout).await {
+                    Ok(ReplicationResponse::Renewed) => {}
+                    Ok(answer) => log::info!("Peer {} did not renew its lease on shard {}: {:?}", holder, content_id, answer),
+                    Err(e) => {
+                        log::warn!("Failed to renew the lease of peer {} on shard {}: {}", holder, content_id, e);
+                        renewed = false;
+                    }
+                }
+            }
+        }
+        if renewed {
+            self.objects.set_holders_until(id, until)?;
+        }
+        Ok(())
+    }
+
+    /// Frees the space the node uses beyond its quota
+    ///
+    /// Shards held for peers are released to their owners first, oldest
+    /// first; a shard whose owner does not answer or cannot place it
+    /// elsewhere is kept. Then local copies of the node's own shards are
+    /// deleted if a peer proved to hold a copy, holders never challenged
+    /// being challenged first. Challenges of the shard are prepared before
+    /// its copy is deleted, and it is marked evicted so that the scrubber
+    /// does not restore it. Returns the number of bytes freed.
+    pub async fn evict(&self) -> ChronicleResult<u64> {
+        let fragments = self.objects.fragments();
+        let excess = fragments.excess_bytes()?;
+        if excess == 0 {
+            return Ok(0);
+        }
+        log::info!("Chronicle stores {} bytes beyond its quota; migrating shards", excess);
+
+        let mut freed = 0;
+        for (content_id, owner) in fragments.held()? {
+            if freed >= excess {
+                break;
+            }
+            let request = ReplicationRequest::Release { content_id: content_id.clone() };
+            match self.request(&owner, &request, self.config.transfer_timeout).await {
+                Ok(ReplicationResponse::Released) => freed += self.remove_local(&content_id)?,
+                answer => log::info!("Peer {} did not take back shard {}: {:?}", owner, content_id, answer),
+            }
+        }
+
+        for (id, _) in self.objects.objects()? {
+            if freed >= excess {
+                break;
+            }
+            if let Err(e) = self.replicate(&id).await {
+                log::warn!("Replication of object {} failed: {}", id, e);
+            }
+            let Some(object) = self.objects.object(&id)? else {
+                continue;
+            };
+            for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+                if freed >= excess {
+                    break;
+                }
+                let data = match fragments.get(content_id) {
+                    Ok(data) => data,
+                    Err(ChronicleError::NotFound(_) | ChronicleError::IntegrityMismatch { .. }) => continue,
+                    Err(e) => return Err(e),
+                };
+                if !self.has_proven_holder(content_id, &data, object.holders_of(shard)).await? {
+                    continue;
+                }
+                let prepared = Challenge::prepare(content_id, &data, PREPARED_CHALLENGES, &mut rand::thread_rng());
+                self.proofs.prepare(content_id, prepared)?;
+                freed += self.remove_local(content_id)?;
+                self.objects.set_evicted(&id, shard, true)?;
+            }
+        }
+
+        if freed < excess {
+            log::warn!("{} bytes are still stored beyond the quota; migration continues on the next pass", excess - freed);
+        }
+        Ok(freed)
+    }
+
+    /// Challenges every holder whose shard copy has not been checked within
+    /// the proof interval
+    ///
+    /// Holders of evicted shards are sent the challenges prepared before
+    /// the local copy was deleted. Once those are used up, the shard is no
+    /// longer marked evicted, so that the scrubber restores it when there
+    /// is room. Other shards missing locally cannot be checked and are
+    /// skipped until they are restored. Returns the number of failed
+    /// proofs.
+    pub async fn challenge_holders(&self) -> ChronicleResult<usize> {
+        let mut failed = 0;
+        for (id, object) in self.objects.objects()? {
+            for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+                let holders = object.holders_of(shard);
+                if holders.is_empty() {
+                    continue;
+                }
+                let data = match self.objects.fragments().get(content_id) {
+                    Ok(data) => Some(data),
+                    Err(ChronicleError::NotFound(_) | ChronicleError::IntegrityMismatch { .. }) => None,
+                    Err(e) => return Err(e),
+                };
+                if data.is_none() && !object.evicted.contains(&shard) {
+                    continue;
+                }
+                for holder in holders {
+                    if !self.proofs.is_due(content_id, holder, self.config.proof_interval, Utc::now())? {
+                        continue;
+                    }
+                    let status = match &data {
+                        Some(data) => self.challenge(content_id, data, holder).await?,
+                        None => match self.proofs.take_prepared(content_id)? {
+                            Some(prepared) => self.prove(content_id, holder, prepared.challenge, Some(prepared.answer)).await?,
+                            None => {
+                                log::info!("Challenges of evicted shard {} are used up; restoring it once there is room", content_id);
+                                self.objects.set_evicted(&id, shard, false)?;
+                                break;
+                            }
+                        },
+                    };
+                    if status != FragmentStatus::Active {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Challenges a holder of a shard whose content is `data`
+    ///
+    /// Returns the resulting status of the holder's copy: `Active` if it
+    /// answered correctly in time, `Corrupted` if it answered wrongly and
+    /// `Missing` if it did not answer.
+    pub async fn challenge(&self, content_id: &ContentId, data: &[u8], holder: &PeerId) -> ChronicleResult<FragmentStatus> {
+        let challenge = Challenge::random(content_id.clone(), data.len() as u64, &mut rand::thread_rng());
+        let expected = challenge.answer(data);
+        self.prove(content_id, holder, challenge, expected).await
+    }
+
+    /// Sends a challenge to a holder and records whether it answered as
+    /// `expected`
+    async fn prove(
+        &self,
+        content_id: &ContentId,
+        holder: &PeerId,
+        challenge: Challenge,
+        expected: Option<ContentId>,
+    ) -> ChronicleResult<FragmentStatus> {
+        let request = ReplicationRequest::Challenge(challenge);
+        let status = match self.request(holder, &request, self.config.proof_deadline).await {
+            Ok(ReplicationResponse::Proof { answer }) if Some(&answer) == expected.as_ref() => FragmentStatus::Active,
+            Ok(ReplicationResponse::Proof { .. }) => FragmentStatus::Corrupted,
+            answer => {
+                log::debug!("Peer {} did not prove shard {}: {:?}", holder, content_id, answer);
+                FragmentStatus::Missing
+            }
+        };
+
+        let record = self.proofs.record(content_id, holder, status.clone())?;
+        let reputation = if status == FragmentStatus::Active {
+            self.reputation.record_agreement(holder)
+        } else {
+            log::warn!("Peer {} failed to prove shard {} ({} in a row): {:?}", holder, content_id, record.failures, status);
+            self.reputation.record_disagreement(holder)
+        };
+        if let Err(e) = reputation {
+            log::error!("Failed to update the reputation of peer {}: {}", holder, e);
+        }
+        Ok(status)
+    }
+
+    /// Reads an object for `owner`, fetching the shards lost locally from
+    /// their holders
+    ///
+    /// Only as many shards as needed to rebuild the object are fetched.
+    pub async fn get(&self, id: &ContentId, owner: &str) -> ChronicleResult<Vec<u8>> {
+        let object = self.objects.object(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        if !object.is_owned_by(owner) {
+            return Err(ChronicleError::NotOwner(id.clone()));
+        }
+        let shards = self.gather(&object).await?;
+        self.objects.decode_object(id, &object, owner, shards)
+    }
+
+    /// Collects enough shards of an object to rebuild it: local ones first,
+    /// the others from their holders (`None` for shards not collected)
+    pub async fn gather(&self, object: &StoredObject) -> ChronicleResult<Vec<Option<Vec<u8>>>> {
+        let required = object.manifest.config.data_shards;
+        let mut shards = Vec::with_capacity(object.manifest.shards.len());
+        let mut available = 0;
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            if available >= required {
+                shards.push(None);
+                continue;
+            }
+            let data = match self.objects.fragments().get(content_id) {
+                Ok(data) => Some(data),
+                Err(ChronicleError::NotFound(_) | ChronicleError::IntegrityMismatch { .. }) => {
+                    self.fetch(content_id, object.holders_of(shard)).await
+                }
+                Err(e) => return Err(e),
+            };
+            available += usize::from(data.is_some());
+            shards.push(data);
+        }
+        Ok(shards)
+    }
+
+    /// Gets the nodes holding the shards of an object
+    ///
+    /// The local node counts the shards it still has on disk.
+    pub fn node_distribution(&self, id: &ContentId) -> ChronicleResult<Vec<NodeFragmentInfo>> {
+        self.objects.node_distribution(id, &self.transport.local_peer())
+    }
+
+    /// Fetches a shard from the first holder that returns it intact
+    async fn fetch(&self, content_id: &ContentId, holders: &[PeerId]) -> Option<Vec<u8>> {
+        let request = ReplicationRequest::Fetch { content_id: content_id.clone() };
+        for holder in holders {
+            match self.request(holder, &request, self.config.transfer_timeout).await {
+                Ok(ReplicationResponse::Shard { data }) if content_id.verify(&data).is_ok() => return Some(data),
+                Ok(ReplicationResponse::Shard { .. }) => {
+                    log::warn!("Peer {} returned a damaged copy of shard {}", holder, content_id);
+                    if let Err(e) = self.reputation.record_disagreement(holder) {
+                        log::error!("Failed to update the reputation of peer {}: {}", holder, e);
+                    }
+                }
+                answer => log::debug!("Peer {} did not return shard {}: {:?}", holder, content_id, answer),
+            }
+        }
+        None
+    }
+
+    /// Asks every reachable peer for storage and ranks the offers
+    async fn collect_offers(&self, shard_size: u64) -> Vec<Candidate> {
+        let peers = self.transport.peers();
+        let request = ReplicationRequest::Offer { shard_size };
+        let answers = join_all(peers.iter().map(|peer| self.request(peer, &request, self.config.offer_timeout))).await;
+
+        peers
+            .into_iter()
+            .zip(answers)
+            .filter_map(|(peer, answer)| match answer {
+                Ok(ReplicationResponse::Offer(offer)) => {
+                    let reputation = self.reputation.get(&peer);
+                    (!reputation.is_banned()).then(|| Candidate { reputation: reputation.score(), peer, offer })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Serves a replication request from `from`
+    async fn handle_request(&self, from: &PeerId, request: ReplicationRequest) -> ChronicleResult<ReplicationResponse> {
+        let fragments = self.objects.fragments();
+        match request {
+            ReplicationRequest::Offer { shard_size } => {
+                let offer = self.offer()?;
+                if offer.free_bytes < shard_size {
+                    return Ok(ReplicationResponse::Declined { reason: "Not enough free storage".to_string() });
+                }
+                Ok(ReplicationResponse::Offer(offer))
+            }
+            ReplicationRequest::Store { data, until } => {
+                let content_id = fragments.put_held(&data, from, until)?;
+                log::debug!("Holding shard {} ({} bytes) for peer {} until {}", content_id, data.len(), from, until);
+                Ok(ReplicationResponse::Stored { content_id })
+            }
+            ReplicationRequest::Renew { content_id, until } => {
+                if !fragments.renew_held(&content_id, from, until)? {
+                    return Ok(ReplicationResponse::Declined { reason: "The shard is not held for this peer".to_string() });
+                }
+                Ok(ReplicationResponse::Renewed)
+            }
+            ReplicationRequest::Fetch { content_id } => Ok(ReplicationResponse::Shard { data: fragments.get(&content_id)? }),
+            ReplicationRequest::Challenge(challenge) => match challenge.answer(&fragments.get(&challenge.content_id)?) {
+                Some(answer) => Ok(ReplicationResponse::Proof { answer }),
+                None => Ok(ReplicationResponse::Declined { reason: "Challenge range is outside the shard".to_string() }),
+            },
+            ReplicationRequest::Release { content_id } => self.release(from, &content_id).await,
+            ReplicationRequest::Drop { content_id } => {
+                let held = fragments.entry(&content_id)?.and_then(|entry| entry.held);
+                if held.is_none_or(|hold| hold.peer != *from) {
+                    return Ok(ReplicationResponse::Declined { reason: "The shard is not held for this peer".to_string() });
+                }
+                fragments.remove(&content_id)?;
+                log::debug!("Dropped shard {} deleted by peer {}", content_id, from);
+                Ok(ReplicationResponse::Dropped)
+            }
+        }
+    }
+
+    /// Tells the holders of a deleted object to delete their copies
+    ///
+    /// Holders that cannot be reached keep their copies.
+    pub async fn forget(&self, object: &StoredObject) {
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            for holder in object.holders_of(shard) {
+                let request = ReplicationRequest::Drop { content_id: content_id.clone() };
+                match self.request(holder, &request, self.config.transfer_timeout).await {
+                    Ok(ReplicationResponse::Dropped) => {}
+                    answer => log::info!("Peer {} did not drop shard {}: {:?}", holder, content_id, answer),
+                }
+            }
+        }
+    }
+
+    /// Takes a shard back from a holder that can no longer keep it
+    ///
+    /// If the holder has the only copy, the shard is fetched from it and
+    /// placed on another peer before the holder is released.
+    async fn release(&self, holder: &PeerId, content_id: &ContentId) -> ChronicleResult<ReplicationResponse> {
+        for (id, object) in self.objects.objects()? {
+            for (shard, _) in object.manifest.shards.iter().enumerate().filter(|(_, shard)| *shard == content_id) {
+                let holders = object.holders_of(shard);
+                if !holders.contains(holder) {
+                    continue;
+                }
+                let only_copy = !self.objects.fragments().contains(content_id)?
+                    && holders.iter().all(|other| other == holder);
+                if only_copy {
+                    let Some(data) = self.fetch(content_id, std::slice::from_ref(holder)).await else {
+                        return Ok(ReplicationResponse::Declined { reason: "The shard could not be fetched".to_string() });
+                    };
+                    if self.place(&id, &object, vec![(shard, data)]).await? == 0 {
+                        return Ok(ReplicationResponse::Declined { reason: "No peer can take the shard".to_string() });
+                    }
+                }
+                self.objects.remove_holder(&id, shard, holder)?;
+                log::info!("Peer {} released shard {} of object {}", holder, shard, id);
+            }
+        }
+        Ok(ReplicationResponse::Released)
+    }
+
+    /// Whether one of `holders` answered the last challenge of a shard
+    /// whose content is `data` correctly, challenging those never
+    /// challenged before
+    async fn has_proven_holder(&self, content_id: &ContentId, data: &[u8], holders: &[PeerId]) -> ChronicleResult<bool> {
+        for holder in holders {
+            let status = match self.proofs.get(content_id, holder)? {
+                Some(record) => record.status,
+                None => self.challenge(content_id, data, holder).await?,
+            };
+            if status == FragmentStatus::Active {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Deletes the local copy of a shard; returns the bytes freed
+    fn remove_local(&self, content_id: &ContentId) -> ChronicleResult<u64> {
+        let fragments = self.objects.fragments();
+        let size = fragments.entry(content_id)?.map_or(0, |entry| entry.size);
+        Ok(if fragments.remove(content_id)? { size } else { 0 })
+    }
+
+    /// Gets the storage this node offers to its peers
+    fn offer(&self) -> ChronicleResult<StorageOffer> {
+        let fragments = self.objects.fragments();
+        Ok(StorageOffer {
+            free_bytes: fragments.free_bytes()?,
+            quota_bytes: fragments.stats()?.allocated_bytes,
+            region: self.config.region.clone(),
+        })
+    }
+
+    async fn request(&self, to: &PeerId, request: &ReplicationRequest, timeout: Duration) -> RpcResult<ReplicationResponse> {
+        rpc::call(self.transport.as_ref(), to, REPLICATION_PROTOCOL, request, timeout).await
+    }
+}
+
+impl RpcHandler for Replicator {
+    fn handle(&self, from: PeerId, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+        let replicator = self.clone();
+        Box::pin(async move {
+            let request: ReplicationRequest = rpc::decode(&payload)?;
+            let response = replicator
+                .handle_request(&from, request)
+                .await
+                .unwrap_or_else(|e| ReplicationResponse::Declined { reason: e.to_string() });
+            rpc::encode(&response)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::chronicle::crypto::KeyStore;
+    use crate::chronicle::fragments::FragmentStore;
+    use crate::rpc::{LoopbackNetwork, RpcRouter};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    pub(crate) struct Node {
+        pub(crate) replicator: Replicator,
+        pub(crate) objects: Arc<ObjectStore>,
+        pub(crate) peer_id: PeerId,
+        _dir: TempDir,
+    }
+
+    pub(crate) fn start_node(network: &LoopbackNetwork, peer_id: &str, allocated_bytes: u64) -> Node {
+        let dir = tempfile::tempdir().unwrap();
+        let fragments = Arc::new(FragmentStore::open(dir.path(), allocated_bytes).unwrap());
+        let keys = KeyStore::open(&dir.path().join("keystore.json")).unwrap();
+        let objects = Arc::new(ObjectStore::open(dir.path(), fragments, keys, peer_id).unwrap());
+        let reputation = Arc::new(ReputationBook::open(dir.path().join("reputation.json")).unwrap());
+        let proofs = Arc::new(ProofBook::open(dir.path().join("proofs.json")).unwrap());
+
+        let router = Arc::new(RpcRouter::default());
+        let transport = network.join(peer_id.to_string(), Arc::clone(&router));
+        let config = ReplicationConfig {
+            offer_timeout: Duration::from_secs(1),
+            transfer_timeout: Duration::from_secs(5),
+            proof_interval: Duration::ZERO,
+            proof_deadline: Duration::from_secs(1),
+            ..ReplicationConfig::default()
+        };
+        let replicator = Replicator::new(Arc::clone(&objects), transport, reputation, proofs, config);
+        router.register(REPLICATION_PROTOCOL, Arc::new(replicator.clone()));
+        Node { replicator, objects, peer_id: peer_id.to_string(), _dir: dir }
+    }
+
+    /// Starts `count` peers with 1 MiB of storage each
+    pub(crate) fn start_peers(network: &LoopbackNetwork, count: usize) -> Vec<Node> {
+        (0..count).map(|i| start_node(network, &format!("peer-{}", i), 1 << 20)).collect()
+    }
+
+    /// Opens the unbounded object store of a node without peers in `dir`
+    pub(crate) fn open_objects(dir: &Path) -> Arc<ObjectStore> {
+        let fragments = Arc::new(FragmentStore::open(dir, u64::MAX).unwrap());
+        let keys = KeyStore::open(&dir.join("keystore.json")).unwrap();
+        Arc::new(ObjectStore::open(dir, fragments, keys, "node").unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_object_survives_loss_of_nodes() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let peers = start_peers(&network, 6);
+        let full = start_node(&network, "full", 16);
+
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        let id = owner.objects.put(&data).unwrap();
+        assert_eq!(owner.replicator.replicate(&id).await.unwrap(), 6);
+        assert_eq!(owner.replicator.replicate(&id).await.unwrap(), 0);
+
+        // Every shard went to a different peer, none to the full one
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let distribution = owner.replicator.node_distribution(&id).unwrap();
+        assert_eq!(distribution.len(), 7);
+        assert!(distribution[0].is_your_node && distribution[0].fragment_count == 6);
+        assert!(distribution[1..].iter().all(|node| node.fragment_count == 1 && node.node_id != full.peer_id));
+
+        // The owner loses its disk and two holders go offline
+        for shard in &object.manifest.shards {
+            owner.objects.fragments().remove(shard).unwrap();
+        }
+        for holder in [object.holders_of(0)[0].clone(), object.holders_of(4)[0].clone()] {
+            network.leave(&holder);
+        }
+        assert!(owner.objects.get(&id).is_err());
+        assert_eq!(owner.replicator.get(&id, "owner").await.unwrap(), data);
+
+        // A third lost holder is one too many
+        network.leave(&object.holders_of(1)[0]);
+        assert!(matches!(
+            owner.replicator.get(&id, "owner").await,
+            Err(ChronicleError::NotEnoughShards { available: 3, required: 4 })
+        ));
+        assert!(peers.iter().any(|peer| peer.objects.fragments().stats().unwrap().fragment_count == 1));
+    }
+
+    #[tokio::test]
+    async fn test_lowered_quota_migrates_shards() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let peers = start_peers(&network, 7);
+        let data = b"archive of the aibox conversations".repeat(400);
+        let id = owner.objects.put(&data).unwrap();
+        assert_eq!(owner.replicator.replicate(&id).await.unwrap(), 6);
+
+        // The owner keeps no copies of its own once peers hold them
+        let fragments = owner.objects.fragments();
+        let used = fragments.stats().unwrap().used_bytes;
+        fragments.set_allocated_bytes(0);
+        assert_eq!(owner.replicator.evict().await.unwrap(), used);
+        assert_eq!(fragments.stats().unwrap().fragment_count, 0);
+        assert!(matches!(owner.objects.put(b"one more object"), Err(ChronicleError::QuotaExceeded { .. })));
+
+        // A holder over its quota hands its only copy to another peer first
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let holder = peers.iter().find(|peer| peer.peer_id == object.holders_of(0)[0]).unwrap();
+        holder.objects.fragments().set_allocated_bytes(0);
+        assert!(holder.replicator.evict().await.unwrap() > 0);
+        assert_eq!(holder.objects.fragments().stats().unwrap().fragment_count, 0);
+
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        assert_eq!(object.holders_of(0).len(), 1);
+        assert_ne!(object.holders_of(0)[0], holder.peer_id);
+        assert_eq!(owner.replicator.get(&id, "owner").await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_evicted_shards_are_still_challenged() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let peers = start_peers(&network, 6);
+        let id = owner.objects.put(&b"weights the owner has no room for".repeat(400)).unwrap();
+        owner.replicator.replicate(&id).await.unwrap();
+        owner.objects.fragments().set_allocated_bytes(0);
+        owner.replicator.evict().await.unwrap();
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        assert_eq!(object.evicted.len(), 6);
+
+        // Without local copies, the prepared challenges still catch a
+        // holder that dropped its shard
+        assert_eq!(owner.replicator.challenge_holders().await.unwrap(), 0);
+        let shard = &object.manifest.shards[0];
+        let holder = peers.iter().find(|peer| peer.peer_id == object.holders_of(0)[0]).unwrap();
+        holder.objects.fragments().remove(shard).unwrap();
+        assert_eq!(owner.replicator.challenge_holders().await.unwrap(), 1);
+        let record = owner.replicator.proofs().get(shard, &holder.peer_id).unwrap().unwrap();
+        assert_ne!(record.status, FragmentStatus::Active);
+
+        // Used up, they leave the shards to be restored
+        for _ in 0..PREPARED_CHALLENGES {
+            owner.replicator.challenge_holders().await.unwrap();
+        }
+        assert!(owner.objects.object(&id).unwrap().unwrap().evicted.is_empty());
+    }
+
+    /// Holder that keeps its shards but answers challenges without them
+    struct Liar(Replicator);
+
+    impl RpcHandler for Liar {
+        fn handle(&self, from: PeerId, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+            match rpc::decode(&payload) {
+                Ok(ReplicationRequest::Challenge(_)) => {
+                    let answer = ContentId::for_bytes(b"trust me");
+                    Box::pin(async move { rpc::encode(&ReplicationResponse::Proof { answer }) })
+                }
+                _ => self.0.handle(from, payload),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_challenges_downgrade_holders() {
+        let network = LoopbackNetwork::new();
+        let owner = start_node(&network, "owner", u64::MAX);
+        let honest = start_node(&network, "honest", 1 << 20);
+        let offline = start_node(&network, "offline", 1 << 20);
+        let liar = start_node(&network, "liar", 1 << 20);
+        network.join(liar.peer_id.clone(), {
+            let router = Arc::new(RpcRouter::default());
+            router.register(REPLICATION_PROTOCOL, Arc::new(Liar(liar.replicator.clone())));
+            router
+        });
+
+        let id = owner.objects.put(&b"ledger of the aibox".repeat(500)).unwrap();
+        assert_eq!(owner.replicator.replicate(&id).await.unwrap(), 6);
+        assert_eq!(owner.replicator.challenge_holders().await.unwrap(), 2);
+        network.leave(&offline.peer_id);
+        assert_eq!(owner.replicator.challenge_holders().await.unwrap(), 4);
+
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let status_of = |peer: &PeerId| {
+            let shard = (0..6).find(|shard| object.holders_of(*shard).contains(peer)).unwrap();
+            owner.replicator.proofs().get(&object.manifest.shards[shard], peer).unwrap().unwrap()
+        };
+        let proved = status_of(&honest.peer_id);
+        assert_eq!(proved.status, FragmentStatus::Active);
+        assert!(proved.last_proof.is_some());
+        assert_eq!(status_of(&liar.peer_id).status, FragmentStatus::Corrupted);
+        let missing = status_of(&offline.peer_id);
+        assert_eq!((missing.status, missing.failures), (FragmentStatus::Missing, 1));
+
+        let reputation = owner.replicator.reputation.get(&liar.peer_id);
+        assert_eq!((reputation.agreements, reputation.disagreements), (0, 4));
+        assert_eq!(owner.replicator.reputation.get(&honest.peer_id).disagreements, 0);
+    }
+}
@@ -0,0 +1,726 @@
+//! Integrity scrubbing and repair
+//!
+//! Fragments rot on disk and holders go away, so the [`Scrubber`]
+//! regularly checks all of the node's data:
+//!
+//! 1. Every local fragment is re-hashed; damaged ones are deleted so that
+//!    they are not served or mistaken for intact copies.
+//! 2. Shards lost locally are rebuilt if there is room for them: enough
+//!    shards are gathered to decode their object, which is encoded again
+//!    (yielding the very same shards) and the lost ones are stored. Shards
+//!    [evicted](Replicator::evict) to a proven holder are left there.
+//! 3. The holders of replicated shards are challenged against the local
+//!    copies, or with challenges prepared before a shard was evicted (see
+//!    [`Replicator::challenge_holders`]).
+//! 4. Holders that failed their challenge are dropped and their shards
+//!    are placed on other peers.
+//!
+//! A shard counts as available if the node has an intact copy or a holder
+//! that answered its last challenge; a holder never challenged is unknown
+//! and does not count. The share of available shards over all objects is
+//! the node's data integrity. The scrubber also describes objects and
+//! [streams](super::stream) for the fragment views of the UI.
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::crypto;
+use super::erasure;
+use super::objects::{ObjectStore, StoredObject};
+use super::replication::Replicator;
+use super::stream::StreamManifest;
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+use crate::identity::PeerId;
+use crate::ui_api::{ActiveFragment, FragmentDetails, FragmentStatus, ScrubProgress};
+
+/// Interval between two scrubs
+pub const DEFAULT_SCRUB_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+
+/// Fragments re-hashed between two progress updates
+const PROGRESS_BATCH: u32 = 256;
+
+/// Number of progress updates buffered for slow subscribers
+const EVENT_BUFFER: usize = 64;
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Availability of the shards of one object
+struct ObjectHealth {
+    /// Shards with an intact local copy or a proven holder
+    available: usize,
+    /// Shards without an intact local copy, except evicted shards with a
+    /// proven holder
+    missing_locally: Vec<usize>,
+    /// Holders that failed their last challenge, by shard
+    failed_holders: Vec<(usize, PeerId)>,
+}
+
+/// Checks the node's data and repairs what it can
+#[derive(Clone)]
+pub struct Scrubber {
+    objects: Arc<ObjectStore>,
+    replicator: Option<Replicator>,
+    interval: Duration,
+    progress: Arc<Mutex<ScrubProgress>>,
+    statuses: Arc<Mutex<HashMap<ContentId, FragmentStatus>>>,
+    events: broadcast::Sender<ScrubProgress>,
+}
+
+impl Scrubber {
+    /// Creates a scrubber for `objects`, running every `interval`
+    ///
+    /// Without a `replicator`, only local fragments are checked and objects
+    /// are repaired from the shards left on this node.
+    pub fn new(objects: Arc<ObjectStore>, replicator: Option<Replicator>, interval: Duration) -> Self {
+        Self {
+            objects,
+            replicator,
+            interval,
+            progress: Arc::new(Mutex::new(ScrubProgress { data_integrity: 100, ..ScrubProgress::default() })),
+            statuses: Arc::new(Mutex::new(HashMap::new())),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    /// Subscribes to progress updates
+    pub fn subscribe(&self) -> broadcast::Receiver<ScrubProgress> {
+        self.events.subscribe()
+    }
+
+    /// Gets the progress of the running scrub, or the outcome of the last
+    pub fn progress(&self) -> ScrubProgress {
+        self.progress.lock().map(|progress| progress.clone()).unwrap_or_default()
+    }
+
+    /// Gets the status of an object as of the last scrub (`Active` until
+    /// it was scrubbed)
+    pub fn object_status(&self, id: &ContentId) -> FragmentStatus {
+        self.statuses
+            .lock()
+            .ok()
+            .and_then(|statuses| statuses.get(id).cloned())
+            .unwrap_or(FragmentStatus::Active)
+    }
+
+    /// Gets the share of the shards of an object that have an intact local
+    /// copy or a holder that answered its last challenge, in percent
+    ///
+    /// Holders never challenged are not counted.
+    pub fn availability(&self, object: &StoredObject) -> ChronicleResult<u8> {
+        let available = self.health(object)?.available * 100;
+        Ok(available.checked_div(object.manifest.shards.len()).map_or(100, |share| share as u8))
+    }
+
+    /// Gets when the shards of an object were last proven to be intact
+    ///
+    /// That is the latest correct answer of one of its holders, or the
+    /// last scrub if it re-hashed local shards of the object.
+    pub fn last_proof(&self, object: &StoredObject) -> ChronicleResult<Option<DateTime<Utc>>> {
+        let mut last_proof = None;
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            if let Some(replicator) = &self.replicator {
+                for holder in object.holders_of(shard) {
+                    let proven = replicator.proofs().get(content_id, holder)?.and_then(|record| record.last_proof);
+                    last_proof = last_proof.max(proven);
+                }
+            }
+            if self.objects.fragments().contains(content_id)? {
+                let scrubbed = self.progress().finished_at.filter(|finished_at| *finished_at >= object.stored_at);
+                last_proof = last_proof.max(scrubbed);
+            }
+        }
+        Ok(last_proof)
+    }
+
+    /// Describes an object for the fragment list
+    pub fn active_fragment(&self, id: &ContentId, object: &StoredObject) -> ActiveFragment {
+        ActiveFragment {
+            name: id.to_string(),
+            aibox_id: object.owner.clone(),
+            size_gb: object.size as f64 / BYTES_PER_GB,
+            fragment_count: object.manifest.shards.len() as u32,
+            status: self.object_status(id),
+        }
+    }
+
+    /// Describes an object in detail
+    ///
+    /// An object whose shards were never proven intact reports when it was
+    /// stored as its last proof.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ChronicleError::NotFound`] if the object is unknown
+    pub fn fragment_details(&self, id: &ContentId) -> ChronicleResult<FragmentDetails> {
+        let object = self.objects.object(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let fragment = self.active_fragment(id, &object);
+        let node_distribution = match &self.replicator {
+            Some(replicator) => replicator.node_distribution(id)?,
+            None => self.objects.node_distribution(id, &self.objects.owner().to_string())?,
+        };
+        Ok(FragmentDetails {
+            size_gb: fragment.size_gb,
+            fragment_count: fragment.fragment_count,
+            encoding_type: object.manifest.config.to_string(),
+            encryption_algorithm: object
+                .encryption
+                .as_ref()
+                .map_or_else(|| "None".to_string(), |encryption| encryption.algorithm.clone()),
+            key_size_bits: if object.encryption.is_some() { crypto::KEY_BITS } else { 0 },
+            last_proof_update: self.last_proof(&object)?.unwrap_or(object.stored_at),
+            integrity_status: fragment.status == FragmentStatus::Active,
+            availability_percent: self.availability(&object)?,
+            node_distribution,
+            min_fragments_for_recovery: object.manifest.config.data_shards as u32,
+            fragment,
+        })
+    }
+
+    /// Describes a streamed object for the fragment list, over the shards
+    /// of all its chunks
+    ///
+    /// A stream is in the state of its worst chunk; a chunk gone from the
+    /// catalog makes it missing.
+    pub fn stream_fragment(&self, id: &ContentId, stream: &StreamManifest) -> ChronicleResult<ActiveFragment> {
+        let mut fragment = ActiveFragment {
+            name: id.to_string(),
+            aibox_id: stream.owner.clone(),
+            size_gb: stream.size as f64 / BYTES_PER_GB,
+            fragment_count: 0,
+            status: FragmentStatus::Active,
+        };
+        for chunk in &stream.chunks {
+            let status = match self.objects.object(chunk)? {
+                Some(object) => {
+                    fragment.fragment_count += object.manifest.shards.len() as u32;
+                    self.object_status(chunk)
+                }
+                None => FragmentStatus::Missing,
+            };
+            fragment.status = worse(fragment.status, status);
+        }
+        Ok(fragment)
+    }
+
+    /// Describes a streamed object in detail, over the shards of all its
+    /// chunks
+    ///
+    /// Reading the stream takes every chunk, so it is as available and as
+    /// recently proven as its weakest chunk. Every chunk needs its own
+    /// minimum of shards.
+    pub fn stream_details(&self, id: &ContentId, stream: &StreamManifest) -> ChronicleResult<FragmentDetails> {
+        let fragment = self.stream_fragment(id, stream)?;
+        let mut details = FragmentDetails {
+            size_gb: fragment.size_gb,
+            fragment_count: fragment.fragment_count,
+            encoding_type: self.objects.erasure().to_string(),
+            encryption_algorithm: "None".to_string(),
+            key_size_bits: 0,
+            last_proof_update: stream.stored_at,
+            integrity_status: fragment.status == FragmentStatus::Active,
+            availability_percent: 100,
+            node_distribution: Vec::new(),
+            min_fragments_for_recovery: 0,
+            fragment,
+        };
+        let mut last_proof = None;
+        for chunk in &stream.chunks {
+            let chunk = match self.fragment_details(chunk) {
+                Ok(chunk) => chunk,
+                Err(ChronicleError::NotFound(_)) => {
+                    details.availability_percent = 0;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if last_proof.is_none() {
+                details.encoding_type = chunk.encoding_type;
+                details.encryption_algorithm = chunk.encryption_algorithm;
+                details.key_size_bits = chunk.key_size_bits;
+            }
+            last_proof = Some(last_proof.map_or(chunk.last_proof_update, |proven: DateTime<Utc>| proven.min(chunk.last_proof_update)));
+            details.availability_percent = details.availability_percent.min(chunk.availability_percent);
+            details.min_fragments_for_recovery += chunk.min_fragments_for_recovery;
+            for node in chunk.node_distribution {
+                match details.node_distribution.iter_mut().find(|known| known.node_id == node.node_id) {
+                    Some(known) => known.fragment_count += node.fragment_count,
+                    None => details.node_distribution.push(node),
+                }
+            }
+        }
+        details.last_proof_update = last_proof.unwrap_or(stream.stored_at);
+        Ok(details)
+    }
+
+    /// Runs a scrub right away and then every interval
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.scrub().await {
+                log::error!("Chronicle scrub failed: {}", e);
+            }
+        }
+    }
+
+    /// Checks all fragments and objects once and repairs damaged objects
+    ///
+    /// Fragments and objects that cannot be checked are logged and skipped;
+    /// the scrub is marked finished however it ends.
+    pub async fn scrub(&self) -> ChronicleResult<ScrubProgress> {
+        let fragments = self.objects.fragments();
+        let ids = fragments.ids()?;
+        self.update(|progress| {
+            *progress = ScrubProgress {
+                running: true,
+                total_fragments: ids.len() as u32,
+                data_integrity: progress.data_integrity,
+                finished_at: progress.finished_at,
+                ..ScrubProgress::default()
+            }
+        });
+        let _running = Running(self);
+
+        let mut damaged: Vec<ContentId> = Vec::new();
+        for (checked, id) in ids.iter().enumerate() {
+            match fragments.get(id) {
+                Ok(_) | Err(ChronicleError::NotFound(_)) => {}
+                Err(ChronicleError::IntegrityMismatch { .. }) => {
+                    log::warn!("Fragment {} is damaged; deleting it", id);
+                    if let Err(e) = fragments.remove(id) {
+                        log::warn!("Failed to delete damaged fragment {}: {}", id, e);
+                    }
+                    damaged.push(id.clone());
+                }
+                Err(e) => log::warn!("Failed to check fragment {}: {}", id, e),
+            }
+            let checked = checked as u32 + 1;
+            if checked.is_multiple_of(PROGRESS_BATCH) || checked == ids.len() as u32 {
+                let damaged = damaged.len() as u32;
+                self.update(|progress| {
+                    progress.checked_fragments = checked;
+                    progress.damaged_fragments = damaged;
+                });
+            }
+        }
+
+        // Local copies first: holders can only be challenged against them
+        let mut repaired = HashSet::new();
+        for (id, object) in self.objects.objects()? {
+            let missing = match self.health(&object) {
+                Ok(health) => health.missing_locally,
+                Err(e) => {
+                    log::warn!("Failed to check the shards of object {}: {}", id, e);
+                    continue;
+                }
+            };
+            if missing.is_empty() {
+                continue;
+            }
+            self.set_status(&id, FragmentStatus::Recovering);
+            match self.restore(&id, &object, &missing).await {
+                Ok(()) => {
+                    repaired.insert(id);
+                }
+                // Lost shards stay with their holders until there is room
+                Err(ChronicleError::QuotaExceeded { .. }) => {
+                    log::debug!("No room to restore the shards of object {} locally", id);
+                }
+                Err(e) => log::warn!("Failed to restore the shards of object {}: {}", id, e),
+            }
+        }
+
+        if let Some(replicator) = &self.replicator {
+            if let Err(e) = replicator.challenge_holders().await {
+                log::warn!("Failed to challenge the holders of replicated shards: {}", e);
+            }
+        }
+
+        let (mut available, mut total) = (0, 0);
+        for (id, object) in self.objects.objects()? {
+            match self.check(&id, object, &damaged, &repaired).await {
+                Ok((object_available, shards)) => {
+                    available += object_available;
+                    total += shards;
+                }
+                Err(e) => log::warn!("Failed to check object {}: {}", id, e),
+            }
+        }
+
+        let data_integrity = (available * 100).checked_div(total).map_or(100, |share| share as u8);
+        self.update(|progress| {
+            progress.running = false;
+            progress.data_integrity = data_integrity;
+            progress.finished_at = Some(Utc::now());
+        });
+        let progress = self.progress();
+        log::info!(
+            "Chronicle scrub finished: {} damaged fragments, {} objects repaired, {} lost, integrity {}%",
+            progress.damaged_fragments, progress.repaired_objects, progress.lost_objects, progress.data_integrity
+        );
+        Ok(progress)
+    }
+
+    /// Replaces the failed holders of an object and records its status
+    ///
+    /// Returns the number of available shards and the number of shards.
+    async fn check(
+        &self,
+        id: &ContentId,
+        mut object: StoredObject,
+        damaged: &[ContentId],
+        repaired: &HashSet<ContentId>,
+    ) -> ChronicleResult<(usize, usize)> {
+        let mut repaired = repaired.contains(id);
+        let mut health = self.health(&object)?;
+        if let (Some(replicator), false) = (&self.replicator, health.failed_holders.is_empty()) {
+            self.set_status(id, FragmentStatus::Recovering);
+            for (shard, holder) in &health.failed_holders {
+                self.objects.remove_holder(id, *shard, holder)?;
+            }
+            match replicator.replicate(id).await {
+                Ok(_) => repaired = true,
+                Err(e) => log::warn!("Failed to place the shards of object {} again: {}", id, e),
+            }
+            object = self.objects.object(id)?.unwrap_or(object);
+            health = self.health(&object)?;
+        }
+
+        let shards = object.manifest.shards.len();
+        let status = if health.available == shards {
+            FragmentStatus::Active
+        } else if health.available >= object.manifest.config.data_shards {
+            FragmentStatus::Recovering
+        } else {
+            self.update(|progress| progress.lost_objects += 1);
+            if object.manifest.shards.iter().any(|shard| damaged.contains(shard)) {
+                FragmentStatus::Corrupted
+            } else {
+                FragmentStatus::Missing
+            }
+        };
+        if repaired && status == FragmentStatus::Active {
+            log::info!("Repaired object {}", id);
+            self.update(|progress| progress.repaired_objects += 1);
+        }
+        self.set_status(id, status);
+        Ok((health.available, shards))
+    }
+
+    /// Determines which copies of the shards of an object are intact
+    fn health(&self, object: &StoredObject) -> ChronicleResult<ObjectHealth> {
+        let mut health = ObjectHealth { available: 0, missing_locally: Vec::new(), failed_holders: Vec::new() };
+        for (shard, content_id) in object.manifest.shards.iter().enumerate() {
+            let local = self.objects.fragments().contains(content_id)?;
+            let mut remote = false;
+            for holder in object.holders_of(shard) {
+                let record = match &self.replicator {
+                    Some(replicator) => replicator.proofs().get(content_id, holder)?,
+                    None => None,
+                };
+                match record.map(|record| record.status) {
+                    Some(FragmentStatus::Active) => remote = true,
+                    Some(_) => health.failed_holders.push((shard, holder.clone())),
+                    // Never challenged: unknown until it is
+                    None => {}
+                }
+            }
+            let evicted = remote && object.evicted.contains(&shard);
+            if !local && !evicted {
+                health.missing_locally.push(shard);
+            }
+            health.available += usize::from(local || remote);
+        }
+        Ok(health)
+    }
+
+    /// Rebuilds the shards of an object missing on this node from the
+    /// shards left here and at its holders
+    async fn restore(&self, id: &ContentId, object: &StoredObject, missing: &[usize]) -> ChronicleResult<()> {
+        let fragments = self.objects.fragments();
+        // Checked before anything is fetched from the holders
+        let requested = object.manifest.shard_size() * missing.len() as u64;
+        let free = fragments.free_bytes()?;
+        if requested > free {
+            return Err(ChronicleError::QuotaExceeded { requested, free });
+        }
+        let shards = match &self.replicator {
+            Some(replicator) => replicator.gather(object).await?,
+            None => object
+                .manifest
+                .shards
+                .iter()
+                .map(|shard| fragments.get(shard).ok())
+                .collect(),
+        };
+        let stored = erasure::decode(&object.manifest, shards)?;
+        let (manifest, rebuilt) = erasure::encode(&stored, object.manifest.config)?;
+        if manifest.shards != object.manifest.shards {
+            return Err(ChronicleError::Erasure(format!("rebuilt shards of object {} do not match", id)));
+        }
+        for &shard in missing {
+            fragments.put(&rebuilt[shard])?;
+            self.objects.set_evicted(id, shard, false)?;
+            if let Some(replicator) = &self.replicator {
+                replicator.proofs().discard_prepared(&object.manifest.shards[shard])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_status(&self, id: &ContentId, status: FragmentStatus) {
+        if let Ok(mut statuses) = self.statuses.lock() {
+            statuses.insert(id.clone(), status);
+        }
+    }
+
+    /// Changes the progress and publishes it
+    fn update(&self, f: impl FnOnce(&mut ScrubProgress)) {
+        let Ok(mut progress) = self.progress.lock() else {
+            return;
+        };
+        f(&mut progress);
+        let _ = self.events.send(progress.clone());
+    }
+}
+
+/// Gets the worse of two statuses
+fn worse(a: FragmentStatus, b: FragmentStatus) -> FragmentStatus {
+    let severity = |status: &FragmentStatus| match status {
+        FragmentStatus::Active => 0,
+        FragmentStatus::Recovering => 1,
+        FragmentStatus::Missing => 2,
+        FragmentStatus::Corrupted => 3,
+    };
+    if severity(&b) > severity(&a) { b } else { a }
+}
+
+/// Marks the scrub finished when dropped, also if it failed half-way
+struct Running<'a>(&'a Scrubber);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.0.progress().running {
+            self.0.update(|progress| progress.running = false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::replication::tests::{open_objects, start_node, start_peers, Node};
+    use crate::rpc::LoopbackNetwork;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    /// Starts an owner with seven peers and a scrubber of its objects
+    fn start_owner(network: &LoopbackNetwork) -> (Node, Vec<Node>, Scrubber) {
+        let owner = start_node(network, "owner", u64::MAX);
+        let peers = start_peers(network, 7);
+        let scrubber = Scrubber::new(Arc::clone(&owner.objects), Some(owner.replicator.clone()), DEFAULT_SCRUB_INTERVAL);
+        (owner, peers, scrubber)
+    }
+
+    #[tokio::test]
+    async fn test_damaged_fragments_are_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let scrubber = Scrubber::new(Arc::clone(&objects), None, DEFAULT_SCRUB_INTERVAL);
+        let mut events = scrubber.subscribe();
+
+        let healthy = objects.put(&b"weights of the aibox".repeat(300)).unwrap();
+        let doomed = objects.put(&b"an object about to be lost".repeat(300)).unwrap();
+        let shards = objects.object(&healthy).unwrap().unwrap().manifest.shards;
+
+        // Bit rot in one shard, another one deleted
+        let rotten = dir.path().join("objects").join(&shards[1].as_str()[..2]).join(shards[1].as_str());
+        fs::write(&rotten, b"rotten").unwrap();
+        objects.fragments().remove(&shards[4]).unwrap();
+        // Three shards of a 4+2 object are one too many
+        for shard in &objects.object(&doomed).unwrap().unwrap().manifest.shards[..3] {
+            objects.fragments().remove(shard).unwrap();
+        }
+
+        let progress = scrubber.scrub().await.unwrap();
+        assert_eq!((progress.checked_fragments, progress.total_fragments), (8, 8));
+        assert_eq!(progress.damaged_fragments, 1);
+        assert_eq!((progress.repaired_objects, progress.lost_objects), (1, 1));
+        assert_eq!(progress.data_integrity, 75);
+        assert!(!progress.running && progress.finished_at.is_some());
+        assert_eq!(scrubber.object_status(&healthy), FragmentStatus::Active);
+        assert_eq!(scrubber.object_status(&doomed), FragmentStatus::Missing);
+        let lost = objects.object(&doomed).unwrap().unwrap();
+        assert_eq!(scrubber.availability(&lost).unwrap(), 50);
+        assert_eq!(scrubber.last_proof(&lost).unwrap(), progress.finished_at);
+
+        assert_eq!(fs::read(&rotten).unwrap().len() as u64, objects.fragments().entry(&shards[1]).unwrap().unwrap().size);
+        assert!(objects.fragments().contains(&shards[4]).unwrap());
+        assert!(events.try_recv().unwrap().running);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_fragments_do_not_stop_the_scrub() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let scrubber = Scrubber::new(Arc::clone(&objects), None, DEFAULT_SCRUB_INTERVAL);
+
+        let id = objects.put(&b"dataset shard".repeat(300)).unwrap();
+        let shards = objects.object(&id).unwrap().unwrap().manifest.shards;
+        // Reading a directory fails with an I/O error rather than a mismatch
+        let unreadable = dir.path().join("objects").join(&shards[2].as_str()[..2]).join(shards[2].as_str());
+        fs::remove_file(&unreadable).unwrap();
+        fs::create_dir(&unreadable).unwrap();
+
+        let progress = scrubber.scrub().await.unwrap();
+        assert_eq!((progress.checked_fragments, progress.total_fragments), (6, 6));
+        assert_eq!(progress.damaged_fragments, 0);
+        assert!(!progress.running && progress.finished_at.is_some());
+        assert!(!scrubber.progress().running);
+    }
+
+    #[tokio::test]
+    async fn test_lost_shards_wait_for_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let fragments = objects.fragments();
+        let scrubber = Scrubber::new(Arc::clone(&objects), None, DEFAULT_SCRUB_INTERVAL);
+
+        let id = objects.put(&b"evaluation results".repeat(300)).unwrap();
+        let lost = objects.object(&id).unwrap().unwrap().manifest.shards[0].clone();
+        fragments.remove(&lost).unwrap();
+        fragments.set_allocated_bytes(fragments.stats().unwrap().used_bytes);
+
+        let progress = scrubber.scrub().await.unwrap();
+        assert_eq!((progress.repaired_objects, progress.lost_objects), (0, 0));
+        assert!(!fragments.contains(&lost).unwrap());
+        assert_eq!(scrubber.object_status(&id), FragmentStatus::Recovering);
+
+        fragments.set_allocated_bytes(u64::MAX);
+        assert_eq!(scrubber.scrub().await.unwrap().repaired_objects, 1);
+        assert!(fragments.contains(&lost).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_evicted_shards_are_left_to_their_holders() {
+        let network = LoopbackNetwork::new();
+        let (owner, _peers, scrubber) = start_owner(&network);
+
+        let id = owner.objects.put(&b"log of a finished training run".repeat(300)).unwrap();
+        owner.replicator.replicate(&id).await.unwrap();
+        let fragments = owner.objects.fragments();
+        fragments.set_allocated_bytes(0);
+        owner.replicator.evict().await.unwrap();
+        assert_eq!(owner.objects.object(&id).unwrap().unwrap().evicted.len(), 6);
+
+        // Room again, but the holders keep the shards
+        fragments.set_allocated_bytes(u64::MAX);
+        let progress = scrubber.scrub().await.unwrap();
+        assert_eq!((progress.repaired_objects, progress.data_integrity), (0, 100));
+        assert_eq!(fragments.stats().unwrap().fragment_count, 0);
+        assert_eq!(scrubber.object_status(&id), FragmentStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn test_fragment_details_follow_the_shards() {
+        let network = LoopbackNetwork::new();
+        let (owner, _peers, scrubber) = start_owner(&network);
+
+        let id = owner.objects.put(&b"tokenizer vocabulary".repeat(300)).unwrap();
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let details = scrubber.fragment_details(&id).unwrap();
+        assert_eq!(details.fragment.name, id.to_string());
+        assert_eq!((details.fragment_count, details.min_fragments_for_recovery), (6, 4));
+        assert_eq!((details.availability_percent, details.integrity_status), (100, true));
+        // Never proven yet: the time it was stored
+        assert_eq!(details.last_proof_update, object.stored_at);
+        assert_eq!(details.node_distribution.len(), 1);
+        assert!(details.node_distribution[0].is_your_node && details.node_distribution[0].fragment_count == 6);
+
+        owner.replicator.replicate(&id).await.unwrap();
+        // Only the holder of the first shard proves its copy
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let first = &object.manifest.shards[0];
+        let data = owner.objects.fragments().get(first).unwrap();
+        owner.replicator.challenge(first, &data, &object.holders_of(0)[0]).await.unwrap();
+        for shard in &object.manifest.shards[..2] {
+            owner.objects.fragments().remove(shard).unwrap();
+        }
+        let details = scrubber.fragment_details(&id).unwrap();
+        assert_eq!(details.node_distribution.len(), 7);
+        assert_eq!(details.node_distribution[0].fragment_count, 4);
+        assert!(details.node_distribution[1..].iter().all(|node| node.fragment_count == 1));
+        // The holder of the second shard is unknown, not healthy
+        assert_eq!(details.availability_percent, 83);
+
+        let unknown = ContentId::for_bytes(b"never stored");
+        assert!(matches!(scrubber.fragment_details(&unknown), Err(ChronicleError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_streams_are_described_over_their_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = open_objects(dir.path());
+        let scrubber = Scrubber::new(Arc::clone(&objects), None, DEFAULT_SCRUB_INTERVAL);
+
+        let chunks: Vec<ContentId> = [b"first chunk".repeat(300), b"second chunk".repeat(300)]
+            .iter()
+            .map(|chunk| objects.put_object(chunk, "aibox", true).unwrap())
+            .collect();
+        let id = ContentId::for_bytes(b"the whole stream");
+        let stream = StreamManifest {
+            owner: "aibox".to_string(),
+            size: 6900,
+            chunk_size: 3300,
+            chunks: chunks.clone(),
+            stored_at: Utc::now(),
+            leases: BTreeMap::from([("aibox".to_string(), Utc::now())]),
+        };
+
+        let details = scrubber.stream_details(&id, &stream).unwrap();
+        assert_eq!((details.fragment.name.as_str(), details.fragment.aibox_id.as_str()), (id.as_str(), "aibox"));
+        assert_eq!((details.fragment_count, details.min_fragments_for_recovery), (12, 8));
+        assert_eq!((details.encryption_algorithm.as_str(), details.key_size_bits), (crypto::ALGORITHM, crypto::KEY_BITS));
+        assert_eq!(details.node_distribution.len(), 1);
+        assert_eq!(details.node_distribution[0].fragment_count, 12);
+        assert_eq!(details.last_proof_update, objects.object(&chunks[0]).unwrap().unwrap().stored_at);
+
+        // Two lost shards of one chunk hold back the whole stream
+        for shard in &objects.object(&chunks[1]).unwrap().unwrap().manifest.shards[..2] {
+            objects.fragments().remove(shard).unwrap();
+        }
+        let details = scrubber.stream_details(&id, &stream).unwrap();
+        assert_eq!((details.availability_percent, details.node_distribution[0].fragment_count), (66, 10));
+
+        objects.delete_object(&chunks[1]).unwrap();
+        assert_eq!(scrubber.stream_fragment(&id, &stream).unwrap().status, FragmentStatus::Missing);
+        let details = scrubber.stream_details(&id, &stream).unwrap();
+        assert_eq!((details.availability_percent, details.integrity_status), (0, false));
+    }
+
+    #[tokio::test]
+    async fn test_lost_holder_is_replaced() {
+        let network = LoopbackNetwork::new();
+        let (owner, _peers, scrubber) = start_owner(&network);
+
+        let id = owner.objects.put(&b"checkpoint of a long training run".repeat(300)).unwrap();
+        owner.replicator.replicate(&id).await.unwrap();
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        let gone = object.holders_of(0)[0].clone();
+        network.leave(&gone);
+        owner.objects.fragments().remove(&object.manifest.shards[0]).unwrap();
+
+        let progress = scrubber.scrub().await.unwrap();
+        assert_eq!((progress.repaired_objects, progress.data_integrity), (1, 100));
+        let object = owner.objects.object(&id).unwrap().unwrap();
+        assert_eq!(object.holders_of(0).len(), 1);
+        assert_ne!(object.holders_of(0)[0], gone);
+        assert!(owner.objects.fragments().contains(&object.manifest.shards[0]).unwrap());
+        assert_eq!(scrubber.object_status(&id), FragmentStatus::Active);
+        assert_eq!(scrubber.availability(&object).unwrap(), 100);
+        assert!(scrubber.last_proof(&object).unwrap().is_some());
+    }
+}
@@ -0,0 +1,675 @@
+//! Streamed transfer of large objects
+//!
+//! Objects too large to hold in memory are split into chunks of
+//! [`CHUNK_SIZE`] bytes. Every chunk is stored as an object of its own,
+//! encrypted, erasure coded and replicated like any other, and a stream
+//! manifest lists the chunks of the whole object. The whole object is
+//! still addressed by the content ID of all its bytes, which is computed
+//! while they stream through:
+//!
+//! ```text
+//! <dir>/streams.json
+//! <dir>/uploads/<transfer>.chunks
+//! ```
+//!
+//! Every chunk is recorded in the log of its upload once stored. When an
+//! interrupted upload is started again, the stream is read again but the
+//! chunks whose hash matches the log are not stored twice. Downloads can
+//! start at any offset, so a partial download resumes after the last chunk
+//! that verifies.
+//!
+//! Every owner that uploaded a stream holds a retention lease of its own
+//! on it, just like on an object, and only owners holding a lease can
+//! download it. The leases of the chunks are extended with the stream's:
+//! the chunks are kept for as long as the stream is listed, and the
+//! [garbage collector](super::gc) forgets the stream once all its leases
+//! ended.
+//!
+//! As a [`ContentStore`], the stream store stages task data: readers and
+//! writers are streamed chunk by chunk, while data handed over in one
+//! piece is stored as a single object.
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+use super::objects::{self, ObjectStore};
+use super::replication::Replicator;
+use super::{ChronicleError, ChronicleResult, ContentId, ContentStore};
+use crate::ui_api::{TransferDirection, TransferProgress};
+use crate::util::write_atomic;
+
+/// Size of the chunks streams are split into
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Chunks stored or fetched at the same time
+const PARALLEL_CHUNKS: usize = 4;
+
+/// Number of progress updates buffered for slow subscribers
+const EVENT_BUFFER: usize = 64;
+
+/// Chunks making up a streamed object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamManifest {
+    /// Owner that first stored the object
+    pub owner: String,
+    /// Size of the whole object in bytes
+    pub size: u64,
+    /// Size of every chunk but the last
+    pub chunk_size: u64,
+    /// Content IDs of the chunks, in order
+    pub chunks: Vec<ContentId>,
+    /// When the upload completed
+    pub stored_at: DateTime<Utc>,
+    /// End of the lease of every owner, including the first
+    pub leases: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl StreamManifest {
+    /// Whether `owner` holds a lease on the stream
+    pub fn is_owned_by(&self, owner: &str) -> bool {
+        self.leases.contains_key(owner)
+    }
+
+    /// Gets an owner whose lease reads the chunks, the first one while
+    /// it holds a lease
+    fn reader(&self) -> &str {
+        if self.is_owned_by(&self.owner) {
+            return &self.owner;
+        }
+        self.leases.keys().next().map_or(&self.owner, String::as_str)
+    }
+}
+
+/// Streamed uploads and downloads on top of the object store
+pub struct StreamStore {
+    objects: Arc<ObjectStore>,
+    replicator: Option<Replicator>,
+    dir: PathBuf,
+    chunk_size: usize,
+    streams: Mutex<HashMap<ContentId, StreamManifest>>,
+    events: broadcast::Sender<TransferProgress>,
+}
+
+impl StreamStore {
+    /// Opens the stream manifests stored in `dir`
+    ///
+    /// Chunks are stored in `objects` and, given a `replicator`, placed on
+    /// peers right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifests exist but cannot be read
+    pub fn open(dir: impl AsRef<Path>, objects: Arc<ObjectStore>, replicator: Option<Replicator>) -> ChronicleResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("uploads"))?;
+        let streams: HashMap<ContentId, StreamManifest> = match fs::read(dir.join("streams.json")) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            objects,
+            replicator,
+            dir,
+            chunk_size: CHUNK_SIZE,
+            streams: Mutex::new(streams),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        })
+    }
+
+    /// Subscribes to transfer progress
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferProgress> {
+        self.events.subscribe()
+    }
+
+    /// Gets the manifest of a streamed object
+    pub fn stream(&self, id: &ContentId) -> ChronicleResult<Option<StreamManifest>> {
+        Ok(self.lock_streams()?.get(id).cloned())
+    }
+
+    /// Lists all streamed objects, ordered by content ID
+    pub fn streams(&self) -> ChronicleResult<Vec<(ContentId, StreamManifest)>> {
+        let mut streams: Vec<(ContentId, StreamManifest)> = self.lock_streams()?.clone().into_iter().collect();
+        streams.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(streams)
+    }
+
+    /// Extends the lease of `owner` on a streamed object and on all its
+    /// chunks to a retention period from now
+    ///
+    /// Returns the end of the lease.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is unknown or `owner` holds no lease
+    pub fn renew(&self, id: &ContentId, owner: &str) -> ChronicleResult<DateTime<Utc>> {
+        let until = objects::lease_until(Utc::now(), self.objects.retention());
+        let mut streams = self.lock_streams()?;
+        let mut manifest = streams.get(id).cloned().ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        let lease = manifest.leases.get_mut(owner).ok_or_else(|| ChronicleError::NotOwner(id.clone()))?;
+        *lease = (*lease).max(until);
+        let expires_at = *lease;
+        self.hold_chunks(id, &manifest)?;
+        streams.insert(id.clone(), manifest);
+        self.save_streams(&streams)?;
+        Ok(expires_at)
+    }
+
+    /// Ends the stream leases that ran out by `now` and forgets the
+    /// streams left without one
+    ///
+    /// Returns the IDs of the forgotten streams, ordered. Their chunks are
+    /// collected like any other object once their own leases end.
+    pub fn end_expired_streams(&self, now: DateTime<Utc>) -> ChronicleResult<Vec<ContentId>> {
+        let mut streams = self.lock_streams()?;
+        let mut ended = false;
+        let mut expired = Vec::new();
+        for (id, manifest) in streams.iter_mut() {
+            let leases = manifest.leases.len();
+            manifest.leases.retain(|_, expires_at| *expires_at > now);
+            ended |= manifest.leases.len() != leases;
+            if manifest.leases.is_empty() {
+                expired.push(id.clone());
+            }
+        }
+        for id in &expired {
+            streams.remove(id);
+        }
+        if ended {
+            self.save_streams(&streams)?;
+        }
+        expired.sort();
+        Ok(expired)
+    }
+
+    /// Extends the leases of the chunks of every listed stream to the end
+    /// of the stream's lease
+    ///
+    /// Run before leases are ended, so that no chunk of a listed stream is
+    /// collected.
+    pub fn hold_all_chunks(&self) -> ChronicleResult<()> {
+        for (id, manifest) in &self.streams()? {
+            self.hold_chunks(id, manifest)?;
+        }
+        Ok(())
+    }
+
+    /// Stores everything `reader` yields as an object of `owner`
+    ///
+    /// Uploads are identified by `transfer_id`: if an earlier upload with
+    /// the same ID was interrupted, the chunks it stored are verified
+    /// against the stream and skipped. Returns the content ID of the whole
+    /// object.
+    pub async fn put_stream<R: AsyncRead + Unpin>(&self, transfer_id: &str, owner: &str, reader: R) -> ChronicleResult<ContentId> {
+        let log_path = self.upload_log(transfer_id);
+        let logged = read_upload_log(&log_path)?;
+        // Rewritten chunk by chunk, so that it always matches the stream
+        let mut log = File::create(&log_path)?;
+
+        let chunk_size = self.chunk_size;
+        let chunks = stream::try_unfold(reader, move |mut reader| async move {
+            let chunk = read_chunk(&mut reader, chunk_size).await?;
+            Ok::<_, ChronicleError>((!chunk.is_empty()).then_some((chunk, reader)))
+        });
+        let mut stored = pin!(chunks
+            .enumerate()
+            .map(|(index, chunk)| {
+                let logged = &logged;
+                async move {
+                    let chunk = chunk?;
+                    let id = ContentId::for_bytes(&chunk);
+                    let resumed = logged.get(index) == Some(&id)
+                        && self.objects.object(&id)?.is_some_and(|chunk| chunk.is_owned_by(owner));
+                    if !resumed {
+                        self.store_chunk(&chunk, owner).await?;
+                    }
+                    Ok::<_, ChronicleError>((chunk, id, resumed))
+                }
+            })
+            .buffered(PARALLEL_CHUNKS));
+
+        let mut progress = TransferProgress {
+            transfer_id: transfer_id.to_string(),
+            direction: TransferDirection::Upload,
+            transferred_bytes: 0,
+            total_bytes: None,
+            resumed_bytes: 0,
+            chunks: 0,
+            object_id: None,
+        };
+        let mut hasher = blake3::Hasher::new();
+        let mut ids = Vec::new();
+        while let Some((chunk, id, resumed)) = stored.try_next().await? {
+            hasher.update(&chunk);
+            writeln!(log, "{}", id)?;
+            log.sync_data()?;
+            if resumed {
+                progress.resumed_bytes += chunk.len() as u64;
+            }
+            ids.push(id);
+            progress.transferred_bytes += chunk.len() as u64;
+            progress.chunks += 1;
+            let _ = self.events.send(progress.clone());
+        }
+
+        let id = ContentId(hasher.finalize().to_hex().to_string());
+        self.record_stream(&id, owner, progress.transferred_bytes, ids)?;
+        drop(log);
+        fs::remove_file(&log_path)?;
+        log::info!("Stored stream {} ({} bytes, {} resumed)", id, progress.transferred_bytes, progress.resumed_bytes);
+
+        progress.total_bytes = Some(progress.transferred_bytes);
+        progress.object_id = Some(id.to_string());
+        let _ = self.events.send(progress);
+        Ok(id)
+    }
+
+    /// Writes a streamed object of `owner` to `writer`, starting `offset`
+    /// bytes into it
+    ///
+    /// Every chunk is verified before it is written; a download from the
+    /// start is also checked against the ID of the whole object. Returns
+    /// the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is unknown, `owner` holds no lease on
+    /// it or a chunk cannot be read
+    pub async fn get_stream<W: AsyncWrite + Unpin>(
+        &self,
+        transfer_id: &str,
+        id: &ContentId,
+        owner: &str,
+        offset: u64,
+        mut writer: W,
+    ) -> ChronicleResult<u64> {
+        let manifest = self.leased_stream(id, owner)?;
+        let offset = offset.min(manifest.size);
+        let first = (offset / manifest.chunk_size) as usize;
+        let mut skip = (offset % manifest.chunk_size) as usize;
+
+        let mut progress = TransferProgress {
+            transfer_id: transfer_id.to_string(),
+            direction: TransferDirection::Download,
+            transferred_bytes: offset,
+            total_bytes: Some(manifest.size),
+            resumed_bytes: offset,
+            chunks: first as u32,
+            object_id: None,
+        };
+        let mut hasher = (offset == 0).then(blake3::Hasher::new);
+        let mut chunks = pin!(stream::iter(&manifest.chunks[first.min(manifest.chunks.len())..])
+            .map(|chunk| self.get_chunk(chunk, owner))
+            .buffered(PARALLEL_CHUNKS));
+        while let Some(chunk) = chunks.try_next().await? {
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            let part = chunk.get(skip..).unwrap_or_default();
+            writer.write_all(part).await?;
+            skip = 0;
+            progress.transferred_bytes += part.len() as u64;
+            progress.chunks += 1;
+            let _ = self.events.send(progress.clone());
+        }
+        writer.flush().await?;
+
+        if let Some(hasher) = hasher {
+            let actual = ContentId(hasher.finalize().to_hex().to_string());
+            if &actual != id {
+                return Err(ChronicleError::IntegrityMismatch { expected: id.clone(), actual });
+            }
+        }
+        progress.object_id = Some(id.to_string());
+        let _ = self.events.send(progress.clone());
+        Ok(progress.transferred_bytes - offset)
+    }
+
+    /// Uploads a file; an interrupted upload of the same path resumes
+    pub async fn put_file(&self, path: &Path, owner: &str) -> ChronicleResult<ContentId> {
+        let file = tokio::fs::File::open(path).await?;
+        self.put_stream(&path.display().to_string(), owner, file).await
+    }
+
+    /// Downloads a streamed object of `owner` into a file
+    ///
+    /// If the file already holds part of the object, the download resumes
+    /// after the last chunk that verifies. Returns the number of bytes
+    /// downloaded.
+    pub async fn get_file(&self, id: &ContentId, owner: &str, path: &Path) -> ChronicleResult<u64> {
+        let manifest = self.leased_stream(id, owner)?;
+        let mut file = tokio::fs::OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path).await?;
+
+        let mut verified = 0;
+        for chunk in &manifest.chunks {
+            let data = read_chunk(&mut file, manifest.chunk_size as usize).await?;
+            if data.is_empty() || chunk.verify(&data).is_err() {
+                break;
+            }
+            verified += data.len() as u64;
+        }
+        if verified > 0 {
+            log::info!("Resuming download of {} after {} verified bytes", id, verified);
+        }
+        file.set_len(verified).await?;
+        file.seek(SeekFrom::Start(verified)).await?;
+        self.get_stream(&path.display().to_string(), id, owner, verified, &mut file).await
+    }
+
+    /// Gets the manifest of a stream `owner` holds a lease on
+    fn leased_stream(&self, id: &ContentId, owner: &str) -> ChronicleResult<StreamManifest> {
+        let manifest = self.stream(id)?.ok_or_else(|| ChronicleError::NotFound(id.clone()))?;
+        if !manifest.is_owned_by(owner) {
+            return Err(ChronicleError::NotOwner(id.clone()));
+        }
+        Ok(manifest)
+    }
+
+    /// Stores a chunk and places its shards on peers
+    async fn store_chunk(&self, chunk: &[u8], owner: &str) -> ChronicleResult<()> {
+        // Encryption and erasure coding are CPU bound
+        let (objects, chunk, owner) = (Arc::clone(&self.objects), chunk.to_vec(), owner.to_string());
+        let id = tokio::task::spawn_blocking(move || objects.put_object(&chunk, &owner, true))
+            .await
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))??;
+        if let Some(replicator) = &self.replicator {
+            if let Err(e) = replicator.replicate(&id).await {
+                log::warn!("Replication of chunk {} failed: {}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and verifies a chunk, from peers if it was lost locally
+    async fn get_chunk(&self, id: &ContentId, owner: &str) -> ChronicleResult<Vec<u8>> {
+        if let Some(replicator) = &self.replicator {
+            return replicator.get(id, owner).await;
+        }
+        let (objects, id, owner) = (Arc::clone(&self.objects), id.clone(), owner.to_string());
+        tokio::task::spawn_blocking(move || objects.get_object(&id, &owner))
+            .await
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))?
+    }
+
+    /// Extends the lease of every owner of a stream on its chunks to the
+    /// end of the owner's lease on the stream
+    fn hold_chunks(&self, id: &ContentId, manifest: &StreamManifest) -> ChronicleResult<()> {
+        for chunk in &manifest.chunks {
+            for (owner, expires_at) in &manifest.leases {
+                match self.objects.extend_lease(chunk, owner, *expires_at) {
+                    Ok(_) => {}
+                    Err(ChronicleError::NotFound(_) | ChronicleError::NotOwner(_)) => {
+                        log::warn!("Chunk {} of stream {} is no longer stored for {}", chunk, id, owner);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the manifest of a stored stream and leases it with its
+    /// chunks to `owner`
+    ///
+    /// A stream another owner stored before keeps its manifest: the same
+    /// bytes make the same chunks, which `owner` now holds leases on too.
+    fn record_stream(&self, id: &ContentId, owner: &str, size: u64, chunks: Vec<ContentId>) -> ChronicleResult<()> {
+        let stored_at = Utc::now();
+        let expires_at = objects::lease_until(stored_at, self.objects.retention());
+        let mut streams = self.lock_streams()?;
+        let mut manifest = streams.get(id).cloned().unwrap_or_else(|| StreamManifest {
+            owner: owner.to_string(),
+            size,
+            chunk_size: self.chunk_size as u64,
+            chunks,
+            stored_at,
+            leases: BTreeMap::new(),
+        });
+        let lease = manifest.leases.entry(owner.to_string()).or_insert(expires_at);
+        *lease = (*lease).max(expires_at);
+        self.hold_chunks(id, &manifest)?;
+        streams.insert(id.clone(), manifest);
+        self.save_streams(&streams)
+    }
+
+    fn upload_log(&self, transfer_id: &str) -> PathBuf {
+        self.dir
+            .join("uploads")
+            .join(format!("{}.chunks", ContentId::for_bytes(transfer_id.as_bytes())))
+    }
+
+    fn save_streams(&self, streams: &HashMap<ContentId, StreamManifest>) -> ChronicleResult<()> {
+        let bytes = serde_json::to_vec(streams).map_err(io::Error::other)?;
+        write_atomic(&self.dir.join("streams.json"), &bytes)?;
+        Ok(())
+    }
+
+    fn lock_streams(&self) -> ChronicleResult<MutexGuard<'_, HashMap<ContentId, StreamManifest>>> {
+        self.streams
+            .lock()
+            .map_err(|e| ChronicleError::StateUnavailable(e.to_string()))
+    }
+}
+
+impl ContentStore for StreamStore {
+    fn put(&self, data: &[u8]) -> ChronicleResult<ContentId> {
+        self.objects.put(data)
+    }
+
+    fn get(&self, id: &ContentId) -> ChronicleResult<Vec<u8>> {
+        let mut data = Vec::new();
+        self.get_into(id, &mut data)?;
+        Ok(data)
+    }
+
+    fn contains(&self, id: &ContentId) -> ChronicleResult<bool> {
+        Ok(self.lock_streams()?.contains_key(id) || self.objects.contains(id)?)
+    }
+
+    /// Stores the data as a stream owned by the node
+    ///
+    /// The chunks are only stored locally; the replicator places them on
+    /// peers in its next pass.
+    fn put_reader(&self, reader: &mut dyn Read) -> ChronicleResult<ContentId> {
+        let owner = self.objects.owner().to_string();
+        let mut hasher = blake3::Hasher::new();
+        let mut chunks = Vec::new();
+        let mut size = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(self.chunk_size);
+            (&mut *reader).take(self.chunk_size as u64).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(&chunk);
+            chunks.push(self.objects.put_object(&chunk, &owner, true)?);
+            size += chunk.len() as u64;
+        }
+        let id = ContentId(hasher.finalize().to_hex().to_string());
+        self.record_stream(&id, &owner, size, chunks)?;
+        Ok(id)
+    }
+
+    fn get_into(&self, id: &ContentId, writer: &mut dyn Write) -> ChronicleResult<u64> {
+        let Some(manifest) = self.stream(id)? else {
+            return self.objects.get_into(id, writer);
+        };
+        let mut hasher = blake3::Hasher::new();
+        for chunk in &manifest.chunks {
+            let data = self.objects.get_object(chunk, manifest.reader())?;
+            hasher.update(&data);
+            writer.write_all(&data)?;
+        }
+        let actual = ContentId(hasher.finalize().to_hex().to_string());
+        if &actual != id {
+            return Err(ChronicleError::IntegrityMismatch { expected: id.clone(), actual });
+        }
+        Ok(manifest.size)
+    }
+}
+
+/// Reads the chunk IDs logged by an upload, up to the first damaged line
+fn read_upload_log(path: &Path) -> ChronicleResult<Vec<ContentId>> {
+    match fs::read_to_string(path) {
+        Ok(log) => Ok(log.lines().map_while(|line| ContentId::parse(line).ok()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads up to `size` bytes, fewer only at the end of the stream
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    while chunk.len() < size {
+        let read = (&mut *reader).take((size - chunk.len()) as u64).read_to_end(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::replication::tests::open_objects;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    fn open_store(dir: &Path) -> StreamStore {
+        let mut store = StreamStore::open(dir, open_objects(dir), None).unwrap();
+        store.chunk_size = 1000;
+        store
+    }
+
+    /// Reader whose source goes away after `fail_at` bytes
+    struct Interrupted<'a> {
+        data: &'a [u8],
+        fail_at: usize,
+    }
+
+    impl AsyncRead for Interrupted<'_> {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if self.fail_at == 0 {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let len = buf.remaining().min(self.fail_at).min(self.data.len());
+            buf.put_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            self.fail_at -= len;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..10_500u32).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_upload_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let data = sample();
+        let mut events = store.subscribe();
+
+        let interrupted = Interrupted { data: &data, fail_at: 5500 };
+        assert!(matches!(store.put_stream("dataset", "aibox", interrupted).await, Err(ChronicleError::Io(_))));
+        while events.try_recv().is_ok() {}
+
+        let id = store.put_stream("dataset", "aibox", data.as_slice()).await.unwrap();
+        assert_eq!(id, ContentId::for_bytes(&data));
+        let manifest = store.stream(&id).unwrap().unwrap();
+        assert_eq!((manifest.size, manifest.chunks.len()), (10_500, 11));
+        let last = std::iter::from_fn(|| events.try_recv().ok()).last().unwrap();
+        assert_eq!((last.transferred_bytes, last.resumed_bytes, last.chunks), (10_500, 5000, 11));
+        assert_eq!(last.object_id, Some(id.to_string()));
+
+        let mut copy = Vec::new();
+        assert_eq!(store.get_stream("copy", &id, "aibox", 0, &mut copy).await.unwrap(), 10_500);
+        assert_eq!(copy, data);
+        let mut tail = Vec::new();
+        assert_eq!(store.get_stream("tail", &id, "aibox", 2500, &mut tail).await.unwrap(), 8000);
+        assert_eq!(tail, &data[2500..]);
+    }
+
+    #[tokio::test]
+    async fn test_every_owner_holds_its_own_lease() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let data = sample();
+
+        let id = store.put_stream("dataset", "aibox", data.as_slice()).await.unwrap();
+        assert_eq!(store.put_stream("copy", "other", data.as_slice()).await.unwrap(), id);
+        let manifest = store.stream(&id).unwrap().unwrap();
+        assert_eq!(manifest.owner, "aibox");
+        assert!(manifest.is_owned_by("aibox") && manifest.is_owned_by("other"));
+
+        let mut copy = Vec::new();
+        assert_eq!(store.get_stream("copy", &id, "other", 0, &mut copy).await.unwrap(), 10_500);
+        assert_eq!(copy, data);
+        assert!(matches!(store.get_stream("stolen", &id, "intruder", 0, Vec::new()).await, Err(ChronicleError::NotOwner(_))));
+        assert!(matches!(
+            store.get_file(&id, "intruder", &dir.path().join("stolen.bin")).await,
+            Err(ChronicleError::NotOwner(_))
+        ));
+
+        // The stream stays listed for as long as one lease lasts
+        store.objects.set_retention(std::time::Duration::from_secs(60 * 24 * 3600));
+        let until = store.renew(&id, "other").unwrap();
+        let first = store.stream(&id).unwrap().unwrap().leases["aibox"];
+        assert!(store.end_expired_streams(first).unwrap().is_empty());
+        let manifest = store.stream(&id).unwrap().unwrap();
+        assert!(!manifest.is_owned_by("aibox") && manifest.is_owned_by("other"));
+        assert_eq!(store.end_expired_streams(until).unwrap(), vec![id.clone()]);
+        assert!(store.stream(&id).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_partial_download_resumes_after_verified_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let data = sample();
+        let source = dir.path().join("dataset.bin");
+        fs::write(&source, &data).unwrap();
+        let id = store.put_file(&source, "aibox").await.unwrap();
+
+        // Three good chunks, then garbage from a torn write
+        let target = dir.path().join("download.bin");
+        fs::write(&target, [&data[..3000], &[0u8; 700][..]].concat()).unwrap();
+        assert_eq!(store.get_file(&id, "aibox", &target).await.unwrap(), 7500);
+        assert_eq!(fs::read(&target).unwrap(), data);
+        assert_eq!(store.get_file(&id, "aibox", &target).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_readers_are_staged_in_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let data = sample();
+
+        let id = store.put_reader(&mut data.as_slice()).unwrap();
+        assert_eq!(id, ContentId::for_bytes(&data));
+        let manifest = store.stream(&id).unwrap().unwrap();
+        assert_eq!((manifest.owner.as_str(), manifest.size, manifest.chunks.len()), ("node", 10_500, 11));
+        assert!(store.contains(&id).unwrap());
+
+        let mut copy = Vec::new();
+        assert_eq!(store.get_into(&id, &mut copy).unwrap(), 10_500);
+        assert_eq!(copy, data);
+
+        // Data handed over whole is a plain object
+        let small = store.put(b"config").unwrap();
+        assert!(store.stream(&small).unwrap().is_none());
+        assert_eq!(store.get(&small).unwrap(), b"config");
+    }
+}
@@ -0,0 +1,135 @@
+//! Node identity
+//!
+//! Each node owns an Ed25519 key pair stored in its data directory. The
+//! peer ID is the hex-encoded public key, so a signature can be checked
+//! against the peer ID alone without trusting a key sent alongside it.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Peer identifier: hex-encoded Ed25519 public key
+pub type PeerId = String;
+
+/// Signing identity of this node
+pub struct NodeIdentity {
+    signing_key: SigningKey,
+}
+
+impl NodeIdentity {
+    /// Generates a fresh identity
+    pub fn generate() -> Self {
+        Self { signing_key: SigningKey::generate(&mut OsRng) }
+    }
+
+    /// Loads the identity stored at `path`, creating it on first use
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key file cannot be read or written, or does
+    /// not contain a valid key
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            let bytes = fs::read(path)?;
+            let secret: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "node key must be 32 bytes")
+            })?;
+            return Ok(Self { signing_key: SigningKey::from_bytes(&secret) });
+        }
+
+        let identity = Self::generate();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_secret(path, &identity.signing_key.to_bytes())?;
+        log::info!("Generated node identity {}", identity.peer_id());
+        Ok(identity)
+    }
+
+    /// Gets the peer ID of this node
+    pub fn peer_id(&self) -> PeerId {
+        hex::encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// Signs `message` with the node key
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(message).to_bytes().to_vec()
+    }
+}
+
+/// Verifies that `signature` over `message` was made by `peer_id`
+pub fn verify_signature(peer_id: &str, message: &[u8], signature: &[u8]) -> bool {
+    let Ok(key_bytes) = hex::decode(peer_id) else {
+        return false;
+    };
+    let Ok(key_bytes) = <[u8; 32]>::try_from(key_bytes.as_slice()) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&key_bytes) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify(message, &signature).is_ok()
+}
+
+/// Writes key material to `path`
+///
+/// The file is readable by its owner only from the moment it is created,
+/// and both the file and its name are on disk before this returns.
+pub(crate) fn write_secret(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    // Left over if a crash interrupted an earlier write
+    let _ = fs::remove_file(&tmp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.key");
+        let identity = NodeIdentity::load_or_create(&path).unwrap();
+        let reloaded = NodeIdentity::load_or_create(&path).unwrap();
+        assert_eq!(identity.peer_id(), reloaded.peer_id());
+
+        let signature = identity.sign(b"result");
+        assert!(verify_signature(&identity.peer_id(), b"result", &signature));
+        assert!(!verify_signature(&identity.peer_id(), b"tampered", &signature));
+        assert!(!verify_signature(&NodeIdentity::generate().peer_id(), b"result", &signature));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
@@ -1,15 +1,50 @@
-mod p2p;
-mod system;
-mod ui_api;
+pub mod benchmark;
+pub mod chronicle;
+pub mod identity;
+pub mod p2p;
+pub mod rpc;
+pub mod settings;
+pub mod synapse;
+pub mod system;
+pub mod ui_api;
+mod util;
 
+use benchmark::{BenchmarkConfig, NodeProfileStore};
+use chronicle::ContentId;
+use chronicle::crypto::{self, KeyStore};
+use chronicle::fragments::{FragmentStore, DEFAULT_ALLOCATED_BYTES};
+use chronicle::gc::{GarbageCollector, DEFAULT_GC_INTERVAL};
+use chronicle::objects::ObjectStore;
+use chronicle::proofs::ProofBook;
+use chronicle::replication::{ReplicationConfig, Replicator, REPLICATION_PROTOCOL};
+use chronicle::scrub::{Scrubber, DEFAULT_SCRUB_INTERVAL};
+use chronicle::stream::StreamStore;
+use identity::NodeIdentity;
 use p2p::{RealP2PNode, P2PEvent};
+use rpc::{LoopbackNetwork, RpcRouter, Transport};
+use settings::SettingsStore;
+use synapse::Synapse;
+use synapse::dispatch::{DispatchConfig, Dispatcher, DISPATCH_PROTOCOL};
+use synapse::engine::TaskEvent;
+use synapse::manifest::{TaskManifest, TaskPlacement};
+use synapse::scheduler::ResourceCapacity;
+use synapse::workflow::WorkflowManifest;
 use system::{SystemMonitor, SystemInfo};
 use ui_api::*;
-use std::sync::Mutex;
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tauri::{Emitter, Runtime};
 use chrono::Utc;
 
+/// Bytes per GB as shown in the UI
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Number of entries kept in the activity feed
+const MAX_RECENT_ACTIVITY: usize = 50;
+
 /// Application state containing P2P node and system monitoring
 pub struct AppState {
     p2p_node: Mutex<Option<RealP2PNode>>,
@@ -17,8 +52,28 @@ pub struct AppState {
     event_sender: Mutex<Option<mpsc::UnboundedSender<P2PEvent>>>,
     /// Dashboard data cache
     dashboard_data: Mutex<Option<DashboardData>>,
-    /// Active tasks cache
-    active_tasks: Mutex<Vec<ActiveTask>>,
+    /// Signing identity of this node (available once the data directory is known)
+    identity: Mutex<Option<Arc<NodeIdentity>>>,
+    /// Connection to the peers of this node
+    transport: Mutex<Option<Arc<dyn Transport>>>,
+    /// Synapse task engine (available once the data directory is known)
+    synapse: Mutex<Option<Synapse>>,
+    /// Chronicle object store (available once the data directory is known)
+    chronicle: Mutex<Option<Arc<ObjectStore>>>,
+    /// Chronicle shard replication to peers
+    replicator: Mutex<Option<Replicator>>,
+    /// Chronicle integrity scrubber
+    scrubber: Mutex<Option<Scrubber>>,
+    /// Chronicle garbage collector
+    garbage_collector: Mutex<Option<GarbageCollector>>,
+    /// Streamed Chronicle transfers
+    streams: Mutex<Option<Arc<StreamStore>>>,
+    /// Activity feed, newest first
+    recent_activity: Arc<Mutex<VecDeque<ActivityItem>>>,
+    /// Profile of this node (available once the data directory is known)
+    node_profile: Mutex<Option<Arc<NodeProfileStore>>>,
+    /// Permission settings last applied (available once the data directory is known)
+    settings: Mutex<Option<Arc<SettingsStore>>>,
     /// Conversations cache
     conversations: Mutex<Vec<Conversation>>,
     /// Permission profiles cache
@@ -32,6 +87,7 @@ impl Default for AppState {
     /// - P2P node is set to None (not running)
     /// - System monitor is created with default configuration
     /// - Event sender is set to None (no active sender)
+    /// - Node identity, peer transport, Synapse engine, Chronicle stores, replicator, scrubber, garbage collector, node profile and saved settings are set to None until `initialize` is called
     /// - UI data caches are initialized as empty
     /// 
     /// # Returns
@@ -43,13 +99,407 @@ impl Default for AppState {
             system_monitor: Mutex::new(SystemMonitor::new()),
             event_sender: Mutex::new(None),
             dashboard_data: Mutex::new(None),
-            active_tasks: Mutex::new(Vec::new()),
+            identity: Mutex::new(None),
+            transport: Mutex::new(None),
+            synapse: Mutex::new(None),
+            chronicle: Mutex::new(None),
+            replicator: Mutex::new(None),
+            scrubber: Mutex::new(None),
+            garbage_collector: Mutex::new(None),
+            streams: Mutex::new(None),
+            recent_activity: Arc::new(Mutex::new(VecDeque::new())),
+            node_profile: Mutex::new(None),
+            settings: Mutex::new(None),
             conversations: Mutex::new(Vec::new()),
             permission_profiles: Mutex::new(Vec::new()),
         }
     }
 }
 
+impl AppState {
+    /// Opens the persistent protocol state stored under `data_dir` and
+    /// starts the Synapse engine
+    /// 
+    /// Must be called once during application setup, before any task
+    /// command is invoked. On first start the compute benchmark runs in
+    /// the background. The permission settings the user last applied are
+    /// applied again, and Chronicle is opened with their storage quota.
+    /// 
+    /// There is no network transport yet: the node joins a loopback
+    /// network of its own, so its protocols are served but no peer is
+    /// reachable. Until there is one, tasks only run locally and Chronicle
+    /// shards are kept only on this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data_dir` - Application data directory
+    /// 
+    /// # Returns
+    /// 
+    /// Returns Ok(()) on success, or an error message on failure
+    pub fn initialize(&self, data_dir: PathBuf) -> Result<(), String> {
+        let identity = NodeIdentity::load_or_create(&data_dir.join("node.key"))
+            .map_err(|e| format!("Failed to load node identity: {}", e))?;
+        log::info!("Node identity: {}", identity.peer_id());
+        let node_profile = NodeProfileStore::open(&data_dir.join("node_profile.json"), &identity.peer_id())
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to load node profile: {}", e))?;
+        let settings = SettingsStore::open(&data_dir.join("settings.json"))
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to load permission settings: {}", e))?;
+        {
+            let mut identity_guard = self.identity.lock().map_err(|e| e.to_string())?;
+            *identity_guard = Some(Arc::new(identity));
+        }
+        let router = Arc::new(RpcRouter::default());
+        let transport: Arc<dyn Transport> = LoopbackNetwork::new().join(self.identity()?.peer_id(), Arc::clone(&router));
+
+        let allocated_bytes = settings.settings().as_ref().map_or(DEFAULT_ALLOCATED_BYTES, storage_quota);
+        let fragments = FragmentStore::open(data_dir.join("chronicle"), allocated_bytes)
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to open fragment store: {}", e))?;
+        let keys = KeyStore::open(&data_dir.join("keystore.json"))
+            .map_err(|e| format!("Failed to open keystore: {}", e))?;
+        let chronicle = ObjectStore::open(data_dir.join("chronicle"), fragments, keys, &self.identity()?.peer_id())
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to open object store: {}", e))?;
+        let (synapse, report) = Synapse::open(&data_dir)
+            .map_err(|e| format!("Failed to open task store: {}", e))?;
+        log::info!(
+            "Synapse recovered: {} requeued, {} resumed from checkpoint, {} failed",
+            report.requeued.len(), report.resumed.len(), report.failed.len()
+        );
+        let proofs = ProofBook::open(data_dir.join("chronicle").join("proofs.json"))
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to open proof book: {}", e))?;
+        let replicator = Replicator::new(
+            chronicle.clone(),
+            Arc::clone(&transport),
+            Arc::clone(synapse.reputation()),
+            proofs,
+            ReplicationConfig::default(),
+        );
+        router.register(REPLICATION_PROTOCOL, Arc::new(replicator.clone()));
+        let streams = StreamStore::open(data_dir.join("chronicle"), chronicle.clone(), Some(replicator.clone()))
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to open stream store: {}", e))?;
+        // Task data is streamed, so inputs uploaded as streams can be staged
+        synapse.set_content_store(streams.clone()).map_err(|e| e.to_string())?;
+        if let Some(saved) = settings.settings() {
+            apply_permission_settings(&synapse, &chronicle, &saved)?;
+        }
+
+        tauri::async_runtime::spawn(synapse.clone().run());
+        let dispatcher = Dispatcher::new(synapse.clone(), self.identity()?, Arc::clone(&transport), DispatchConfig::default());
+        router.register(DISPATCH_PROTOCOL, Arc::new(dispatcher.clone()));
+        tauri::async_runtime::spawn(dispatcher.run());
+        tauri::async_runtime::spawn(replicator.clone().run());
+        let scrubber = Scrubber::new(chronicle.clone(), Some(replicator.clone()), DEFAULT_SCRUB_INTERVAL);
+        tauri::async_runtime::spawn(scrubber.clone().run());
+        let garbage_collector = GarbageCollector::new(
+            chronicle.clone(),
+            Some(replicator.clone()),
+            Some(streams.clone()),
+            DEFAULT_GC_INTERVAL,
+        );
+        tauri::async_runtime::spawn(garbage_collector.clone().run());
+
+        match node_profile.profile().compute {
+            Some(benchmark) => synapse.set_compute_score(benchmark.compute_score).map_err(|e| e.to_string())?,
+            None => {
+                let (node_profile, synapse) = (Arc::clone(&node_profile), synapse.clone());
+                tauri::async_runtime::spawn_blocking(move || {
+                    if let Err(e) = benchmark_node(&node_profile, &synapse) {
+                        log::error!("Initial compute benchmark failed: {}", e);
+                    }
+                });
+            }
+        }
+
+        let mut transport_guard = self.transport.lock().map_err(|e| e.to_string())?;
+        *transport_guard = Some(transport);
+        let mut synapse_guard = self.synapse.lock().map_err(|e| e.to_string())?;
+        *synapse_guard = Some(synapse);
+        let mut chronicle_guard = self.chronicle.lock().map_err(|e| e.to_string())?;
+        *chronicle_guard = Some(chronicle);
+        let mut replicator_guard = self.replicator.lock().map_err(|e| e.to_string())?;
+        *replicator_guard = Some(replicator);
+        let mut scrubber_guard = self.scrubber.lock().map_err(|e| e.to_string())?;
+        *scrubber_guard = Some(scrubber);
+        let mut collector_guard = self.garbage_collector.lock().map_err(|e| e.to_string())?;
+        *collector_guard = Some(garbage_collector);
+        let mut streams_guard = self.streams.lock().map_err(|e| e.to_string())?;
+        *streams_guard = Some(streams);
+        let mut profile_guard = self.node_profile.lock().map_err(|e| e.to_string())?;
+        *profile_guard = Some(node_profile);
+        let mut settings_guard = self.settings.lock().map_err(|e| e.to_string())?;
+        *settings_guard = Some(settings);
+        Ok(())
+    }
+
+    /// Gets the signing identity of this node
+    /// 
+    /// Protocols that talk to peers (such as the Synapse dispatcher) sign
+    /// their messages with it.
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the node identity, or an error message if the application
+    /// state has not been initialized yet
+    pub fn identity(&self) -> Result<Arc<NodeIdentity>, String> {
+        let identity_guard = self.identity.lock().map_err(|e| e.to_string())?;
+        identity_guard.clone().ok_or_else(|| "Node identity is not initialized".to_string())
+    }
+
+    /// Streams task output to the frontend as `task_log` events
+    /// 
+    /// # Arguments
+    /// 
+    /// * `app` - Tauri application handle used to emit events
+    /// 
+    /// # Returns
+    /// 
+    /// Returns Ok(()) on success, or an error message if the application
+    /// state has not been initialized yet
+    pub fn stream_task_logs<R: Runtime>(&self, app: tauri::AppHandle<R>) -> Result<(), String> {
+        let mut events = self.synapse()?.subscribe();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(TaskEvent::Log(line)) => {
+                        let _ = app.emit("task_log", line);
+                    }
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Dropped {} task log events; the UI can page them in", skipped);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Streams Chronicle scrub progress to the frontend as
+    /// `chronicle_scrub` events
+    /// 
+    /// # Arguments
+    /// 
+    /// * `app` - Tauri application handle used to emit events
+    /// 
+    /// # Returns
+    /// 
+    /// Returns Ok(()) on success, or an error message if the application
+    /// state has not been initialized yet
+    pub fn stream_scrub_progress<R: Runtime>(&self, app: tauri::AppHandle<R>) -> Result<(), String> {
+        let mut events = self.scrubber()?.subscribe();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(progress) => {
+                        let _ = app.emit("chronicle_scrub", progress);
+                    }
+                    // Every update carries the full progress, so skipping some is harmless
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Streams the progress of Chronicle uploads and downloads to the
+    /// frontend as `chronicle_transfer` events
+    /// 
+    /// # Arguments
+    /// 
+    /// * `app` - Tauri application handle used to emit events
+    /// 
+    /// # Returns
+    /// 
+    /// Returns Ok(()) on success, or an error message if the application
+    /// state has not been initialized yet
+    pub fn stream_transfer_progress<R: Runtime>(&self, app: tauri::AppHandle<R>) -> Result<(), String> {
+        let mut events = self.streams()?.subscribe();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(progress) => {
+                        let _ = app.emit("chronicle_transfer", progress);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Adds Chronicle deletions to the activity feed and streams them to
+    /// the frontend as `activity` events
+    /// 
+    /// # Arguments
+    /// 
+    /// * `app` - Tauri application handle used to emit events
+    /// 
+    /// # Returns
+    /// 
+    /// Returns Ok(()) on success, or an error message if the application
+    /// state has not been initialized yet
+    pub fn stream_storage_deletions<R: Runtime>(&self, app: tauri::AppHandle<R>) -> Result<(), String> {
+        let mut deletions = {
+            let collector_guard = self.garbage_collector.lock().map_err(|e| e.to_string())?;
+            collector_guard
+                .as_ref()
+                .ok_or_else(|| "Chronicle garbage collector is not initialized".to_string())?
+                .subscribe()
+        };
+        let recent_activity = Arc::clone(&self.recent_activity);
+        tauri::async_runtime::spawn(async move {
+            loop {
+                let deletion = match deletions.recv().await {
+                    Ok(deletion) => deletion,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Dropped {} Chronicle deletions from the activity feed", skipped);
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let description = match deletion.owner {
+                    Some(_) => format!("Deleted expired object {} ({} bytes freed)", deletion.content_id, deletion.freed_bytes),
+                    None => format!("Deleted unreferenced fragment {} ({} bytes freed)", deletion.content_id, deletion.freed_bytes),
+                };
+                let item = ActivityItem {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    activity_type: ActivityType::StorageFragmentDeleted,
+                    description,
+                    timestamp: Utc::now(),
+                    aibox_id: deletion.owner,
+                    status: ActivityStatus::Completed,
+                };
+                if let Ok(mut recent_activity) = recent_activity.lock() {
+                    recent_activity.push_front(item.clone());
+                    recent_activity.truncate(MAX_RECENT_ACTIVITY);
+                }
+                let _ = app.emit("activity", item);
+            }
+        });
+        Ok(())
+    }
+
+    /// Gets a handle to the Synapse engine
+    fn synapse(&self) -> Result<Synapse, String> {
+        let synapse_guard = self.synapse.lock().map_err(|e| e.to_string())?;
+        synapse_guard.clone().ok_or_else(|| "Synapse is not initialized".to_string())
+    }
+
+    /// Gets the connection to the peers of this node
+    fn transport(&self) -> Result<Arc<dyn Transport>, String> {
+        let transport_guard = self.transport.lock().map_err(|e| e.to_string())?;
+        transport_guard.clone().ok_or_else(|| "Peer transport is not initialized".to_string())
+    }
+
+    /// Refuses a task placed on a remote peer while no peer is reachable,
+    /// as it would wait in the queue forever
+    fn check_placement(&self, manifest: &TaskManifest) -> Result<(), String> {
+        if manifest.placement == TaskPlacement::Remote && self.transport()?.peers().is_empty() {
+            return Err(format!("Task {} is placed on a remote peer, but no peer is reachable", manifest.id));
+        }
+        Ok(())
+    }
+
+    /// Gets the Chronicle object store
+    fn chronicle(&self) -> Result<Arc<ObjectStore>, String> {
+        let chronicle_guard = self.chronicle.lock().map_err(|e| e.to_string())?;
+        chronicle_guard.clone().ok_or_else(|| "Chronicle is not initialized".to_string())
+    }
+
+    /// Gets the Chronicle stream store
+    fn streams(&self) -> Result<Arc<StreamStore>, String> {
+        let streams_guard = self.streams.lock().map_err(|e| e.to_string())?;
+        streams_guard.clone().ok_or_else(|| "Chronicle streams are not initialized".to_string())
+    }
+
+    /// Gets a handle to the Chronicle replicator
+    fn replicator(&self) -> Result<Replicator, String> {
+        let replicator_guard = self.replicator.lock().map_err(|e| e.to_string())?;
+        replicator_guard.clone().ok_or_else(|| "Chronicle replicator is not initialized".to_string())
+    }
+
+    /// Gets a handle to the Chronicle scrubber
+    fn scrubber(&self) -> Result<Scrubber, String> {
+        let scrubber_guard = self.scrubber.lock().map_err(|e| e.to_string())?;
+        scrubber_guard.clone().ok_or_else(|| "Chronicle scrubber is not initialized".to_string())
+    }
+
+    /// Gets the profile of this node
+    fn node_profile(&self) -> Result<Arc<NodeProfileStore>, String> {
+        let profile_guard = self.node_profile.lock().map_err(|e| e.to_string())?;
+        profile_guard.clone().ok_or_else(|| "Node profile is not initialized".to_string())
+    }
+
+    /// Gets the permission settings last applied
+    fn settings(&self) -> Result<Arc<SettingsStore>, String> {
+        let settings_guard = self.settings.lock().map_err(|e| e.to_string())?;
+        settings_guard.clone().ok_or_else(|| "Permission settings are not initialized".to_string())
+    }
+
+    /// Gets the compute power of this node in TFLOPS (0.0 until the
+    /// benchmark ran)
+    fn compute_power(&self) -> f64 {
+        self.node_profile()
+            .ok()
+            .and_then(|store| store.profile().compute)
+            .map_or(0.0, |benchmark| benchmark::tflops(&benchmark))
+    }
+}
+
+/// Gets the storage quota in bytes set by `settings`
+fn storage_quota(settings: &PermissionSettings) -> u64 {
+    (settings.storage_gb.max(0.0) * BYTES_PER_GB) as u64
+}
+
+/// Gets the retention period of Chronicle objects in whole days
+fn retention_days(chronicle: &ObjectStore) -> u32 {
+    (chronicle.retention().as_secs() / (24 * 3600)) as u32
+}
+
+/// Applies permission settings to the Synapse engine and Chronicle
+/// 
+/// Settings left as `None` are not changed.
+fn apply_permission_settings(synapse: &Synapse, chronicle: &ObjectStore, settings: &PermissionSettings) -> Result<(), String> {
+    synapse
+        .set_capacity(ResourceCapacity {
+            cpu_percent: settings.cpu_percent as f32,
+            ram_gb: settings.ram_gb,
+            gpu_percent: settings.gpu_percent as f32,
+        })
+        .map_err(|e| e.to_string())?;
+    synapse.set_idle_settings(settings.idle.clone()).map_err(|e| e.to_string())?;
+    synapse.set_remote_task_settings(settings.remote_tasks.clone()).map_err(|e| e.to_string())?;
+    if let Some(required) = settings.encryption_required {
+        chronicle.set_encryption_required(required);
+    }
+    if let Some(days) = settings.data_retention_days {
+        chronicle.set_retention(Duration::from_secs(u64::from(days) * 24 * 3600));
+    }
+    chronicle.fragments().set_allocated_bytes(storage_quota(settings));
+    Ok(())
+}
+
+/// Runs the compute benchmark, stores the result in the node profile and
+/// advertises the new score
+/// 
+/// Blocks for several seconds; call it from a blocking task.
+fn benchmark_node(node_profile: &NodeProfileStore, synapse: &Synapse) -> Result<ComputeBenchmark, String> {
+    let result = benchmark::run(&BenchmarkConfig::default());
+    node_profile
+        .set_compute(result.clone())
+        .map_err(|e| format!("Failed to store node profile: {}", e))?;
+    synapse.set_compute_score(result.compute_score).map_err(|e| e.to_string())?;
+    Ok(result)
+}
+
 /// Starts the P2P node and begins network operations
 /// 
 /// # Arguments
@@ -183,17 +633,36 @@ fn get_system_info(state: tauri::State<'_, AppState>) -> Result<SystemInfo, Stri
 /// Returns DashboardData on success, or an error message on failure
 #[tauri::command]
 async fn get_dashboard_data(state: tauri::State<'_, AppState>) -> Result<DashboardData, String> {
-    {
+    let recent_activity: Vec<ActivityItem> = {
+        let activity_guard = state.recent_activity.lock().map_err(|e| e.to_string())?;
+        activity_guard.iter().cloned().collect()
+    };
+    let cached = {
         let dashboard_guard = state.dashboard_data.lock().map_err(|e| e.to_string())?;
-        
-        if let Some(data) = &*dashboard_guard {
-            return Ok(data.clone());
-        }
-    }
+        dashboard_guard.clone()
+    };
     
     // Generate default dashboard data if not available
-    let default_data = generate_default_dashboard_data().await;
-    Ok(default_data)
+    let mut data = match cached {
+        Some(data) => data,
+        None => generate_default_dashboard_data(state.compute_power()).await,
+    };
+    data.recent_activity = recent_activity;
+    // The benchmark may have finished since the data was generated
+    let compute_power = state.compute_power();
+    data.network_status.total_compute_power = compute_power;
+    data.protocol_summaries.synapse.network_performance.total_compute_power = compute_power;
+    // Rewards are earned by countersigned receipts of completed tasks
+    let peer_id = state.identity()?.peer_id();
+    data.protocol_summaries.synapse.total_earned_tokens =
+        state.synapse()?.receipts().earned_tokens(&peer_id).map_err(|e| e.to_string())?;
+    let chronicle = state.chronicle()?;
+    let stats = chronicle.fragments().stats().map_err(|e| e.to_string())?;
+    let storage = &mut data.protocol_summaries.covenant.storage;
+    storage.max_storage_gb = stats.allocated_bytes as f64 / BYTES_PER_GB;
+    storage.data_retention_days = retention_days(&chronicle);
+    storage.encryption_required = chronicle.encryption_required();
+    Ok(data)
 }
 
 /// Updates dashboard data with new information
@@ -228,8 +697,77 @@ async fn update_dashboard_data(state: tauri::State<'_, AppState>, data: Dashboar
 /// Returns Vec<ActiveTask> on success, or an error message on failure
 #[tauri::command]
 async fn get_active_tasks(state: tauri::State<'_, AppState>) -> Result<Vec<ActiveTask>, String> {
-    let tasks_guard = state.active_tasks.lock().map_err(|e| e.to_string())?;
-    Ok(tasks_guard.clone())
+    state.synapse()?.active_tasks().map_err(|e| e.to_string())
+}
+
+/// Submits a new task to the Synapse queue
+/// 
+/// Tasks placed on a remote peer are refused while no peer is reachable.
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `manifest` - Task manifest describing what to run
+/// 
+/// # Returns
+/// 
+/// Returns Ok(()) on success, or an error message on failure
+#[tauri::command]
+async fn submit_task(state: tauri::State<'_, AppState>, manifest: TaskManifest) -> Result<(), String> {
+    state.check_placement(&manifest)?;
+    state.synapse()?.submit(manifest).map_err(|e| e.to_string())
+}
+
+/// Gets a page of the output captured from a task
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `task_id` - Task ID to get the output of
+/// * `offset` - Sequence number of the first line to return
+/// 
+/// # Returns
+/// 
+/// Returns TaskLogPage on success, or an error message on failure
+#[tauri::command]
+async fn get_task_logs(state: tauri::State<'_, AppState>, task_id: String, offset: u64) -> Result<TaskLogPage, String> {
+    state.synapse()?.task_logs(&task_id, offset).map_err(|e| e.to_string())
+}
+
+/// Submits a workflow of dependent tasks to the Synapse queue
+/// 
+/// Workflows with tasks placed on a remote peer are refused while no peer
+/// is reachable.
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `workflow` - Workflow manifest with the tasks and their dependencies
+/// 
+/// # Returns
+/// 
+/// Returns Ok(()) on success, or an error message on failure
+#[tauri::command]
+async fn submit_workflow(state: tauri::State<'_, AppState>, workflow: WorkflowManifest) -> Result<(), String> {
+    for task in &workflow.tasks {
+        state.check_placement(&task.manifest)?;
+    }
+    state.synapse()?.submit_workflow(workflow).map_err(|e| e.to_string())
+}
+
+/// Gets the progress of a workflow and each of its tasks
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `workflow_id` - Workflow ID to get progress for
+/// 
+/// # Returns
+/// 
+/// Returns WorkflowProgress on success, or an error message on failure
+#[tauri::command]
+async fn get_workflow_progress(state: tauri::State<'_, AppState>, workflow_id: String) -> Result<WorkflowProgress, String> {
+    state.synapse()?.workflow_progress(&workflow_id).map_err(|e| e.to_string())
 }
 
 /// Gets detailed information about a specific task
@@ -244,41 +782,7 @@ async fn get_active_tasks(state: tauri::State<'_, AppState>) -> Result<Vec<Activ
 /// Returns TaskDetails on success, or an error message on failure
 #[tauri::command]
 async fn get_task_details(state: tauri::State<'_, AppState>, task_id: String) -> Result<TaskDetails, String> {
-    // For now, return a mock task details
-    // In real implementation, this would query the actual task data
-    let mock_task = ActiveTask {
-        id: task_id.clone(),
-        name: "ML Model Training".to_string(),
-        aibox_id: "AIbox #8472".to_string(),
-        progress: 67,
-        time_remaining: Some("1ч 26м".to_string()),
-        priority: TaskPriority::Critical,
-        reward_tokens: 45,
-        status: TaskStatus::Running,
-        resource_usage: ResourceUsage {
-            cpu_percent: 85,
-            ram_gb: 2.1,
-            gpu_percent: 92,
-        },
-    };
-
-    let task_details = TaskDetails {
-        task: mock_task,
-        task_type: TaskType::ModelTraining,
-        model: Some("GPT-4 Fine-tuning".to_string()),
-        data_size_gb: 2.3,
-        complexity: TaskComplexity::High,
-        verification: VerificationMethod::CryptographicSignature,
-        security: SecurityLevel::Isolated,
-        detailed_resource_usage: DetailedResourceUsage {
-            cpu_percent: 85,
-            ram_gb: 2.1,
-            gpu_percent: 92,
-            gpu_memory_gb: 4.0,
-        },
-    };
-
-    Ok(task_details)
+    state.synapse()?.task_details(&task_id).map_err(|e| e.to_string())
 }
 
 /// Pauses a running task
@@ -294,8 +798,7 @@ async fn get_task_details(state: tauri::State<'_, AppState>, task_id: String) ->
 #[tauri::command]
 async fn pause_task(state: tauri::State<'_, AppState>, task_id: String) -> Result<(), String> {
     log::info!("Pausing task: {}", task_id);
-    // In real implementation, this would pause the actual task
-    Ok(())
+    state.synapse()?.pause(&task_id).map_err(|e| e.to_string())
 }
 
 /// Resumes a paused task
@@ -311,8 +814,7 @@ async fn pause_task(state: tauri::State<'_, AppState>, task_id: String) -> Resul
 #[tauri::command]
 async fn resume_task(state: tauri::State<'_, AppState>, task_id: String) -> Result<(), String> {
     log::info!("Resuming task: {}", task_id);
-    // In real implementation, this would resume the actual task
-    Ok(())
+    state.synapse()?.resume(&task_id).map_err(|e| e.to_string())
 }
 
 /// Cancels a task
@@ -328,8 +830,7 @@ async fn resume_task(state: tauri::State<'_, AppState>, task_id: String) -> Resu
 #[tauri::command]
 async fn cancel_task(state: tauri::State<'_, AppState>, task_id: String) -> Result<(), String> {
     log::info!("Cancelling task: {}", task_id);
-    // In real implementation, this would cancel the actual task
-    Ok(())
+    state.synapse()?.cancel(&task_id).map_err(|e| e.to_string())
 }
 
 // ============================================================================
@@ -347,28 +848,52 @@ async fn cancel_task(state: tauri::State<'_, AppState>, task_id: String) -> Resu
 /// Returns ChronicleSummary on success, or an error message on failure
 #[tauri::command]
 async fn get_storage_summary(state: tauri::State<'_, AppState>) -> Result<ChronicleSummary, String> {
-    // Mock storage summary
+    let chronicle = state.chronicle()?;
+    let stats = chronicle.fragments().stats().map_err(|e| e.to_string())?;
+    let scrub = state.scrubber()?.progress();
+    // Distribution is still mocked
     let summary = ChronicleSummary {
-        allocated_storage_gb: 50.0,
-        used_storage_gb: 23.4,
-        fragment_count: 1247,
-        data_integrity: 99,
+        allocated_storage_gb: stats.allocated_bytes as f64 / BYTES_PER_GB,
+        used_storage_gb: stats.used_bytes as f64 / BYTES_PER_GB,
+        fragment_count: stats.fragment_count as u32,
+        data_integrity: scrub.data_integrity,
         geographic_distribution: GeographicDistribution {
             europe: RegionInfo { percentage: 45, fragment_count: 562 },
             asia: RegionInfo { percentage: 32, fragment_count: 399 },
             america: RegionInfo { percentage: 23, fragment_count: 286 },
         },
         storage_security: StorageSecurity {
-            encryption_algorithm: "AES-256-GCM".to_string(),
-            redundancy_factor: 3,
+            encryption_algorithm: crypto::ALGORITHM.to_string(),
+            redundancy_factor: chronicle.erasure().redundancy_factor(),
         },
     };
 
     Ok(summary)
 }
 
+/// Gets the progress of the running Chronicle scrub, or the outcome of
+/// the last one
+/// 
+/// Updates are also pushed as `chronicle_scrub` events while a scrub runs.
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// 
+/// # Returns
+/// 
+/// Returns ScrubProgress on success, or an error message on failure
+#[tauri::command]
+async fn get_scrub_progress(state: tauri::State<'_, AppState>) -> Result<ScrubProgress, String> {
+    Ok(state.scrubber()?.progress())
+}
+
 /// Gets active fragments for the Chronicle protocol
 /// 
+/// Every object in the Chronicle catalog is listed with its status as of
+/// the last scrub. Streamed objects are listed as a whole rather than by
+/// their chunks.
+/// 
 /// # Arguments
 /// 
 /// * `state` - Application state
@@ -378,34 +903,93 @@ async fn get_storage_summary(state: tauri::State<'_, AppState>) -> Result<Chroni
 /// Returns Vec<ActiveFragment> on success, or an error message on failure
 #[tauri::command]
 async fn get_active_fragments(state: tauri::State<'_, AppState>) -> Result<Vec<ActiveFragment>, String> {
-    // Mock active fragments
-    let fragments = vec![
-        ActiveFragment {
-            name: "AI Model Data".to_string(),
-            aibox_id: "AIbox #8472".to_string(),
-            size_gb: 2.3,
-            fragment_count: 156,
-            status: FragmentStatus::Active,
-        },
-        ActiveFragment {
-            name: "Training Dataset".to_string(),
-            aibox_id: "AIbox #1563".to_string(),
-            size_gb: 8.7,
-            fragment_count: 543,
-            status: FragmentStatus::Active,
-        },
-        ActiveFragment {
-            name: "Configuration Files".to_string(),
-            aibox_id: "AIbox #2341".to_string(),
-            size_gb: 0.5,
-            fragment_count: 32,
-            status: FragmentStatus::Recovering,
-        },
-    ];
-
+    let scrubber = state.scrubber()?;
+    let streams = state.streams()?.streams().map_err(|e| e.to_string())?;
+    let chunks: HashSet<&ContentId> = streams.iter().flat_map(|(_, stream)| &stream.chunks).collect();
+    let objects = state.chronicle()?.objects().map_err(|e| e.to_string())?;
+    let mut fragments: Vec<ActiveFragment> = objects
+        .iter()
+        .filter(|(id, _)| !chunks.contains(id))
+        .map(|(id, object)| scrubber.active_fragment(id, object))
+        .collect();
+    for (id, stream) in &streams {
+        fragments.push(scrubber.stream_fragment(id, stream).map_err(|e| e.to_string())?);
+    }
     Ok(fragments)
 }
 
+/// Gets the details of a Chronicle object
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `object_id` - Content ID of the object or stream, as listed in the
+///   `name` of its active fragment
+/// 
+/// # Returns
+/// 
+/// Returns FragmentDetails on success, or an error message if the object
+/// is unknown
+#[tauri::command]
+async fn get_fragment_details(state: tauri::State<'_, AppState>, object_id: String) -> Result<FragmentDetails, String> {
+    let scrubber = state.scrubber()?;
+    let id = ContentId::parse(&object_id).map_err(|e| e.to_string())?;
+    let details = match state.streams()?.stream(&id).map_err(|e| e.to_string())? {
+        Some(stream) => scrubber.stream_details(&id, &stream),
+        None => scrubber.fragment_details(&id),
+    };
+    details.map_err(|e| e.to_string())
+}
+
+/// Uploads a file to Chronicle without loading it into memory
+/// 
+/// Progress is reported as `chronicle_transfer` events with the path as
+/// transfer ID. Uploading a path again after an interruption resumes the
+/// upload.
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `path` - Path of the file to upload
+/// 
+/// # Returns
+/// 
+/// Returns the content ID of the stored object on success, or an error
+/// message on failure
+#[tauri::command]
+async fn upload_file(state: tauri::State<'_, AppState>, path: String) -> Result<String, String> {
+    let streams = state.streams()?;
+    let owner = state.identity()?.peer_id();
+    log::info!("Uploading {} to Chronicle", path);
+    let id = streams.put_file(&PathBuf::from(path), &owner).await.map_err(|e| e.to_string())?;
+    Ok(id.to_string())
+}
+
+/// Downloads a streamed Chronicle object of this node into a file
+/// 
+/// Progress is reported as `chronicle_transfer` events with the path as
+/// transfer ID. If the file already holds part of the object, the download
+/// resumes after its last verified chunk.
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// * `object_id` - Content ID of the object
+/// * `path` - Path of the file to write
+/// 
+/// # Returns
+/// 
+/// Returns Ok(()) on success, or an error message on failure
+#[tauri::command]
+async fn download_object(state: tauri::State<'_, AppState>, object_id: String, path: String) -> Result<(), String> {
+    let streams = state.streams()?;
+    let owner = state.identity()?.peer_id();
+    let id = ContentId::parse(&object_id).map_err(|e| e.to_string())?;
+    log::info!("Downloading Chronicle object {} to {}", id, path);
+    streams.get_file(&id, &owner, &PathBuf::from(path)).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 // ============================================================================
 // CONTACT PROTOCOL API COMMANDS
 // ============================================================================
@@ -464,6 +1048,11 @@ async fn get_permission_profiles(state: tauri::State<'_, AppState>) -> Result<Ve
 
 /// Updates permission settings
 /// 
+/// The settings are saved and applied again after a restart; settings
+/// left out keep their current value. A storage quota below the space in
+/// use frees the excess right away: shards held for peers are released and
+/// local copies of shards that peers hold are dropped.
+/// 
 /// # Arguments
 /// 
 /// * `state` - Application state
@@ -471,15 +1060,75 @@ async fn get_permission_profiles(state: tauri::State<'_, AppState>) -> Result<Ve
 /// 
 /// # Returns
 /// 
-/// Returns Ok(()) on success, or an error message on failure
+/// Returns Ok(()) on success, or an error message on failure, including
+/// when not all of the excess storage could be freed yet
 #[tauri::command]
-async fn update_permission_settings(state: tauri::State<'_, AppState>, settings: PermissionSettings) -> Result<(), String> {
+async fn update_permission_settings(state: tauri::State<'_, AppState>, mut settings: PermissionSettings) -> Result<(), String> {
     log::info!("Updating permission settings: CPU {}%, RAM {}GB, GPU {}%", 
                settings.cpu_percent, settings.ram_gb, settings.gpu_percent);
-    // In real implementation, this would update the actual permissions
+    let chronicle = state.chronicle()?;
+    // Saved with the values they keep, so that a restart applies them too
+    settings.encryption_required.get_or_insert(chronicle.encryption_required());
+    settings.data_retention_days.get_or_insert(retention_days(&chronicle));
+    apply_permission_settings(&state.synapse()?, &chronicle, &settings)?;
+    state
+        .settings()?
+        .save(settings)
+        .map_err(|e| format!("Failed to save permission settings: {}", e))?;
+    let excess = chronicle.fragments().excess_bytes().map_err(|e| e.to_string())?;
+    if excess > 0 {
+        log::info!("Chronicle stores {:.2} GB beyond the new quota", excess as f64 / BYTES_PER_GB);
+        let freed = state
+            .replicator()?
+            .evict()
+            .await
+            .map_err(|e| format!("Failed to free storage beyond the new quota: {}", e))?;
+        if freed < excess {
+            // New shards are refused meanwhile; the replicator keeps migrating
+            return Err(format!(
+                "{:.2} GB are still stored beyond the new quota until peers take them over",
+                (excess - freed) as f64 / BYTES_PER_GB
+            ));
+        }
+    }
     Ok(())
 }
 
+// ============================================================================
+// NODE PROFILE API COMMANDS
+// ============================================================================
+
+/// Gets the profile of this node
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// 
+/// # Returns
+/// 
+/// Returns NodeProfile on success, or an error message on failure
+#[tauri::command]
+async fn get_node_profile(state: tauri::State<'_, AppState>) -> Result<NodeProfile, String> {
+    Ok(state.node_profile()?.profile())
+}
+
+/// Runs the compute benchmark and stores the result in the node profile
+/// 
+/// # Arguments
+/// 
+/// * `state` - Application state
+/// 
+/// # Returns
+/// 
+/// Returns ComputeBenchmark on success, or an error message on failure
+#[tauri::command]
+async fn run_benchmark(state: tauri::State<'_, AppState>) -> Result<ComputeBenchmark, String> {
+    let (node_profile, synapse) = (state.node_profile()?, state.synapse()?);
+    tauri::async_runtime::spawn_blocking(move || benchmark_node(&node_profile, &synapse))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
 // ============================================================================
 // ANALYTICS API COMMANDS
 // ============================================================================
@@ -495,22 +1144,26 @@ async fn update_permission_settings(state: tauri::State<'_, AppState>, settings:
 /// Returns AnalyticsData on success, or an error message on failure
 #[tauri::command]
 async fn get_analytics_data(state: tauri::State<'_, AppState>) -> Result<AnalyticsData, String> {
-    // Mock analytics data
+    let compute = state.node_profile()?.profile().compute;
+    let compute_power = compute.as_ref().map_or(0.0, benchmark::tflops);
+    let performance_score = compute.as_ref().map_or(0.0, |benchmark| benchmark.compute_score as f32);
+
+    // Mock analytics data, apart from the benchmarked figures of this node
     let analytics = AnalyticsData {
         network_stats: NetworkStatistics {
             total_active_nodes: 1247,
-            total_compute_power: 15432.0,
+            total_compute_power: compute_power,
             total_storage_tb: 1250.0,
             avg_node_reliability: 98.5,
         },
         performance_metrics: PerformanceMetrics {
-            your_performance_score: 85.2,
+            your_performance_score: performance_score,
             network_avg_performance: 78.9,
             performance_ranking: 156,
             performance_trends: vec![
                 PerformanceTrend {
                     timestamp: Utc::now(),
-                    value: 85.2,
+                    value: performance_score,
                 },
             ],
         },
@@ -547,18 +1200,21 @@ async fn get_analytics_data(state: tauri::State<'_, AppState>) -> Result<Analyti
 
 /// Initializes dashboard data with default values
 async fn initialize_dashboard_data(state: &tauri::State<'_, AppState>) {
-    let dashboard_data = generate_default_dashboard_data().await;
+    let dashboard_data = generate_default_dashboard_data(state.compute_power()).await;
     let mut dashboard_guard = state.dashboard_data.lock().unwrap();
     *dashboard_guard = Some(dashboard_data);
 }
 
 /// Generates default dashboard data for initial display
-async fn generate_default_dashboard_data() -> DashboardData {
+/// 
+/// Until peers are known, the network consists of this node with its
+/// benchmarked `compute_power` (in TFLOPS).
+async fn generate_default_dashboard_data(compute_power: f64) -> DashboardData {
     DashboardData {
         network_status: NetworkStatus {
             is_connected: false,
             active_nodes: 0,
-            total_compute_power: 0.0,
+            total_compute_power: compute_power,
             network_health: 0,
             connection_quality: ConnectionQuality::Poor,
         },
@@ -586,7 +1242,7 @@ async fn generate_default_dashboard_data() -> DashboardData {
                 total_earned_tokens: 0,
                 weekly_token_growth: 0.0,
                 network_performance: NetworkPerformance {
-                    total_compute_power: 0.0,
+                    total_compute_power: compute_power,
                     active_nodes: 0,
                     avg_completion_time: 0.0,
                     reliability: 0,
@@ -606,7 +1262,7 @@ async fn generate_default_dashboard_data() -> DashboardData {
                 },
                 storage_security: StorageSecurity {
                     encryption_algorithm: "None".to_string(),
-                    redundancy_factor: 0,
+                    redundancy_factor: 0.0,
                 },
             },
             contact: ContactSummary {
@@ -23,6 +23,15 @@ fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .setup(|app| {
+            // Open persistent protocol state
+            let data_dir = app.path().app_data_dir()?;
+            let state = app.state::<mycelium_app_lib::AppState>();
+            state.initialize(data_dir)?;
+            state.stream_task_logs(app.handle().clone())?;
+            state.stream_scrub_progress(app.handle().clone())?;
+            state.stream_storage_deletions(app.handle().clone())?;
+            state.stream_transfer_progress(app.handle().clone())?;
+
             // Get the main window
             let window = app.get_window("main").unwrap();
             
@@ -46,15 +55,25 @@ fn main() {
             mycelium_app_lib::update_dashboard_data,
             mycelium_app_lib::get_active_tasks,
             mycelium_app_lib::get_task_details,
+            mycelium_app_lib::get_task_logs,
+            mycelium_app_lib::submit_task,
+            mycelium_app_lib::submit_workflow,
+            mycelium_app_lib::get_workflow_progress,
             mycelium_app_lib::pause_task,
             mycelium_app_lib::resume_task,
             mycelium_app_lib::cancel_task,
             mycelium_app_lib::get_storage_summary,
+            mycelium_app_lib::get_scrub_progress,
             mycelium_app_lib::get_active_fragments,
+            mycelium_app_lib::get_fragment_details,
+            mycelium_app_lib::upload_file,
+            mycelium_app_lib::download_object,
             mycelium_app_lib::get_conversations,
             mycelium_app_lib::send_message,
             mycelium_app_lib::get_permission_profiles,
             mycelium_app_lib::update_permission_settings,
+            mycelium_app_lib::get_node_profile,
+            mycelium_app_lib::run_benchmark,
             mycelium_app_lib::get_analytics_data
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,224 @@
+//! Request/response messaging between peers
+//!
+//! Protocols (Synapse dispatch, Chronicle replication, ...) exchange typed
+//! messages through a [`Transport`]. Each protocol registers an
+//! [`RpcHandler`] in the node's [`RpcRouter`] under its protocol name.
+//! Messages are encoded as JSON; authenticity of results is established
+//! by signatures at the protocol level, not by the transport.
+//!
+//! [`LoopbackNetwork`] connects several nodes inside one process. It is
+//! used by the multi-node tests and by local simulations.
+
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::identity::PeerId;
+
+/// RPC error types
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum RpcError {
+    #[error("Peer is unreachable: {0}")]
+    Unreachable(PeerId),
+    #[error("Peer does not serve protocol {0}")]
+    UnknownProtocol(String),
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Malformed message: {0}")]
+    Codec(String),
+    #[error("Remote peer failed to handle the request: {0}")]
+    Remote(String),
+}
+
+/// Result type for RPC operations
+pub type RpcResult<T> = Result<T, RpcError>;
+
+/// Serves requests of one protocol
+pub trait RpcHandler: Send + Sync {
+    /// Handles a request from `from` and produces the encoded response
+    fn handle(&self, from: PeerId, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>>;
+}
+
+/// Connection of the local node to the network
+pub trait Transport: Send + Sync {
+    /// Gets the peer ID of the local node
+    fn local_peer(&self) -> PeerId;
+
+    /// Gets the peers currently reachable
+    fn peers(&self) -> Vec<PeerId>;
+
+    /// Sends an encoded request to `to` and waits for its response
+    fn request(&self, to: &PeerId, protocol: &str, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>>;
+}
+
+/// Sends a typed request and decodes the typed response
+///
+/// # Errors
+///
+/// Returns an error if the peer cannot be reached, does not answer within
+/// `timeout` or answers with a message that cannot be decoded
+pub async fn call<Req, Resp>(
+    transport: &dyn Transport,
+    to: &PeerId,
+    protocol: &str,
+    request: &Req,
+    timeout: Duration,
+) -> RpcResult<Resp>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+{
+    let payload = encode(request)?;
+    let response = tokio::time::timeout(timeout, transport.request(to, protocol, payload))
+        .await
+        .map_err(|_| RpcError::Timeout(timeout))??;
+    decode(&response)
+}
+
+/// Encodes a message for the wire
+pub fn encode<T: Serialize>(message: &T) -> RpcResult<Vec<u8>> {
+    serde_json::to_vec(message).map_err(|e| RpcError::Codec(e.to_string()))
+}
+
+/// Decodes a message received from the wire
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> RpcResult<T> {
+    serde_json::from_slice(payload).map_err(|e| RpcError::Codec(e.to_string()))
+}
+
+/// Protocol handlers of a node
+#[derive(Default)]
+pub struct RpcRouter {
+    handlers: Mutex<HashMap<String, Arc<dyn RpcHandler>>>,
+}
+
+impl RpcRouter {
+    /// Registers the handler of `protocol`, replacing any previous one
+    pub fn register(&self, protocol: &str, handler: Arc<dyn RpcHandler>) {
+        if let Ok(mut handlers) = self.handlers.lock() {
+            handlers.insert(protocol.to_string(), handler);
+        }
+    }
+
+    /// Routes a request to the handler of its protocol
+    pub fn dispatch(&self, from: PeerId, protocol: &str, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+        let handler = self
+            .handlers
+            .lock()
+            .ok()
+            .and_then(|handlers| handlers.get(protocol).cloned());
+        match handler {
+            Some(handler) => handler.handle(from, payload),
+            None => {
+                let protocol = protocol.to_string();
+                Box::pin(async move { Err(RpcError::UnknownProtocol(protocol)) })
+            }
+        }
+    }
+}
+
+/// In-process network connecting several nodes
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    nodes: Arc<Mutex<HashMap<PeerId, Arc<RpcRouter>>>>,
+}
+
+impl LoopbackNetwork {
+    /// Creates an empty network
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects a node serving `router` to the network
+    pub fn join(&self, peer_id: PeerId, router: Arc<RpcRouter>) -> Arc<LoopbackTransport> {
+        if let Ok(mut nodes) = self.nodes.lock() {
+            nodes.insert(peer_id.clone(), router);
+        }
+        Arc::new(LoopbackTransport { network: self.clone(), local: peer_id })
+    }
+
+    /// Disconnects a node, as if it went offline
+    pub fn leave(&self, peer_id: &str) {
+        if let Ok(mut nodes) = self.nodes.lock() {
+            nodes.remove(peer_id);
+        }
+    }
+
+    fn router(&self, peer_id: &str) -> Option<Arc<RpcRouter>> {
+        self.nodes.lock().ok()?.get(peer_id).cloned()
+    }
+}
+
+/// Transport of a node connected to a [`LoopbackNetwork`]
+pub struct LoopbackTransport {
+    network: LoopbackNetwork,
+    local: PeerId,
+}
+
+impl Transport for LoopbackTransport {
+    fn local_peer(&self) -> PeerId {
+        self.local.clone()
+    }
+
+    fn peers(&self) -> Vec<PeerId> {
+        let Ok(nodes) = self.network.nodes.lock() else {
+            return Vec::new();
+        };
+        nodes.keys().filter(|peer| **peer != self.local).cloned().collect()
+    }
+
+    fn request(&self, to: &PeerId, protocol: &str, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+        // A node that left the network cannot send either.
+        let reachable = self.network.router(&self.local).is_some();
+        match self.network.router(to).filter(|_| reachable) {
+            Some(router) => router.dispatch(self.local.clone(), protocol, payload),
+            None => {
+                let to = to.clone();
+                Box::pin(async move { Err(RpcError::Unreachable(to)) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct Ping(u32);
+
+    struct Echo;
+
+    impl RpcHandler for Echo {
+        fn handle(&self, _from: PeerId, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+            Box::pin(async move {
+                let Ping(n) = decode(&payload)?;
+                encode(&Ping(n + 1))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loopback_request_response() {
+        let network = LoopbackNetwork::new();
+        let a = network.join("a".to_string(), Arc::new(RpcRouter::default()));
+        let router_b = Arc::new(RpcRouter::default());
+        router_b.register("echo", Arc::new(Echo));
+        network.join("b".to_string(), router_b);
+
+        let timeout = Duration::from_secs(1);
+        let Ping(n) = call(a.as_ref(), &"b".to_string(), "echo", &Ping(1), timeout).await.unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(a.peers(), vec!["b".to_string()]);
+
+        let unknown = call::<_, Ping>(a.as_ref(), &"b".to_string(), "other", &Ping(1), timeout).await;
+        assert!(matches!(unknown, Err(RpcError::UnknownProtocol(_))));
+
+        network.leave("b");
+        let gone = call::<_, Ping>(a.as_ref(), &"b".to_string(), "echo", &Ping(1), timeout).await;
+        assert!(matches!(gone, Err(RpcError::Unreachable(_))));
+    }
+}
@@ -0,0 +1,96 @@
+//! Saved permission settings
+//!
+//! The permission settings last applied by the user are kept in the data
+//! directory, so that the node comes back with the same limits after a
+//! restart instead of falling back to the defaults.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ui_api::PermissionSettings;
+use crate::util::write_atomic;
+
+/// Permission settings stored in a JSON file
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: Mutex<Option<PermissionSettings>>,
+}
+
+impl SettingsStore {
+    /// Loads the settings stored at `path`, if any were saved yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings exist but cannot be read
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let settings = if path.exists() {
+            Some(serde_json::from_slice(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+        } else {
+            None
+        };
+        Ok(Self { path: path.to_path_buf(), settings: Mutex::new(settings) })
+    }
+
+    /// Gets a copy of the saved settings
+    pub fn settings(&self) -> Option<PermissionSettings> {
+        self.settings.lock().ok().and_then(|settings| settings.clone())
+    }
+
+    /// Saves `settings`, replacing the saved ones
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings cannot be written
+    pub fn save(&self, settings: PermissionSettings) -> io::Result<()> {
+        let mut saved = self
+            .settings
+            .lock()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let bytes = serde_json::to_vec_pretty(&settings).map_err(io::Error::other)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&self.path, &bytes)?;
+        *saved = Some(settings);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        assert!(SettingsStore::open(&path).unwrap().settings().is_none());
+
+        let settings: PermissionSettings = serde_json::from_value(serde_json::json!({
+            "cpu_percent": 50,
+            "ram_gb": 4.0,
+            "gpu_percent": 0,
+            "storage_gb": 2.5,
+            "communication": {
+                "direct_communication_allowed": true,
+                "max_messages_per_hour": 10,
+                "emergency_contact_allowed": true
+            },
+            "token": { "max_monthly_earnings": 1000, "min_task_reward": 5, "max_single_task_reward": 100 },
+            "time_restrictions": {
+                "start_hour": 0,
+                "end_hour": 23,
+                "allowed_days": [0, 1, 2, 3, 4],
+                "allow_weekends": false,
+                "allow_holidays": false
+            }
+        }))
+        .unwrap();
+        SettingsStore::open(&path).unwrap().save(settings).unwrap();
+
+        let saved = SettingsStore::open(&path).unwrap().settings().unwrap();
+        assert_eq!((saved.cpu_percent, saved.storage_gb), (50, 2.5));
+    }
+}
@@ -0,0 +1,482 @@
+//! Content-addressed cache of task results
+//!
+//! Tasks that opt in with a [`CacheMode`] are looked up by a key hashing
+//! everything that determines their output: the runtime (program, its
+//! binary if it is a file, arguments and environment), the model and
+//! task type, and the staged input files. A task whose key is cached
+//! completes at once with the cached output files instead of running.
+//!
+//! Output files are stored once per content ID under `blobs/`. The cache
+//! is bounded in size; the least recently used entries are evicted first.
+//! Results of tasks in [`CacheMode::Shared`] can be published to Chronicle
+//! and imported by peers, like checkpoint handoffs. A shared result is
+//! signed by its publisher, which vouches for the key it is filed under.
+//! Workers attach it to the results of remote tasks when the user enables
+//! [`share_results`](crate::ui_api::RemoteTaskSettings::share_results).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use super::manifest::{TaskManifest, TaskRuntime};
+use super::util::{is_plain_file_name, push_field};
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::{ContentId, ContentStore};
+use crate::identity::{verify_signature, NodeIdentity, PeerId};
+use crate::util::write_atomic;
+
+/// Domain separator of cache keys
+const CACHE_KEY_DOMAIN: &[u8] = b"mycelium/synapse/cache/v1";
+
+/// Domain separator of shared result signatures
+const SHARED_RESULT_DOMAIN: &[u8] = b"mycelium/synapse/shared-result/v1";
+
+const INDEX_FILE: &str = "index.json";
+
+/// Whether and how the result of a task is cached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheMode {
+    /// The task always runs (for tasks with non-deterministic output)
+    #[default]
+    Disabled,
+    /// The result is cached on this node only
+    Local,
+    /// The result is cached and may be shared with peers via Chronicle
+    Shared,
+}
+
+/// Output file of a cached result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedOutput {
+    /// File name in the output directory
+    pub name: String,
+    /// Content ID of the file
+    pub content_id: ContentId,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Cached result of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Cache key of the task
+    pub key: ContentId,
+    /// Exit code of the task that produced the result
+    pub exit_code: Option<i32>,
+    /// Output files
+    pub outputs: Vec<CachedOutput>,
+    /// When the result was cached
+    pub created_at: DateTime<Utc>,
+    /// When the result was last used
+    pub last_used_at: DateTime<Utc>,
+    /// Number of tasks served from this entry
+    pub hits: u64,
+}
+
+impl CacheEntry {
+    /// Gets the total size of the output files
+    pub fn size(&self) -> u64 {
+        self.outputs.iter().map(|output| output.size).sum()
+    }
+}
+
+/// Cached result published to Chronicle for other peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedResult {
+    /// Cache key of the task
+    pub key: ContentId,
+    /// Exit code of the task that produced the result
+    pub exit_code: Option<i32>,
+    /// Output files; their content is stored in Chronicle
+    pub outputs: Vec<CachedOutput>,
+    /// Peer that published the result
+    pub publisher: PeerId,
+    /// Publisher signature over the key, exit code and outputs
+    pub signature: Vec<u8>,
+}
+
+impl SharedResult {
+    /// Whether the result is signed by the publisher it names
+    pub fn verify(&self) -> bool {
+        verify_signature(&self.publisher, &self.signing_message(), &self.signature)
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        let mut message = SHARED_RESULT_DOMAIN.to_vec();
+        push_field(&mut message, Some(self.key.as_str().as_bytes()));
+        push_field(&mut message, self.exit_code.map(i32::to_le_bytes).as_ref().map(|code| code.as_slice()));
+        for output in &self.outputs {
+            push_field(&mut message, Some(output.name.as_bytes()));
+            push_field(&mut message, Some(output.content_id.as_str().as_bytes()));
+        }
+        message
+    }
+}
+
+/// Size-bounded result cache on disk
+pub struct ResultCache {
+    dir: PathBuf,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    max_bytes: u64,
+    entries: HashMap<ContentId, CacheEntry>,
+}
+
+impl ResultCache {
+    /// Opens the cache in `dir`, holding at most `max_bytes` of output
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or the index
+    /// cannot be read
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> SynapseResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("blobs"))?;
+        let index = dir.join(INDEX_FILE);
+        let entries: Vec<CacheEntry> = if index.exists() {
+            serde_json::from_slice(&fs::read(&index)?)
+                .map_err(|e| SynapseError::Corrupted(format!("result cache index: {}", e)))?
+        } else {
+            Vec::new()
+        };
+        let entries = entries.into_iter().map(|entry| (entry.key.clone(), entry)).collect();
+        Ok(Self { dir, state: Mutex::new(CacheState { max_bytes, entries }) })
+    }
+
+    /// Changes the size limit, evicting entries that no longer fit
+    pub fn set_max_bytes(&self, max_bytes: u64) -> SynapseResult<()> {
+        let mut state = self.state()?;
+        state.max_bytes = max_bytes;
+        self.evict(&mut state, None)
+    }
+
+    /// Gets the total size of the cached output files
+    pub fn size(&self) -> SynapseResult<u64> {
+        Ok(self.state()?.entries.values().map(CacheEntry::size).sum())
+    }
+
+    /// Looks up a result and marks it as used
+    pub fn lookup(&self, key: &ContentId) -> SynapseResult<Option<CacheEntry>> {
+        let mut state = self.state()?;
+        let Some(entry) = state.entries.get_mut(key) else {
+            return Ok(None);
+        };
+        entry.last_used_at = Utc::now();
+        entry.hits += 1;
+        let entry = entry.clone();
+        self.save_index(&state)?;
+        Ok(Some(entry))
+    }
+
+    /// Copies the output files of a cached result into `output_dir`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file is missing or does not match its content
+    /// ID; the entry is dropped from the cache in that case
+    pub fn restore(&self, entry: &CacheEntry, output_dir: &Path) -> SynapseResult<()> {
+        fs::create_dir_all(output_dir)?;
+        for output in &entry.outputs {
+            let restored = fs::read(self.blob_path(&output.content_id))
+                .map_err(SynapseError::from)
+                .and_then(|data| Ok(output.content_id.verify(&data).map(|_| data)?));
+            match restored {
+                Ok(data) => fs::write(output_dir.join(&output.name), data)?,
+                Err(e) => {
+                    log::warn!("Dropping damaged cache entry {}: {}", entry.key, e);
+                    let mut state = self.state()?;
+                    state.entries.remove(&entry.key);
+                    self.save_index(&state)?;
+                    self.collect_garbage(&state)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Caches the files in `output_dir` as the result of the task with
+    /// cache key `key`
+    ///
+    /// A result larger than the whole cache is not stored.
+    pub fn insert(&self, key: ContentId, exit_code: Option<i32>, output_dir: &Path) -> SynapseResult<()> {
+        let mut outputs = Vec::new();
+        for (name, path) in read_dir_files(output_dir)? {
+            let content_id = ContentId::for_reader(fs::File::open(&path)?)?;
+            let blob = self.blob_path(&content_id);
+            if !blob.exists() {
+                let tmp = blob.with_extension("tmp");
+                fs::copy(&path, &tmp)?;
+                fs::File::open(&tmp)?.sync_all()?;
+                fs::rename(&tmp, &blob)?;
+            }
+            let size = fs::metadata(&blob)?.len();
+            outputs.push(CachedOutput { name, content_id, size });
+        }
+        self.insert_entry(key, exit_code, outputs)
+    }
+
+    /// Publishes a cached result to Chronicle, signed by `identity`
+    pub fn publish(&self, key: &ContentId, content: &dyn ContentStore, identity: &NodeIdentity) -> SynapseResult<SharedResult> {
+        let entry = self
+            .state()?
+            .entries
+            .get(key)
+            .cloned()
+            .ok_or_else(|| SynapseError::Execution(format!("result {} is not cached", key)))?;
+        for output in &entry.outputs {
+            let data = fs::read(self.blob_path(&output.content_id))?;
+            output.content_id.verify(&data)?;
+            content.put(&data)?;
+        }
+        let mut shared = SharedResult {
+            key: entry.key,
+            exit_code: entry.exit_code,
+            outputs: entry.outputs,
+            publisher: identity.peer_id(),
+            signature: Vec::new(),
+        };
+        shared.signature = identity.sign(&shared.signing_message());
+        Ok(shared)
+    }
+
+    /// Fetches a result shared by a peer from Chronicle into the cache
+    ///
+    /// The signature only proves who published the result; whether the
+    /// publisher is trusted to file it under its key is up to the caller.
+    pub fn import(&self, shared: &SharedResult, content: &dyn ContentStore) -> SynapseResult<()> {
+        if !shared.verify() {
+            return Err(SynapseError::Execution(format!("shared result {} is not signed by its publisher", shared.key)));
+        }
+        for output in &shared.outputs {
+            if !is_plain_file_name(&output.name) {
+                return Err(SynapseError::Execution(format!("shared result has invalid file name {:?}", output.name)));
+            }
+            let data = content.get(&output.content_id)?;
+            output.content_id.verify(&data)?;
+            let blob = self.blob_path(&output.content_id);
+            if !blob.exists() {
+                write_atomic(&blob, &data)?;
+            }
+        }
+        let outputs = shared
+            .outputs
+            .iter()
+            .map(|output| CachedOutput { size: fs::metadata(self.blob_path(&output.content_id)).map_or(0, |m| m.len()), ..output.clone() })
+            .collect();
+        self.insert_entry(shared.key.clone(), shared.exit_code, outputs)
+    }
+
+    fn insert_entry(&self, key: ContentId, exit_code: Option<i32>, outputs: Vec<CachedOutput>) -> SynapseResult<()> {
+        let now = Utc::now();
+        let entry = CacheEntry { key: key.clone(), exit_code, outputs, created_at: now, last_used_at: now, hits: 0 };
+        let mut state = self.state()?;
+        if entry.size() > state.max_bytes {
+            log::info!("Result {} ({} bytes) is larger than the cache", key, entry.size());
+            self.collect_garbage(&state)?;
+            return Ok(());
+        }
+        state.entries.insert(key.clone(), entry);
+        self.evict(&mut state, Some(&key))
+    }
+
+    /// Evicts least recently used entries (never `keep`) until the cache
+    /// fits its limit, then removes blobs no entry refers to
+    fn evict(&self, state: &mut CacheState, keep: Option<&ContentId>) -> SynapseResult<()> {
+        let mut size: u64 = state.entries.values().map(CacheEntry::size).sum();
+        let mut by_age: Vec<(DateTime<Utc>, ContentId)> = state
+            .entries
+            .values()
+            .filter(|entry| Some(&entry.key) != keep)
+            .map(|entry| (entry.last_used_at, entry.key.clone()))
+            .collect();
+        by_age.sort();
+        for (_, key) in by_age {
+            if size <= state.max_bytes {
+                break;
+            }
+            if let Some(entry) = state.entries.remove(&key) {
+                log::debug!("Evicting cached result {} ({} bytes)", key, entry.size());
+                size -= entry.size();
+            }
+        }
+        self.save_index(state)?;
+        self.collect_garbage(state)
+    }
+
+    fn collect_garbage(&self, state: &CacheState) -> SynapseResult<()> {
+        let referenced: HashSet<String> = state
+            .entries
+            .values()
+            .flat_map(|entry| entry.outputs.iter().map(|output| output.content_id.to_string()))
+            .collect();
+        for blob in fs::read_dir(self.dir.join("blobs"))? {
+            let path = blob?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if !referenced.contains(name) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_index(&self, state: &CacheState) -> SynapseResult<()> {
+        let entries: Vec<&CacheEntry> = state.entries.values().collect();
+        let bytes = serde_json::to_vec(&entries).map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        write_atomic(&self.dir.join(INDEX_FILE), &bytes)?;
+        Ok(())
+    }
+
+    fn blob_path(&self, content_id: &ContentId) -> PathBuf {
+        self.dir.join("blobs").join(content_id.as_str())
+    }
+
+    fn state(&self) -> SynapseResult<MutexGuard<'_, CacheState>> {
+        self.state
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+}
+
+/// Computes the cache key of a task whose inputs are staged in `input_dir`
+/// or referenced by its manifest
+///
+/// The key ignores everything that does not change the output: task ID,
+/// name, submitting AIbox, priority, reward, resources and deadline.
+pub fn cache_key(manifest: &TaskManifest, input_dir: &Path) -> SynapseResult<ContentId> {
+    let mut canonical = CACHE_KEY_DOMAIN.to_vec();
+    push_field(&mut canonical, Some(format!("{:?}", manifest.task_type).as_bytes()));
+    push_field(&mut canonical, manifest.model.as_deref().map(str::as_bytes));
+
+    match &manifest.runtime {
+        TaskRuntime::Process { program, args, env } => {
+            push_field(&mut canonical, Some(b"process"));
+            push_field(&mut canonical, Some(program.as_bytes()));
+            // The same path may hold a different binary on another node
+            let binary = Path::new(program);
+            let code = binary.is_file().then(|| fs::File::open(binary).and_then(ContentId::for_reader)).transpose()?;
+            push_field(&mut canonical, code.as_ref().map(|id| id.as_str().as_bytes()));
+            push_field(&mut canonical, Some(&(args.len() as u64).to_le_bytes()));
+            for arg in args {
+                push_field(&mut canonical, Some(arg.as_bytes()));
+            }
+            let mut env: Vec<(&String, &String)> = env.iter().collect();
+            env.sort();
+            push_field(&mut canonical, Some(&(env.len() as u64).to_le_bytes()));
+            for (name, value) in env {
+                push_field(&mut canonical, Some(name.as_bytes()));
+                push_field(&mut canonical, Some(value.as_bytes()));
+            }
+        }
+    }
+
+    // Inputs referenced in Chronicle are fetched only when the task
+    // starts, and replace staged files of the same name.
+    let mut inputs: BTreeMap<String, ContentId> = read_dir_files(input_dir)?
+        .into_iter()
+        .map(|(name, path)| Ok((name, ContentId::for_reader(fs::File::open(path)?)?)))
+        .collect::<SynapseResult<_>>()?;
+    inputs.extend(manifest.inputs.iter().map(|input| (input.name.clone(), input.content_id.clone())));
+    push_field(&mut canonical, Some(&(inputs.len() as u64).to_le_bytes()));
+    for (name, content_id) in inputs {
+        push_field(&mut canonical, Some(name.as_bytes()));
+        push_field(&mut canonical, Some(content_id.as_str().as_bytes()));
+    }
+    Ok(ContentId::for_bytes(&canonical))
+}
+
+/// Lists the regular files of a flat directory sorted by name (missing
+/// means empty)
+fn read_dir_files(dir: &Path) -> SynapseResult<Vec<(String, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            files.push((name.to_string(), entry.path()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::MemoryContentStore;
+    use crate::synapse::store::tests::manifest;
+
+    fn output_dir(root: &Path, name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, data) in files {
+            fs::write(dir.join(file), data).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_key_depends_on_inputs_not_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = output_dir(dir.path(), "inputs", &[("data", b"1,2,3")]);
+        let a = cache_key(&manifest("a"), &inputs).unwrap();
+        let mut other_box = manifest("b");
+        other_box.aibox_id = "AIbox #2".to_string();
+        assert_eq!(a, cache_key(&other_box, &inputs).unwrap());
+
+        fs::write(inputs.join("data"), b"1,2,4").unwrap();
+        assert_ne!(a, cache_key(&manifest("a"), &inputs).unwrap());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::open(dir.path().join("cache"), 10).unwrap();
+        let key = |n: u8| ContentId::for_bytes(&[n]);
+        cache.insert(key(1), Some(0), &output_dir(dir.path(), "1", &[("out", b"aaaa")])).unwrap();
+        cache.insert(key(2), Some(0), &output_dir(dir.path(), "2", &[("out", b"bbbb")])).unwrap();
+        assert!(cache.lookup(&key(1)).unwrap().is_some());
+        cache.insert(key(3), Some(0), &output_dir(dir.path(), "3", &[("out", b"cccc")])).unwrap();
+
+        assert!(cache.lookup(&key(2)).unwrap().is_none());
+        assert_eq!(cache.size().unwrap(), 8);
+
+        let cache = ResultCache::open(dir.path().join("cache"), 10).unwrap();
+        let entry = cache.lookup(&key(1)).unwrap().unwrap();
+        assert_eq!(entry.hits, 2);
+        let restored = dir.path().join("restored");
+        cache.restore(&entry, &restored).unwrap();
+        assert_eq!(fs::read(restored.join("out")).unwrap(), b"aaaa");
+        assert_eq!(fs::read_dir(dir.path().join("cache/blobs")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_shared_result_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let chronicle = MemoryContentStore::default();
+        let origin = ResultCache::open(dir.path().join("origin"), 1024).unwrap();
+        let key = ContentId::for_bytes(b"task");
+        origin.insert(key.clone(), Some(0), &output_dir(dir.path(), "out", &[("model", b"weights")])).unwrap();
+
+        let shared = origin.publish(&key, &chronicle, &NodeIdentity::generate()).unwrap();
+        let peer = ResultCache::open(dir.path().join("peer"), 1024).unwrap();
+        let mut moved = shared.clone();
+        moved.key = ContentId::for_bytes(b"another task");
+        assert!(peer.import(&moved, &chronicle).is_err());
+        assert!(peer.lookup(&moved.key).unwrap().is_none());
+
+        peer.import(&shared, &chronicle).unwrap();
+        let entry = peer.lookup(&key).unwrap().unwrap();
+        assert_eq!(entry.outputs, shared.outputs);
+    }
+}
@@ -0,0 +1,170 @@
+//! Task checkpoints
+//!
+//! Long-running tasks periodically hand a snapshot of their state to the
+//! executor. Snapshots are kept under `checkpoints/<task_id>/` so that a
+//! task can restart from the latest one after a restart or preemption, or
+//! be handed to another peer through Chronicle.
+
+use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::manifest::is_safe_id;
+use super::store::CheckpointRef;
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::{ContentId, ContentStore};
+
+/// Number of checkpoints kept per task
+const RETAINED_CHECKPOINTS: usize = 2;
+
+/// Descriptor of a checkpoint published to Chronicle for another peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointHandoff {
+    /// Task the checkpoint belongs to
+    pub task_id: String,
+    /// Checkpoint number within the task
+    pub sequence: u64,
+    /// Task progress at the time of the checkpoint
+    pub progress: f32,
+    /// Chronicle content ID of the checkpoint data
+    pub content_id: ContentId,
+}
+
+/// On-disk checkpoint storage
+pub struct CheckpointStore {
+    dir: PathBuf,
+}
+
+impl CheckpointStore {
+    /// Opens the checkpoint store in `dir`
+    pub fn open(dir: impl AsRef<Path>) -> SynapseResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Saves checkpoint data for a task
+    ///
+    /// The data is written atomically; older checkpoints beyond the
+    /// retention limit are removed afterwards.
+    pub fn save(&self, task_id: &str, sequence: u64, progress: f32, data: &[u8]) -> SynapseResult<CheckpointRef> {
+        if !is_safe_id(task_id) {
+            return Err(SynapseError::InvalidManifest(format!("invalid task ID {:?}", task_id)));
+        }
+        let task_dir = self.dir.join(task_id);
+        fs::create_dir_all(&task_dir)?;
+
+        let path = task_dir.join(format!("{:08}.ckpt", sequence));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        self.prune(&task_dir)?;
+        log::debug!("Saved checkpoint #{} for task {} ({} bytes)", sequence, task_id, data.len());
+
+        Ok(CheckpointRef {
+            sequence,
+            path,
+            progress,
+            content_id: ContentId::for_bytes(data),
+            created_at: Utc::now(),
+        })
+    }
+
+    /// Loads checkpoint data, verifying it against the recorded hash
+    pub fn load(&self, checkpoint: &CheckpointRef) -> SynapseResult<Vec<u8>> {
+        let data = fs::read(&checkpoint.path)?;
+        checkpoint.content_id.verify(&data)?;
+        Ok(data)
+    }
+
+    /// Removes all checkpoints of a task
+    pub fn remove_task(&self, task_id: &str) -> SynapseResult<()> {
+        let task_dir = self.dir.join(task_id);
+        if is_safe_id(task_id) && task_dir.exists() {
+            fs::remove_dir_all(task_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a checkpoint to Chronicle so another peer can resume the task
+    pub fn handoff(
+        &self,
+        task_id: &str,
+        checkpoint: &CheckpointRef,
+        store: &dyn ContentStore,
+    ) -> SynapseResult<CheckpointHandoff> {
+        let data = self.load(checkpoint)?;
+        let content_id = store.put(&data)?;
+        Ok(CheckpointHandoff {
+            task_id: task_id.to_string(),
+            sequence: checkpoint.sequence,
+            progress: checkpoint.progress,
+            content_id,
+        })
+    }
+
+    /// Fetches a checkpoint handed over by another peer into local storage
+    pub fn accept_handoff(&self, handoff: &CheckpointHandoff, store: &dyn ContentStore) -> SynapseResult<CheckpointRef> {
+        let data = store.get(&handoff.content_id)?;
+        handoff.content_id.verify(&data)?;
+        self.save(&handoff.task_id, handoff.sequence, handoff.progress, &data)
+    }
+
+    fn prune(&self, task_dir: &Path) -> SynapseResult<()> {
+        let mut checkpoints: Vec<PathBuf> = fs::read_dir(task_dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ckpt"))
+            .collect();
+        checkpoints.sort();
+        let excess = checkpoints.len().saturating_sub(RETAINED_CHECKPOINTS);
+        for path in checkpoints.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::MemoryContentStore;
+
+    #[test]
+    fn test_only_latest_checkpoints_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = CheckpointStore::open(dir.path()).unwrap();
+        let refs: Vec<CheckpointRef> = (1..=3)
+            .map(|sequence| checkpoints.save("task", sequence, sequence as f32 * 10.0, format!("state {}", sequence).as_bytes()).unwrap())
+            .collect();
+
+        assert!(!refs[0].path.exists());
+        assert_eq!(checkpoints.load(&refs[2]).unwrap(), b"state 3");
+        fs::write(&refs[1].path, b"tampered").unwrap();
+        assert!(checkpoints.load(&refs[1]).is_err());
+
+        checkpoints.remove_task("task").unwrap();
+        assert!(!dir.path().join("task").exists());
+    }
+
+    #[test]
+    fn test_handoff_roundtrip_stays_inside_the_store() {
+        let content = MemoryContentStore::default();
+        let sender_dir = tempfile::tempdir().unwrap();
+        let sender = CheckpointStore::open(sender_dir.path()).unwrap();
+        let checkpoint = sender.save("task", 4, 40.0, b"model weights").unwrap();
+        let handoff = sender.handoff("task", &checkpoint, &content).unwrap();
+
+        let receiver_dir = tempfile::tempdir().unwrap();
+        let receiver = CheckpointStore::open(receiver_dir.path().join("checkpoints")).unwrap();
+        let accepted = receiver.accept_handoff(&handoff, &content).unwrap();
+        assert_eq!((accepted.sequence, accepted.progress), (4, 40.0));
+        assert_eq!(receiver.load(&accepted).unwrap(), b"model weights");
+
+        let escaping = CheckpointHandoff { task_id: "../escaped".to_string(), ..handoff };
+        assert!(matches!(receiver.accept_handoff(&escaping, &content), Err(SynapseError::InvalidManifest(_))));
+        assert!(!receiver_dir.path().join("escaped").exists());
+    }
+}
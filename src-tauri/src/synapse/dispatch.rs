@@ -0,0 +1,1379 @@
+//! Dispatch of tasks to remote peers
+//!
+//! Tasks placed with [`TaskPlacement::Remote`] are not run by the local
+//! executor. The [`Dispatcher`] offers them to the reachable peers, assigns
+//! each one to the best bidder together with its input files and follows
+//! the worker's progress. Workers push progress updates and their signed
+//! result; the requester also polls every worker, so that a lost message
+//! or a vanished worker is noticed. A worker that misses too many polls
+//! loses the task, which is then offered to the remaining peers.
+//!
+//! Workers publish each checkpoint of a task to Chronicle and hand it to
+//! the requester, which keeps the latest one. A task that moves to another
+//! worker is assigned together with that checkpoint and resumes from it.
+//!
+//! The same dispatcher serves the worker side: it bids on offers, queues
+//! assigned tasks in the local engine and reports back to the requester.
+//! Only tasks the user allowed in the
+//! [`RemoteTaskSettings`](crate::ui_api::RemoteTaskSettings) are accepted;
+//! by default the node runs nothing for its peers.
+
+use chrono::Utc;
+use futures::future::{join_all, BoxFuture};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::cache::{CacheMode, SharedResult};
+use super::checkpoint::CheckpointHandoff;
+use super::engine::{Synapse, TaskEvent};
+use super::manifest::{TaskManifest, TaskPlacement, TaskRuntime};
+use super::receipt::{ReceiptResources, TaskReceipt};
+use super::staging::StagedFile;
+use super::store::{TaskRecord, TaskResult};
+use super::util::{is_plain_file_name, push_field};
+use super::verification::{tally, Verdict, VerificationPolicy};
+use super::{SynapseError, SynapseResult};
+use crate::benchmark::REFERENCE_SCORE;
+use crate::chronicle::ContentId;
+use crate::identity::{verify_signature, NodeIdentity, PeerId};
+use crate::rpc::{self, RpcHandler, RpcResult, Transport};
+use crate::ui_api::{FailureKind, TaskStatus};
+
+/// Protocol name of task dispatch messages
+pub const DISPATCH_PROTOCOL: &str = "/mycelium/synapse/dispatch/1";
+
+/// Largest file sent inside a dispatch message; larger task data is
+/// referenced by content ID and moved through Chronicle
+pub const MAX_INLINE_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Domain separator of result signatures
+const RESULT_SIGNATURE_DOMAIN: &[u8] = b"mycelium/synapse/result/v1";
+
+/// Dispatch timeouts and limits
+#[derive(Debug, Clone)]
+pub struct DispatchConfig {
+    /// How long to wait for bids on an offer
+    pub bid_timeout: Duration,
+    /// How long the transfer of a task and its inputs may take
+    pub transfer_timeout: Duration,
+    /// Interval between dispatch passes and worker polls
+    pub poll_interval: Duration,
+    /// Consecutive failed polls after which a worker loses its task
+    pub max_missed_polls: u32,
+    /// Number of workers each replica of a task is assigned to before the
+    /// task is given up
+    pub max_assignments: u32,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            bid_timeout: Duration::from_secs(5),
+            transfer_timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(10),
+            max_missed_polls: 3,
+            max_assignments: 3,
+        }
+    }
+}
+
+/// File transferred along with a task
+///
+/// Only flat directories of files up to [`MAX_INLINE_FILE_BYTES`] are
+/// transferred; `name` is a plain file name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskFile {
+    /// File name
+    pub name: String,
+    /// File content
+    pub data: Vec<u8>,
+}
+
+impl From<&TaskFile> for StagedFile {
+    fn from(file: &TaskFile) -> Self {
+        StagedFile {
+            name: file.name.clone(),
+            content_id: ContentId::for_bytes(&file.data),
+            size: file.data.len() as u64,
+        }
+    }
+}
+
+/// Offer answer describing the free resources of a worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bid {
+    /// Free CPU share in percent of the worker host
+    pub free_cpu_percent: f32,
+    /// Free RAM in GB
+    pub free_ram_gb: f32,
+    /// Local tasks waiting in the worker's queue
+    pub queued_tasks: usize,
+    /// Benchmarked compute score of the worker (unknown for workers that
+    /// have not benchmarked themselves yet)
+    #[serde(default)]
+    pub compute_score: Option<f64>,
+}
+
+impl Bid {
+    /// Ranks the bid; higher is better
+    ///
+    /// The free CPU share is weighted by the speed of the worker relative
+    /// to the reference machine; workers of unknown speed count as the
+    /// reference.
+    pub fn score(&self) -> f32 {
+        let speed = self.compute_score.map_or(1.0, |score| score / REFERENCE_SCORE) as f32;
+        self.free_cpu_percent * speed / (1 + self.queued_tasks) as f32
+    }
+}
+
+/// Result of a task as produced and signed by its worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteResult {
+    /// Task ID
+    pub task_id: String,
+    /// Process exit code (if the task ran to completion)
+    pub exit_code: Option<i32>,
+    /// Error description for failed tasks
+    pub error: Option<String>,
+    /// Cause of the failure, as classified by the worker
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    /// Files written by the task to its output directory
+    pub outputs: Vec<TaskFile>,
+    /// Peer that produced the result
+    pub worker: PeerId,
+    /// Worker signature over the result hash
+    pub signature: Vec<u8>,
+    /// Receipt for the result, signed separately by the worker
+    #[serde(default)]
+    pub receipt: Option<TaskReceipt>,
+    /// Cached result the worker published to Chronicle, signed separately
+    /// (only for tasks that share their result)
+    #[serde(default)]
+    pub shared: Option<SharedResult>,
+}
+
+impl RemoteResult {
+    /// Builds a result and signs it with the worker identity
+    pub fn new(
+        task_id: String,
+        exit_code: Option<i32>,
+        error: Option<String>,
+        failure: Option<FailureKind>,
+        outputs: Vec<TaskFile>,
+        identity: &NodeIdentity,
+    ) -> Self {
+        let mut result = Self {
+            task_id,
+            exit_code,
+            error,
+            failure,
+            outputs,
+            worker: identity.peer_id(),
+            signature: Vec::new(),
+            receipt: None,
+            shared: None,
+        };
+        result.signature = identity.sign(&result.signing_message());
+        result
+    }
+
+    /// Hash binding the task ID to its exit status, failure and output files
+    pub fn result_hash(&self) -> ContentId {
+        let mut canonical = Vec::new();
+        push_field(&mut canonical, Some(self.task_id.as_bytes()));
+        push_field(&mut canonical, self.exit_code.map(i32::to_le_bytes).as_ref().map(|c| c.as_slice()));
+        push_field(&mut canonical, self.error.as_deref().map(str::as_bytes));
+        push_field(&mut canonical, self.failure.map(|kind| format!("{:?}", kind)).as_deref().map(str::as_bytes));
+
+        let mut outputs: Vec<&TaskFile> = self.outputs.iter().collect();
+        outputs.sort_by(|a, b| a.name.cmp(&b.name));
+        for file in outputs {
+            push_field(&mut canonical, Some(file.name.as_bytes()));
+            push_field(&mut canonical, Some(ContentId::for_bytes(&file.data).as_str().as_bytes()));
+        }
+        ContentId::for_bytes(&canonical)
+    }
+
+    /// Whether the result is signed by the worker it names
+    pub fn verify(&self) -> bool {
+        verify_signature(&self.worker, &self.signing_message(), &self.signature)
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        let mut message = RESULT_SIGNATURE_DOMAIN.to_vec();
+        message.extend_from_slice(self.result_hash().as_str().as_bytes());
+        message
+    }
+}
+
+/// Request of the dispatch protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DispatchRequest {
+    /// Asks a peer to bid on a task
+    Offer { manifest: TaskManifest },
+    /// Assigns a task and its inputs to a peer, with the checkpoint to
+    /// resume it from (if a previous worker made progress)
+    Assign { manifest: TaskManifest, inputs: Vec<TaskFile>, checkpoint: Option<CheckpointHandoff> },
+    /// Progress update from a worker
+    Progress { task_id: String, progress: f32 },
+    /// Checkpoint a worker published to Chronicle
+    Checkpoint { handoff: CheckpointHandoff },
+    /// Final result from a worker
+    Result { result: RemoteResult },
+    /// Asks a worker for the state of a task
+    Status { task_id: String },
+    /// Withdraws a task from a worker
+    Cancel { task_id: String },
+    /// Returns the countersigned receipt of an accepted result to its worker
+    Receipt { receipt: TaskReceipt },
+}
+
+/// Response of the dispatch protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DispatchResponse {
+    /// The peer is willing to run the offered task
+    Bid(Bid),
+    /// The peer queued the assigned task
+    Accepted,
+    /// The peer refused the request
+    Declined { reason: String },
+    /// State of a task on its worker; finished tasks include the result
+    Status { status: TaskStatus, progress: f32, result: Option<Box<RemoteResult>> },
+    /// The request was processed
+    Ack,
+}
+
+/// Requester-side bookkeeping of a remote task
+#[derive(Debug, Default)]
+struct Assignment {
+    /// Number of peers the task runs on at the same time
+    replicas: Option<u32>,
+    /// Number of workers the task has been assigned to
+    assignments: u32,
+    /// Consecutive polls each worker did not answer
+    missed_polls: HashMap<PeerId, u32>,
+    /// Results delivered by the workers so far
+    results: HashMap<PeerId, RemoteResult>,
+    /// Workers that lost the task and are not offered it again
+    excluded: HashSet<PeerId>,
+}
+
+/// Dispatches remote tasks to peers and runs tasks dispatched by peers
+#[derive(Clone)]
+pub struct Dispatcher {
+    synapse: Synapse,
+    identity: Arc<NodeIdentity>,
+    transport: Arc<dyn Transport>,
+    config: DispatchConfig,
+    assignments: Arc<Mutex<HashMap<String, Assignment>>>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher for the engine reachable through `transport`
+    ///
+    /// The dispatcher must also be registered as the
+    /// [`DISPATCH_PROTOCOL`] handler of the node to serve its peers.
+    pub fn new(
+        synapse: Synapse,
+        identity: Arc<NodeIdentity>,
+        transport: Arc<dyn Transport>,
+        config: DispatchConfig,
+    ) -> Self {
+        Self {
+            synapse,
+            identity,
+            transport,
+            config,
+            assignments: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Runs the dispatcher: assigns queued remote tasks, polls workers and
+    /// reports on tasks run for other peers
+    pub async fn run(self) {
+        let mut events = self.synapse.subscribe();
+        let mut interval = tokio::time::interval(self.config.poll_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.dispatch_pass().await,
+                event = events.recv() => match event {
+                    Ok(event) => self.on_task_event(event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Dispatcher missed {} task events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+
+    /// Assigns remote tasks that lack workers and polls the workers of
+    /// assigned ones
+    async fn dispatch_pass(&self) {
+        let records = match self.synapse.remote_tasks() {
+            Ok(records) => records,
+            Err(e) => {
+                log::error!("Failed to list remote tasks: {}", e);
+                return;
+            }
+        };
+        for record in records {
+            let mut outcome = Ok(());
+            let waiting = record.is_backing_off(Utc::now());
+            if matches!(record.status, TaskStatus::Pending | TaskStatus::Running) && !waiting {
+                outcome = self.dispatch(&record).await;
+            }
+            for worker in &record.workers {
+                outcome = outcome.and(self.poll(&record, worker).await);
+            }
+            if let Err(e) = outcome {
+                log::warn!("Dispatch of task {} failed: {}", record.manifest.id, e);
+            }
+        }
+    }
+
+    /// Offers a task to the peers and assigns it to the best bidders until
+    /// it runs on as many peers as its verification needs
+    ///
+    /// The number of replicas is decided at the first assignment. After a
+    /// restart, a task keeps running on the workers it already has.
+    async fn dispatch(&self, record: &TaskRecord) -> SynapseResult<()> {
+        let task_id = &record.manifest.id;
+        let (planned, mut excluded) = self.with_assignment(task_id, |a| {
+            if a.replicas.is_none() && !record.workers.is_empty() {
+                a.replicas = Some(record.workers.len() as u32);
+            }
+            (a.replicas, a.excluded.clone())
+        })?;
+        if planned.is_some_and(|replicas| record.workers.len() as u32 >= replicas) {
+            return Ok(());
+        }
+
+        excluded.extend(record.workers.iter().cloned());
+        if record.manifest.retry.different_peer {
+            excluded.extend(record.failed_attempts.iter().flat_map(|attempt| attempt.workers.iter().cloned()));
+        }
+        let reputation = self.synapse.reputation();
+        let mut bids: Vec<(PeerId, Bid)> = self
+            .collect_bids(&record.manifest, &excluded)
+            .await
+            .into_iter()
+            .filter(|(peer, _)| !reputation.get(peer).is_banned())
+            .collect();
+        if bids.is_empty() {
+            log::debug!("No peer bid on task {}", task_id);
+            return Ok(());
+        }
+        bids.sort_by(|a, b| b.1.score().total_cmp(&a.1.score()));
+
+        let replicas = match planned {
+            Some(replicas) => replicas,
+            None => {
+                let policy = VerificationPolicy::for_method(&record.manifest.verification);
+                let trusted = reputation.get(&bids[0].0).is_trusted();
+                let replicas = policy.replicas_for(trusted, &mut rand::thread_rng());
+                self.with_assignment(task_id, |a| a.replicas = Some(replicas))?;
+                if replicas < policy.replicas {
+                    log::info!("Task {} runs on trusted peer {} without redundancy", task_id, bids[0].0);
+                }
+                replicas
+            }
+        };
+        let mut missing = replicas.saturating_sub(record.workers.len() as u32);
+
+        let inputs = read_files(&self.synapse.input_dir(task_id))?;
+        let checkpoint = match &record.checkpoint {
+            Some(checkpoint) => {
+                log::info!("Task {} resumes from checkpoint #{}", task_id, checkpoint.sequence);
+                Some(self.synapse.handoff_checkpoint(task_id)?)
+            }
+            None => None,
+        };
+        for (peer, _) in bids {
+            if missing == 0 {
+                break;
+            }
+            let request = DispatchRequest::Assign {
+                manifest: record.manifest.clone(),
+                inputs: inputs.clone(),
+                checkpoint: checkpoint.clone(),
+            };
+            match self.request(&peer, &request, self.config.transfer_timeout).await {
+                Ok(DispatchResponse::Accepted) => {
+                    // The task may have been cancelled or finished during
+                    // the transfer.
+                    let status = self.synapse.record(task_id)?.status;
+                    if !matches!(status, TaskStatus::Pending | TaskStatus::Running) {
+                        self.send_cancel(peer, task_id.clone());
+                        return Ok(());
+                    }
+                    self.synapse.assign_worker(task_id, &peer)?;
+                    self.with_assignment(task_id, |a| {
+                        a.assignments += 1;
+                        a.missed_polls.remove(&peer);
+                    })?;
+                    log::info!("Task {} assigned to peer {}", task_id, peer);
+                    missing -= 1;
+                }
+                Ok(DispatchResponse::Declined { reason }) => {
+                    log::info!("Peer {} declined task {}: {}", peer, task_id, reason);
+                }
+                Ok(other) => log::warn!("Unexpected answer from peer {} to assignment: {:?}", peer, other),
+                Err(e) => log::warn!("Failed to transfer task {} to peer {}: {}", task_id, peer, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends an offer to every eligible peer and gathers the bids
+    async fn collect_bids(&self, manifest: &TaskManifest, excluded: &HashSet<PeerId>) -> Vec<(PeerId, Bid)> {
+        let peers: Vec<PeerId> = self
+            .transport
+            .peers()
+            .into_iter()
+            .filter(|peer| !excluded.contains(peer))
+            .collect();
+        let offer = DispatchRequest::Offer { manifest: manifest.clone() };
+        let answers = join_all(peers.iter().map(|peer| self.request(peer, &offer, self.config.bid_timeout))).await;
+
+        peers
+            .into_iter()
+            .zip(answers)
+            .filter_map(|(peer, answer)| match answer {
+                Ok(DispatchResponse::Bid(bid)) => Some((peer, bid)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks on a worker of an assigned task that has not delivered its
+    /// result yet
+    async fn poll(&self, record: &TaskRecord, worker: &PeerId) -> SynapseResult<()> {
+        let task_id = &record.manifest.id;
+        if self.with_assignment(task_id, |a| a.results.contains_key(worker))? {
+            return Ok(());
+        }
+
+        let request = DispatchRequest::Status { task_id: task_id.clone() };
+        match self.request(worker, &request, self.config.bid_timeout).await {
+            Ok(DispatchResponse::Status { result: Some(result), .. }) => self.accept_result(worker, *result),
+            Ok(DispatchResponse::Status { status, progress, .. }) => {
+                self.with_assignment(task_id, |a| a.missed_polls.remove(worker))?;
+                match status {
+                    TaskStatus::Cancelled => self.release(task_id, worker, "Worker dropped the task"),
+                    _ => self.synapse.record_remote_progress(task_id, progress),
+                }
+            }
+            Ok(DispatchResponse::Declined { reason }) => {
+                self.release(task_id, worker, &format!("Worker lost the task: {}", reason))
+            }
+            answer => {
+                let missed = self.with_assignment(task_id, |a| {
+                    let missed = a.missed_polls.entry(worker.clone()).or_default();
+                    *missed += 1;
+                    *missed
+                })?;
+                log::warn!("Worker {} of task {} did not answer ({} missed): {:?}", worker, task_id, missed, answer);
+                if missed >= self.config.max_missed_polls {
+                    self.release(task_id, worker, "Worker stopped responding")?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes a task away from one of its workers so that it is assigned to
+    /// another peer, or gives it up once it has been assigned too often
+    fn release(&self, task_id: &str, worker: &PeerId, reason: &str) -> SynapseResult<()> {
+        log::warn!("Releasing task {} from peer {}: {}", task_id, worker, reason);
+        let (assignments, replicas) = self.with_assignment(task_id, |a| {
+            a.excluded.insert(worker.clone());
+            a.missed_polls.remove(worker);
+            a.results.remove(worker);
+            (a.assignments, a.replicas.unwrap_or(1))
+        })?;
+        self.send_cancel(worker.clone(), task_id.to_string());
+        self.synapse.release_worker(task_id, worker, reason)?;
+
+        if assignments >= self.config.max_assignments * replicas {
+            let error = format!("Task was lost by {} workers; last reason: {}", assignments, reason);
+            self.give_up(task_id, FailureKind::Other, error)?;
+        }
+        Ok(())
+    }
+
+    /// Collects a result returned by a worker of a task and stores the
+    /// task result once enough workers agree on it
+    fn accept_result(&self, from: &PeerId, result: RemoteResult) -> SynapseResult<()> {
+        let task_id = result.task_id.clone();
+        let record = self.synapse.record(&task_id)?;
+        if record.result.is_some() || record.status == TaskStatus::Cancelled {
+            return Ok(());
+        }
+        if !record.workers.contains(from) || result.worker != *from {
+            return Err(SynapseError::Dispatch(format!("peer {} is not a worker of task {}", from, task_id)));
+        }
+        if !result.verify() {
+            self.synapse.reputation().record_disagreement(from)?;
+            self.release(&task_id, from, "Worker returned a result with an invalid signature")?;
+            return Err(SynapseError::Dispatch(format!("invalid result signature from peer {}", from)));
+        }
+        if let Err(reason) = self.check_receipt(&record, &result) {
+            self.synapse.reputation().record_disagreement(from)?;
+            self.release(&task_id, from, &format!("Worker returned an invalid receipt: {}", reason))?;
+            return Err(SynapseError::Dispatch(format!("invalid receipt from peer {}: {}", from, reason)));
+        }
+
+        let (replicas, results) = self.with_assignment(&task_id, |a| {
+            a.results.insert(from.clone(), result);
+            let replicas = *a.replicas.get_or_insert(record.workers.len() as u32);
+            (replicas, a.results.clone())
+        })?;
+        let policy = VerificationPolicy::for_method(&record.manifest.verification);
+        let votes: Vec<(PeerId, ContentId)> = results
+            .iter()
+            .map(|(peer, result)| (peer.clone(), result.result_hash()))
+            .collect();
+
+        match tally(&votes, replicas, policy.quorum_for(replicas)) {
+            Verdict::Undecided => {
+                log::info!("Task {}: {} of {} results received", task_id, results.len(), replicas);
+                Ok(())
+            }
+            Verdict::NoQuorum => {
+                log::warn!("Task {}: {} workers disagree on the result", task_id, results.len());
+                let error = format!("Verification failed: no quorum among {} results", results.len());
+                self.give_up(&task_id, FailureKind::VerificationMismatch, error)
+            }
+            Verdict::Accepted { result_hash, agreeing, dissenting } => {
+                if replicas > 1 {
+                    for peer in &agreeing {
+                        self.synapse.reputation().record_agreement(peer)?;
+                    }
+                }
+                for peer in &dissenting {
+                    self.synapse.reputation().record_disagreement(peer)?;
+                }
+                let accepted = agreeing
+                    .iter()
+                    .find(|peer| *peer == from)
+                    .or(agreeing.first())
+                    .and_then(|peer| results.get(peer))
+                    .ok_or_else(|| SynapseError::Dispatch(format!("no accepted result for task {}", task_id)))?;
+                self.withdraw_unfinished(&record, &results);
+                for peer in &agreeing {
+                    if let Some(receipt) = results.get(peer).and_then(|result| result.receipt.clone()) {
+                        self.countersign(receipt)?;
+                    }
+                }
+
+                write_files(&self.synapse.output_dir(&task_id), &accepted.outputs)?;
+                let status = if accepted.error.is_none() { TaskStatus::Completed } else { TaskStatus::Failed };
+                let cache_key = match status {
+                    TaskStatus::Completed => self.import_shared(&task_id, accepted),
+                    _ => None,
+                };
+                log::info!("Task {} finished on peer {}: {:?}", task_id, accepted.worker, status);
+                self.synapse.finish_remote(&task_id, status, TaskResult {
+                    exit_code: accepted.exit_code,
+                    error: accepted.error.clone(),
+                    finished_at: Utc::now(),
+                    result_hash: Some(result_hash),
+                    worker: Some(accepted.worker.clone()),
+                    verified_by: if replicas > 1 { agreeing } else { Vec::new() },
+                    failure: accepted.failure.filter(|_| status == TaskStatus::Failed),
+                    cache_key,
+                    usage: None,
+                    outputs: Vec::new(),
+                })?;
+                self.forget(&task_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Imports the result the worker of an accepted result shared into the
+    /// result cache and returns its cache key
+    ///
+    /// The shared result must list the output files that were verified.
+    /// Sharing is best effort: a result that is not imported is cached from
+    /// the returned outputs instead.
+    fn import_shared(&self, task_id: &str, accepted: &RemoteResult) -> Option<ContentId> {
+        let shared = accepted.shared.as_ref()?;
+        if !self.synapse.remote_task_settings().is_ok_and(|settings| settings.share_results) {
+            return None;
+        }
+        let mut returned: Vec<(&str, ContentId)> = accepted
+            .outputs
+            .iter()
+            .map(|file| (file.name.as_str(), ContentId::for_bytes(&file.data)))
+            .collect();
+        let mut listed: Vec<(&str, ContentId)> = shared
+            .outputs
+            .iter()
+            .map(|output| (output.name.as_str(), output.content_id.clone()))
+            .collect();
+        returned.sort();
+        listed.sort();
+        if shared.publisher != accepted.worker || returned != listed {
+            log::warn!("Peer {} shared a result that differs from its result of task {}", accepted.worker, task_id);
+            return None;
+        }
+        match self.synapse.import_shared_result(task_id, shared) {
+            Ok(()) => Some(shared.key.clone()),
+            Err(e) => {
+                log::info!("Not importing the result of task {} shared by peer {}: {}", task_id, accepted.worker, e);
+                None
+            }
+        }
+    }
+
+    /// Checks that a result carries a receipt this node can countersign
+    fn check_receipt(&self, record: &TaskRecord, result: &RemoteResult) -> Result<(), String> {
+        let receipt = result.receipt.as_ref().ok_or("missing receipt")?;
+        if receipt.task_id != record.manifest.id || receipt.worker != result.worker {
+            return Err("receipt belongs to another task or worker".to_string());
+        }
+        if receipt.requester != self.identity.peer_id() {
+            return Err("receipt names another requester".to_string());
+        }
+        if receipt.manifest_hash != record.manifest.content_id() {
+            return Err("manifest hash mismatch".to_string());
+        }
+        if receipt.result_hash != result.result_hash() {
+            return Err("result hash mismatch".to_string());
+        }
+        let outputs: Vec<StagedFile> = result.outputs.iter().map(StagedFile::from).collect();
+        if receipt.outputs != outputs {
+            return Err("output content IDs mismatch".to_string());
+        }
+        if !receipt.verify_worker() {
+            return Err("invalid worker signature".to_string());
+        }
+        Ok(())
+    }
+
+    /// Countersigns the receipt of an accepted result, keeps it and returns
+    /// it to the worker
+    fn countersign(&self, mut receipt: TaskReceipt) -> SynapseResult<()> {
+        receipt.countersign(&self.identity)?;
+        self.synapse.receipts().save(&receipt)?;
+        self.send_report(receipt.worker.clone(), DispatchRequest::Receipt { receipt });
+        Ok(())
+    }
+
+    /// Ends the current attempt of a remote task as failed and withdraws
+    /// it from its workers; the engine decides whether it is retried
+    fn give_up(&self, task_id: &str, kind: FailureKind, error: String) -> SynapseResult<()> {
+        let record = self.synapse.record(task_id)?;
+        let results = self.with_assignment(task_id, |a| a.results.clone())?;
+        self.withdraw_unfinished(&record, &results);
+        self.synapse.finish_remote(task_id, TaskStatus::Failed, TaskResult::failed(kind, error))?;
+        self.forget(task_id);
+        Ok(())
+    }
+
+    /// Cancels the replicas of a task that are still running
+    fn withdraw_unfinished(&self, record: &TaskRecord, results: &HashMap<PeerId, RemoteResult>) {
+        for worker in record.workers.iter().filter(|worker| !results.contains_key(*worker)) {
+            self.send_cancel(worker.clone(), record.manifest.id.clone());
+        }
+    }
+
+    /// Forwards engine events about tasks run for other peers, and
+    /// withdraws cancelled or failed (e.g. overdue) remote tasks from their
+    /// workers
+    fn on_task_event(&self, event: TaskEvent) {
+        let task_id = match &event {
+            TaskEvent::Progress { task_id, .. }
+            | TaskEvent::Checkpointed { task_id }
+            | TaskEvent::Finished { task_id, .. } => task_id,
+            TaskEvent::Log(_) => return,
+        };
+        let Ok(record) = self.synapse.record(task_id) else {
+            return;
+        };
+
+        match (event, &record.requester, record.workers.is_empty()) {
+            (TaskEvent::Progress { task_id, progress }, Some(requester), _) => {
+                self.send_report(requester.clone(), DispatchRequest::Progress { task_id, progress });
+            }
+            (TaskEvent::Checkpointed { task_id }, Some(requester), _) => match self.synapse.handoff_checkpoint(&task_id) {
+                Ok(handoff) => self.send_report(requester.clone(), DispatchRequest::Checkpoint { handoff }),
+                Err(e) => log::warn!("Failed to publish checkpoint of task {}: {}", task_id, e),
+            },
+            (TaskEvent::Finished { status: TaskStatus::Completed | TaskStatus::Failed, .. }, Some(requester), _) => {
+                match self.signed_result(&record) {
+                    Ok(result) => self.send_report(requester.clone(), DispatchRequest::Result { result }),
+                    Err(e) => log::error!("Failed to build result of task {}: {}", record.manifest.id, e),
+                }
+            }
+            (TaskEvent::Finished { task_id, status: TaskStatus::Cancelled | TaskStatus::Failed }, None, false) => {
+                for worker in &record.workers {
+                    self.send_cancel(worker.clone(), task_id.clone());
+                }
+                self.forget(&task_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Serves a dispatch request from `from`
+    fn handle_request(&self, from: &PeerId, request: DispatchRequest) -> SynapseResult<DispatchResponse> {
+        match request {
+            DispatchRequest::Offer { manifest } => Ok(self.bid(&manifest)),
+            DispatchRequest::Assign { manifest, inputs, checkpoint } => {
+                self.accept_assignment(from, manifest, inputs, checkpoint)
+            }
+            DispatchRequest::Progress { task_id, progress } => {
+                let record = self.synapse.record(&task_id)?;
+                if !record.workers.contains(from) || record.result.is_some() {
+                    return Err(SynapseError::Dispatch(format!("peer {} is not a worker of task {}", from, task_id)));
+                }
+                self.synapse.record_remote_progress(&task_id, progress)?;
+                Ok(DispatchResponse::Ack)
+            }
+            DispatchRequest::Checkpoint { handoff } => {
+                let record = self.synapse.record(&handoff.task_id)?;
+                if !record.workers.contains(from) || record.result.is_some() {
+                    return Err(SynapseError::Dispatch(format!(
+                        "peer {} is not a worker of task {}", from, handoff.task_id
+                    )));
+                }
+                self.synapse.record_remote_checkpoint(&handoff)?;
+                Ok(DispatchResponse::Ack)
+            }
+            DispatchRequest::Result { result } => {
+                self.accept_result(from, result)?;
+                Ok(DispatchResponse::Ack)
+            }
+            DispatchRequest::Status { task_id } => self.worker_status(from, &task_id),
+            DispatchRequest::Receipt { receipt } => {
+                self.requested_by(from, &receipt.task_id)?;
+                if receipt.worker != self.identity.peer_id() || receipt.requester != *from || !receipt.verify() {
+                    return Err(SynapseError::Dispatch(format!(
+                        "invalid receipt for task {} from peer {}", receipt.task_id, from
+                    )));
+                }
+                self.synapse.receipts().save(&receipt)?;
+                Ok(DispatchResponse::Ack)
+            }
+            DispatchRequest::Cancel { task_id } => {
+                let record = self.requested_by(from, &task_id)?;
+                if matches!(record.status, TaskStatus::Pending | TaskStatus::Running | TaskStatus::Paused) {
+                    log::info!("Peer {} withdrew task {}", from, task_id);
+                    self.synapse.cancel(&task_id)?;
+                }
+                Ok(DispatchResponse::Ack)
+            }
+        }
+    }
+
+    /// Answers an offer with a bid, or declines it
+    fn bid(&self, manifest: &TaskManifest) -> DispatchResponse {
+        if let Err(e) = manifest.validate() {
+            return DispatchResponse::Declined { reason: e.to_string() };
+        }
+        if let Err(reason) = self.authorize(manifest) {
+            return DispatchResponse::Declined { reason };
+        }
+        match (self.synapse.free_capacity(), self.synapse.queued_tasks()) {
+            (Ok(free), Ok(queued_tasks)) if free.fits(&manifest.resources) => DispatchResponse::Bid(Bid {
+                free_cpu_percent: free.cpu_percent,
+                free_ram_gb: free.ram_gb,
+                queued_tasks,
+                compute_score: self.synapse.compute_score(),
+            }),
+            (Ok(_), Ok(_)) => DispatchResponse::Declined { reason: "Insufficient free resources".to_string() },
+            (Err(e), _) | (_, Err(e)) => DispatchResponse::Declined { reason: e.to_string() },
+        }
+    }
+
+    /// Checks that the user allows peers to run `manifest` on this node
+    ///
+    /// An allowlisted manifest is accepted as it is. An allowlisted program
+    /// is accepted with any arguments, but not with dynamic loader
+    /// variables, which would let the task inject code into it.
+    fn authorize(&self, manifest: &TaskManifest) -> Result<(), String> {
+        let settings = self.synapse.remote_task_settings().map_err(|e| e.to_string())?;
+        if !settings.enabled {
+            return Err("This node does not run tasks for peers".to_string());
+        }
+        let content_id = manifest.content_id();
+        if settings.allowed_manifests.iter().any(|allowed| allowed == content_id.as_str()) {
+            return Ok(());
+        }
+        match &manifest.runtime {
+            TaskRuntime::Process { program, .. } if !settings.allowed_programs.contains(program) => {
+                Err(format!("Program {} is not allowed for peers", program))
+            }
+            TaskRuntime::Process { env, .. } if env.keys().any(|key| key.starts_with("LD_") || key.starts_with("DYLD_")) => {
+                Err("Tasks of peers may not set dynamic loader variables".to_string())
+            }
+            TaskRuntime::Process { .. } => Ok(()),
+        }
+    }
+
+    /// Stages the inputs of an assigned task and queues it locally,
+    /// resuming it from `checkpoint` if one is handed over
+    fn accept_assignment(
+        &self,
+        from: &PeerId,
+        mut manifest: TaskManifest,
+        inputs: Vec<TaskFile>,
+        checkpoint: Option<CheckpointHandoff>,
+    ) -> SynapseResult<DispatchResponse> {
+        manifest.validate()?;
+        if let Ok(existing) = self.synapse.record(&manifest.id) {
+            // A retried transfer of a task this node already accepted
+            if existing.requester.as_ref() == Some(from) {
+                return Ok(DispatchResponse::Accepted);
+            }
+            return Err(SynapseError::DuplicateTask(manifest.id));
+        }
+        if let declined @ DispatchResponse::Declined { .. } = self.bid(&manifest) {
+            return Ok(declined);
+        }
+
+        write_files(&self.synapse.input_dir(&manifest.id), &inputs)?;
+        manifest.placement = TaskPlacement::Local;
+        match checkpoint {
+            Some(handoff) => self.synapse.accept_handoff(manifest, &handoff, from.clone())?,
+            None => self.synapse.submit_for(manifest, from.clone())?,
+        }
+        Ok(DispatchResponse::Accepted)
+    }
+
+    /// Reports the state of a task run for `from`
+    fn worker_status(&self, from: &PeerId, task_id: &str) -> SynapseResult<DispatchResponse> {
+        let record = self.requested_by(from, task_id)?;
+        let result = match record.status {
+            TaskStatus::Completed | TaskStatus::Failed => Some(Box::new(self.signed_result(&record)?)),
+            _ => None,
+        };
+        Ok(DispatchResponse::Status {
+            status: record.status,
+            progress: record.progress,
+            result,
+        })
+    }
+
+    /// Gets a task this node runs on behalf of `from`
+    fn requested_by(&self, from: &PeerId, task_id: &str) -> SynapseResult<TaskRecord> {
+        self.synapse
+            .record(task_id)
+            .ok()
+            .filter(|record| record.requester.as_ref() == Some(from))
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))
+    }
+
+    /// Builds the signed result of a finished task, with a receipt for
+    /// its requester
+    ///
+    /// The receipt is signed and stored when the result is first built and
+    /// served from the receipt store afterwards.
+    fn signed_result(&self, record: &TaskRecord) -> SynapseResult<RemoteResult> {
+        let manifest = &record.manifest;
+        let (exit_code, error, failure, finished_at) = record.result.as_ref().map_or(
+            (None, None, None, record.updated_at),
+            |result| (result.exit_code, result.error.clone(), result.failure, result.finished_at),
+        );
+        let outputs = read_files(&self.synapse.output_dir(&manifest.id))?;
+        let mut result = RemoteResult::new(manifest.id.clone(), exit_code, error, failure, outputs, &self.identity);
+        if record.status == TaskStatus::Completed
+            && manifest.cache == CacheMode::Shared
+            && self.synapse.remote_task_settings()?.share_results
+        {
+            match self.synapse.share_cached_result(&manifest.id, &self.identity) {
+                Ok(shared) => result.shared = Some(shared),
+                Err(e) => log::warn!("Failed to share the result of task {}: {}", manifest.id, e),
+            }
+        }
+
+        if let Some(requester) = &record.requester {
+            let stored = self
+                .synapse
+                .receipts()
+                .get(&manifest.id, &result.worker)?
+                .filter(|receipt| receipt.requester == *requester && receipt.result_hash == result.result_hash());
+            if let Some(receipt) = stored {
+                result.receipt = Some(receipt);
+                return Ok(result);
+            }
+            let started_at = record
+                .status_history
+                .iter()
+                .find(|change| change.status == TaskStatus::Running)
+                .map_or(manifest.submitted_at, |change| change.at);
+            let receipt = TaskReceipt::sign(
+                manifest.id.clone(),
+                manifest.content_id(),
+                result.result_hash(),
+                result.outputs.iter().map(StagedFile::from).collect(),
+                record.status,
+                ReceiptResources {
+                    used: record.result.as_ref().and_then(|result| result.usage.clone()).unwrap_or_default(),
+                    wall_time_secs: (finished_at - started_at).num_milliseconds().max(0) as f64 / 1000.0,
+                },
+                manifest.reward_tokens,
+                started_at,
+                finished_at,
+                requester.clone(),
+                &self.identity,
+            );
+            self.synapse.receipts().save(&receipt)?;
+            result.receipt = Some(receipt);
+        }
+        Ok(result)
+    }
+
+    /// Sends a progress update or result to a requester in the background
+    ///
+    /// Delivery is best effort: the requester polls for anything it missed.
+    fn send_report(&self, requester: PeerId, request: DispatchRequest) {
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            let timeout = dispatcher.config.transfer_timeout;
+            if let Err(e) = dispatcher.request(&requester, &request, timeout).await {
+                log::debug!("Failed to report to peer {}: {}", requester, e);
+            }
+        });
+    }
+
+    /// Withdraws a task from a worker in the background
+    fn send_cancel(&self, worker: PeerId, task_id: String) {
+        self.send_report(worker, DispatchRequest::Cancel { task_id });
+    }
+
+    async fn request(&self, to: &PeerId, request: &DispatchRequest, timeout: Duration) -> RpcResult<DispatchResponse> {
+        rpc::call(self.transport.as_ref(), to, DISPATCH_PROTOCOL, request, timeout).await
+    }
+
+    fn with_assignment<T>(&self, task_id: &str, f: impl FnOnce(&mut Assignment) -> T) -> SynapseResult<T> {
+        let mut assignments = self
+            .assignments
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        Ok(f(assignments.entry(task_id.to_string()).or_default()))
+    }
+
+    fn forget(&self, task_id: &str) {
+        if let Ok(mut assignments) = self.assignments.lock() {
+            assignments.remove(task_id);
+        }
+    }
+}
+
+impl RpcHandler for Dispatcher {
+    fn handle(&self, from: PeerId, payload: Vec<u8>) -> BoxFuture<'static, RpcResult<Vec<u8>>> {
+        let dispatcher = self.clone();
+        Box::pin(async move {
+            let request: DispatchRequest = rpc::decode(&payload)?;
+            let response = dispatcher
+                .handle_request(&from, request)
+                .unwrap_or_else(|e| DispatchResponse::Declined { reason: e.to_string() });
+            rpc::encode(&response)
+        })
+    }
+}
+
+/// Reads the regular files of a flat directory (missing means empty)
+///
+/// Files too large to send inline are refused before they are read.
+fn read_files(dir: &Path) -> SynapseResult<Vec<TaskFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let size = entry.metadata()?.len();
+        if size > MAX_INLINE_FILE_BYTES {
+            return Err(SynapseError::Dispatch(format!(
+                "{} has {} bytes, more than the {} sent with a task; reference it through Chronicle",
+                name, size, MAX_INLINE_FILE_BYTES
+            )));
+        }
+        files.push(TaskFile { name, data: fs::read(entry.path())? });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Writes received files into a flat directory
+fn write_files(dir: &Path, files: &[TaskFile]) -> SynapseResult<()> {
+    fs::create_dir_all(dir)?;
+    for file in files {
+        if !is_plain_file_name(&file.name) {
+            return Err(SynapseError::Dispatch(format!("invalid file name {:?}", file.name)));
+        }
+        fs::write(dir.join(&file.name), &file.data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_signature_covers_outputs() {
+        let identity = NodeIdentity::generate();
+        let outputs = vec![TaskFile { name: "out".to_string(), data: b"42".to_vec() }];
+        let result = RemoteResult::new("task".to_string(), Some(0), None, None, outputs, &identity);
+        assert!(result.verify());
+
+        let mut tampered = result.clone();
+        tampered.outputs[0].data = b"43".to_vec();
+        assert!(!tampered.verify());
+
+        let mut impostor = result.clone();
+        impostor.worker = NodeIdentity::generate().peer_id();
+        assert!(!impostor.verify());
+    }
+
+    #[test]
+    fn test_large_files_are_not_sent_inline() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small"), b"42").unwrap();
+        assert_eq!(read_files(dir.path()).unwrap().len(), 1);
+
+        // Sparse, so the test never holds the data
+        let large = fs::File::create(dir.path().join("large")).unwrap();
+        large.set_len(MAX_INLINE_FILE_BYTES + 1).unwrap();
+        assert!(matches!(read_files(dir.path()), Err(SynapseError::Dispatch(_))));
+    }
+
+    #[test]
+    fn test_faster_worker_wins_equal_bids() {
+        let bid = |compute_score| Bid { free_cpu_percent: 50.0, free_ram_gb: 4.0, queued_tasks: 0, compute_score };
+        assert!(bid(Some(2.0 * REFERENCE_SCORE)).score() > bid(None).score());
+        assert!(bid(None).score() > bid(Some(0.5 * REFERENCE_SCORE)).score());
+    }
+
+    #[cfg(unix)]
+    mod network {
+        use super::super::*;
+        use crate::chronicle::MemoryContentStore;
+        use crate::rpc::{LoopbackNetwork, RpcRouter};
+        use crate::synapse::manifest::{RetryPolicy, TaskRuntime};
+        use crate::synapse::scheduler::ResourceCapacity;
+        use crate::synapse::store::tests::manifest;
+        use crate::ui_api::{IdleSettings, RemoteTaskSettings, VerificationMethod};
+        use tempfile::TempDir;
+
+        struct Node {
+            synapse: Synapse,
+            peer_id: PeerId,
+            dir: TempDir,
+        }
+
+        fn start_node(network: &LoopbackNetwork, cpu_percent: f32) -> Node {
+            let dir = tempfile::tempdir().unwrap();
+            let (synapse, _) = Synapse::open(dir.path()).unwrap();
+            synapse
+                .set_capacity(ResourceCapacity { cpu_percent, ram_gb: 8.0, gpu_percent: 0.0 })
+                .unwrap();
+
+            let identity = Arc::new(NodeIdentity::generate());
+            let peer_id = identity.peer_id();
+            let router = Arc::new(RpcRouter::default());
+            let transport = network.join(peer_id.clone(), Arc::clone(&router));
+            let config = DispatchConfig {
+                bid_timeout: Duration::from_secs(1),
+                transfer_timeout: Duration::from_secs(5),
+                poll_interval: Duration::from_millis(100),
+                max_missed_polls: 2,
+                max_assignments: 3,
+            };
+            let dispatcher = Dispatcher::new(synapse.clone(), identity, transport, config);
+            router.register(DISPATCH_PROTOCOL, Arc::new(dispatcher.clone()));
+
+            synapse.set_idle_settings(IdleSettings { enabled: false, ..IdleSettings::default() }).unwrap();
+            synapse
+                .set_remote_task_settings(RemoteTaskSettings {
+                    enabled: true,
+                    allowed_programs: vec!["sh".to_string()],
+                    allowed_manifests: Vec::new(),
+                    share_results: true,
+                })
+                .unwrap();
+            tokio::spawn(synapse.clone().run());
+            tokio::spawn(dispatcher.run());
+            Node { synapse, peer_id, dir }
+        }
+
+        fn remote_task(id: &str, script: &str) -> TaskManifest {
+            let mut manifest = manifest(id);
+            manifest.placement = TaskPlacement::Remote;
+            manifest.runtime = TaskRuntime::Process {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                env: HashMap::new(),
+            };
+            manifest
+        }
+
+        async fn wait_for(mut condition: impl FnMut() -> bool) {
+            let waited = tokio::time::timeout(Duration::from_secs(30), async {
+                while !condition() {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await;
+            assert!(waited.is_ok(), "condition not reached in time");
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_task_runs_on_remote_peer() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let worker = start_node(&network, 80.0);
+
+            let script = r#"
+                n=$(cat "$MYCELIUM_INPUT_DIR/number")
+                echo "@mycelium progress 50"
+                echo $((n * 2)) > "$MYCELIUM_OUTPUT_DIR/doubled"
+            "#;
+            fs::create_dir_all(requester.synapse.input_dir("double")).unwrap();
+            fs::write(requester.synapse.input_dir("double").join("number"), "21").unwrap();
+            requester.synapse.submit(remote_task("double", script)).unwrap();
+
+            wait_for(|| requester.synapse.record("double").unwrap().status == TaskStatus::Completed).await;
+
+            let record = requester.synapse.record("double").unwrap();
+            let result = record.result.unwrap();
+            assert_eq!(result.worker, Some(worker.peer_id.clone()));
+            assert!(result.result_hash.is_some());
+            let doubled = fs::read_to_string(requester.synapse.output_dir("double").join("doubled")).unwrap();
+            assert_eq!(doubled.trim(), "42");
+
+            let worker_record = worker.synapse.record("double").unwrap();
+            assert_eq!(worker_record.requester, Some(requester.peer_id.clone()));
+            assert_eq!(worker_record.manifest.placement, TaskPlacement::Local);
+
+            // Both sides end up with the countersigned receipt
+            let receipts = requester.synapse.receipts().for_task("double").unwrap();
+            assert_eq!(receipts.len(), 1);
+            assert!(receipts[0].is_payable());
+            assert_eq!(receipts[0].result_hash, result.result_hash.unwrap());
+            // The receipt carries what the worker measured, not the reservation
+            let usage = worker_record.result.and_then(|result| result.usage).unwrap();
+            assert_eq!(receipts[0].resources.used, usage);
+            wait_for(|| {
+                worker.synapse.receipts().for_task("double").unwrap().iter().any(|r| r.verify())
+            })
+            .await;
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_unauthorised_assignment_is_rejected() {
+            let network = LoopbackNetwork::new();
+            let worker = start_node(&network, 80.0);
+            let requester = network.join("requester".to_string(), Arc::new(RpcRouter::default()));
+            let assign = |manifest: TaskManifest| {
+                let (requester, worker) = (Arc::clone(&requester), worker.peer_id.clone());
+                async move {
+                    let request = DispatchRequest::Assign { manifest, inputs: Vec::new(), checkpoint: None };
+                    let timeout = Duration::from_secs(5);
+                    rpc::call::<_, DispatchResponse>(requester.as_ref(), &worker, DISPATCH_PROTOCOL, &request, timeout)
+                        .await
+                        .unwrap()
+                }
+            };
+
+            let mut python = remote_task("python", "");
+            python.runtime = TaskRuntime::Process {
+                program: "python3".to_string(),
+                args: vec!["-c".to_string(), "print(42)".to_string()],
+                env: HashMap::new(),
+            };
+            assert!(matches!(assign(python.clone()).await, DispatchResponse::Declined { .. }));
+            let mut preloaded = remote_task("preloaded", "");
+            preloaded.runtime = TaskRuntime::Process {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), "true".to_string()],
+                env: HashMap::from([("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string())]),
+            };
+            assert!(matches!(assign(preloaded).await, DispatchResponse::Declined { .. }));
+
+            worker.synapse.set_remote_task_settings(RemoteTaskSettings::default()).unwrap();
+            assert!(matches!(assign(remote_task("shell", "true")).await, DispatchResponse::Declined { .. }));
+            for task_id in ["python", "preloaded", "shell"] {
+                assert!(worker.synapse.record(task_id).is_err());
+            }
+
+            // A manifest the user allowlisted runs even though its program is not allowed
+            let settings = RemoteTaskSettings {
+                enabled: true,
+                allowed_programs: Vec::new(),
+                allowed_manifests: vec![python.content_id().to_string()],
+                share_results: false,
+            };
+            worker.synapse.set_remote_task_settings(settings).unwrap();
+            assert!(matches!(assign(python).await, DispatchResponse::Accepted));
+            assert!(worker.synapse.record("python").is_ok());
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_task_reassigned_when_worker_disappears() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let preferred = start_node(&network, 90.0);
+            let fallback = start_node(&network, 60.0);
+
+            let script = r#"sleep 1; echo done > "$MYCELIUM_OUTPUT_DIR/out""#;
+            requester.synapse.submit(remote_task("fragile", script)).unwrap();
+
+            wait_for(|| !requester.synapse.record("fragile").unwrap().workers.is_empty()).await;
+            assert_eq!(requester.synapse.record("fragile").unwrap().workers, vec![preferred.peer_id.clone()]);
+            network.leave(&preferred.peer_id);
+
+            wait_for(|| requester.synapse.record("fragile").unwrap().status == TaskStatus::Completed).await;
+            let record = requester.synapse.record("fragile").unwrap();
+            assert_eq!(record.result.unwrap().worker, Some(fallback.peer_id.clone()));
+            assert!(record
+                .status_history
+                .iter()
+                .any(|change| change.reason.as_deref() == Some("Worker stopped responding")));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_reassigned_task_resumes_from_checkpoint() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let preferred = start_node(&network, 90.0);
+            let fallback = start_node(&network, 60.0);
+            // The peers reach each other's checkpoints through Chronicle
+            let chronicle = Arc::new(MemoryContentStore::default());
+            for node in [&requester, &preferred, &fallback] {
+                node.synapse.set_content_store(chronicle.clone()).unwrap();
+            }
+
+            // The first worker saves its state and then hangs; only a
+            // resumed run finishes.
+            let script = r#"
+                if [ -n "$MYCELIUM_RESUME_CHECKPOINT" ]; then
+                    cp "$MYCELIUM_RESUME_CHECKPOINT" "$MYCELIUM_OUTPUT_DIR/resumed"
+                    exit 0
+                fi
+                echo halfway > state
+                echo "@mycelium checkpoint state"
+                sleep 30
+            "#;
+            requester.synapse.submit(remote_task("handed", script)).unwrap();
+
+            wait_for(|| requester.synapse.record("handed").unwrap().checkpoint.is_some()).await;
+            assert_eq!(requester.synapse.record("handed").unwrap().workers, vec![preferred.peer_id.clone()]);
+            network.leave(&preferred.peer_id);
+
+            wait_for(|| requester.synapse.record("handed").unwrap().status == TaskStatus::Completed).await;
+            let record = requester.synapse.record("handed").unwrap();
+            assert_eq!(record.result.unwrap().worker, Some(fallback.peer_id.clone()));
+            let resumed = fs::read_to_string(requester.synapse.output_dir("handed").join("resumed")).unwrap();
+            assert_eq!(resumed.trim(), "halfway");
+            let accepted = fallback.synapse.record("handed").unwrap();
+            assert_eq!(accepted.requester, Some(requester.peer_id.clone()));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_trusted_worker_shares_cached_result() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let worker = start_node(&network, 80.0);
+            let chronicle = Arc::new(MemoryContentStore::default());
+            for node in [&requester, &worker] {
+                node.synapse.set_content_store(chronicle.clone()).unwrap();
+            }
+            for _ in 0..10 {
+                requester.synapse.reputation().record_agreement(&worker.peer_id).unwrap();
+            }
+
+            let mut manifest = remote_task("shared", r#"echo 42 > "$MYCELIUM_OUTPUT_DIR/answer""#);
+            manifest.cache = CacheMode::Shared;
+            requester.synapse.submit(manifest).unwrap();
+            wait_for(|| requester.synapse.record("shared").unwrap().status == TaskStatus::Completed).await;
+
+            let key = worker.synapse.record("shared").unwrap().result.and_then(|result| result.cache_key).unwrap();
+            let record = requester.synapse.record("shared").unwrap();
+            assert_eq!(record.result.and_then(|result| result.cache_key), Some(key.clone()));
+            let entry = requester.synapse.cache().lookup(&key).unwrap().unwrap();
+            assert_eq!(entry.outputs.len(), 1);
+            assert_eq!(entry.outputs[0].name, "answer");
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_failed_task_is_retried_on_another_peer() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let broken = start_node(&network, 90.0);
+            let healthy = start_node(&network, 60.0);
+            fs::write(broken.dir.path().join("broken"), "").unwrap();
+
+            let script = r#"
+                [ -f "$MYCELIUM_WORK_DIR/../../../broken" ] && exit 1
+                echo ok > "$MYCELIUM_OUTPUT_DIR/out"
+            "#;
+            let mut manifest = remote_task("retried", script);
+            manifest.retry = RetryPolicy { max_attempts: 2, backoff_secs: 0, ..RetryPolicy::default() };
+            requester.synapse.submit(manifest).unwrap();
+
+            wait_for(|| requester.synapse.record("retried").unwrap().status == TaskStatus::Completed).await;
+            let record = requester.synapse.record("retried").unwrap();
+            assert_eq!(record.failed_attempts.len(), 1);
+            assert_eq!(record.failed_attempts[0].kind, FailureKind::NonZeroExit);
+            assert_eq!(record.failed_attempts[0].workers, vec![broken.peer_id.clone()]);
+            assert_eq!(record.result.unwrap().worker, Some(healthy.peer_id.clone()));
+        }
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_consensus_outvotes_dissenting_worker() {
+            let network = LoopbackNetwork::new();
+            let requester = start_node(&network, 0.0);
+            let honest = [start_node(&network, 80.0), start_node(&network, 70.0)];
+            let cheater = start_node(&network, 90.0);
+            fs::write(cheater.dir.path().join("cheat"), "").unwrap();
+
+            // The work directory lives three levels below the node's data
+            // directory, where only the cheater has its marker file. The
+            // cheater answers first, so its result is part of the vote.
+            let script = r#"
+                if [ -f "$MYCELIUM_WORK_DIR/../../../cheat" ]; then
+                    echo 666 > "$MYCELIUM_OUTPUT_DIR/answer"
+                else
+                    sleep 1
+                    echo 42 > "$MYCELIUM_OUTPUT_DIR/answer"
+                fi
+            "#;
+            let mut manifest = remote_task("voted", script);
+            manifest.verification = VerificationMethod::Consensus;
+            requester.synapse.submit(manifest).unwrap();
+
+            wait_for(|| requester.synapse.record("voted").unwrap().status == TaskStatus::Completed).await;
+            let result = requester.synapse.record("voted").unwrap().result.unwrap();
+            let mut verified_by = result.verified_by.clone();
+            verified_by.sort();
+            let mut honest_peers: Vec<PeerId> = honest.iter().map(|node| node.peer_id.clone()).collect();
+            honest_peers.sort();
+            assert_eq!(verified_by, honest_peers);
+            let answer = fs::read_to_string(requester.synapse.output_dir("voted").join("answer")).unwrap();
+            assert_eq!(answer.trim(), "42");
+
+            let reputation = requester.synapse.reputation();
+            assert_eq!(reputation.get(&cheater.peer_id).disagreements, 1);
+            assert_eq!(reputation.get(&honest[0].peer_id).agreements, 1);
+        }
+    }
+}
@@ -0,0 +1,1314 @@
+//! Synapse engine handle shared between Tauri commands and background work
+
+use chrono::Utc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+
+use super::cache::{self, CacheMode, ResultCache, SharedResult};
+use super::checkpoint::{CheckpointHandoff, CheckpointStore};
+use super::executor::{Executor, ExecutorEvent};
+use super::idle::{HostActivity, IdleMonitor};
+use super::logs::TaskLogStore;
+use super::manifest::{TaskManifest, TaskPlacement};
+use super::progress::ProgressEstimator;
+use super::scheduler::{PlannedAction, ResourceCapacity, Scheduler};
+use super::staging::StagedFile;
+use super::store::{FailedAttempt, RecoveryReport, TaskRecord, TaskResult, TaskStore};
+use super::receipt::ReceiptStore;
+use super::verification::ReputationBook;
+use super::workflow::{self, StepAction, WorkflowManifest, WorkflowTask};
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::{ContentId, ContentStore};
+use crate::identity::{NodeIdentity, PeerId};
+use crate::ui_api::{
+    ActiveTask, DetailedResourceUsage, FailureKind, IdleSettings, RemoteTaskSettings, ResourceUsage,
+    SchedulingAction, SchedulingDecision, TaskDetails, TaskLogLine, TaskLogPage, TaskStatus, WorkflowProgress,
+};
+
+/// Interval between scheduling passes
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Silence after which a task that reported progress is flagged as stalled
+const STALL_TIMEOUT_MINUTES: i64 = 5;
+
+/// Capacity used until the Covenant permission settings are applied
+const DEFAULT_CAPACITY: ResourceCapacity = ResourceCapacity {
+    cpu_percent: 50.0,
+    ram_gb: 4.0,
+    gpu_percent: 0.0,
+};
+
+/// Size limit of the result cache
+const DEFAULT_CACHE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Number of task events buffered for slow subscribers
+const EVENT_BUFFER: usize = 256;
+
+/// Task lifecycle event published by the engine
+#[derive(Debug, Clone)]
+pub enum TaskEvent {
+    /// A task reported progress (0.0 - 100.0)
+    Progress { task_id: String, progress: f32 },
+    /// A task running on this node saved a checkpoint
+    Checkpointed { task_id: String },
+    /// A task reached a final status
+    Finished { task_id: String, status: TaskStatus },
+    /// A task wrote a line of output
+    Log(TaskLogLine),
+}
+
+/// Result of a finished task whose outputs are being stored in Chronicle
+enum Finishing {
+    /// The task process exited on this node
+    Exited(TaskResult),
+    /// The worker of the remote task returned it
+    Remote(TaskResult),
+    /// The task was served from the result cache
+    Cached(TaskResult),
+}
+
+/// Cheaply clonable handle to the Synapse task engine
+#[derive(Clone)]
+pub struct Synapse {
+    store: Arc<Mutex<TaskStore>>,
+    scheduler: Scheduler,
+    executor: Executor,
+    executor_events: Arc<Mutex<Option<mpsc::UnboundedReceiver<ExecutorEvent>>>>,
+    capacity: Arc<Mutex<ResourceCapacity>>,
+    compute_score: Arc<Mutex<Option<f64>>>,
+    idle: Arc<Mutex<IdleMonitor>>,
+    remote_tasks: Arc<Mutex<RemoteTaskSettings>>,
+    progress: Arc<Mutex<HashMap<String, ProgressEstimator>>>,
+    events: broadcast::Sender<TaskEvent>,
+    reputation: Arc<ReputationBook>,
+    receipts: Arc<ReceiptStore>,
+    cache: Arc<ResultCache>,
+    /// Cache keys of cacheable tasks that missed the cache, by task ID
+    cache_keys: Arc<Mutex<HashMap<String, ContentId>>>,
+    /// Finished tasks waiting for their outputs to be stored, by task ID
+    finishing: Arc<Mutex<HashMap<String, Finishing>>>,
+}
+
+impl Synapse {
+    /// Opens the task store under `data_dir` and reconciles tasks left
+    /// behind by a previous run
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or recovered
+    pub fn open(data_dir: &Path) -> SynapseResult<(Self, RecoveryReport)> {
+        let synapse_dir = data_dir.join("synapse");
+        let mut store = TaskStore::open(&synapse_dir)?;
+        let report = store.recover()?;
+
+        let checkpoints = Arc::new(CheckpointStore::open(synapse_dir.join("checkpoints"))?);
+        let logs = Arc::new(TaskLogStore::open(synapse_dir.join("logs"))?);
+        let (executor, executor_events) = Executor::new(synapse_dir.join("work"), checkpoints, logs)?;
+        let reputation = Arc::new(ReputationBook::open(synapse_dir.join("reputation.json"))?);
+        let receipts = Arc::new(ReceiptStore::open(synapse_dir.join("receipts"))?);
+        let cache = Arc::new(ResultCache::open(synapse_dir.join("cache"), DEFAULT_CACHE_BYTES)?);
+
+        let synapse = Self {
+            store: Arc::new(Mutex::new(store)),
+            scheduler: Scheduler::default(),
+            executor,
+            executor_events: Arc::new(Mutex::new(Some(executor_events))),
+            capacity: Arc::new(Mutex::new(DEFAULT_CAPACITY)),
+            compute_score: Arc::new(Mutex::new(None)),
+            idle: Arc::new(Mutex::new(IdleMonitor::default())),
+            remote_tasks: Arc::new(Mutex::new(RemoteTaskSettings::default())),
+            progress: Arc::new(Mutex::new(HashMap::new())),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            reputation,
+            receipts,
+            cache,
+            cache_keys: Arc::new(Mutex::new(HashMap::new())),
+            finishing: Arc::new(Mutex::new(HashMap::new())),
+        };
+        Ok((synapse, report))
+    }
+
+    /// Runs the engine: schedules queued tasks and tracks running ones
+    ///
+    /// Only the first call does any work; the future completes when the
+    /// executor shuts down.
+    pub async fn run(self) {
+        let events = self.executor_events.lock().ok().and_then(|mut events| events.take());
+        let Some(mut events) = events else {
+            log::warn!("Synapse engine is already running");
+            return;
+        };
+
+        let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = self.tick() {
+                        log::error!("Synapse scheduling pass failed: {}", e);
+                    }
+                }
+                event = events.recv() => {
+                    let Some(event) = event else { break };
+                    if let Err(e) = self.handle_event(event) {
+                        log::error!("Failed to handle executor event: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets the resources the node is willing to give to tasks
+    pub fn set_capacity(&self, capacity: ResourceCapacity) -> SynapseResult<()> {
+        let mut current = self
+            .capacity
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        *current = capacity;
+        Ok(())
+    }
+
+    /// Sets the benchmarked compute score of the node, advertised to peers
+    /// bidding for tasks
+    pub fn set_compute_score(&self, score: f64) -> SynapseResult<()> {
+        let mut current = self
+            .compute_score
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        *current = Some(score);
+        Ok(())
+    }
+
+    /// Gets the benchmarked compute score of the node (none until the
+    /// benchmark ran)
+    pub fn compute_score(&self) -> Option<f64> {
+        self.compute_score.lock().ok().and_then(|score| *score)
+    }
+
+    /// Sets the host load at which tasks are throttled or paused
+    pub fn set_idle_settings(&self, settings: IdleSettings) -> SynapseResult<()> {
+        self.idle
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?
+            .set_settings(settings);
+        Ok(())
+    }
+
+    /// Sets which tasks peers may run on this node
+    pub fn set_remote_task_settings(&self, settings: RemoteTaskSettings) -> SynapseResult<()> {
+        let mut current = self
+            .remote_tasks
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        *current = settings;
+        Ok(())
+    }
+
+    /// Gets which tasks peers may run on this node (none by default)
+    pub fn remote_task_settings(&self) -> SynapseResult<RemoteTaskSettings> {
+        self.remote_tasks
+            .lock()
+            .map(|settings| settings.clone())
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+
+    /// Sets the Chronicle store task inputs are fetched from and outputs
+    /// are stored in
+    pub fn set_content_store(&self, store: Arc<dyn ContentStore>) -> SynapseResult<()> {
+        self.executor.set_content_store(store)
+    }
+
+    /// Subscribes to task lifecycle events
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
+    }
+
+    /// Gets the reputation of the peers that ran tasks or hold shards for
+    /// this node
+    pub fn reputation(&self) -> &Arc<ReputationBook> {
+        &self.reputation
+    }
+
+    /// Gets the receipts of tasks run for or by peers
+    pub fn receipts(&self) -> &ReceiptStore {
+        &self.receipts
+    }
+
+    /// Gets the cache of task results
+    pub fn cache(&self) -> &ResultCache {
+        &self.cache
+    }
+
+    /// Gets the resources not taken by running local tasks
+    pub fn free_capacity(&self) -> SynapseResult<ResourceCapacity> {
+        let capacity = *self
+            .capacity
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        Ok(capacity.remaining(self.store()?.records()))
+    }
+
+    /// Gets the number of local tasks waiting in the queue
+    pub fn queued_tasks(&self) -> SynapseResult<usize> {
+        Ok(self
+            .store()?
+            .records()
+            .filter(|r| r.status == TaskStatus::Pending && r.manifest.placement == TaskPlacement::Local)
+            .count())
+    }
+
+    /// Submits a new task to the queue
+    pub fn submit(&self, manifest: TaskManifest) -> SynapseResult<()> {
+        log::info!("Submitting task {} ({})", manifest.id, manifest.name);
+        let task_id = manifest.id.clone();
+        self.store()?.submit(manifest)?;
+        self.serve_from_cache(&task_id)
+    }
+
+    /// Queues a task dispatched to this node by a remote peer
+    pub fn submit_for(&self, manifest: TaskManifest, requester: PeerId) -> SynapseResult<()> {
+        log::info!("Accepting task {} ({}) from peer {}", manifest.id, manifest.name, requester);
+        let task_id = manifest.id.clone();
+        self.store()?.submit_for(manifest, Some(requester))?;
+        self.serve_from_cache(&task_id)
+    }
+
+    /// Submits a workflow; its tasks are queued as their dependencies
+    /// complete
+    pub fn submit_workflow(&self, workflow: WorkflowManifest) -> SynapseResult<()> {
+        log::info!("Submitting workflow {} ({}) with {} tasks", workflow.id, workflow.name, workflow.tasks.len());
+        self.store()?.submit_workflow(workflow)?;
+        self.advance_workflows()
+    }
+
+    /// Gets a page of the output captured from a task, starting at line
+    /// `offset`
+    pub fn task_logs(&self, task_id: &str, offset: u64) -> SynapseResult<TaskLogPage> {
+        if self.store()?.get(task_id).is_none() {
+            return Err(SynapseError::TaskNotFound(task_id.to_string()));
+        }
+        self.executor.logs().page(task_id, offset)
+    }
+
+    /// Gets the progress of a workflow and its tasks
+    pub fn workflow_progress(&self, workflow_id: &str) -> SynapseResult<WorkflowProgress> {
+        let store = self.store()?;
+        let workflow = store
+            .workflow(workflow_id)
+            .ok_or_else(|| SynapseError::WorkflowNotFound(workflow_id.to_string()))?;
+        Ok(workflow::progress(workflow, &store))
+    }
+
+    /// Gets a copy of a task record
+    pub fn record(&self, task_id: &str) -> SynapseResult<TaskRecord> {
+        self.store()?
+            .get(task_id)
+            .cloned()
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))
+    }
+
+    /// Gets the unfinished tasks placed on remote peers
+    pub fn remote_tasks(&self) -> SynapseResult<Vec<TaskRecord>> {
+        let store = self.store()?;
+        let finishing = self.finishing()?;
+        let mut records: Vec<TaskRecord> = store
+            .records()
+            .filter(|r| r.manifest.placement == TaskPlacement::Remote)
+            .filter(|r| !finishing.contains_key(&r.manifest.id))
+            .filter(|r| matches!(r.status, TaskStatus::Pending | TaskStatus::Running | TaskStatus::Paused))
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| r.manifest.submitted_at);
+        Ok(records)
+    }
+
+    /// Gets the directory holding the input files of a task
+    pub fn input_dir(&self, task_id: &str) -> PathBuf {
+        self.executor.input_dir(task_id)
+    }
+
+    /// Gets the directory receiving the output files of a task
+    pub fn output_dir(&self, task_id: &str) -> PathBuf {
+        self.executor.output_dir(task_id)
+    }
+
+    /// Marks a remote task as running on `worker` (in addition to any
+    /// workers already running replicas of it)
+    pub fn assign_worker(&self, task_id: &str, worker: &PeerId) -> SynapseResult<()> {
+        let mut store = self.store()?;
+        store.add_worker(task_id, worker.clone())?;
+        let Some(record) = store.get(task_id) else {
+            return Ok(());
+        };
+        if record.status != TaskStatus::Pending {
+            return Ok(());
+        }
+        let progress = record.progress;
+        store.set_status(task_id, TaskStatus::Running, Some(format!("Assigned to peer {}", worker)))?;
+        drop(store);
+        self.estimators()?.insert(task_id.to_string(), ProgressEstimator::new(progress, Utc::now()));
+        Ok(())
+    }
+
+    /// Takes a remote task away from one of its workers
+    ///
+    /// A task left without workers goes back into the dispatch queue.
+    pub fn release_worker(&self, task_id: &str, worker: &PeerId, reason: &str) -> SynapseResult<()> {
+        let mut store = self.store()?;
+        store.remove_worker(task_id, worker.clone())?;
+        let Some(record) = store.get(task_id) else {
+            return Ok(());
+        };
+        if !record.workers.is_empty() || record.status != TaskStatus::Running {
+            return Ok(());
+        }
+        store.set_status(task_id, TaskStatus::Pending, Some(reason.to_string()))?;
+        drop(store);
+        self.estimators()?.remove(task_id);
+        Ok(())
+    }
+
+    /// Records progress reported by a worker of a remote task
+    ///
+    /// With several workers running replicas, the task is as far along
+    /// as the most advanced one.
+    pub fn record_remote_progress(&self, task_id: &str, progress: f32) -> SynapseResult<()> {
+        let mut store = self.store()?;
+        if store.get(task_id).is_some_and(|r| progress <= r.progress) {
+            return Ok(());
+        }
+        store.set_progress(task_id, progress)?;
+        drop(store);
+        if let Some(estimator) = self.estimators()?.get_mut(task_id) {
+            estimator.record(progress, Utc::now());
+        }
+        let _ = self.events.send(TaskEvent::Progress { task_id: task_id.to_string(), progress });
+        Ok(())
+    }
+
+    /// Stores the result returned by the worker of a remote task
+    ///
+    /// A completed task is recorded as such once its outputs are stored in
+    /// Chronicle. A failed task is queued again if its retry policy allows
+    /// it.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn finish_remote(&self, task_id: &str, status: TaskStatus, result: TaskResult) -> SynapseResult<()> {
+        match status {
+            TaskStatus::Completed => return self.store_outputs(task_id, Finishing::Remote(result)),
+            TaskStatus::Failed => {
+                let final_status = self.fail(task_id, result)?;
+                return self.finish_local(task_id, final_status);
+            }
+            _ => {}
+        }
+        self.estimators()?.remove(task_id);
+        self.store()?.finish(task_id, status, result)?;
+        let _ = self.events.send(TaskEvent::Finished { task_id: task_id.to_string(), status });
+        Ok(())
+    }
+
+    /// Gets all tasks that have not finished yet
+    pub fn active_tasks(&self) -> SynapseResult<Vec<ActiveTask>> {
+        let store = self.store()?;
+        let mut records: Vec<&TaskRecord> = store
+            .records()
+            .filter(|r| matches!(r.status, TaskStatus::Pending | TaskStatus::Running | TaskStatus::Paused))
+            .collect();
+        records.sort_by_key(|r| r.manifest.submitted_at);
+        Ok(records.into_iter().map(|r| self.live_task(r)).collect())
+    }
+
+    /// Gets detailed information about a task
+    pub fn task_details(&self, task_id: &str) -> SynapseResult<TaskDetails> {
+        let store = self.store()?;
+        let record = store
+            .get(task_id)
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))?;
+        let manifest = &record.manifest;
+        let measured_usage = self
+            .executor
+            .usage(task_id)
+            .or_else(|| record.result.as_ref().and_then(|result| result.usage.clone()));
+
+        Ok(TaskDetails {
+            task: self.live_task(record),
+            task_type: manifest.task_type.clone(),
+            model: manifest.model.clone(),
+            data_size_gb: manifest.data_size_gb,
+            complexity: manifest.complexity.clone(),
+            verification: manifest.verification.clone(),
+            security: manifest.security.clone(),
+            detailed_resource_usage: measured_usage.as_ref().map_or(
+                DetailedResourceUsage {
+                    cpu_percent: manifest.resources.cpu_percent,
+                    ram_gb: manifest.resources.ram_gb,
+                    gpu_percent: manifest.resources.gpu_percent,
+                    gpu_memory_gb: 0.0,
+                },
+                DetailedResourceUsage::from,
+            ),
+            scheduling_decisions: record.scheduling_decisions.clone(),
+            failure_kind: record.failure_kind(),
+            measured_usage,
+        })
+    }
+
+    /// Runs one scheduling pass against the given free capacity
+    ///
+    /// Every decision is recorded with its task. Started tasks are moved
+    /// to `Running` and preempted ones to `Paused`; those actions are
+    /// returned so that the caller can start or pause the actual work.
+    pub fn schedule(&self, capacity: ResourceCapacity) -> SynapseResult<Vec<PlannedAction>> {
+        let mut store = self.store()?;
+        let finishing = self.finishing()?;
+        let records = store.records().filter(|r| !finishing.contains_key(&r.manifest.id));
+        let actions = self.scheduler.plan(records, capacity, Utc::now());
+        drop(finishing);
+
+        let mut applied = Vec::new();
+        for action in actions {
+            let status = match action.decision.action {
+                SchedulingAction::Started => Some(TaskStatus::Running),
+                SchedulingAction::Preempted => Some(TaskStatus::Paused),
+                SchedulingAction::Deferred => None,
+            };
+            store.record_decision(&action.task_id, action.decision.clone())?;
+            if let Some(status) = status {
+                log::info!("Scheduler: {} -> {:?} ({})", action.task_id, status, action.decision.reason);
+                store.set_status(&action.task_id, status, Some(action.decision.reason.clone()))?;
+                applied.push(action);
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Pauses a running task
+    pub fn pause(&self, task_id: &str) -> SynapseResult<()> {
+        self.transition(task_id, &[TaskStatus::Running], TaskStatus::Paused, "Paused by user")?;
+        self.executor.suspend(task_id)
+    }
+
+    /// Resumes a paused task
+    ///
+    /// The task goes back to the queue; the scheduler continues its
+    /// suspended process or restarts it from the latest checkpoint.
+    pub fn resume(&self, task_id: &str) -> SynapseResult<()> {
+        self.transition(task_id, &[TaskStatus::Paused], TaskStatus::Pending, "Resumed by user")
+    }
+
+    /// Cancels a task that has not finished yet
+    pub fn cancel(&self, task_id: &str) -> SynapseResult<()> {
+        self.transition(
+            task_id,
+            &[TaskStatus::Pending, TaskStatus::Running, TaskStatus::Paused],
+            TaskStatus::Cancelled,
+            "Cancelled by user",
+        )?;
+        self.executor.stop(task_id)?;
+        self.cache_keys()?.remove(task_id);
+        let _ = self.events.send(TaskEvent::Finished {
+            task_id: task_id.to_string(),
+            status: TaskStatus::Cancelled,
+        });
+        self.executor.checkpoints().remove_task(task_id)
+    }
+
+    /// Publishes the latest checkpoint of a task to Chronicle so that
+    /// another peer can continue the task
+    pub fn handoff_checkpoint(&self, task_id: &str) -> SynapseResult<CheckpointHandoff> {
+        let checkpoint = self
+            .store()?
+            .get(task_id)
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))?
+            .checkpoint
+            .clone()
+            .ok_or_else(|| SynapseError::Execution(format!("task {} has no checkpoint", task_id)))?;
+        self.executor.checkpoints().handoff(task_id, &checkpoint, self.executor.content_store()?.as_ref())
+    }
+
+    /// Keeps a checkpoint the worker of a remote task published in
+    /// Chronicle, so that the task resumes from it if it moves to another
+    /// worker
+    ///
+    /// A checkpoint older than the one kept is ignored.
+    pub fn record_remote_checkpoint(&self, handoff: &CheckpointHandoff) -> SynapseResult<()> {
+        let record = self.record(&handoff.task_id)?;
+        if record.checkpoint.is_some_and(|kept| kept.sequence >= handoff.sequence) {
+            return Ok(());
+        }
+        let content = self.executor.content_store()?;
+        let checkpoint = self.executor.checkpoints().accept_handoff(handoff, content.as_ref())?;
+        log::info!("Task {} has a new checkpoint #{} from its worker", handoff.task_id, handoff.sequence);
+        self.store()?.record_checkpoint(&handoff.task_id, checkpoint)
+    }
+
+    /// Publishes the cached result of a completed task to Chronicle so
+    /// that peers running the same task can use it
+    ///
+    /// Only tasks submitted with [`CacheMode::Shared`] are published. The
+    /// result is signed with `identity`.
+    pub fn share_cached_result(&self, task_id: &str, identity: &NodeIdentity) -> SynapseResult<SharedResult> {
+        let record = self.record(task_id)?;
+        if record.manifest.cache != CacheMode::Shared {
+            return Err(SynapseError::Execution(format!("task {} does not share its result", task_id)));
+        }
+        let key = record
+            .result
+            .and_then(|result| result.cache_key)
+            .ok_or_else(|| SynapseError::Execution(format!("task {} has no cached result", task_id)))?;
+        self.cache.publish(&key, self.executor.content_store()?.as_ref(), identity)
+    }
+
+    /// Adds the result a worker shared for remote task `task_id` to the
+    /// result cache, in place of the outputs it returned
+    ///
+    /// Any peer could file made-up outputs under the key of someone else's
+    /// task, so only results filed under the task's own key and signed by
+    /// peers whose results were trusted in verification are accepted.
+    pub fn import_shared_result(&self, task_id: &str, shared: &SharedResult) -> SynapseResult<()> {
+        if self.record(task_id)?.manifest.cache != CacheMode::Shared {
+            return Err(SynapseError::Execution(format!("task {} does not share its result", task_id)));
+        }
+        if self.cache_keys()?.get(task_id) != Some(&shared.key) {
+            return Err(SynapseError::Execution(format!(
+                "shared result {} is not the result of task {}", shared.key, task_id
+            )));
+        }
+        if !self.reputation.get(&shared.publisher).is_trusted() {
+            return Err(SynapseError::Execution(format!(
+                "peer {} is not trusted to share results", shared.publisher
+            )));
+        }
+        log::info!("Importing shared result {} from peer {}", shared.key, shared.publisher);
+        self.cache.import(shared, self.executor.content_store()?.as_ref())?;
+        self.cache_keys()?.remove(task_id);
+        Ok(())
+    }
+
+    /// Queues a task handed over by `requester`, resuming it from the
+    /// checkpoint published in Chronicle
+    ///
+    /// The checkpoint is only written once the manifest is known to be
+    /// valid and new, so that a handoff cannot touch another task's
+    /// checkpoints.
+    pub fn accept_handoff(
+        &self,
+        manifest: TaskManifest,
+        handoff: &CheckpointHandoff,
+        requester: PeerId,
+    ) -> SynapseResult<()> {
+        if handoff.task_id != manifest.id {
+            return Err(SynapseError::InvalidManifest(format!(
+                "checkpoint belongs to task {}, not {}", handoff.task_id, manifest.id
+            )));
+        }
+        log::info!(
+            "Accepting task {} ({}) from peer {} at checkpoint #{}",
+            manifest.id, manifest.name, requester, handoff.sequence
+        );
+        let mut store = self.store()?;
+        store.check_new(&manifest)?;
+        let content = self.executor.content_store()?;
+        let checkpoint = self.executor.checkpoints().accept_handoff(handoff, content.as_ref())?;
+        store.submit_for(manifest, Some(requester))?;
+        store.set_progress(&handoff.task_id, checkpoint.progress)?;
+        store.record_checkpoint(&handoff.task_id, checkpoint)
+    }
+
+    /// Fails overdue tasks and queues workflow tasks that became ready,
+    /// then runs one scheduling pass and starts or suspends the affected
+    /// tasks
+    ///
+    /// While the host is busy with other work no task is started; running
+    /// tasks are throttled or paused instead.
+    fn tick(&self) -> SynapseResult<()> {
+        let capacity = *self
+            .capacity
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+
+        let load = self.executor.sample_usage()?;
+        let activity = self
+            .idle
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?
+            .update(&load, Instant::now())
+            .clone();
+        self.enforce_deadlines()?;
+        self.advance_workflows()?;
+        match activity {
+            HostActivity::Idle => self.restore_priorities(),
+            HostActivity::Busy(reason) => return self.give_way(&reason, false),
+            HostActivity::Overloaded(reason) => return self.give_way(&reason, true),
+        }
+        for action in self.schedule(capacity)? {
+            match action.decision.action {
+                SchedulingAction::Started => {
+                    let record = self.store()?.get(&action.task_id).cloned();
+                    let Some(record) = record else { continue };
+                    // Rates measured before a pause or restart say nothing
+                    // about the new run.
+                    self.estimators()?.insert(
+                        record.manifest.id.clone(),
+                        ProgressEstimator::new(record.progress, Utc::now()),
+                    );
+                    if let Err(e) = self.executor.start(&record) {
+                        log::error!("Failed to start task {}: {}", action.task_id, e);
+                        self.fail(&action.task_id, TaskResult::failed(FailureKind::Other, e.to_string()))?;
+                    }
+                }
+                SchedulingAction::Preempted => self.executor.suspend(&action.task_id)?,
+                SchedulingAction::Deferred => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Throttles the tasks running on this node, or pauses them if `pause`
+    /// is set, because the host is busy for `reason`
+    ///
+    /// Paused tasks are recorded as preempted, so the scheduler resumes
+    /// them once the host is idle again.
+    fn give_way(&self, reason: &str, pause: bool) -> SynapseResult<()> {
+        let now = Utc::now();
+        let running: Vec<TaskRecord> = self
+            .store()?
+            .records()
+            .filter(|r| r.status == TaskStatus::Running && self.executor.is_running(&r.manifest.id))
+            .cloned()
+            .collect();
+
+        for record in running {
+            let task_id = &record.manifest.id;
+            if !pause {
+                self.executor.throttle(task_id)?;
+                continue;
+            }
+            let reason = format!("Host is busy: {}", reason);
+            log::info!("Pausing task {} ({})", task_id, reason);
+            let mut store = self.store()?;
+            store.record_decision(task_id, SchedulingDecision {
+                timestamp: now,
+                action: SchedulingAction::Preempted,
+                effective_priority: self.scheduler.effective_priority(&record, now),
+                related_task_id: None,
+                reason: reason.clone(),
+            })?;
+            store.set_status(task_id, TaskStatus::Paused, Some(reason))?;
+            drop(store);
+            self.executor.suspend(task_id)?;
+        }
+        Ok(())
+    }
+
+    /// Gives throttled tasks their normal CPU priority back once the host
+    /// is idle
+    fn restore_priorities(&self) {
+        for task_id in self.executor.throttled() {
+            if let Err(e) = self.executor.unthrottle(&task_id) {
+                log::warn!("Task {} keeps the lowest priority: {}", task_id, e);
+            }
+        }
+    }
+
+    /// Queues workflow tasks whose dependencies have completed and skips
+    /// those whose dependencies failed or were cancelled
+    ///
+    /// Skipping a task can unblock (and skip) its own dependents, so this
+    /// repeats until no task changes.
+    fn advance_workflows(&self) -> SynapseResult<()> {
+        loop {
+            let steps = workflow::next_steps(&*self.store()?);
+            if steps.is_empty() {
+                return Ok(());
+            }
+            for step in steps {
+                let task_id = step.task.manifest.id.clone();
+                let skip = match step.action {
+                    StepAction::Submit => self.stage_inputs(&step.task).err().map(|e| (TaskStatus::Failed, e.to_string())),
+                    StepAction::Skip { status, reason } => Some((status, reason)),
+                };
+
+                let mut store = self.store()?;
+                store.submit_workflow_task(step.task.manifest)?;
+                let Some((status, reason)) = skip else {
+                    drop(store);
+                    log::info!("Workflow task {} is ready", task_id);
+                    self.serve_from_cache(&task_id)?;
+                    continue;
+                };
+                log::info!("Skipping workflow task {}: {}", task_id, reason);
+                let mut result = TaskResult::failed(FailureKind::DependencyFailed, reason);
+                result.failure = result.failure.filter(|_| status == TaskStatus::Failed);
+                store.finish(&task_id, status, result)?;
+                drop(store);
+                let _ = self.events.send(TaskEvent::Finished { task_id, status });
+            }
+        }
+    }
+
+    /// Stops and fails the unfinished tasks whose deadline has passed
+    fn enforce_deadlines(&self) -> SynapseResult<()> {
+        let now = Utc::now();
+        let overdue: Vec<String> = self
+            .store()?
+            .records()
+            .filter(|r| matches!(r.status, TaskStatus::Pending | TaskStatus::Running | TaskStatus::Paused))
+            .filter(|r| r.manifest.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|r| r.manifest.id.clone())
+            .collect();
+
+        for task_id in overdue {
+            log::warn!("Task {} missed its deadline", task_id);
+            self.executor.stop(&task_id)?;
+            self.estimators()?.remove(&task_id);
+            self.store()?.finish(&task_id, TaskStatus::Failed, TaskResult::failed(
+                FailureKind::Timeout,
+                "Task did not finish before its deadline".to_string(),
+            ))?;
+            let _ = self.events.send(TaskEvent::Finished { task_id: task_id.clone(), status: TaskStatus::Failed });
+            self.executor.checkpoints().remove_task(&task_id)?;
+        }
+        Ok(())
+    }
+
+    /// Ends a failed attempt of a task: queues it again if its retry
+    /// policy allows another attempt, otherwise stores the result and marks
+    /// the task as failed
+    ///
+    /// Tasks run on behalf of other peers are never retried here; their
+    /// requester decides. Returns the status the task moved to.
+    fn fail(&self, task_id: &str, result: TaskResult) -> SynapseResult<TaskStatus> {
+        self.estimators()?.remove(task_id);
+        let mut store = self.store()?;
+        let record = store
+            .get(task_id)
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))?;
+        let kind = result.failure.unwrap_or(FailureKind::Other);
+        let policy = &record.manifest.retry;
+        let attempt = record.attempt();
+        let retry_at = Utc::now() + chrono::Duration::from_std(policy.backoff(attempt + 1)).unwrap_or_default();
+
+        let retry = record.requester.is_none()
+            && attempt < policy.max_attempts
+            && policy.retries(kind)
+            && record.manifest.deadline.is_none_or(|deadline| retry_at < deadline);
+        if retry {
+            log::info!("Task {} failed on attempt {} ({:?}); retrying at {}", task_id, attempt, kind, retry_at);
+            let failed = FailedAttempt {
+                kind,
+                error: result.error,
+                workers: record.workers.clone(),
+                failed_at: result.finished_at,
+            };
+            store.schedule_retry(task_id, failed, retry_at)?;
+            return Ok(TaskStatus::Pending);
+        }
+
+        store.finish(task_id, TaskStatus::Failed, result)?;
+        drop(store);
+        self.cache_keys()?.remove(task_id);
+        let _ = self.events.send(TaskEvent::Finished { task_id: task_id.to_string(), status: TaskStatus::Failed });
+        Ok(TaskStatus::Failed)
+    }
+
+    /// Completes a newly queued task from the result cache if it opted
+    /// into caching and an identical task ran before
+    ///
+    /// On a hit the task completes once the cached outputs are stored in
+    /// Chronicle. On a miss the cache key is kept so that the result can be cached
+    /// when the task completes. Cache errors never fail the task; it just
+    /// runs.
+    fn serve_from_cache(&self, task_id: &str) -> SynapseResult<()> {
+        let record = self.record(task_id)?;
+        if record.manifest.cache == CacheMode::Disabled || record.status != TaskStatus::Pending {
+            return Ok(());
+        }
+        let key = match cache::cache_key(&record.manifest, &self.input_dir(task_id)) {
+            Ok(key) => key,
+            Err(e) => {
+                log::warn!("Cannot compute cache key of task {}: {}", task_id, e);
+                return Ok(());
+            }
+        };
+        let hit = self.cache.lookup(&key).and_then(|entry| match entry {
+            Some(entry) => self.cache.restore(&entry, &self.output_dir(task_id)).map(|_| Some(entry)),
+            None => Ok(None),
+        });
+        let entry = match hit {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                self.cache_keys()?.insert(task_id.to_string(), key);
+                return Ok(());
+            }
+            Err(e) => {
+                log::warn!("Cannot serve task {} from the result cache: {}", task_id, e);
+                self.cache_keys()?.insert(task_id.to_string(), key);
+                return Ok(());
+            }
+        };
+
+        log::info!("Task {} hit the result cache ({})", task_id, key);
+        self.store_outputs(task_id, Finishing::Cached(TaskResult {
+            exit_code: entry.exit_code,
+            error: None,
+            finished_at: Utc::now(),
+            result_hash: None,
+            worker: None,
+            verified_by: Vec::new(),
+            failure: None,
+            cache_key: Some(key),
+            usage: None,
+            outputs: Vec::new(),
+        }))
+    }
+
+    /// Stores the outputs of a finished task in Chronicle in the background
+    ///
+    /// The task keeps its status, and is left alone by the scheduler and
+    /// the dispatcher, until the executor reports the upload.
+    fn store_outputs(&self, task_id: &str, finishing: Finishing) -> SynapseResult<()> {
+        self.finishing()?.insert(task_id.to_string(), finishing);
+        self.executor.upload_outputs(task_id);
+        Ok(())
+    }
+
+    /// Finishes a task once its outputs are stored in Chronicle, or fails
+    /// it if they could not be
+    ///
+    /// A task cancelled or timed out during the upload stays as it is. A
+    /// task served from the result cache whose outputs could not be stored
+    /// runs instead.
+    fn finish_upload(&self, task_id: &str, outputs: Result<Vec<StagedFile>, String>) -> SynapseResult<()> {
+        let Some(finishing) = self.finishing()?.remove(task_id) else {
+            return Ok(());
+        };
+        let status = self.store()?.get(task_id).map(|r| r.status);
+        match finishing {
+            Finishing::Exited(mut result) if status == Some(TaskStatus::Running) => {
+                let final_status = match outputs {
+                    Ok(outputs) => {
+                        result.outputs = outputs;
+                        self.complete(task_id, result)?;
+                        TaskStatus::Completed
+                    }
+                    Err(e) => {
+                        result.error = Some(format!("Failed to store outputs in Chronicle: {}", e));
+                        result.failure = Some(FailureKind::Other);
+                        self.fail(task_id, result)?
+                    }
+                };
+                self.finish_local(task_id, final_status)
+            }
+            Finishing::Remote(mut result) if status == Some(TaskStatus::Running) => {
+                let final_status = match outputs {
+                    Ok(outputs) => {
+                        result.outputs = outputs;
+                        self.estimators()?.remove(task_id);
+                        self.complete(task_id, result)?;
+                        TaskStatus::Completed
+                    }
+                    Err(e) => {
+                        let error = format!("Failed to store outputs in Chronicle: {}", e);
+                        self.fail(task_id, TaskResult::failed(FailureKind::Other, error))?
+                    }
+                };
+                self.finish_local(task_id, final_status)
+            }
+            Finishing::Cached(mut result) if status == Some(TaskStatus::Pending) => match outputs {
+                Ok(outputs) => {
+                    log::info!("Task {} completed from the result cache", task_id);
+                    result.outputs = outputs;
+                    self.store()?.finish(task_id, TaskStatus::Completed, result)?;
+                    let _ = self.events.send(TaskEvent::Finished {
+                        task_id: task_id.to_string(),
+                        status: TaskStatus::Completed,
+                    });
+                    Ok(())
+                }
+                Err(e) => {
+                    log::warn!("Cannot store cached outputs of task {} in Chronicle: {}", task_id, e);
+                    if let Some(key) = result.cache_key {
+                        self.cache_keys()?.insert(task_id.to_string(), key);
+                    }
+                    Ok(())
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a task as completed, caching its result if it opted into
+    /// caching and is not cached yet
+    fn complete(&self, task_id: &str, mut result: TaskResult) -> SynapseResult<()> {
+        if result.cache_key.is_none() {
+            result.cache_key = self.cache_result(task_id, result.exit_code)?;
+        }
+        self.store()?.finish(task_id, TaskStatus::Completed, result)?;
+        let _ = self.events.send(TaskEvent::Finished { task_id: task_id.to_string(), status: TaskStatus::Completed });
+        Ok(())
+    }
+
+    /// Drops the checkpoints of a task once it has reached `status`
+    fn finish_local(&self, task_id: &str, status: TaskStatus) -> SynapseResult<()> {
+        // A task waiting for a retry resumes from its checkpoint.
+        if status == TaskStatus::Pending {
+            return Ok(());
+        }
+        log::info!("Task {} finished: {:?}", task_id, status);
+        self.executor.checkpoints().remove_task(task_id)
+    }
+
+    /// Stores the outputs of a completed task in the result cache and
+    /// returns its cache key (if the task is cached)
+    fn cache_result(&self, task_id: &str, exit_code: Option<i32>) -> SynapseResult<Option<ContentId>> {
+        let Some(key) = self.cache_keys()?.remove(task_id) else {
+            return Ok(None);
+        };
+        if let Err(e) = self.cache.insert(key.clone(), exit_code, &self.output_dir(task_id)) {
+            log::warn!("Cannot cache the result of task {}: {}", task_id, e);
+            return Ok(None);
+        }
+        Ok(Some(key))
+    }
+
+    /// Copies the outputs of a workflow task's dependencies into its input
+    /// directory
+    fn stage_inputs(&self, task: &WorkflowTask) -> SynapseResult<()> {
+        if task.inputs.is_empty() {
+            return Ok(());
+        }
+        let input_dir = self.input_dir(&task.manifest.id);
+        std::fs::create_dir_all(&input_dir)?;
+        for edge in &task.inputs {
+            let output = self.output_dir(&edge.from_task).join(&edge.output);
+            std::fs::copy(&output, input_dir.join(&edge.input)).map_err(|e| {
+                SynapseError::Execution(format!(
+                    "dependency {} did not produce output {}: {}", edge.from_task, edge.output, e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Applies an executor event to the task store
+    fn handle_event(&self, event: ExecutorEvent) -> SynapseResult<()> {
+        match event {
+            ExecutorEvent::Progress { task_id, progress } => {
+                if let Some(estimator) = self.estimators()?.get_mut(&task_id) {
+                    estimator.record(progress, Utc::now());
+                }
+                self.store()?.set_progress(&task_id, progress)?;
+                let _ = self.events.send(TaskEvent::Progress { task_id, progress });
+                Ok(())
+            }
+            ExecutorEvent::Checkpointed { task_id, checkpoint } => {
+                self.store()?.record_checkpoint(&task_id, checkpoint)?;
+                let _ = self.events.send(TaskEvent::Checkpointed { task_id });
+                Ok(())
+            }
+            ExecutorEvent::Log(line) => {
+                let _ = self.events.send(TaskEvent::Log(line));
+                Ok(())
+            }
+            ExecutorEvent::Exited { task_id, exit_code, error, failure, usage } => {
+                self.estimators()?.remove(&task_id);
+                let Some(status) = self.store()?.get(&task_id).map(|r| r.status) else {
+                    return Ok(());
+                };
+                // A process exiting while its task is not running was stopped
+                // on purpose: cancelled, or paused on a platform without
+                // suspend support (it will restart from its checkpoint).
+                if status != TaskStatus::Running {
+                    return Ok(());
+                }
+
+                let result = TaskResult {
+                    exit_code,
+                    error,
+                    finished_at: Utc::now(),
+                    result_hash: None,
+                    worker: None,
+                    verified_by: Vec::new(),
+                    failure,
+                    cache_key: None,
+                    usage,
+                    outputs: Vec::new(),
+                };
+                if result.error.is_none() {
+                    return self.store_outputs(&task_id, Finishing::Exited(result));
+                }
+                let final_status = self.fail(&task_id, result)?;
+                self.finish_local(&task_id, final_status)
+            }
+            ExecutorEvent::Uploaded { task_id, outputs } => self.finish_upload(&task_id, outputs),
+        }
+    }
+
+    /// Converts a record into a task summary with live progress estimates
+    /// and measured resource usage
+    fn live_task(&self, record: &TaskRecord) -> ActiveTask {
+        let mut task = record.to_active_task();
+        if let Some(usage) = self.executor.usage(&record.manifest.id) {
+            task.resource_usage = ResourceUsage::from(&usage);
+        }
+        if record.status == TaskStatus::Running {
+            if let Ok(estimators) = self.progress.lock() {
+                if let Some(estimator) = estimators.get(&record.manifest.id) {
+                    let stalled = estimator.is_stalled(Utc::now(), chrono::Duration::minutes(STALL_TIMEOUT_MINUTES));
+                    task.is_stalled = stalled;
+                    task.time_remaining_secs = if stalled { None } else { estimator.time_remaining_secs() };
+                }
+            }
+        }
+        task
+    }
+
+    fn estimators(&self) -> SynapseResult<MutexGuard<'_, HashMap<String, ProgressEstimator>>> {
+        self.progress
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+
+    fn finishing(&self) -> SynapseResult<MutexGuard<'_, HashMap<String, Finishing>>> {
+        self.finishing
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+
+    fn cache_keys(&self) -> SynapseResult<MutexGuard<'_, HashMap<String, ContentId>>> {
+        self.cache_keys
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+
+    fn transition(&self, task_id: &str, from: &[TaskStatus], to: TaskStatus, reason: &str) -> SynapseResult<()> {
+        let mut store = self.store()?;
+        let current = store
+            .get(task_id)
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))?
+            .status;
+        if !from.contains(&current) {
+            return Err(SynapseError::InvalidTransition(format!(
+                "cannot move task {} from {:?} to {:?}", task_id, current, to
+            )));
+        }
+        store.set_status(task_id, to, Some(reason.to_string()))
+    }
+
+    fn store(&self) -> SynapseResult<MutexGuard<'_, TaskStore>> {
+        self.store
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::chronicle::MemoryContentStore;
+    use crate::synapse::manifest::{RetryPolicy, TaskRuntime};
+    use crate::synapse::store::tests::manifest;
+
+    fn shell_task(id: &str, script: &str) -> TaskManifest {
+        let mut manifest = manifest(id);
+        manifest.runtime = TaskRuntime::Process {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: HashMap::new(),
+        };
+        manifest
+    }
+
+    async fn wait_until_finished(synapse: &Synapse, task_id: &str) -> TaskRecord {
+        let finished = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                let record = synapse.record(task_id).unwrap();
+                if record.result.is_some() {
+                    return record;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        finished.expect("task did not finish in time")
+    }
+
+    #[test]
+    fn test_handoff_is_checked_before_its_checkpoint_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let (synapse, _) = Synapse::open(dir.path()).unwrap();
+        let content = Arc::new(MemoryContentStore::default());
+        synapse.set_content_store(content.clone()).unwrap();
+        let checkpoints = synapse.executor.checkpoints();
+        let own = checkpoints.save("own", 1, 10.0, b"own state").unwrap();
+        synapse.submit(manifest("own")).unwrap();
+
+        // Same ID as a local task
+        let handoff = CheckpointHandoff {
+            task_id: "own".to_string(),
+            sequence: 9,
+            progress: 90.0,
+            content_id: content.put(b"foreign state").unwrap(),
+        };
+        assert!(matches!(
+            synapse.accept_handoff(manifest("own"), &handoff, "requester".to_string()),
+            Err(SynapseError::DuplicateTask(_))
+        ));
+        assert_eq!(checkpoints.load(&own).unwrap(), b"own state");
+        assert!(!own.path.with_file_name("00000009.ckpt").exists());
+
+        let escaping = CheckpointHandoff { task_id: "../escaped".to_string(), ..handoff.clone() };
+        assert!(matches!(
+            synapse.accept_handoff(manifest("../escaped"), &escaping, "requester".to_string()),
+            Err(SynapseError::InvalidManifest(_))
+        ));
+
+        let accepted = CheckpointHandoff { task_id: "handed".to_string(), ..handoff };
+        synapse.accept_handoff(manifest("handed"), &accepted, "requester".to_string()).unwrap();
+        let record = synapse.record("handed").unwrap();
+        assert_eq!(record.progress, 90.0);
+        assert_eq!(record.requester.as_deref(), Some("requester"));
+        assert_eq!(checkpoints.load(record.checkpoint.as_ref().unwrap()).unwrap(), b"foreign state");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_task_is_retried_and_overdue_task_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let (synapse, _) = Synapse::open(dir.path()).unwrap();
+        // Tests running in parallel keep the host busy
+        synapse.set_idle_settings(IdleSettings { enabled: false, ..IdleSettings::default() }).unwrap();
+        tokio::spawn(synapse.clone().run());
+
+        // Fails on the first attempt, succeeds on the second
+        let mut flaky = shell_task("flaky", "[ -f attempted ] && exit 0; touch attempted; exit 1");
+        flaky.retry = RetryPolicy { max_attempts: 3, backoff_secs: 0, ..RetryPolicy::default() };
+        synapse.submit(flaky).unwrap();
+
+        let mut broken = shell_task("broken", "exit 1");
+        broken.retry = RetryPolicy { max_attempts: 2, backoff_secs: 0, ..RetryPolicy::default() };
+        synapse.submit(broken).unwrap();
+
+        let mut slow = shell_task("slow", "sleep 30");
+        slow.deadline = Some(Utc::now() + chrono::Duration::seconds(1));
+        slow.retry = RetryPolicy { max_attempts: 3, ..RetryPolicy::default() };
+        synapse.submit(slow).unwrap();
+
+        let flaky = wait_until_finished(&synapse, "flaky").await;
+        assert_eq!(flaky.status, TaskStatus::Completed);
+        assert_eq!(flaky.failed_attempts.len(), 1);
+        assert_eq!(flaky.failed_attempts[0].kind, FailureKind::NonZeroExit);
+
+        let broken = wait_until_finished(&synapse, "broken").await;
+        assert_eq!(broken.status, TaskStatus::Failed);
+        assert_eq!(broken.attempt(), 2);
+        assert_eq!(synapse.task_details("broken").unwrap().failure_kind, Some(FailureKind::NonZeroExit));
+
+        let slow = wait_until_finished(&synapse, "slow").await;
+        assert_eq!(slow.status, TaskStatus::Failed);
+        assert!(slow.failed_attempts.is_empty());
+        assert_eq!(synapse.task_details("slow").unwrap().failure_kind, Some(FailureKind::Timeout));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_identical_task_completes_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (synapse, _) = Synapse::open(dir.path()).unwrap();
+        synapse.set_idle_settings(IdleSettings { enabled: false, ..IdleSettings::default() }).unwrap();
+        tokio::spawn(synapse.clone().run());
+        let runs = dir.path().join("runs");
+        let script = format!(
+            "echo run >> {}; tr a-z A-Z < \"$MYCELIUM_INPUT_DIR/text\" > \"$MYCELIUM_OUTPUT_DIR/upper\"",
+            runs.display()
+        );
+        let cached_task = |id: &str, text: &str| {
+            let mut task = shell_task(id, &script);
+            task.cache = CacheMode::Local;
+            std::fs::create_dir_all(synapse.input_dir(id)).unwrap();
+            std::fs::write(synapse.input_dir(id).join("text"), text).unwrap();
+            task
+        };
+
+        synapse.submit(cached_task("first", "hello")).unwrap();
+        let first = wait_until_finished(&synapse, "first").await;
+        assert_eq!(first.status, TaskStatus::Completed);
+        assert!(first.result.unwrap().cache_key.is_some());
+
+        // Same inputs: served without running
+        synapse.submit(cached_task("second", "hello")).unwrap();
+        assert_eq!(wait_until_finished(&synapse, "second").await.status, TaskStatus::Completed);
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 1);
+        assert_eq!(std::fs::read(synapse.output_dir("second").join("upper")).unwrap(), b"HELLO");
+
+        // Different inputs: runs
+        synapse.submit(cached_task("third", "world")).unwrap();
+        wait_until_finished(&synapse, "third").await;
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_shared_results_need_a_trusted_publisher() {
+        let dir = tempfile::tempdir().unwrap();
+        let chronicle = Arc::new(MemoryContentStore::default());
+        let (synapse, _) = Synapse::open(&dir.path().join("node")).unwrap();
+        synapse.set_content_store(chronicle.clone()).unwrap();
+        let mut task = manifest("shared");
+        task.cache = CacheMode::Shared;
+        task.placement = TaskPlacement::Remote;
+        synapse.submit(task).unwrap();
+        let key = synapse.cache_keys().unwrap()["shared"].clone();
+
+        let publisher = NodeIdentity::generate();
+        let outputs = dir.path().join("outputs");
+        std::fs::create_dir_all(&outputs).unwrap();
+        std::fs::write(outputs.join("answer"), b"42").unwrap();
+        let origin = ResultCache::open(dir.path().join("origin"), 1024).unwrap();
+        origin.insert(key.clone(), Some(0), &outputs).unwrap();
+        let shared = origin.publish(&key, chronicle.as_ref(), &publisher).unwrap();
+        let other = ContentId::for_bytes(b"someone else's task");
+        origin.insert(other.clone(), Some(0), &outputs).unwrap();
+        let misfiled = origin.publish(&other, chronicle.as_ref(), &publisher).unwrap();
+
+        assert!(synapse.import_shared_result("shared", &shared).is_err());
+        for _ in 0..10 {
+            synapse.reputation().record_agreement(&publisher.peer_id()).unwrap();
+        }
+        let mut poisoned = shared.clone();
+        poisoned.key = other.clone();
+        assert!(synapse.import_shared_result("shared", &poisoned).is_err());
+        // Validly signed, but filed under the key of another task
+        assert!(synapse.import_shared_result("shared", &misfiled).is_err());
+        assert!(synapse.cache().lookup(&other).unwrap().is_none());
+
+        synapse.import_shared_result("shared", &shared).unwrap();
+        assert!(synapse.cache().lookup(&key).unwrap().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_task_data_is_staged_through_chronicle() {
+        let dir = tempfile::tempdir().unwrap();
+        let (synapse, _) = Synapse::open(dir.path()).unwrap();
+        synapse.set_idle_settings(IdleSettings { enabled: false, ..IdleSettings::default() }).unwrap();
+        let chronicle = Arc::new(MemoryContentStore::default());
+        synapse.set_content_store(chronicle.clone()).unwrap();
+        tokio::spawn(synapse.clone().run());
+
+        let mut task = shell_task("upper", "tr a-z A-Z < \"$MYCELIUM_INPUT_DIR/text\" > \"$MYCELIUM_OUTPUT_DIR/upper\"");
+        task.inputs = vec![StagedFile { name: "text".to_string(), content_id: chronicle.put(b"hello").unwrap(), size: 5 }];
+        synapse.submit(task).unwrap();
+        let record = wait_until_finished(&synapse, "upper").await;
+        assert_eq!(record.status, TaskStatus::Completed);
+        let outputs = record.result.unwrap().outputs;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(chronicle.get(&outputs[0].content_id).unwrap(), b"HELLO");
+
+        // An input missing from Chronicle fails the task before it starts
+        let mut task = shell_task("orphan", "true");
+        task.inputs = vec![StagedFile { name: "text".to_string(), content_id: ContentId::for_bytes(b"lost"), size: 4 }];
+        synapse.submit(task).unwrap();
+        assert_eq!(wait_until_finished(&synapse, "orphan").await.status, TaskStatus::Failed);
+    }
+}
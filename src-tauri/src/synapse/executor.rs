@@ -0,0 +1,937 @@
+//! Task executor
+//!
+//! Runs task processes in their own work directory and talks to them over
+//! a line-based control channel on stdout:
+//!
+//! ```text
+//! @mycelium progress <percent>
+//! @mycelium checkpoint <path>
+//! ```
+//!
+//! `progress` reports completion between 0 and 100. `checkpoint` tells the
+//! executor that the task has written a snapshot of its state to `<path>`
+//! (relative to the work directory). The executor copies it into the
+//! [`CheckpointStore`]; when the task is restarted, the latest copy is
+//! passed back through the `MYCELIUM_RESUME_CHECKPOINT` variable.
+//!
+//! Input files staged for the task are found in `MYCELIUM_INPUT_DIR`,
+//! including those the manifest references in Chronicle, which are fetched
+//! before the process starts. Files the task writes to
+//! `MYCELIUM_OUTPUT_DIR` make up its result. All other
+//! output on stdout and stderr is captured in the task's log. The resources
+//! used by each task's process tree are [sampled](super::usage) while it
+//! runs and summarized when it exits.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::AsyncRead;
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::mpsc;
+
+use super::checkpoint::CheckpointStore;
+use super::staging::{self, StagedFile};
+use super::logs::TaskLogStore;
+use super::manifest::TaskRuntime;
+use super::store::{CheckpointRef, TaskRecord};
+use super::usage::{HostLoad, UsageSampler, UsageTracker};
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::{ContentStore, MemoryContentStore};
+use crate::ui_api::{FailureKind, LogStream, MeasuredResourceUsage, TaskLogLine};
+
+/// Prefix of control lines written by tasks to stdout
+const CONTROL_PREFIX: &str = "@mycelium ";
+
+/// Niceness of throttled task processes (the lowest priority)
+#[cfg(unix)]
+const THROTTLED_NICENESS: libc::c_int = 19;
+
+/// How long to wait for a task's output to drain after it exited
+const OUTPUT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Events reported by the executor about running tasks
+#[derive(Debug, Clone)]
+pub enum ExecutorEvent {
+    /// The task reported progress (0.0 - 100.0)
+    Progress { task_id: String, progress: f32 },
+    /// The task saved a new checkpoint
+    Checkpointed { task_id: String, checkpoint: CheckpointRef },
+    /// The task wrote a line of output
+    Log(TaskLogLine),
+    /// The task process exited
+    Exited {
+        task_id: String,
+        exit_code: Option<i32>,
+        error: Option<String>,
+        failure: Option<FailureKind>,
+        /// Resources used by the run
+        usage: Option<MeasuredResourceUsage>,
+    },
+    /// The output files of a task were stored in Chronicle, or failed to be
+    Uploaded { task_id: String, outputs: Result<Vec<StagedFile>, String> },
+}
+
+/// Directive parsed from a task's control line
+#[derive(Debug, Clone, PartialEq)]
+enum Directive {
+    Progress(f32),
+    Checkpoint(PathBuf),
+}
+
+enum Control {
+    Kill,
+}
+
+struct RunningTask {
+    pid: Option<u32>,
+    control: mpsc::UnboundedSender<Control>,
+    suspended: bool,
+    /// Niceness the task had before it was throttled
+    throttled: Option<i32>,
+    usage: Option<UsageTracker>,
+}
+
+/// Runs task processes and reports their checkpoints and exit status
+#[derive(Clone)]
+pub struct Executor {
+    work_dir: PathBuf,
+    checkpoints: Arc<CheckpointStore>,
+    logs: Arc<TaskLogStore>,
+    content: Arc<Mutex<Arc<dyn ContentStore>>>,
+    running: Arc<Mutex<HashMap<String, RunningTask>>>,
+    sampler: Arc<Mutex<UsageSampler>>,
+    events: mpsc::UnboundedSender<ExecutorEvent>,
+}
+
+impl Executor {
+    /// Creates an executor that keeps task work directories under `work_dir`
+    /// and task output in `logs`
+    ///
+    /// Task data is staged through a store kept in memory until
+    /// [`set_content_store`](Self::set_content_store) is called. Returns
+    /// the executor and the receiver of its events.
+    pub fn new(
+        work_dir: impl AsRef<Path>,
+        checkpoints: Arc<CheckpointStore>,
+        logs: Arc<TaskLogStore>,
+    ) -> SynapseResult<(Self, mpsc::UnboundedReceiver<ExecutorEvent>)> {
+        let work_dir = work_dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&work_dir)?;
+        let (events, receiver) = mpsc::unbounded_channel();
+        let executor = Self {
+            work_dir,
+            checkpoints,
+            logs,
+            content: Arc::new(Mutex::new(Arc::new(MemoryContentStore::default()))),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            sampler: Arc::new(Mutex::new(UsageSampler::default())),
+            events,
+        };
+        Ok((executor, receiver))
+    }
+
+    /// Gets the checkpoint store used by the executor
+    pub fn checkpoints(&self) -> &Arc<CheckpointStore> {
+        &self.checkpoints
+    }
+
+    /// Gets the store of captured task output
+    pub fn logs(&self) -> &Arc<TaskLogStore> {
+        &self.logs
+    }
+
+    /// Sets the Chronicle store task inputs are fetched from and outputs
+    /// are stored in
+    pub fn set_content_store(&self, store: Arc<dyn ContentStore>) -> SynapseResult<()> {
+        *self
+            .content
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))? = store;
+        Ok(())
+    }
+
+    /// Gets the Chronicle store used for task data
+    pub fn content_store(&self) -> SynapseResult<Arc<dyn ContentStore>> {
+        self.content
+            .lock()
+            .map(|store| store.clone())
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+
+    /// Stores the output files of a task in Chronicle on a blocking task
+    /// and reports them as [`ExecutorEvent::Uploaded`]
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn upload_outputs(&self, task_id: &str) {
+        let executor = self.clone();
+        let task_id = task_id.to_string();
+        tokio::spawn(async move {
+            let uploading = async {
+                let (content, dir) = (executor.content_store()?, executor.output_dir(&task_id));
+                tokio::task::spawn_blocking(move || staging::upload_outputs(&dir, content.as_ref()))
+                    .await
+                    .map_err(|e| SynapseError::Execution(e.to_string()))?
+            };
+            let outputs = uploading.await.map_err(|e| e.to_string());
+            let _ = executor.events.send(ExecutorEvent::Uploaded { task_id, outputs });
+        });
+    }
+
+    /// Gets the work directory of a task
+    pub fn task_dir(&self, task_id: &str) -> PathBuf {
+        self.work_dir.join(task_id)
+    }
+
+    /// Gets the directory holding the input files of a task
+    pub fn input_dir(&self, task_id: &str) -> PathBuf {
+        self.task_dir(task_id).join("input")
+    }
+
+    /// Gets the directory receiving the output files of a task
+    pub fn output_dir(&self, task_id: &str) -> PathBuf {
+        self.task_dir(task_id).join("output")
+    }
+
+    /// Whether the executor currently holds a process for the task
+    pub fn is_running(&self, task_id: &str) -> bool {
+        self.running
+            .lock()
+            .map(|running| running.contains_key(task_id))
+            .unwrap_or(false)
+    }
+
+    /// Starts a task, or resumes it if its process is suspended
+    ///
+    /// The manifest's inputs are fetched from Chronicle first, on a
+    /// blocking task; the process is started once they are staged, and a
+    /// failure to stage them is reported as the task's exit. A task with
+    /// a recorded checkpoint is restarted from it. A checkpoint that fails
+    /// verification is ignored and the task starts from scratch.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn start(&self, record: &TaskRecord) -> SynapseResult<()> {
+        let task_id = record.manifest.id.clone();
+        {
+            let mut running = self.lock_running()?;
+            if let Some(task) = running.get_mut(&task_id) {
+                if task.suspended {
+                    resume_process(task.pid)?;
+                    task.suspended = false;
+                    log::info!("Resumed suspended task {}", task_id);
+                }
+                return Ok(());
+            }
+        }
+
+        std::fs::create_dir_all(self.output_dir(&task_id))?;
+        let (control, control_rx) = mpsc::unbounded_channel();
+        if record.manifest.inputs.is_empty() {
+            return self.spawn(record, control, control_rx);
+        }
+        // Holds the task's place while its inputs are staged
+        self.lock_running()?.insert(task_id, RunningTask {
+            pid: None,
+            control: control.clone(),
+            suspended: false,
+            throttled: None,
+            usage: None,
+        });
+        tokio::spawn(stage_and_spawn(record.clone(), control, control_rx, self.clone()));
+        Ok(())
+    }
+
+    /// Starts the process of a task whose inputs are staged
+    fn spawn(
+        &self,
+        record: &TaskRecord,
+        control: mpsc::UnboundedSender<Control>,
+        control_rx: mpsc::UnboundedReceiver<Control>,
+    ) -> SynapseResult<()> {
+        let task_id = record.manifest.id.clone();
+        let task_dir = self.task_dir(&task_id);
+        let resume_from = record.checkpoint.as_ref().filter(|checkpoint| {
+            match self.checkpoints.load(checkpoint) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::warn!("Ignoring checkpoint #{} of task {}: {}", checkpoint.sequence, task_id, e);
+                    false
+                }
+            }
+        });
+
+        let mut command = match &record.manifest.runtime {
+            TaskRuntime::Process { program, args, env } => {
+                let mut command = Command::new(program);
+                command.args(args).envs(env);
+                command
+            }
+        };
+        command
+            .current_dir(&task_dir)
+            .env("MYCELIUM_TASK_ID", &task_id)
+            .env("MYCELIUM_WORK_DIR", &task_dir)
+            .env("MYCELIUM_INPUT_DIR", self.input_dir(&task_id))
+            .env("MYCELIUM_OUTPUT_DIR", self.output_dir(&task_id))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(checkpoint) = resume_from {
+            command.env("MYCELIUM_RESUME_CHECKPOINT", &checkpoint.path);
+            log::info!("Starting task {} from checkpoint #{}", task_id, checkpoint.sequence);
+        }
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| SynapseError::Execution(format!("failed to spawn task {}: {}", task_id, e)))?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        {
+            let mut running = self.lock_running()?;
+            // Suspended while its inputs were staged
+            let suspended = running.get(&task_id).is_some_and(|task| task.suspended);
+            #[cfg(unix)]
+            if suspended {
+                signal_group(child.id(), libc::SIGSTOP)?;
+            }
+            running.insert(task_id.clone(), RunningTask {
+                pid: child.id(),
+                control,
+                suspended,
+                throttled: None,
+                usage: child.id().map(UsageTracker::new),
+            });
+        }
+
+        let next_sequence = record.checkpoint.as_ref().map_or(1, |c| c.sequence + 1);
+        let mut readers = Vec::new();
+        readers.extend(stdout.map(|stdout| {
+            tokio::spawn(read_control_channel(
+                task_id.clone(),
+                task_dir,
+                stdout,
+                next_sequence,
+                record.progress,
+                self.clone(),
+            ))
+        }));
+        readers.extend(stderr.map(|stderr| {
+            tokio::spawn(capture_output(task_id.clone(), LogStream::Stderr, stderr, self.clone()))
+        }));
+        tokio::spawn(supervise(task_id, child, control_rx, readers, self.clone()));
+        Ok(())
+    }
+
+    /// Suspends a running task without losing its state
+    ///
+    /// On Unix the process group is stopped with `SIGSTOP`. Other platforms
+    /// cannot suspend processes, so the task is stopped and will restart
+    /// from its latest checkpoint when resumed.
+    pub fn suspend(&self, task_id: &str) -> SynapseResult<()> {
+        let mut running = self.lock_running()?;
+        let Some(task) = running.get_mut(task_id) else {
+            return Ok(());
+        };
+        #[cfg(unix)]
+        {
+            signal_group(task.pid, libc::SIGSTOP)?;
+            task.suspended = true;
+            log::info!("Suspended task {}", task_id);
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = task.control.send(Control::Kill);
+            log::info!("Stopped task {}; it will resume from its latest checkpoint", task_id);
+        }
+        Ok(())
+    }
+
+    /// Stops a task's process
+    pub fn stop(&self, task_id: &str) -> SynapseResult<()> {
+        if let Some(task) = self.lock_running()?.get(task_id) {
+            let _ = task.control.send(Control::Kill);
+        }
+        Ok(())
+    }
+
+    /// Samples the resources used by all running tasks and the load of
+    /// the rest of the host
+    pub fn sample_usage(&self) -> SynapseResult<HostLoad> {
+        let mut sampler = self
+            .sampler
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        let mut running = self.lock_running()?;
+        Ok(sampler.sample(running.values_mut().filter_map(|task| task.usage.as_mut())))
+    }
+
+    /// Lowers the CPU priority of a running task so that it only gets CPU
+    /// time the rest of the host does not need
+    ///
+    /// Only supported on Unix.
+    pub fn throttle(&self, task_id: &str) -> SynapseResult<()> {
+        let mut running = self.lock_running()?;
+        // Tasks whose inputs are still staged have no process yet
+        let Some(task) = running.get_mut(task_id).filter(|task| task.pid.is_some()) else {
+            return Ok(());
+        };
+        if task.throttled.is_some() {
+            return Ok(());
+        }
+        let mut niceness = 0;
+        #[cfg(unix)]
+        if let Some(pid) = task.pid {
+            niceness = group_niceness(pid)?;
+            set_group_niceness(pid, THROTTLED_NICENESS).map_err(|e| {
+                SynapseError::Execution(format!("failed to throttle task process {}: {}", pid, e))
+            })?;
+            log::info!("Throttled task {}", task_id);
+        }
+        task.throttled = Some(niceness);
+        Ok(())
+    }
+
+    /// Gives a throttled task its CPU priority from before it was throttled
+    ///
+    /// Raising the priority needs `CAP_SYS_NICE` or a high enough
+    /// `RLIMIT_NICE`. If the system refuses, the task keeps the lowest
+    /// priority until it is restarted and the error is returned; it is not
+    /// retried. Only supported on Unix.
+    pub fn unthrottle(&self, task_id: &str) -> SynapseResult<()> {
+        let mut running = self.lock_running()?;
+        let Some(niceness) = running.get_mut(task_id).and_then(|task| task.throttled.take()) else {
+            return Ok(());
+        };
+        #[cfg(unix)]
+        if let Some(pid) = running.get(task_id).and_then(|task| task.pid) {
+            set_group_niceness(pid, niceness).map_err(|e| {
+                SynapseError::Execution(format!("failed to restore the priority of task process {}: {}", pid, e))
+            })?;
+            log::info!("Task {} runs at its normal priority again", task_id);
+        }
+        #[cfg(not(unix))]
+        let _ = niceness;
+        Ok(())
+    }
+
+    /// Gets the IDs of the running tasks that are throttled
+    pub fn throttled(&self) -> Vec<String> {
+        self.running
+            .lock()
+            .map(|running| {
+                running
+                    .iter()
+                    .filter(|(_, task)| task.throttled.is_some())
+                    .map(|(task_id, _)| task_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Gets the resources a running task used so far
+    pub fn usage(&self, task_id: &str) -> Option<MeasuredResourceUsage> {
+        let running = self.running.lock().ok()?;
+        running.get(task_id)?.usage.as_ref().map(UsageTracker::usage)
+    }
+
+    fn lock_running(&self) -> SynapseResult<std::sync::MutexGuard<'_, HashMap<String, RunningTask>>> {
+        self.running
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+}
+
+/// Stages the inputs of a task and starts its process, unless it is
+/// stopped first
+async fn stage_and_spawn(
+    record: TaskRecord,
+    control: mpsc::UnboundedSender<Control>,
+    mut control_rx: mpsc::UnboundedReceiver<Control>,
+    executor: Executor,
+) {
+    let task_id = record.manifest.id.clone();
+    let staging = async {
+        let content = executor.content_store()?;
+        let (inputs, dir) = (record.manifest.inputs.clone(), executor.input_dir(&task_id));
+        tokio::task::spawn_blocking(move || staging::fetch_inputs(&inputs, &dir, content.as_ref()))
+            .await
+            .map_err(|e| SynapseError::Execution(e.to_string()))?
+    };
+    let staged = tokio::select! {
+        staged = staging => staged,
+        Some(Control::Kill) = control_rx.recv() => {
+            Err(SynapseError::Execution("stopped while its inputs were staged".to_string()))
+        }
+    };
+    let Err(e) = staged.and_then(|()| executor.spawn(&record, control, control_rx)) else {
+        return;
+    };
+    if let Ok(mut running) = executor.running.lock() {
+        running.remove(&task_id);
+    }
+    let _ = executor.events.send(ExecutorEvent::Exited {
+        task_id,
+        exit_code: None,
+        error: Some(format!("Failed to start task: {}", e)),
+        failure: Some(FailureKind::Other),
+        usage: None,
+    });
+}
+
+/// Waits for a task process to exit (or be killed) and reports it
+async fn supervise(
+    task_id: String,
+    mut child: Child,
+    mut control: mpsc::UnboundedReceiver<Control>,
+    readers: Vec<tokio::task::JoinHandle<()>>,
+    executor: Executor,
+) {
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status,
+            Some(Control::Kill) = control.recv() => {
+                #[cfg(unix)]
+                let _ = signal_group(child.id(), libc::SIGKILL);
+                let _ = child.start_kill();
+            }
+        }
+    };
+
+    // Let the control channel drain so that a checkpoint written right
+    // before exit is reported before the exit itself.
+    let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, futures::future::join_all(readers)).await;
+    executor.logs.close(&task_id);
+
+    let usage = executor
+        .running
+        .lock()
+        .ok()
+        .and_then(|mut running| running.remove(&task_id))
+        .and_then(|task| task.usage)
+        .map(|tracker| tracker.usage());
+
+    let (exit_code, error, failure) = match status {
+        Ok(status) if status.success() => (status.code(), None, None),
+        Ok(status) => (
+            status.code(),
+            Some(format!("Task process exited with {}", status)),
+            Some(classify_exit(&status)),
+        ),
+        Err(e) => (None, Some(format!("Failed to wait for task process: {}", e)), Some(FailureKind::Other)),
+    };
+    let _ = executor.events.send(ExecutorEvent::Exited { task_id, exit_code, error, failure, usage });
+}
+
+/// Classifies the exit status of a failed task process
+///
+/// Processes the executor stops itself are never classified: their exit
+/// is ignored. An unexpected `SIGKILL` is what the kernel OOM killer (or
+/// a memory-limited cgroup) sends, and `SIGSYS` is what a seccomp filter
+/// sends for a forbidden system call. Shells that report a signal in the
+/// exit code (128 + signal) are treated the same.
+fn classify_exit(status: &std::process::ExitStatus) -> FailureKind {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        let signal = status.signal().or_else(|| status.code().filter(|code| *code > 128).map(|code| code - 128));
+        match signal {
+            Some(libc::SIGKILL) => return FailureKind::OutOfMemory,
+            Some(libc::SIGSYS) => return FailureKind::SandboxViolation,
+            _ => {}
+        }
+    }
+    match status.code() {
+        Some(_) => FailureKind::NonZeroExit,
+        None => FailureKind::Other,
+    }
+}
+
+/// Reads a task's stdout, acts on its control lines and logs the rest
+async fn read_control_channel(
+    task_id: String,
+    task_dir: PathBuf,
+    stdout: ChildStdout,
+    mut next_sequence: u64,
+    mut progress: f32,
+    executor: Executor,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match parse_directive(&line) {
+            Some(Directive::Progress(reported)) => {
+                progress = reported;
+                let _ = executor.events.send(ExecutorEvent::Progress {
+                    task_id: task_id.clone(),
+                    progress,
+                });
+            }
+            Some(Directive::Checkpoint(path)) => save_checkpoint(
+                &task_id,
+                &task_dir,
+                &path,
+                &mut next_sequence,
+                progress,
+                &executor.checkpoints,
+                &executor.events,
+            )
+            .await,
+            None => log_line(&executor, &task_id, LogStream::Stdout, &line),
+        }
+    }
+}
+
+/// Logs every line a task writes to `output`
+async fn capture_output(task_id: String, stream: LogStream, output: impl AsyncRead + Unpin, executor: Executor) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log_line(&executor, &task_id, stream, &line);
+    }
+}
+
+fn log_line(executor: &Executor, task_id: &str, stream: LogStream, line: &str) {
+    match executor.logs.append(task_id, stream, line) {
+        Ok(entry) => {
+            let _ = executor.events.send(ExecutorEvent::Log(entry));
+        }
+        Err(e) => log::warn!("Failed to log output of task {}: {}", task_id, e),
+    }
+}
+
+/// Copies a checkpoint written by a task into the checkpoint store
+async fn save_checkpoint(
+    task_id: &str,
+    task_dir: &Path,
+    reported: &Path,
+    next_sequence: &mut u64,
+    progress: f32,
+    checkpoints: &CheckpointStore,
+    events: &mpsc::UnboundedSender<ExecutorEvent>,
+) {
+    let saved = async {
+        let data = tokio::fs::read(checkpoint_path(task_dir, reported).await?).await?;
+        checkpoints.save(task_id, *next_sequence, progress, &data)
+    }
+    .await;
+    match saved {
+        Ok(checkpoint) => {
+            *next_sequence += 1;
+            let _ = events.send(ExecutorEvent::Checkpointed {
+                task_id: task_id.to_string(),
+                checkpoint,
+            });
+        }
+        Err(e) => log::warn!("Task {} checkpoint {} rejected: {}", task_id, reported.display(), e),
+    }
+}
+
+/// Resolves a checkpoint path reported by a task to a file in its directory
+///
+/// Only relative paths made of plain names are accepted, and symlinks may
+/// not lead out of the directory, so a task cannot pass another file of the
+/// node off as its checkpoint.
+async fn checkpoint_path(task_dir: &Path, reported: &Path) -> SynapseResult<PathBuf> {
+    let outside = || SynapseError::Execution(format!("{} is outside the task directory", reported.display()));
+    let plain = reported.components().next().is_some()
+        && reported.components().all(|component| matches!(component, Component::Normal(_)));
+    if !plain {
+        return Err(outside());
+    }
+    let path = tokio::fs::canonicalize(task_dir.join(reported)).await?;
+    if !path.starts_with(tokio::fs::canonicalize(task_dir).await?) {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+fn parse_directive(line: &str) -> Option<Directive> {
+    let mut parts = line.trim_end().strip_prefix(CONTROL_PREFIX)?.splitn(2, ' ');
+    match (parts.next()?, parts.next()) {
+        ("progress", Some(value)) => value
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(|value| Directive::Progress(value.clamp(0.0, 100.0))),
+        ("checkpoint", Some(path)) if !path.is_empty() => Some(Directive::Checkpoint(PathBuf::from(path))),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pid: Option<u32>, signal: libc::c_int) -> SynapseResult<()> {
+    let Some(pid) = pid else {
+        return Ok(());
+    };
+    // The task was started as the leader of its own process group, so the
+    // negative PID reaches every process it spawned.
+    let result = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+    if result != 0 {
+        return Err(SynapseError::Execution(format!(
+            "failed to signal task process {}: {}", pid, std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn resume_process(pid: Option<u32>) -> SynapseResult<()> {
+    signal_group(pid, libc::SIGCONT)
+}
+
+#[cfg(not(unix))]
+fn resume_process(_pid: Option<u32>) -> SynapseResult<()> {
+    Ok(())
+}
+
+/// Gets the niceness of a task's process group (that of its most favoured
+/// process)
+#[cfg(unix)]
+fn group_niceness(pid: u32) -> SynapseResult<libc::c_int> {
+    // -1 is a valid niceness, so failures only show in errno
+    clear_errno();
+    let niceness = unsafe { libc::getpriority(libc::PRIO_PGRP, pid as libc::id_t) };
+    let error = std::io::Error::last_os_error();
+    if niceness == -1 && error.raw_os_error().is_some_and(|code| code != 0) {
+        return Err(SynapseError::Execution(format!("failed to read the priority of task process {}: {}", pid, error)));
+    }
+    Ok(niceness)
+}
+
+#[cfg(unix)]
+fn set_group_niceness(pid: u32, niceness: libc::c_int) -> std::io::Result<()> {
+    // The task leads its own process group, so every process it spawned
+    // changes priority with it.
+    if unsafe { libc::setpriority(libc::PRIO_PGRP, pid as libc::id_t, niceness) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn clear_errno() {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe {
+        *libc::__errno_location() = 0;
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe {
+        *libc::__error() = 0;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::synapse::manifest::TaskRuntime;
+    use crate::synapse::store::tests::manifest;
+    use chrono::Utc;
+    use std::time::Duration;
+
+    fn shell_task(id: &str, script: &str) -> TaskRecord {
+        let mut manifest = manifest(id);
+        manifest.runtime = TaskRuntime::Process {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: HashMap::new(),
+        };
+        TaskRecord::new(manifest, None, Utc::now())
+    }
+
+    /// Opens an executor with its stores under `dir`
+    fn executor(dir: &Path) -> (Executor, mpsc::UnboundedReceiver<ExecutorEvent>) {
+        let checkpoints = Arc::new(CheckpointStore::open(dir.join("checkpoints")).unwrap());
+        let logs = Arc::new(TaskLogStore::open(dir.join("logs")).unwrap());
+        Executor::new(dir.join("work"), checkpoints, logs).unwrap()
+    }
+
+    /// Gets the next event other than a log line
+    async fn next_event(events: &mut mpsc::UnboundedReceiver<ExecutorEvent>) -> ExecutorEvent {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+                .await
+                .expect("executor event timed out")
+                .expect("executor event channel closed");
+            if !matches!(event, ExecutorEvent::Log(_)) {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        assert_eq!(
+            parse_directive("@mycelium checkpoint state.bin"),
+            Some(Directive::Checkpoint(PathBuf::from("state.bin")))
+        );
+        assert_eq!(parse_directive("@mycelium progress 42.5"), Some(Directive::Progress(42.5)));
+        assert_eq!(parse_directive("@mycelium progress 140"), Some(Directive::Progress(100.0)));
+        assert_eq!(parse_directive("@mycelium progress NaN"), None);
+        assert_eq!(parse_directive("checkpoint state.bin"), None);
+        assert_eq!(parse_directive("@mycelium checkpoint"), None);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_path_stays_in_task_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let task_dir = dir.path().join("task");
+        std::fs::create_dir_all(task_dir.join("state")).unwrap();
+        std::fs::write(task_dir.join("state/model.bin"), b"weights").unwrap();
+        std::fs::write(dir.path().join("secret"), b"node key").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), task_dir.join("link")).unwrap();
+
+        let resolved = checkpoint_path(&task_dir, Path::new("state/model.bin")).await.unwrap();
+        assert_eq!(std::fs::read(resolved).unwrap(), b"weights");
+        for reported in ["../secret", "state/../../secret", "/etc/passwd", "./state/model.bin", "link"] {
+            assert!(checkpoint_path(&task_dir, Path::new(reported)).await.is_err(), "{} accepted", reported);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_covers_process_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, mut events) = executor(dir.path());
+
+        // The CPU is burnt by a child of the task process
+        let record = shell_task("busy", "(while :; do :; done) & sleep 1; kill $!");
+        executor.start(&record).unwrap();
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            executor.sample_usage().unwrap();
+        }
+        let live = executor.usage("busy").unwrap();
+        assert!(live.cpu_time_secs > 0.0);
+        assert!(live.peak_ram_bytes > 0);
+
+        match next_event(&mut events).await {
+            ExecutorEvent::Exited { usage: Some(usage), .. } => {
+                assert!(usage.wall_time_secs >= 1.0);
+                assert!(usage.cpu_time_secs >= live.cpu_time_secs);
+            }
+            other => panic!("expected exit with usage, got {:?}", other),
+        }
+        assert!(executor.usage("busy").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_task_resumes_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, mut events) = executor(dir.path());
+
+        // The task counts steps, checkpoints after the first one and then
+        // "crashes"; the restart must pick up the saved step.
+        let script = r#"
+            step=$(cat "$MYCELIUM_RESUME_CHECKPOINT" 2>/dev/null || echo 0)
+            step=$((step + 1))
+            echo $step > state
+            echo "@mycelium checkpoint state"
+            [ "$step" -ge 2 ] && exit 0
+            exit 1
+        "#;
+        let mut record = shell_task("resumable", script);
+        executor.start(&record).unwrap();
+
+        let checkpoint = match next_event(&mut events).await {
+            ExecutorEvent::Checkpointed { checkpoint, .. } => checkpoint,
+            other => panic!("expected checkpoint, got {:?}", other),
+        };
+        assert_eq!(checkpoint.sequence, 1);
+        assert!(matches!(next_event(&mut events).await, ExecutorEvent::Exited { exit_code: Some(1), .. }));
+
+        record.checkpoint = Some(checkpoint);
+        executor.start(&record).unwrap();
+        match next_event(&mut events).await {
+            ExecutorEvent::Checkpointed { checkpoint, .. } => {
+                assert_eq!(checkpoint.sequence, 2);
+                assert_eq!(executor.checkpoints().load(&checkpoint).unwrap(), b"2\n");
+            }
+            other => panic!("expected checkpoint, got {:?}", other),
+        }
+        assert!(matches!(next_event(&mut events).await, ExecutorEvent::Exited { exit_code: Some(0), .. }));
+    }
+
+    #[tokio::test]
+    async fn test_output_is_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, mut events) = executor(dir.path());
+
+        let script = "echo hello; echo '@mycelium progress 50'; echo oops >&2";
+        executor.start(&shell_task("chatty", script)).unwrap();
+        let mut streamed = Vec::new();
+        loop {
+            match events.recv().await.unwrap() {
+                ExecutorEvent::Log(line) => streamed.push((line.stream, line.line)),
+                ExecutorEvent::Exited { .. } => break,
+                _ => {}
+            }
+        }
+        streamed.sort_by_key(|(stream, _)| *stream == LogStream::Stderr);
+        assert_eq!(streamed, vec![
+            (LogStream::Stdout, "hello".to_string()),
+            (LogStream::Stderr, "oops".to_string()),
+        ]);
+        assert_eq!(executor.logs().page("chatty", 0).unwrap().lines.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exit_is_classified() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, mut events) = executor(dir.path());
+
+        for (id, script, expected) in [
+            ("exit", "exit 2", FailureKind::NonZeroExit),
+            ("oom", "kill -KILL $$", FailureKind::OutOfMemory),
+            ("seccomp", "kill -SYS $$", FailureKind::SandboxViolation),
+        ] {
+            executor.start(&shell_task(id, script)).unwrap();
+            match next_event(&mut events).await {
+                ExecutorEvent::Exited { failure, .. } => assert_eq!(failure, Some(expected), "task {}", id),
+                other => panic!("expected exit, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttled_task_gets_its_priority_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, _events) = executor(dir.path());
+
+        executor.start(&shell_task("nice", "sleep 30")).unwrap();
+        let pid = executor.running.lock().unwrap()["nice"].pid.unwrap();
+        let normal = group_niceness(pid).unwrap();
+        executor.throttle("nice").unwrap();
+        assert_eq!(group_niceness(pid).unwrap(), THROTTLED_NICENESS);
+        assert_eq!(executor.throttled(), vec!["nice".to_string()]);
+
+        // Without CAP_SYS_NICE the system may refuse; the task then stays
+        // at the lowest priority and is not retried
+        match executor.unthrottle("nice") {
+            Ok(()) => assert_eq!(group_niceness(pid).unwrap(), normal),
+            Err(_) => assert_eq!(group_niceness(pid).unwrap(), THROTTLED_NICENESS),
+        }
+        assert!(executor.throttled().is_empty());
+        executor.stop("nice").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_kills_task() {
+        let dir = tempfile::tempdir().unwrap();
+        let (executor, mut events) = executor(dir.path());
+
+        executor.start(&shell_task("sleeper", "sleep 30")).unwrap();
+        executor.suspend("sleeper").unwrap();
+        executor.stop("sleeper").unwrap();
+
+        match next_event(&mut events).await {
+            ExecutorEvent::Exited { exit_code, error, .. } => {
+                assert_eq!(exit_code, None);
+                assert!(error.is_some());
+            }
+            other => panic!("expected exit, got {:?}", other),
+        }
+        assert!(!executor.is_running("sleeper"));
+    }
+}
@@ -0,0 +1,177 @@
+//! Idle-aware scheduling
+//!
+//! Tasks should not compete with the user's own work. Every scheduling
+//! pass the engine measures the [`HostLoad`] of everything but the tasks
+//! and classifies it against the [`IdleSettings`] of the active Covenant
+//! profile:
+//!
+//! - [`HostActivity::Idle`]: tasks run normally; throttled tasks get their
+//!   normal priority back.
+//! - [`HostActivity::Busy`]: running tasks are throttled to the lowest CPU
+//!   priority and no new task starts.
+//! - [`HostActivity::Overloaded`]: running tasks are paused; the scheduler
+//!   resumes them once the host is idle again.
+//!
+//! The monitor escalates at once but only calms down after the host has
+//! stayed below the thresholds for [`IdleSettings::resume_after_secs`], so
+//! that short bursts of work do not make tasks flap.
+
+use std::time::{Duration, Instant};
+
+use super::usage::HostLoad;
+use crate::ui_api::IdleSettings;
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            throttle_cpu_percent: 50.0,
+            pause_cpu_percent: 85.0,
+            pause_load_per_cpu: 1.5,
+            heavy_process_cpu_percent: 80.0,
+            resume_after_secs: 60,
+        }
+    }
+}
+
+/// How much the host is used by other work
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostActivity {
+    /// Tasks run normally
+    Idle,
+    /// Tasks are throttled and no new task starts (with the reason)
+    Busy(String),
+    /// Tasks are paused (with the reason)
+    Overloaded(String),
+}
+
+impl HostActivity {
+    fn level(&self) -> u8 {
+        match self {
+            HostActivity::Idle => 0,
+            HostActivity::Busy(_) => 1,
+            HostActivity::Overloaded(_) => 2,
+        }
+    }
+}
+
+/// Tracks the host activity with hysteresis
+#[derive(Debug)]
+pub struct IdleMonitor {
+    settings: IdleSettings,
+    activity: HostActivity,
+    /// Start of the current calm period and the busiest activity seen
+    /// during it
+    calm: Option<(Instant, HostActivity)>,
+}
+
+impl Default for IdleMonitor {
+    fn default() -> Self {
+        Self::new(IdleSettings::default())
+    }
+}
+
+impl IdleMonitor {
+    /// Creates a monitor applying `settings`
+    pub fn new(settings: IdleSettings) -> Self {
+        Self { settings, activity: HostActivity::Idle, calm: None }
+    }
+
+    /// Replaces the thresholds
+    pub fn set_settings(&mut self, settings: IdleSettings) {
+        self.settings = settings;
+    }
+
+    /// Gets the current host activity
+    pub fn activity(&self) -> &HostActivity {
+        &self.activity
+    }
+
+    /// Updates the host activity from a new load sample
+    ///
+    /// A calmer activity only takes over once the host stayed below the
+    /// current one for the configured time; it then steps down to the
+    /// busiest activity seen in the meantime.
+    pub fn update(&mut self, load: &HostLoad, now: Instant) -> &HostActivity {
+        let observed = classify(load, &self.settings);
+        if observed.level() >= self.activity.level() {
+            if observed != self.activity {
+                log::info!("Host activity: {:?}", observed);
+            }
+            self.activity = observed;
+            self.calm = None;
+            return &self.activity;
+        }
+
+        let (since, peak) = self.calm.get_or_insert((now, HostActivity::Idle));
+        if observed.level() >= peak.level() {
+            *peak = observed;
+        }
+        if now.duration_since(*since) >= Duration::from_secs(self.settings.resume_after_secs) {
+            if let Some((_, peak)) = self.calm.take() {
+                log::info!("Host activity: {:?}", peak);
+                self.activity = peak;
+            }
+        }
+        &self.activity
+    }
+}
+
+/// Classifies a load sample without hysteresis
+fn classify(load: &HostLoad, settings: &IdleSettings) -> HostActivity {
+    if !settings.enabled {
+        return HostActivity::Idle;
+    }
+    if load.cpu_percent >= settings.pause_cpu_percent {
+        return HostActivity::Overloaded(format!("host CPU usage at {:.0}%", load.cpu_percent));
+    }
+    if load.load_per_cpu >= settings.pause_load_per_cpu {
+        return HostActivity::Overloaded(format!("host load at {:.2} per CPU", load.load_per_cpu));
+    }
+    if load.cpu_percent >= settings.throttle_cpu_percent {
+        return HostActivity::Busy(format!("host CPU usage at {:.0}%", load.cpu_percent));
+    }
+    match &load.busiest {
+        Some((name, cpu)) if *cpu >= settings.heavy_process_cpu_percent => {
+            HostActivity::Busy(format!("{} uses {:.0}% CPU", name, cpu))
+        }
+        _ => HostActivity::Idle,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cpu_percent: f32, busiest: Option<(&str, f32)>) -> HostLoad {
+        HostLoad {
+            cpu_percent,
+            load_per_cpu: 0.1,
+            busiest: busiest.map(|(name, cpu)| (name.to_string(), cpu)),
+        }
+    }
+
+    #[test]
+    fn test_escalates_at_once_and_calms_down_slowly() {
+        let settings = IdleSettings { resume_after_secs: 60, ..IdleSettings::default() };
+        let mut monitor = IdleMonitor::new(settings);
+        let start = Instant::now();
+
+        assert_eq!(monitor.update(&load(5.0, None), start), &HostActivity::Idle);
+        assert!(matches!(monitor.update(&load(5.0, Some(("ffmpeg", 95.0))), start), HostActivity::Busy(_)));
+        assert!(matches!(monitor.update(&load(90.0, None), start), HostActivity::Overloaded(_)));
+
+        // The host is quiet again, but not for long enough yet
+        let quiet = load(5.0, None);
+        assert!(matches!(monitor.update(&quiet, start + Duration::from_secs(1)), HostActivity::Overloaded(_)));
+        // A smaller spike during the calm period only lets it step down to
+        // being busy
+        monitor.update(&load(60.0, None), start + Duration::from_secs(30));
+        assert!(matches!(monitor.update(&quiet, start + Duration::from_secs(61)), HostActivity::Busy(_)));
+        assert!(matches!(monitor.update(&quiet, start + Duration::from_secs(62)), HostActivity::Busy(_)));
+        assert_eq!(monitor.update(&quiet, start + Duration::from_secs(122)), &HostActivity::Idle);
+
+        monitor.set_settings(IdleSettings { enabled: false, ..IdleSettings::default() });
+        assert_eq!(monitor.update(&load(100.0, None), start + Duration::from_secs(123)), &HostActivity::Idle);
+    }
+}
@@ -0,0 +1,231 @@
+//! Captured task output
+//!
+//! Every line a task writes to stdout or stderr (apart from control
+//! lines) is appended to the task's log as one JSON line with a sequence
+//! number. Logs are size-capped: once the current file of a task exceeds
+//! [`MAX_FILE_BYTES`] it is rotated, and only [`ROTATED_FILES`] older
+//! files are kept. Readers page through the history by sequence number;
+//! lines dropped by rotation are simply skipped.
+
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{SynapseError, SynapseResult};
+use crate::ui_api::{LogStream, TaskLogLine, TaskLogPage};
+
+/// Size after which the current log file of a task is rotated
+pub const MAX_FILE_BYTES: u64 = 256 * 1024;
+
+/// Number of rotated log files kept per task
+pub const ROTATED_FILES: usize = 3;
+
+/// Longest line stored; longer lines are cut
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Most lines returned by one page
+pub const PAGE_LINES: usize = 500;
+
+struct LogWriter {
+    file: File,
+    size: u64,
+    next_seq: u64,
+}
+
+/// Logs of all tasks, one set of files per task
+pub struct TaskLogStore {
+    dir: PathBuf,
+    writers: Mutex<HashMap<String, LogWriter>>,
+}
+
+impl TaskLogStore {
+    /// Opens the log store in `dir`
+    pub fn open(dir: impl AsRef<Path>) -> SynapseResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, writers: Mutex::new(HashMap::new()) })
+    }
+
+    /// Appends a line of task output and returns it as stored
+    pub fn append(&self, task_id: &str, stream: LogStream, line: &str) -> SynapseResult<TaskLogLine> {
+        let mut writers = self.writers()?;
+        if !writers.contains_key(task_id) {
+            let writer = self.open_writer(task_id)?;
+            writers.insert(task_id.to_string(), writer);
+        }
+        let Some(writer) = writers.get_mut(task_id) else {
+            return Err(SynapseError::StateUnavailable(format!("log of task {} is not open", task_id)));
+        };
+
+        if writer.size >= MAX_FILE_BYTES {
+            self.rotate(task_id)?;
+            writer.file = self.open_current(task_id)?;
+            writer.size = 0;
+        }
+
+        let entry = TaskLogLine {
+            task_id: task_id.to_string(),
+            seq: writer.next_seq,
+            stream,
+            line: truncate(line, MAX_LINE_BYTES).to_string(),
+            timestamp: Utc::now(),
+        };
+        let mut bytes = serde_json::to_vec(&entry).map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        bytes.push(b'\n');
+        writer.file.write_all(&bytes)?;
+        writer.size += bytes.len() as u64;
+        writer.next_seq += 1;
+        Ok(entry)
+    }
+
+    /// Closes the log of a task that stopped running
+    pub fn close(&self, task_id: &str) {
+        if let Ok(mut writers) = self.writers.lock() {
+            writers.remove(task_id);
+        }
+    }
+
+    /// Gets up to [`PAGE_LINES`] lines of a task's log, starting at
+    /// sequence number `offset`
+    pub fn page(&self, task_id: &str, offset: u64) -> SynapseResult<TaskLogPage> {
+        let mut lines = Vec::new();
+        let mut first_available = None;
+        let mut next_offset = offset;
+
+        for path in self.files_oldest_first(task_id) {
+            for entry in read_lines(&path)? {
+                first_available.get_or_insert(entry.seq);
+                next_offset = next_offset.max(entry.seq + 1);
+                if entry.seq >= offset && lines.len() < PAGE_LINES {
+                    lines.push(entry);
+                }
+            }
+        }
+        if let Some(last) = lines.last().filter(|_| lines.len() == PAGE_LINES) {
+            next_offset = last.seq + 1;
+        }
+
+        Ok(TaskLogPage {
+            task_id: task_id.to_string(),
+            lines,
+            next_offset,
+            first_available: first_available.unwrap_or(0),
+        })
+    }
+
+    fn open_writer(&self, task_id: &str) -> SynapseResult<LogWriter> {
+        let next_seq = self
+            .files_oldest_first(task_id)
+            .iter()
+            .rev()
+            .find_map(|path| read_lines(path).ok()?.last().map(|entry| entry.seq + 1))
+            .unwrap_or(0);
+        let file = self.open_current(task_id)?;
+        let size = file.metadata()?.len();
+        Ok(LogWriter { file, size, next_seq })
+    }
+
+    fn open_current(&self, task_id: &str) -> SynapseResult<File> {
+        Ok(OpenOptions::new().create(true).append(true).open(self.path(task_id, 0))?)
+    }
+
+    /// Shifts `<task>.log` to `<task>.log.1` and so on, dropping the oldest
+    fn rotate(&self, task_id: &str) -> SynapseResult<()> {
+        let oldest = self.path(task_id, ROTATED_FILES);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for generation in (0..ROTATED_FILES).rev() {
+            let path = self.path(task_id, generation);
+            if path.exists() {
+                fs::rename(&path, self.path(task_id, generation + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn files_oldest_first(&self, task_id: &str) -> Vec<PathBuf> {
+        (0..=ROTATED_FILES)
+            .rev()
+            .map(|generation| self.path(task_id, generation))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    fn path(&self, task_id: &str, generation: usize) -> PathBuf {
+        match generation {
+            0 => self.dir.join(format!("{}.log", task_id)),
+            n => self.dir.join(format!("{}.log.{}", task_id, n)),
+        }
+    }
+
+    fn writers(&self) -> SynapseResult<MutexGuard<'_, HashMap<String, LogWriter>>> {
+        self.writers
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))
+    }
+}
+
+/// Reads the entries of a log file, skipping a torn last line
+fn read_lines(path: &Path) -> SynapseResult<Vec<TaskLogLine>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Cuts `line` to at most `max` bytes on a character boundary
+fn truncate(line: &str, max: usize) -> &str {
+    if line.len() <= max {
+        return line;
+    }
+    let mut end = max;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_rotates_and_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskLogStore::open(dir.path()).unwrap();
+        let line = "x".repeat(1000);
+        // Enough output for more rotations than are kept
+        let total = (MAX_FILE_BYTES as usize / 1000 + 1) * (ROTATED_FILES + 2);
+        for _ in 0..total {
+            store.append("task", LogStream::Stdout, &line).unwrap();
+        }
+        store.append("task", LogStream::Stderr, "last").unwrap();
+
+        let mut size = 0;
+        for path in store.files_oldest_first("task") {
+            size += fs::metadata(path).unwrap().len();
+        }
+        assert!(size <= MAX_FILE_BYTES * (ROTATED_FILES as u64 + 1) + 2 * 1024);
+
+        let page = store.page("task", 0).unwrap();
+        assert!(page.first_available > 0);
+        assert_eq!(page.lines.len(), PAGE_LINES);
+        assert_eq!(page.lines[0].seq, page.first_available);
+
+        // Sequence numbers continue after the log is reopened
+        store.close("task");
+        let store = TaskLogStore::open(dir.path()).unwrap();
+        let next = store.append("task", LogStream::Stdout, "again").unwrap();
+        assert_eq!(next.seq, total as u64 + 1);
+        let tail = store.page("task", total as u64).unwrap();
+        let tail: Vec<&str> = tail.lines.iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(tail, vec!["last", "again"]);
+    }
+}
@@ -0,0 +1,254 @@
+//! Task manifests for the Synapse protocol
+//!
+//! A manifest is the immutable description of a task as submitted by an
+//! AIbox: what to run, how much it needs and how it should be verified.
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use super::cache::CacheMode;
+use super::staging::StagedFile;
+use super::util::is_plain_file_name;
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::ContentId;
+use crate::ui_api::{
+    FailureKind, ResourceUsage, SecurityLevel, TaskComplexity, TaskPriority, TaskType, VerificationMethod,
+};
+
+/// Upper bound of `RetryPolicy::max_attempts`
+const MAX_ATTEMPTS_LIMIT: u32 = 10;
+
+/// Longest wait between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Immutable description of a compute task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskManifest {
+    /// Task ID
+    pub id: String,
+    /// Human readable task name
+    pub name: String,
+    /// AIbox that submitted the task
+    pub aibox_id: String,
+    /// Task type
+    pub task_type: TaskType,
+    /// Task priority
+    pub priority: TaskPriority,
+    /// Reward in VOID tokens
+    pub reward_tokens: u64,
+    /// Model information (if applicable)
+    pub model: Option<String>,
+    /// Input data size in GB
+    pub data_size_gb: f32,
+    /// Input files fetched from Chronicle into the input directory before
+    /// the task starts
+    #[serde(default)]
+    pub inputs: Vec<StagedFile>,
+    /// Complexity level
+    pub complexity: TaskComplexity,
+    /// Verification method requested by the AIbox
+    pub verification: VerificationMethod,
+    /// Security level
+    pub security: SecurityLevel,
+    /// What to execute
+    pub runtime: TaskRuntime,
+    /// Resources reserved for the task
+    pub resources: ResourceRequest,
+    /// Time by which the task must be finished; an overdue task is
+    /// stopped and fails with a timeout
+    pub deadline: Option<DateTime<Utc>>,
+    /// What to do when the task fails
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Whether the result may be served from and stored in the result
+    /// cache
+    #[serde(default)]
+    pub cache: CacheMode,
+    /// Where the task runs
+    #[serde(default)]
+    pub placement: TaskPlacement,
+    /// Submission timestamp
+    pub submitted_at: DateTime<Utc>,
+}
+
+impl TaskManifest {
+    /// Computes the content ID of the manifest
+    ///
+    /// The hash covers a canonical encoding (sorted keys) and ignores the
+    /// placement, which only tells the local node where to run the task, so
+    /// requester and worker arrive at the same ID.
+    pub fn content_id(&self) -> ContentId {
+        let mut manifest = self.clone();
+        manifest.placement = TaskPlacement::default();
+        let mut canonical = Vec::new();
+        if let Ok(value) = serde_json::to_value(&manifest) {
+            write_canonical_json(&value, &mut canonical);
+        }
+        ContentId::for_bytes(&canonical)
+    }
+
+    /// Checks that the manifest can be accepted
+    ///
+    /// Task IDs are used as directory names for scratch space and
+    /// checkpoints, so they are restricted to a safe character set.
+    pub fn validate(&self) -> SynapseResult<()> {
+        if !is_safe_id(&self.id) {
+            return Err(SynapseError::InvalidManifest(format!("invalid task ID {:?}", self.id)));
+        }
+        let retry = &self.retry;
+        if retry.max_attempts == 0 || retry.max_attempts > MAX_ATTEMPTS_LIMIT {
+            return Err(SynapseError::InvalidManifest(format!(
+                "max_attempts must be between 1 and {}", MAX_ATTEMPTS_LIMIT
+            )));
+        }
+        if !retry.backoff_multiplier.is_finite() || retry.backoff_multiplier < 1.0 {
+            return Err(SynapseError::InvalidManifest("backoff_multiplier must be at least 1".to_string()));
+        }
+        let mut names = HashSet::new();
+        for input in &self.inputs {
+            if !is_plain_file_name(&input.name) || !names.insert(input.name.as_str()) {
+                return Err(SynapseError::InvalidManifest(format!("invalid input name {:?}", input.name)));
+            }
+        }
+        match &self.runtime {
+            TaskRuntime::Process { program, .. } if program.is_empty() => {
+                Err(SynapseError::InvalidManifest("process runtime without a program".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Retry policy of a task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Number of times the task is run before it fails for good (1 means
+    /// no retries)
+    pub max_attempts: u32,
+    /// Wait before the first retry in seconds
+    pub backoff_secs: u64,
+    /// Factor by which the wait grows with every further retry
+    pub backoff_multiplier: f32,
+    /// Whether a remote task is retried on peers that have not run it yet
+    pub different_peer: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_secs: 10,
+            backoff_multiplier: 2.0,
+            different_peer: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Gets the wait before attempt number `attempt` (the first retry is
+    /// attempt 2)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(2).min(32) as i32;
+        let secs = self.backoff_secs as f64 * (self.backoff_multiplier as f64).powi(exponent);
+        Duration::from_secs_f64(secs.min(MAX_BACKOFF.as_secs_f64()))
+    }
+
+    /// Whether a failure of the given kind is worth another attempt
+    ///
+    /// Timeouts are final since the deadline has passed; sandbox
+    /// violations and failed dependencies would only happen again.
+    pub fn retries(&self, kind: FailureKind) -> bool {
+        !matches!(
+            kind,
+            FailureKind::Timeout | FailureKind::SandboxViolation | FailureKind::DependencyFailed
+        )
+    }
+}
+
+/// Whether `id` can be used as a directory or file name component
+pub(super) fn is_safe_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id != "."
+        && id != ".."
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Writes `value` as JSON with object keys in sorted order
+fn write_canonical_json(value: &serde_json::Value, out: &mut Vec<u8>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical_json(&serde_json::Value::String(key.clone()), out);
+                out.push(b':');
+                write_canonical_json(&map[key], out);
+            }
+            out.push(b'}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical_json(item, out);
+            }
+            out.push(b']');
+        }
+        scalar => out.extend_from_slice(scalar.to_string().as_bytes()),
+    }
+}
+
+/// Execution environment of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TaskRuntime {
+    /// Native process started on the host
+    Process {
+        /// Program to execute
+        program: String,
+        /// Program arguments
+        args: Vec<String>,
+        /// Extra environment variables
+        env: HashMap<String, String>,
+    },
+}
+
+/// Where a task is executed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskPlacement {
+    /// Run by the local executor
+    #[default]
+    Local,
+    /// Dispatched to a remote peer
+    Remote,
+}
+
+/// Resources a task asks to reserve while it runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceRequest {
+    /// CPU share in percent of the host
+    pub cpu_percent: u8,
+    /// RAM in GB
+    pub ram_gb: f32,
+    /// GPU share in percent
+    pub gpu_percent: u8,
+}
+
+impl From<&ResourceRequest> for ResourceUsage {
+    fn from(request: &ResourceRequest) -> Self {
+        ResourceUsage {
+            cpu_percent: request.cpu_percent,
+            ram_gb: request.ram_gb,
+            gpu_percent: request.gpu_percent,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Synapse protocol: distributed compute for AIboxes
+//!
+//! Tasks submitted by AIboxes are persisted in a write-ahead-logged
+//! [`TaskStore`](store::TaskStore) so that the queue survives restarts,
+//! and ordered for execution by the priority
+//! [`Scheduler`](scheduler::Scheduler). Scheduled tasks run as processes
+//! under the [`Executor`](executor::Executor), which also collects their
+//! checkpoints and [output](logs) and [stages](staging) their data through
+//! Chronicle. Tasks placed on other peers are handed out and followed by
+//! the [`Dispatcher`](dispatch::Dispatcher), which also runs them
+//! redundantly when their results need to be verified by
+//! [consensus](verification). Tasks submitted together as a
+//! [workflow](workflow) are queued as their dependencies complete, and
+//! tasks identical to earlier ones complete from the result [cache]. Tasks
+//! give way to the user's own work while the host is [busy](idle). The
+//! [`Synapse`] handle is the entry point used by the Tauri commands.
+
+pub mod cache;
+pub mod checkpoint;
+pub mod dispatch;
+pub mod engine;
+pub mod executor;
+pub mod idle;
+pub mod logs;
+pub mod manifest;
+pub mod progress;
+pub mod receipt;
+pub mod scheduler;
+pub mod staging;
+pub mod store;
+pub mod usage;
+mod util;
+pub mod verification;
+pub mod workflow;
+
+pub use engine::Synapse;
+
+/// Synapse error types
+#[derive(Debug, thiserror::Error)]
+pub enum SynapseError {
+    #[error("Task store I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Task store is corrupted: {0}")]
+    Corrupted(String),
+    #[error("Task not found: {0}")]
+    TaskNotFound(String),
+    #[error("Task already exists: {0}")]
+    DuplicateTask(String),
+    #[error("Workflow not found: {0}")]
+    WorkflowNotFound(String),
+    #[error("Invalid task transition: {0}")]
+    InvalidTransition(String),
+    #[error("Invalid task manifest: {0}")]
+    InvalidManifest(String),
+    #[error("Task execution failed: {0}")]
+    Execution(String),
+    #[error("Task dispatch failed: {0}")]
+    Dispatch(String),
+    #[error("Peer request failed: {0}")]
+    Rpc(#[from] crate::rpc::RpcError),
+    #[error("Chronicle operation failed: {0}")]
+    Chronicle(#[from] crate::chronicle::ChronicleError),
+    #[error("Synapse state is unavailable: {0}")]
+    StateUnavailable(String),
+}
+
+/// Result type for Synapse operations
+pub type SynapseResult<T> = Result<T, SynapseError>;
@@ -0,0 +1,121 @@
+//! Progress tracking and time-remaining estimation
+//!
+//! Tasks report progress over the executor's control channel. The
+//! estimator smooths the observed progress rate with an exponential moving
+//! average, so that a single slow or fast step does not make the ETA jump,
+//! and flags tasks that stopped reporting as stalled.
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Weight of the newest rate sample in the moving average
+const SMOOTHING: f64 = 0.3;
+
+/// Minimum time between two samples used for a rate measurement
+const MIN_SAMPLE_INTERVAL_MS: i64 = 1000;
+
+/// Progress estimator for a single task run
+#[derive(Debug, Clone)]
+pub struct ProgressEstimator {
+    /// Progress and time of the last sample used for a rate measurement
+    anchor: (f32, DateTime<Utc>),
+    /// Latest reported progress
+    progress: f32,
+    /// Time of the latest report, if the task reported at all
+    last_report: Option<DateTime<Utc>>,
+    /// Smoothed progress rate in percent per second
+    rate: Option<f64>,
+}
+
+impl ProgressEstimator {
+    /// Starts estimating for a task that is at `progress` at `now`
+    pub fn new(progress: f32, now: DateTime<Utc>) -> Self {
+        Self {
+            anchor: (progress, now),
+            progress,
+            last_report: None,
+            rate: None,
+        }
+    }
+
+    /// Records a progress report (0.0 - 100.0)
+    pub fn record(&mut self, progress: f32, now: DateTime<Utc>) {
+        let progress = progress.clamp(0.0, 100.0);
+        self.progress = progress;
+        self.last_report = Some(now);
+
+        let (anchor_progress, anchor_time) = self.anchor;
+        let elapsed_ms = (now - anchor_time).num_milliseconds();
+        if elapsed_ms < MIN_SAMPLE_INTERVAL_MS {
+            return;
+        }
+        if progress < anchor_progress {
+            // The task went backwards (e.g. restarted a phase); measure
+            // from here on instead of producing a negative rate.
+            self.anchor = (progress, now);
+            return;
+        }
+
+        let sample = (progress - anchor_progress) as f64 / (elapsed_ms as f64 / 1000.0);
+        self.rate = Some(match self.rate {
+            Some(rate) => SMOOTHING * sample + (1.0 - SMOOTHING) * rate,
+            None => sample,
+        });
+        self.anchor = (progress, now);
+    }
+
+    /// Gets the latest reported progress
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    /// Estimates the remaining time in whole seconds
+    ///
+    /// Returns `None` until a rate has been measured or while the task is
+    /// not making progress.
+    pub fn time_remaining_secs(&self) -> Option<u64> {
+        let rate = self.rate.filter(|rate| *rate > f64::EPSILON)?;
+        let remaining = (100.0 - self.progress as f64).max(0.0);
+        Some((remaining / rate).ceil() as u64)
+    }
+
+    /// Whether a task that used to report progress has been silent for
+    /// longer than `timeout`
+    pub fn is_stalled(&self, now: DateTime<Utc>, timeout: Duration) -> bool {
+        self.last_report.is_some_and(|last| now - last > timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_follows_progress_rate() {
+        let start = Utc::now();
+        let mut estimator = ProgressEstimator::new(0.0, start);
+        assert_eq!(estimator.time_remaining_secs(), None);
+
+        // 1% per second
+        for second in 1..=10 {
+            estimator.record(second as f32, start + Duration::seconds(second));
+        }
+        assert_eq!(estimator.time_remaining_secs(), Some(90));
+
+        // A burst to 2% per second moves the estimate only partially
+        estimator.record(12.0, start + Duration::seconds(11));
+        let eta = estimator.time_remaining_secs().unwrap();
+        assert!(eta < 88 && eta > 44, "eta {} not smoothed", eta);
+    }
+
+    #[test]
+    fn test_stalled_after_silence() {
+        let start = Utc::now();
+        let mut estimator = ProgressEstimator::new(0.0, start);
+        let timeout = Duration::minutes(5);
+        assert!(!estimator.is_stalled(start + Duration::hours(1), timeout));
+
+        estimator.record(10.0, start + Duration::seconds(10));
+        assert!(!estimator.is_stalled(start + Duration::minutes(2), timeout));
+        assert!(estimator.is_stalled(start + Duration::minutes(6), timeout));
+    }
+}
@@ -0,0 +1,329 @@
+//! Signed task result receipts
+//!
+//! A receipt records that a worker ran a task for a requester: which task
+//! (by ID and manifest hash), what it produced (result hash and the
+//! Chronicle content IDs of the output files), how many resources it took
+//! and when. The worker signs the receipt with its node
+//! key when it returns the result; the requester countersigns it once it
+//! has accepted the result. Both parties keep a copy, and since peer IDs
+//! are public keys, anyone can verify a receipt offline. Countersigned
+//! receipts of completed tasks are the basis for reward accounting.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::staging::StagedFile;
+use super::util::push_field;
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::ContentId;
+use crate::identity::{verify_signature, NodeIdentity, PeerId};
+use crate::ui_api::{MeasuredResourceUsage, TaskStatus};
+use crate::util::write_atomic;
+
+/// Domain separator of worker signatures
+const WORKER_SIGNATURE_DOMAIN: &[u8] = b"mycelium/synapse/receipt/v1/worker";
+
+/// Domain separator of requester countersignatures
+const REQUESTER_SIGNATURE_DOMAIN: &[u8] = b"mycelium/synapse/receipt/v1/requester";
+
+/// Resources a task used on its worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptResources {
+    /// Resources measured for the processes of the task
+    pub used: MeasuredResourceUsage,
+    /// Time between the start of the task and its result in seconds
+    pub wall_time_secs: f64,
+}
+
+/// Receipt for a task run by a worker on behalf of a requester
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskReceipt {
+    /// Task ID
+    pub task_id: String,
+    /// Content ID of the task manifest
+    pub manifest_hash: ContentId,
+    /// Hash of the result returned by the worker
+    pub result_hash: ContentId,
+    /// Output files of the task, sorted by name
+    #[serde(default)]
+    pub outputs: Vec<StagedFile>,
+    /// Final status of the task on the worker
+    pub status: TaskStatus,
+    /// Resources used
+    pub resources: ReceiptResources,
+    /// Reward in VOID tokens offered by the manifest
+    pub reward_tokens: u64,
+    /// Start of execution on the worker
+    pub started_at: DateTime<Utc>,
+    /// End of execution on the worker
+    pub finished_at: DateTime<Utc>,
+    /// Peer that ran the task
+    pub worker: PeerId,
+    /// Peer that dispatched the task
+    pub requester: PeerId,
+    /// Worker signature over the receipt
+    pub worker_signature: Vec<u8>,
+    /// Requester countersignature, once the result has been accepted
+    pub requester_signature: Option<Vec<u8>>,
+}
+
+impl TaskReceipt {
+    /// Builds a receipt and signs it as the worker
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign(
+        task_id: String,
+        manifest_hash: ContentId,
+        result_hash: ContentId,
+        outputs: Vec<StagedFile>,
+        status: TaskStatus,
+        resources: ReceiptResources,
+        reward_tokens: u64,
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+        requester: PeerId,
+        worker: &NodeIdentity,
+    ) -> Self {
+        let mut receipt = Self {
+            task_id,
+            manifest_hash,
+            result_hash,
+            outputs,
+            status,
+            resources,
+            reward_tokens,
+            started_at,
+            finished_at,
+            worker: worker.peer_id(),
+            requester,
+            worker_signature: Vec::new(),
+            requester_signature: None,
+        };
+        receipt.worker_signature = worker.sign(&receipt.worker_message());
+        receipt
+    }
+
+    /// Countersigns the receipt as its requester
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `requester` is not the requester named in the
+    /// receipt or the worker signature is invalid
+    pub fn countersign(&mut self, requester: &NodeIdentity) -> SynapseResult<()> {
+        if requester.peer_id() != self.requester {
+            return Err(SynapseError::Dispatch(format!(
+                "receipt of task {} names another requester", self.task_id
+            )));
+        }
+        if !self.verify_worker() {
+            return Err(SynapseError::Dispatch(format!(
+                "receipt of task {} has an invalid worker signature", self.task_id
+            )));
+        }
+        self.requester_signature = Some(requester.sign(&self.requester_message()));
+        Ok(())
+    }
+
+    /// Whether the worker signature is valid
+    pub fn verify_worker(&self) -> bool {
+        verify_signature(&self.worker, &self.worker_message(), &self.worker_signature)
+    }
+
+    /// Whether both the worker signature and the requester countersignature
+    /// are present and valid
+    pub fn verify(&self) -> bool {
+        self.verify_worker()
+            && self.requester_signature.as_ref().is_some_and(|signature| {
+                verify_signature(&self.requester, &self.requester_message(), signature)
+            })
+    }
+
+    /// Whether the receipt entitles its worker to the task reward
+    pub fn is_payable(&self) -> bool {
+        self.status == TaskStatus::Completed && self.verify()
+    }
+
+    fn worker_message(&self) -> Vec<u8> {
+        let timestamp = |at: &DateTime<Utc>| at.to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let used = &self.resources.used;
+
+        let mut message = WORKER_SIGNATURE_DOMAIN.to_vec();
+        push_field(&mut message, Some(self.task_id.as_bytes()));
+        push_field(&mut message, Some(self.manifest_hash.as_str().as_bytes()));
+        push_field(&mut message, Some(self.result_hash.as_str().as_bytes()));
+        push_field(&mut message, Some(format!("{:?}", self.status).as_bytes()));
+        push_field(&mut message, Some(&used.cpu_percent.to_le_bytes()));
+        push_field(&mut message, Some(&used.peak_cpu_percent.to_le_bytes()));
+        push_field(&mut message, Some(&used.ram_bytes.to_le_bytes()));
+        push_field(&mut message, Some(&used.peak_ram_bytes.to_le_bytes()));
+        push_field(&mut message, Some(&used.read_bytes.to_le_bytes()));
+        push_field(&mut message, Some(&used.written_bytes.to_le_bytes()));
+        push_field(&mut message, Some(&used.wall_time_secs.to_le_bytes()));
+        push_field(&mut message, Some(&used.cpu_time_secs.to_le_bytes()));
+        push_field(&mut message, Some(&self.resources.wall_time_secs.to_le_bytes()));
+        push_field(&mut message, Some(&self.reward_tokens.to_le_bytes()));
+        push_field(&mut message, Some(timestamp(&self.started_at).as_bytes()));
+        push_field(&mut message, Some(timestamp(&self.finished_at).as_bytes()));
+        push_field(&mut message, Some(self.worker.as_bytes()));
+        push_field(&mut message, Some(self.requester.as_bytes()));
+        // Appended last so that receipts without outputs keep their message
+        for output in &self.outputs {
+            push_field(&mut message, Some(output.name.as_bytes()));
+            push_field(&mut message, Some(output.content_id.as_str().as_bytes()));
+            push_field(&mut message, Some(&output.size.to_le_bytes()));
+        }
+        message
+    }
+
+    fn requester_message(&self) -> Vec<u8> {
+        let mut message = REQUESTER_SIGNATURE_DOMAIN.to_vec();
+        push_field(&mut message, Some(&self.worker_message()));
+        push_field(&mut message, Some(&self.worker_signature));
+        message
+    }
+}
+
+/// Receipts kept on disk, one file per task and worker
+pub struct ReceiptStore {
+    dir: PathBuf,
+}
+
+impl ReceiptStore {
+    /// Opens the receipt store in `dir`
+    pub fn open(dir: impl AsRef<Path>) -> SynapseResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Stores a receipt
+    ///
+    /// A countersigned receipt is never replaced by one without the
+    /// countersignature.
+    pub fn save(&self, receipt: &TaskReceipt) -> SynapseResult<()> {
+        let path = self.path(&receipt.task_id, &receipt.worker);
+        if receipt.requester_signature.is_none() && self.load(&path)?.is_some_and(|r| r.requester_signature.is_some()) {
+            return Ok(());
+        }
+        let bytes = serde_json::to_vec_pretty(receipt).map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        write_atomic(&path, &bytes)?;
+        Ok(())
+    }
+
+    /// Gets the receipt `worker` issued for a task
+    pub fn get(&self, task_id: &str, worker: &str) -> SynapseResult<Option<TaskReceipt>> {
+        self.load(&self.path(task_id, worker))
+    }
+
+    /// Gets the receipts of a task
+    pub fn for_task(&self, task_id: &str) -> SynapseResult<Vec<TaskReceipt>> {
+        Ok(self.all()?.into_iter().filter(|r| r.task_id == task_id).collect())
+    }
+
+    /// Gets all stored receipts
+    pub fn all(&self) -> SynapseResult<Vec<TaskReceipt>> {
+        let mut receipts = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                receipts.extend(self.load(&path)?);
+            }
+        }
+        receipts.sort_by_key(|r| r.finished_at);
+        Ok(receipts)
+    }
+
+    /// Sums the rewards `worker` is entitled to by payable receipts
+    pub fn earned_tokens(&self, worker: &str) -> SynapseResult<u64> {
+        Ok(self
+            .all()?
+            .iter()
+            .filter(|r| r.worker == worker && r.is_payable())
+            .map(|r| r.reward_tokens)
+            .sum())
+    }
+
+    fn path(&self, task_id: &str, worker: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}.json", task_id, worker))
+    }
+
+    fn load(&self, path: &Path) -> SynapseResult<Option<TaskReceipt>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        serde_json::from_slice(&fs::read(path)?)
+            .map(Some)
+            .map_err(|e| SynapseError::Corrupted(format!("receipt {}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(worker: &NodeIdentity, requester: &NodeIdentity) -> TaskReceipt {
+        let started_at = Utc::now();
+        TaskReceipt::sign(
+            "task".to_string(),
+            ContentId::for_bytes(b"manifest"),
+            ContentId::for_bytes(b"result"),
+            vec![StagedFile { name: "out".to_string(), content_id: ContentId::for_bytes(b"42"), size: 2 }],
+            TaskStatus::Completed,
+            ReceiptResources {
+                used: MeasuredResourceUsage { peak_cpu_percent: 10.0, peak_ram_bytes: 1 << 29, cpu_time_secs: 1.2, ..Default::default() },
+                wall_time_secs: 1.5,
+            },
+            25,
+            started_at,
+            started_at + chrono::Duration::milliseconds(1500),
+            requester.peer_id(),
+            worker,
+        )
+    }
+
+    #[test]
+    fn test_receipt_is_verifiable_offline() {
+        let worker = NodeIdentity::generate();
+        let requester = NodeIdentity::generate();
+        let mut receipt = receipt(&worker, &requester);
+        assert!(receipt.verify_worker());
+        assert!(!receipt.verify());
+        assert!(receipt.countersign(&NodeIdentity::generate()).is_err());
+
+        receipt.countersign(&requester).unwrap();
+        // The stored form must verify on its own
+        let stored: TaskReceipt = serde_json::from_slice(&serde_json::to_vec(&receipt).unwrap()).unwrap();
+        assert!(stored.verify());
+        assert!(stored.is_payable());
+
+        let mut inflated = stored.clone();
+        inflated.reward_tokens = 1000;
+        assert!(!inflated.verify());
+        let mut understated = stored.clone();
+        understated.resources.used.cpu_time_secs = 0.1;
+        assert!(!understated.verify());
+        let mut swapped = stored.clone();
+        swapped.outputs[0].content_id = ContentId::for_bytes(b"43");
+        assert!(!swapped.verify());
+    }
+
+    #[test]
+    fn test_store_keeps_countersigned_receipt() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ReceiptStore::open(dir.path()).unwrap();
+        let worker = NodeIdentity::generate();
+        let requester = NodeIdentity::generate();
+
+        let unsigned = receipt(&worker, &requester);
+        let mut countersigned = unsigned.clone();
+        countersigned.countersign(&requester).unwrap();
+        store.save(&countersigned).unwrap();
+        store.save(&unsigned).unwrap();
+
+        let receipts = store.for_task("task").unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert!(receipts[0].verify());
+        assert_eq!(store.earned_tokens(&worker.peer_id()).unwrap(), 25);
+    }
+}
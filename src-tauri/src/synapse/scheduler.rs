@@ -0,0 +1,370 @@
+//! Priority scheduler for Synapse tasks
+//!
+//! The scheduler orders runnable tasks by effective priority (the manifest
+//! priority plus an aging bonus for time spent waiting), then by deadline
+//! and reward. When a Critical task does not fit into the free resources,
+//! lower-priority running tasks are preempted to make room for it.
+
+use chrono::{DateTime, Duration, Utc};
+use std::cmp::Ordering;
+
+use super::manifest::{ResourceRequest, TaskPlacement};
+use super::store::TaskRecord;
+use crate::ui_api::{SchedulingAction, SchedulingDecision, TaskPriority, TaskStatus};
+
+/// Scheduler tuning parameters
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    /// Waiting time after which a task gains one priority level
+    pub aging_interval: Duration,
+    /// Waiting time after which a blocked task reserves resources, so that
+    /// smaller tasks can no longer be backfilled ahead of it
+    pub starvation_threshold: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            aging_interval: Duration::minutes(10),
+            starvation_threshold: Duration::minutes(30),
+        }
+    }
+}
+
+/// Resources the node is willing to give to tasks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceCapacity {
+    /// CPU share in percent of the host
+    pub cpu_percent: f32,
+    /// RAM in GB
+    pub ram_gb: f32,
+    /// GPU share in percent
+    pub gpu_percent: f32,
+}
+
+impl ResourceCapacity {
+    /// Whether `request` fits into this capacity
+    pub fn fits(&self, request: &ResourceRequest) -> bool {
+        request.cpu_percent as f32 <= self.cpu_percent
+            && request.ram_gb <= self.ram_gb
+            && request.gpu_percent as f32 <= self.gpu_percent
+    }
+
+    /// Gets what is left of this capacity after reserving the resources of
+    /// the running local tasks among `records`
+    pub fn remaining<'a>(&self, records: impl IntoIterator<Item = &'a TaskRecord>) -> Self {
+        let mut remaining = *self;
+        for record in records {
+            if record.status == TaskStatus::Running && record.manifest.placement == TaskPlacement::Local {
+                remaining.reserve(&record.manifest.resources);
+            }
+        }
+        remaining
+    }
+
+    fn reserve(&mut self, request: &ResourceRequest) {
+        self.cpu_percent -= request.cpu_percent as f32;
+        self.ram_gb -= request.ram_gb;
+        self.gpu_percent -= request.gpu_percent as f32;
+    }
+
+    fn release(&mut self, request: &ResourceRequest) {
+        self.cpu_percent += request.cpu_percent as f32;
+        self.ram_gb += request.ram_gb;
+        self.gpu_percent += request.gpu_percent as f32;
+    }
+}
+
+/// Decision produced by a scheduling pass for one task
+#[derive(Debug, Clone)]
+pub struct PlannedAction {
+    /// Task the decision applies to
+    pub task_id: String,
+    /// The decision itself
+    pub decision: SchedulingDecision,
+}
+
+/// Priority scheduler with preemption and aging
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    config: SchedulerConfig,
+}
+
+struct Candidate<'a> {
+    record: &'a TaskRecord,
+    effective_priority: f32,
+    waited: Duration,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with the given configuration
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Computes the priority of a task including its aging bonus
+    ///
+    /// Aging never lifts a task to Critical, so only genuinely Critical
+    /// tasks can preempt others.
+    pub fn effective_priority(&self, record: &TaskRecord, now: DateTime<Utc>) -> f32 {
+        let base = priority_level(record.manifest.priority);
+        if record.manifest.priority == TaskPriority::Critical {
+            return base;
+        }
+        let aging_secs = self.config.aging_interval.num_seconds().max(1) as f32;
+        let bonus = waiting_time(record, now).num_seconds().max(0) as f32 / aging_secs;
+        (base + bonus).min(priority_level(TaskPriority::Critical) - 0.5)
+    }
+
+    /// Plans one scheduling pass over `records`
+    ///
+    /// Runnable tasks are the pending ones plus those previously paused by
+    /// preemption. Tasks paused by the user are left alone, and so are
+    /// tasks placed on remote peers.
+    pub fn plan<'a>(
+        &self,
+        records: impl IntoIterator<Item = &'a TaskRecord>,
+        capacity: ResourceCapacity,
+        now: DateTime<Utc>,
+    ) -> Vec<PlannedAction> {
+        let mut available = capacity;
+        let mut running: Vec<&TaskRecord> = Vec::new();
+        let mut candidates: Vec<Candidate> = Vec::new();
+
+        for record in records {
+            if record.manifest.placement != TaskPlacement::Local {
+                continue;
+            }
+            if record.status == TaskStatus::Running {
+                available.reserve(&record.manifest.resources);
+                running.push(record);
+            } else if (record.status == TaskStatus::Pending && !record.is_backing_off(now)) || record.is_preempted() {
+                candidates.push(Candidate {
+                    record,
+                    effective_priority: self.effective_priority(record, now),
+                    waited: waiting_time(record, now),
+                });
+            }
+        }
+        candidates.sort_by(compare_candidates);
+
+        // Preempt the least important work first; among equals, the task
+        // that started most recently loses the least progress.
+        running.sort_by(|a, b| {
+            a.manifest.priority
+                .cmp(&b.manifest.priority)
+                .then_with(|| last_status_change(b).cmp(&last_status_change(a)))
+        });
+
+        let mut actions = Vec::new();
+        let mut reserved_for: Option<String> = None;
+
+        for candidate in candidates {
+            let record = candidate.record;
+            let task_id = record.manifest.id.clone();
+            let request = &record.manifest.resources;
+            let decision = |action, related: Option<String>, reason: String| SchedulingDecision {
+                timestamp: now,
+                action,
+                effective_priority: candidate.effective_priority,
+                related_task_id: related,
+                reason,
+            };
+
+            if let Some(blocked) = &reserved_for {
+                if record.manifest.priority != TaskPriority::Critical {
+                    actions.push(PlannedAction {
+                        task_id,
+                        decision: decision(
+                            SchedulingAction::Deferred,
+                            Some(blocked.clone()),
+                            format!("Resources reserved for starving task {}", blocked),
+                        ),
+                    });
+                    continue;
+                }
+            }
+
+            if available.fits(request) {
+                available.reserve(request);
+                actions.push(PlannedAction {
+                    task_id,
+                    decision: decision(
+                        SchedulingAction::Started,
+                        None,
+                        format!("Scheduled with effective priority {:.2}", candidate.effective_priority),
+                    ),
+                });
+                continue;
+            }
+
+            if record.manifest.priority == TaskPriority::Critical {
+                let victims = select_victims(&running, available, request);
+                if let Some(victims) = victims {
+                    for victim in &victims {
+                        available.release(&victim.manifest.resources);
+                        actions.push(PlannedAction {
+                            task_id: victim.manifest.id.clone(),
+                            decision: SchedulingDecision {
+                                timestamp: now,
+                                action: SchedulingAction::Preempted,
+                                effective_priority: self.effective_priority(victim, now),
+                                related_task_id: Some(task_id.clone()),
+                                reason: format!("Preempted by Critical task {}", task_id),
+                            },
+                        });
+                    }
+                    running.retain(|r| !victims.iter().any(|v| v.manifest.id == r.manifest.id));
+                    available.reserve(request);
+                    actions.push(PlannedAction {
+                        task_id,
+                        decision: decision(
+                            SchedulingAction::Started,
+                            None,
+                            format!("Critical task started after preempting {} task(s)", victims.len()),
+                        ),
+                    });
+                    continue;
+                }
+            }
+
+            if candidate.waited >= self.config.starvation_threshold && reserved_for.is_none() {
+                reserved_for = Some(task_id.clone());
+            }
+            actions.push(PlannedAction {
+                task_id,
+                decision: decision(
+                    SchedulingAction::Deferred,
+                    None,
+                    "Waiting for free resources".to_string(),
+                ),
+            });
+        }
+
+        actions
+    }
+}
+
+/// Picks running tasks to preempt so that `request` fits, or `None` if
+/// preempting every non-Critical task would still not be enough
+fn select_victims<'a>(
+    running: &[&'a TaskRecord],
+    available: ResourceCapacity,
+    request: &ResourceRequest,
+) -> Option<Vec<&'a TaskRecord>> {
+    let mut freed = available;
+    let mut victims = Vec::new();
+    for record in running.iter().filter(|r| r.manifest.priority < TaskPriority::Critical) {
+        if freed.fits(request) {
+            break;
+        }
+        freed.release(&record.manifest.resources);
+        victims.push(*record);
+    }
+    freed.fits(request).then_some(victims)
+}
+
+fn compare_candidates(a: &Candidate, b: &Candidate) -> Ordering {
+    b.effective_priority
+        .partial_cmp(&a.effective_priority)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| match (a.record.manifest.deadline, b.record.manifest.deadline) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        })
+        .then_with(|| b.record.manifest.reward_tokens.cmp(&a.record.manifest.reward_tokens))
+        .then_with(|| a.record.manifest.submitted_at.cmp(&b.record.manifest.submitted_at))
+}
+
+fn priority_level(priority: TaskPriority) -> f32 {
+    match priority {
+        TaskPriority::Low => 0.0,
+        TaskPriority::Normal => 1.0,
+        TaskPriority::High => 2.0,
+        TaskPriority::Critical => 3.0,
+    }
+}
+
+fn last_status_change(record: &TaskRecord) -> DateTime<Utc> {
+    record
+        .status_history
+        .last()
+        .map(|change| change.at)
+        .unwrap_or(record.manifest.submitted_at)
+}
+
+/// Time since the task last became runnable
+fn waiting_time(record: &TaskRecord, now: DateTime<Utc>) -> Duration {
+    now - last_status_change(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synapse::store::tests::manifest;
+
+    fn record(id: &str, priority: TaskPriority, status: TaskStatus, cpu: u8) -> TaskRecord {
+        let mut manifest = manifest(id);
+        manifest.priority = priority;
+        manifest.resources.cpu_percent = cpu;
+        let mut record = TaskRecord::new(manifest, None, Utc::now());
+        record.status = status;
+        record
+    }
+
+    fn capacity(cpu: f32) -> ResourceCapacity {
+        ResourceCapacity { cpu_percent: cpu, ram_gb: 16.0, gpu_percent: 100.0 }
+    }
+
+    fn action_for<'a>(actions: &'a [PlannedAction], id: &str) -> &'a SchedulingDecision {
+        &actions.iter().find(|a| a.task_id == id).unwrap().decision
+    }
+
+    #[test]
+    fn test_orders_by_priority_then_reward() {
+        let low = record("low", TaskPriority::Low, TaskStatus::Pending, 50);
+        let high = record("high", TaskPriority::High, TaskStatus::Pending, 50);
+        let mut rich = record("rich", TaskPriority::Normal, TaskStatus::Pending, 50);
+        rich.manifest.reward_tokens = 100;
+        let poor = record("poor", TaskPriority::Normal, TaskStatus::Pending, 50);
+
+        let actions = Scheduler::default().plan([&low, &high, &rich, &poor], capacity(100.0), Utc::now());
+        let started: Vec<&str> = actions
+            .iter()
+            .filter(|a| a.decision.action == SchedulingAction::Started)
+            .map(|a| a.task_id.as_str())
+            .collect();
+        assert_eq!(started, vec!["high", "rich"]);
+    }
+
+    #[test]
+    fn test_critical_task_preempts_lower_priority() {
+        let normal = record("normal", TaskPriority::Normal, TaskStatus::Running, 60);
+        let high = record("high", TaskPriority::High, TaskStatus::Running, 30);
+        let critical = record("critical", TaskPriority::Critical, TaskStatus::Pending, 50);
+
+        let actions = Scheduler::default().plan([&normal, &high, &critical], capacity(100.0), Utc::now());
+        let preempted = action_for(&actions, "normal");
+        assert_eq!(preempted.action, SchedulingAction::Preempted);
+        assert_eq!(preempted.related_task_id.as_deref(), Some("critical"));
+        assert_eq!(action_for(&actions, "critical").action, SchedulingAction::Started);
+        assert!(actions.iter().all(|a| a.task_id != "high"));
+    }
+
+    #[test]
+    fn test_aging_prevents_starvation() {
+        let now = Utc::now();
+        let mut old_low = record("old", TaskPriority::Low, TaskStatus::Pending, 60);
+        old_low.status_history.last_mut().unwrap().at = now - Duration::minutes(45);
+        let fresh_normal = record("fresh", TaskPriority::Normal, TaskStatus::Pending, 60);
+
+        let scheduler = Scheduler::default();
+        assert!(scheduler.effective_priority(&old_low, now) > scheduler.effective_priority(&fresh_normal, now));
+
+        let actions = scheduler.plan([&fresh_normal, &old_low], capacity(100.0), now);
+        assert_eq!(action_for(&actions, "old").action, SchedulingAction::Started);
+        assert_eq!(action_for(&actions, "fresh").action, SchedulingAction::Deferred);
+    }
+}
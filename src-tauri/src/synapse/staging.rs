@@ -0,0 +1,148 @@
+//! Task data staging through Chronicle
+//!
+//! Manifests reference their input files by Chronicle content ID instead
+//! of carrying the data, so that large inputs are moved by the storage
+//! layer rather than inside dispatch messages. Before a task starts, its
+//! inputs are fetched into the input directory and verified against their
+//! IDs; after it completed, the files in its output directory are stored
+//! in Chronicle and their IDs recorded in the task result and receipt.
+//! Files are streamed to and from the store, never read whole, which
+//! blocks: callers on the async runtime stage from a blocking task.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use super::util::is_plain_file_name;
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::{ContentId, ContentStore};
+
+/// File moved through Chronicle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedFile {
+    /// File name in the task's input or output directory
+    pub name: String,
+    /// Content ID of the file
+    pub content_id: ContentId,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Fetches `inputs` from Chronicle into `dir`
+///
+/// Inputs already staged with the expected content (e.g. by an earlier
+/// run of the task) are kept.
+///
+/// # Errors
+///
+/// Returns an error if an input name is not a plain file name, an input
+/// is not available or its content does not match its ID or size
+pub fn fetch_inputs(inputs: &[StagedFile], dir: &Path, content: &dyn ContentStore) -> SynapseResult<()> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    for input in inputs {
+        if !is_plain_file_name(&input.name) {
+            return Err(SynapseError::InvalidManifest(format!("invalid input name {:?}", input.name)));
+        }
+        let path = dir.join(&input.name);
+        if is_staged(&path, input)? {
+            continue;
+        }
+
+        // Written under a temporary name so that an interrupted fetch is
+        // never mistaken for the input
+        let tmp = dir.join(format!(".{}.part", input.name));
+        let size = {
+            let mut file = fs::File::create(&tmp)?;
+            let size = content.get_into(&input.content_id, &mut file)?;
+            file.sync_all()?;
+            size
+        };
+        if size != input.size {
+            fs::remove_file(&tmp)?;
+            return Err(SynapseError::Execution(format!(
+                "input {} has {} bytes, expected {}", input.name, size, input.size
+            )));
+        }
+        fs::rename(&tmp, &path)?;
+        log::debug!("Staged input {} ({}, {} bytes)", input.name, input.content_id, input.size);
+    }
+    Ok(())
+}
+
+/// Stores the regular files of `dir` in Chronicle
+///
+/// Returns the stored files sorted by name (a missing directory holds no
+/// files).
+pub fn upload_outputs(dir: &Path, content: &dyn ContentStore) -> SynapseResult<Vec<StagedFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let mut file = fs::File::open(entry.path())?;
+        let size = file.metadata()?.len();
+        let content_id = content.put_reader(&mut file)?;
+        outputs.push(StagedFile { name, content_id, size });
+    }
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(outputs)
+}
+
+/// Whether `path` already holds the content of `input`
+fn is_staged(path: &Path, input: &StagedFile) -> SynapseResult<bool> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == input.size => {
+            Ok(ContentId::for_reader(fs::File::open(path)?)? == input.content_id)
+        }
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chronicle::MemoryContentStore;
+
+    #[test]
+    fn test_inputs_are_verified_and_outputs_uploaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let chronicle = MemoryContentStore::default();
+        let data = b"training batch".to_vec();
+        let input = StagedFile {
+            name: "batch.bin".to_string(),
+            content_id: chronicle.put(&data).unwrap(),
+            size: data.len() as u64,
+        };
+
+        let input_dir = dir.path().join("input");
+        fetch_inputs(std::slice::from_ref(&input), &input_dir, &chronicle).unwrap();
+        assert_eq!(fs::read(input_dir.join("batch.bin")).unwrap(), data);
+
+        let missing = StagedFile { content_id: ContentId::for_bytes(b"elsewhere"), ..input.clone() };
+        fs::remove_file(input_dir.join("batch.bin")).unwrap();
+        assert!(fetch_inputs(&[missing], &input_dir, &chronicle).is_err());
+        let escaping = StagedFile { name: "../batch.bin".to_string(), ..input.clone() };
+        assert!(fetch_inputs(&[escaping], &input_dir, &chronicle).is_err());
+
+        let output_dir = dir.path().join("output");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("weights"), b"0.5").unwrap();
+        fs::write(output_dir.join("metrics"), b"loss=0.1").unwrap();
+        let outputs = upload_outputs(&output_dir, &chronicle).unwrap();
+        assert_eq!(outputs.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(), ["metrics", "weights"]);
+        assert_eq!(chronicle.get(&outputs[1].content_id).unwrap(), b"0.5");
+        assert_eq!(outputs[1].size, 3);
+    }
+}
@@ -0,0 +1,868 @@
+//! Persistent task store with a write-ahead log
+//!
+//! Every state change is appended to `tasks.wal` as one JSON line before it
+//! is applied in memory. The log is periodically compacted into
+//! `tasks.snapshot.json`, so a restart only has to replay the changes made
+//! since the last snapshot.
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::manifest::{TaskManifest, TaskPlacement};
+use super::staging::StagedFile;
+use super::workflow::WorkflowManifest;
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::ContentId;
+use crate::identity::PeerId;
+use crate::util::write_atomic;
+use crate::ui_api::{
+    ActiveTask, FailureKind, MeasuredResourceUsage, SchedulingAction, SchedulingDecision, TaskStatus,
+};
+
+const WAL_FILE: &str = "tasks.wal";
+const SNAPSHOT_FILE: &str = "tasks.snapshot.json";
+
+/// Number of log entries after which the log is folded into a snapshot
+const COMPACTION_THRESHOLD: u64 = 1000;
+
+/// Single entry in a task's status history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusChange {
+    /// New status
+    pub status: TaskStatus,
+    /// When the change happened
+    pub at: DateTime<Utc>,
+    /// Why the change happened (if known)
+    pub reason: Option<String>,
+}
+
+/// Reference to the latest checkpoint written by a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointRef {
+    /// Monotonic checkpoint number within the task
+    pub sequence: u64,
+    /// Location of the checkpoint data on disk
+    pub path: PathBuf,
+    /// Task progress at the time of the checkpoint
+    pub progress: f32,
+    /// Hash of the checkpoint data
+    pub content_id: ContentId,
+    /// Checkpoint timestamp
+    pub created_at: DateTime<Utc>,
+}
+
+/// Outcome of a finished task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskResult {
+    /// Process exit code (if the task ran to completion)
+    pub exit_code: Option<i32>,
+    /// Error description for failed tasks
+    pub error: Option<String>,
+    /// Completion timestamp
+    pub finished_at: DateTime<Utc>,
+    /// Hash of the result signed by the worker (remote tasks only)
+    #[serde(default)]
+    pub result_hash: Option<ContentId>,
+    /// Peer that produced the result (remote tasks only)
+    #[serde(default)]
+    pub worker: Option<PeerId>,
+    /// Peers whose redundant execution agreed with the result
+    #[serde(default)]
+    pub verified_by: Vec<PeerId>,
+    /// Cause of the failure (failed tasks only)
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    /// Result cache key of the task (cached tasks only)
+    #[serde(default)]
+    pub cache_key: Option<ContentId>,
+    /// Resources used by the last run (local tasks only)
+    #[serde(default)]
+    pub usage: Option<MeasuredResourceUsage>,
+    /// Output files stored in Chronicle (completed tasks only)
+    #[serde(default)]
+    pub outputs: Vec<StagedFile>,
+}
+
+impl TaskResult {
+    /// Builds the result of a task that failed before producing one
+    pub fn failed(kind: FailureKind, error: String) -> Self {
+        Self {
+            exit_code: None,
+            error: Some(error),
+            finished_at: Utc::now(),
+            result_hash: None,
+            worker: None,
+            verified_by: Vec::new(),
+            failure: Some(kind),
+            cache_key: None,
+            usage: None,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// Failed run of a task that was retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedAttempt {
+    /// Cause of the failure
+    pub kind: FailureKind,
+    /// Error description
+    pub error: Option<String>,
+    /// Peers that ran the attempt (remote tasks only)
+    #[serde(default)]
+    pub workers: Vec<PeerId>,
+    /// When the attempt failed
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Full persisted state of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskRecord {
+    /// Task manifest as submitted
+    pub manifest: TaskManifest,
+    /// Current status
+    pub status: TaskStatus,
+    /// Every status the task went through, oldest first
+    pub status_history: Vec<StatusChange>,
+    /// Progress percentage (0.0 - 100.0)
+    pub progress: f32,
+    /// Latest checkpoint (if any)
+    pub checkpoint: Option<CheckpointRef>,
+    /// Result once the task has finished
+    pub result: Option<TaskResult>,
+    /// Scheduler decisions, oldest first
+    #[serde(default)]
+    pub scheduling_decisions: Vec<SchedulingDecision>,
+    /// Peer that dispatched the task to this node
+    #[serde(default)]
+    pub requester: Option<PeerId>,
+    /// Peers the task is currently assigned to (remote tasks only)
+    #[serde(default)]
+    pub workers: Vec<PeerId>,
+    /// Earlier attempts that failed and were retried, oldest first
+    #[serde(default)]
+    pub failed_attempts: Vec<FailedAttempt>,
+    /// Time before which a task waiting for a retry is not started
+    #[serde(default)]
+    pub retry_at: Option<DateTime<Utc>>,
+    /// Last modification timestamp
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TaskRecord {
+    pub(super) fn new(manifest: TaskManifest, requester: Option<PeerId>, now: DateTime<Utc>) -> Self {
+        Self {
+            manifest,
+            status: TaskStatus::Pending,
+            status_history: vec![StatusChange {
+                status: TaskStatus::Pending,
+                at: now,
+                reason: Some("Submitted".to_string()),
+            }],
+            progress: 0.0,
+            checkpoint: None,
+            result: None,
+            scheduling_decisions: Vec::new(),
+            requester,
+            workers: Vec::new(),
+            failed_attempts: Vec::new(),
+            retry_at: None,
+            updated_at: now,
+        }
+    }
+
+    /// Gets the number of the current attempt, starting at 1
+    pub fn attempt(&self) -> u32 {
+        self.failed_attempts.len() as u32 + 1
+    }
+
+    /// Whether the task is waiting for the backoff before a retry
+    pub fn is_backing_off(&self, now: DateTime<Utc>) -> bool {
+        self.status == TaskStatus::Pending && self.retry_at.is_some_and(|at| at > now)
+    }
+
+    /// Gets the cause of the failure of a failed task, or of the last
+    /// failed attempt of a task waiting for a retry
+    pub fn failure_kind(&self) -> Option<FailureKind> {
+        match &self.result {
+            Some(result) => result.failure,
+            None => self.failed_attempts.last().map(|attempt| attempt.kind),
+        }
+    }
+
+    /// Whether the task is paused because the scheduler preempted it
+    /// (as opposed to being paused by the user)
+    ///
+    /// A preempted task may be deferred several times before it resumes,
+    /// so deferrals are skipped when looking for the preemption.
+    pub fn is_preempted(&self) -> bool {
+        self.status == TaskStatus::Paused
+            && self
+                .scheduling_decisions
+                .iter()
+                .rev()
+                .find(|d| d.action != SchedulingAction::Deferred)
+                .is_some_and(|d| d.action == SchedulingAction::Preempted)
+    }
+
+    /// Converts the record into the summary shown in the task list
+    pub fn to_active_task(&self) -> ActiveTask {
+        ActiveTask {
+            id: self.manifest.id.clone(),
+            name: self.manifest.name.clone(),
+            aibox_id: self.manifest.aibox_id.clone(),
+            progress: self.progress.clamp(0.0, 100.0) as u8,
+            time_remaining_secs: None,
+            is_stalled: false,
+            priority: self.manifest.priority,
+            reward_tokens: self.manifest.reward_tokens,
+            status: self.status,
+            resource_usage: (&self.manifest.resources).into(),
+        }
+    }
+}
+
+/// Result of reconciling the store after a restart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// Pending tasks put back into the queue
+    pub requeued: Vec<String>,
+    /// Orphaned tasks that will resume from their latest checkpoint
+    pub resumed: Vec<String>,
+    /// Orphaned tasks without a checkpoint, marked as failed
+    pub failed: Vec<String>,
+}
+
+/// State change recorded in the write-ahead log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op")]
+enum WalEntry {
+    Submitted {
+        manifest: TaskManifest,
+        #[serde(default)]
+        requester: Option<PeerId>,
+        at: DateTime<Utc>,
+    },
+    StatusChanged { task_id: String, change: StatusChange },
+    Progress { task_id: String, progress: f32, at: DateTime<Utc> },
+    Checkpointed { task_id: String, checkpoint: CheckpointRef },
+    Finished { task_id: String, result: TaskResult, change: StatusChange },
+    Scheduled { task_id: String, decision: SchedulingDecision },
+    WorkerAssigned { task_id: String, worker: PeerId, at: DateTime<Utc> },
+    WorkerReleased { task_id: String, worker: PeerId, at: DateTime<Utc> },
+    RetryScheduled { task_id: String, attempt: FailedAttempt, retry_at: DateTime<Utc>, change: StatusChange },
+    WorkflowSubmitted { workflow: WorkflowManifest },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WalLine {
+    seq: u64,
+    #[serde(flatten)]
+    entry: WalEntry,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    /// Sequence number of the last log entry folded into this snapshot
+    last_seq: u64,
+    tasks: Vec<TaskRecord>,
+    #[serde(default)]
+    workflows: Vec<WorkflowManifest>,
+}
+
+/// On-disk task store
+pub struct TaskStore {
+    dir: PathBuf,
+    wal: File,
+    /// Sequence number of the last appended log entry
+    seq: u64,
+    /// Entries appended since the last snapshot
+    wal_len: u64,
+    /// Length of the log up to its last complete entry
+    wal_bytes: u64,
+    tasks: HashMap<String, TaskRecord>,
+    workflows: HashMap<String, WorkflowManifest>,
+}
+
+impl TaskStore {
+    /// Opens the store in `dir`, replaying the snapshot and the log
+    ///
+    /// A torn entry at the end of the log (left by a crash mid-write) is
+    /// discarded together with anything after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or the snapshot
+    /// cannot be read
+    pub fn open(dir: impl AsRef<Path>) -> SynapseResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let snapshot: Snapshot = if snapshot_path.exists() {
+            serde_json::from_slice(&fs::read(&snapshot_path)?)
+                .map_err(|e| SynapseError::Corrupted(format!("snapshot: {}", e)))?
+        } else {
+            Snapshot::default()
+        };
+
+        let mut tasks: HashMap<String, TaskRecord> = snapshot
+            .tasks
+            .into_iter()
+            .map(|record| (record.manifest.id.clone(), record))
+            .collect();
+        let mut workflows: HashMap<String, WorkflowManifest> = snapshot
+            .workflows
+            .into_iter()
+            .map(|workflow| (workflow.id.clone(), workflow))
+            .collect();
+        let mut seq = snapshot.last_seq;
+        let mut wal_len = 0;
+        let mut wal_bytes = 0;
+
+        let wal_path = dir.join(WAL_FILE);
+        if wal_path.exists() {
+            let mut valid_bytes = 0u64;
+            let reader = BufReader::new(File::open(&wal_path)?);
+            for line in reader.split(b'\n') {
+                let line = line?;
+                let parsed = serde_json::from_slice::<WalLine>(&line);
+                match parsed {
+                    Ok(wal_line) => {
+                        valid_bytes += line.len() as u64 + 1;
+                        wal_len += 1;
+                        if wal_line.seq > seq {
+                            seq = wal_line.seq;
+                            apply(&mut tasks, &mut workflows, wal_line.entry);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Discarding torn task log tail at byte {}: {}", valid_bytes, e);
+                        break;
+                    }
+                }
+            }
+            let file = OpenOptions::new().write(true).open(&wal_path)?;
+            file.set_len(valid_bytes)?;
+            file.sync_all()?;
+            wal_bytes = valid_bytes;
+        }
+
+        let wal = OpenOptions::new().create(true).append(true).open(&wal_path)?;
+        log::info!(
+            "Opened task store at {} with {} tasks and {} workflows",
+            dir.display(), tasks.len(), workflows.len()
+        );
+
+        Ok(Self { dir, wal, seq, wal_len, wal_bytes, tasks, workflows })
+    }
+
+    /// Adds a new task to the queue
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest is invalid, a task with the same ID
+    /// already exists or the log cannot be written
+    pub fn submit(&mut self, manifest: TaskManifest) -> SynapseResult<()> {
+        self.submit_for(manifest, None)
+    }
+
+    /// Adds a task dispatched to this node by `requester`
+    pub fn submit_for(&mut self, manifest: TaskManifest, requester: Option<PeerId>) -> SynapseResult<()> {
+        self.check_new(&manifest)?;
+        self.append(WalEntry::Submitted { manifest, requester, at: Utc::now() })
+    }
+
+    /// Checks that `manifest` is valid and its ID is not taken yet
+    pub(super) fn check_new(&self, manifest: &TaskManifest) -> SynapseResult<()> {
+        manifest.validate()?;
+        if self.tasks.contains_key(&manifest.id) || self.workflow_of(&manifest.id).is_some() {
+            return Err(SynapseError::DuplicateTask(manifest.id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Adds a workflow; its tasks are queued as their dependencies finish
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workflow is invalid, or the workflow or one
+    /// of its tasks already exists
+    pub fn submit_workflow(&mut self, workflow: WorkflowManifest) -> SynapseResult<()> {
+        workflow.validate()?;
+        if self.workflows.contains_key(&workflow.id) {
+            return Err(SynapseError::DuplicateTask(format!("workflow {}", workflow.id)));
+        }
+        for task in &workflow.tasks {
+            let id = &task.manifest.id;
+            if self.tasks.contains_key(id) || self.workflow_of(id).is_some() {
+                return Err(SynapseError::DuplicateTask(id.clone()));
+            }
+        }
+        self.append(WalEntry::WorkflowSubmitted { workflow })
+    }
+
+    /// Queues a task of a workflow once its dependencies have finished
+    pub(super) fn submit_workflow_task(&mut self, manifest: TaskManifest) -> SynapseResult<()> {
+        if self.tasks.contains_key(&manifest.id) {
+            return Err(SynapseError::DuplicateTask(manifest.id));
+        }
+        self.append(WalEntry::Submitted { manifest, requester: None, at: Utc::now() })
+    }
+
+    /// Moves a task to a new status
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the task is unknown or already finished
+    pub fn set_status(&mut self, task_id: &str, status: TaskStatus, reason: Option<String>) -> SynapseResult<()> {
+        let record = self.get_required(task_id)?;
+        if is_terminal(record.status) {
+            return Err(SynapseError::InvalidTransition(format!(
+                "task {} is already {:?}", task_id, record.status
+            )));
+        }
+        self.append(WalEntry::StatusChanged {
+            task_id: task_id.to_string(),
+            change: StatusChange { status, at: Utc::now(), reason },
+        })
+    }
+
+    /// Records task progress (0.0 - 100.0)
+    ///
+    /// Progress entries are not fsynced; losing the last few on a crash
+    /// only makes the reported progress slightly stale.
+    pub fn set_progress(&mut self, task_id: &str, progress: f32) -> SynapseResult<()> {
+        self.get_required(task_id)?;
+        self.append(WalEntry::Progress {
+            task_id: task_id.to_string(),
+            progress: progress.clamp(0.0, 100.0),
+            at: Utc::now(),
+        })
+    }
+
+    /// Records the latest checkpoint of a task
+    pub fn record_checkpoint(&mut self, task_id: &str, checkpoint: CheckpointRef) -> SynapseResult<()> {
+        self.get_required(task_id)?;
+        self.append(WalEntry::Checkpointed { task_id: task_id.to_string(), checkpoint })
+    }
+
+    /// Records a scheduler decision for a task
+    ///
+    /// A deferral is only recorded when it differs from the previous
+    /// decision, so that a task waiting in the queue does not flood the log.
+    pub fn record_decision(&mut self, task_id: &str, decision: SchedulingDecision) -> SynapseResult<()> {
+        let record = self.get_required(task_id)?;
+        if decision.action == SchedulingAction::Deferred {
+            if let Some(last) = record.scheduling_decisions.last() {
+                if last.action == SchedulingAction::Deferred && last.reason == decision.reason {
+                    return Ok(());
+                }
+            }
+        }
+        self.append(WalEntry::Scheduled { task_id: task_id.to_string(), decision })
+    }
+
+    /// Records that a remote task was assigned to `worker`
+    pub fn add_worker(&mut self, task_id: &str, worker: PeerId) -> SynapseResult<()> {
+        self.get_required(task_id)?;
+        self.append(WalEntry::WorkerAssigned { task_id: task_id.to_string(), worker, at: Utc::now() })
+    }
+
+    /// Records that `worker` no longer runs a remote task
+    pub fn remove_worker(&mut self, task_id: &str, worker: PeerId) -> SynapseResult<()> {
+        self.get_required(task_id)?;
+        self.append(WalEntry::WorkerReleased { task_id: task_id.to_string(), worker, at: Utc::now() })
+    }
+
+    /// Records a failed attempt and puts the task back into the queue to be
+    /// retried from `retry_at`
+    ///
+    /// The task keeps its latest checkpoint but loses its workers.
+    pub fn schedule_retry(&mut self, task_id: &str, attempt: FailedAttempt, retry_at: DateTime<Utc>) -> SynapseResult<()> {
+        let record = self.get_required(task_id)?;
+        if is_terminal(record.status) {
+            return Err(SynapseError::InvalidTransition(format!(
+                "task {} is already {:?}", task_id, record.status
+            )));
+        }
+        let reason = format!(
+            "Attempt {} failed ({:?}); retrying at {}",
+            record.attempt(), attempt.kind, retry_at.format("%H:%M:%S")
+        );
+        let change = StatusChange { status: TaskStatus::Pending, at: Utc::now(), reason: Some(reason) };
+        self.append(WalEntry::RetryScheduled { task_id: task_id.to_string(), attempt, retry_at, change })
+    }
+
+    /// Stores the result of a finished task and moves it to `status`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the task is unknown, already finished, or
+    /// `status` is not a terminal status
+    pub fn finish(&mut self, task_id: &str, status: TaskStatus, result: TaskResult) -> SynapseResult<()> {
+        if !is_terminal(status) {
+            return Err(SynapseError::InvalidTransition(format!(
+                "{:?} is not a final status", status
+            )));
+        }
+        let record = self.get_required(task_id)?;
+        if is_terminal(record.status) {
+            return Err(SynapseError::InvalidTransition(format!(
+                "task {} is already {:?}", task_id, record.status
+            )));
+        }
+        let change = StatusChange { status, at: result.finished_at, reason: result.error.clone() };
+        self.append(WalEntry::Finished { task_id: task_id.to_string(), result, change })
+    }
+
+    /// Gets a task record by ID
+    pub fn get(&self, task_id: &str) -> Option<&TaskRecord> {
+        self.tasks.get(task_id)
+    }
+
+    /// Iterates over all task records
+    pub fn records(&self) -> impl Iterator<Item = &TaskRecord> {
+        self.tasks.values()
+    }
+
+    /// Gets a workflow by ID
+    pub fn workflow(&self, workflow_id: &str) -> Option<&WorkflowManifest> {
+        self.workflows.get(workflow_id)
+    }
+
+    /// Iterates over all workflows
+    pub fn workflows(&self) -> impl Iterator<Item = &WorkflowManifest> {
+        self.workflows.values()
+    }
+
+    /// Gets the workflow a task belongs to
+    pub fn workflow_of(&self, task_id: &str) -> Option<&WorkflowManifest> {
+        self.workflows.values().find(|workflow| workflow.task(task_id).is_some())
+    }
+
+    /// Gets IDs of pending tasks in submission order
+    pub fn pending_queue(&self) -> Vec<String> {
+        let mut pending: Vec<&TaskRecord> = self
+            .tasks
+            .values()
+            .filter(|r| r.status == TaskStatus::Pending)
+            .collect();
+        pending.sort_by_key(|r| r.manifest.submitted_at);
+        pending.into_iter().map(|r| r.manifest.id.clone()).collect()
+    }
+
+    /// Reconciles tasks left behind by a previous run
+    ///
+    /// No task process survives a restart, so tasks that were `Running`
+    /// are orphaned. Those with a checkpoint go back to `Pending` and will
+    /// resume from it; the rest are marked `Failed`. Paused tasks keep
+    /// their status only if they can be resumed from a checkpoint. Tasks
+    /// running on a remote worker are left alone; the dispatcher picks up
+    /// monitoring them again.
+    pub fn recover(&mut self) -> SynapseResult<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+
+        let orphaned: Vec<(String, TaskStatus, Option<u64>)> = self
+            .tasks
+            .values()
+            .filter(|r| matches!(r.status, TaskStatus::Running | TaskStatus::Paused))
+            .filter(|r| r.manifest.placement == TaskPlacement::Local)
+            .map(|r| (r.manifest.id.clone(), r.status, r.checkpoint.as_ref().map(|c| c.sequence)))
+            .collect();
+
+        for (task_id, status, checkpoint) in orphaned {
+            match (status, checkpoint) {
+                (TaskStatus::Running, Some(sequence)) => {
+                    self.set_status(
+                        &task_id,
+                        TaskStatus::Pending,
+                        Some(format!("Resuming from checkpoint #{} after restart", sequence)),
+                    )?;
+                    report.resumed.push(task_id);
+                }
+                (TaskStatus::Paused, Some(_)) => {}
+                _ => {
+                    self.finish(&task_id, TaskStatus::Failed, TaskResult::failed(
+                        FailureKind::Other,
+                        "Node restarted while the task was running".to_string(),
+                    ))?;
+                    report.failed.push(task_id);
+                }
+            }
+        }
+
+        report.requeued = self
+            .pending_queue()
+            .into_iter()
+            .filter(|id| !report.resumed.contains(id))
+            .collect();
+
+        log::info!(
+            "Task store recovery: {} requeued, {} resumed, {} failed",
+            report.requeued.len(), report.resumed.len(), report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// Folds the log into a fresh snapshot and truncates it
+    pub fn compact(&mut self) -> SynapseResult<()> {
+        let snapshot = Snapshot {
+            last_seq: self.seq,
+            tasks: self.tasks.values().cloned().collect(),
+            workflows: self.workflows.values().cloned().collect(),
+        };
+        let bytes = serde_json::to_vec(&snapshot)
+            .map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        write_atomic(&self.dir.join(SNAPSHOT_FILE), &bytes)?;
+
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.wal_len = 0;
+        self.wal_bytes = 0;
+        Ok(())
+    }
+
+    fn get_required(&self, task_id: &str) -> SynapseResult<&TaskRecord> {
+        self.tasks
+            .get(task_id)
+            .ok_or_else(|| SynapseError::TaskNotFound(task_id.to_string()))
+    }
+
+    fn append(&mut self, entry: WalEntry) -> SynapseResult<()> {
+        let durable = !matches!(entry, WalEntry::Progress { .. });
+        let line = WalLine { seq: self.seq + 1, entry };
+
+        let mut bytes = serde_json::to_vec(&line)
+            .map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        bytes.push(b'\n');
+        let written = self.wal.write_all(&bytes).and_then(|()| {
+            if durable {
+                self.wal.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            // A partial line would hide every later entry from replay
+            if let Err(truncate) = self.wal.set_len(self.wal_bytes) {
+                log::error!("Failed to cut a torn entry off the task log: {}", truncate);
+            }
+            return Err(e.into());
+        }
+
+        self.seq = line.seq;
+        self.wal_bytes += bytes.len() as u64;
+        self.wal_len += 1;
+        apply(&mut self.tasks, &mut self.workflows, line.entry);
+
+        if self.wal_len >= COMPACTION_THRESHOLD {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies a log entry to the in-memory state
+fn apply(tasks: &mut HashMap<String, TaskRecord>, workflows: &mut HashMap<String, WorkflowManifest>, entry: WalEntry) {
+    match entry {
+        WalEntry::Submitted { manifest, requester, at } => {
+            tasks
+                .entry(manifest.id.clone())
+                .or_insert_with(|| TaskRecord::new(manifest, requester, at));
+        }
+        WalEntry::StatusChanged { task_id, change } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.status = change.status;
+                record.updated_at = change.at;
+                record.status_history.push(change);
+            }
+        }
+        WalEntry::Progress { task_id, progress, at } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.progress = progress;
+                record.updated_at = at;
+            }
+        }
+        WalEntry::Checkpointed { task_id, checkpoint } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.updated_at = checkpoint.created_at;
+                record.checkpoint = Some(checkpoint);
+            }
+        }
+        WalEntry::Finished { task_id, result, change } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.result = Some(result);
+                record.status = change.status;
+                record.updated_at = change.at;
+                record.status_history.push(change);
+            }
+        }
+        WalEntry::Scheduled { task_id, decision } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.scheduling_decisions.push(decision);
+            }
+        }
+        WalEntry::WorkerAssigned { task_id, worker, at } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                if !record.workers.contains(&worker) {
+                    record.workers.push(worker);
+                }
+                record.updated_at = at;
+            }
+        }
+        WalEntry::WorkerReleased { task_id, worker, at } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.workers.retain(|w| *w != worker);
+                record.updated_at = at;
+            }
+        }
+        WalEntry::RetryScheduled { task_id, attempt, retry_at, change } => {
+            if let Some(record) = tasks.get_mut(&task_id) {
+                record.progress = record.checkpoint.as_ref().map_or(0.0, |c| c.progress);
+                record.workers.clear();
+                record.failed_attempts.push(attempt);
+                record.retry_at = Some(retry_at);
+                record.status = change.status;
+                record.updated_at = change.at;
+                record.status_history.push(change);
+            }
+        }
+        WalEntry::WorkflowSubmitted { workflow } => {
+            workflows.entry(workflow.id.clone()).or_insert(workflow);
+        }
+    }
+}
+
+fn is_terminal(status: TaskStatus) -> bool {
+    matches!(status, TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::synapse::cache::CacheMode;
+    use crate::synapse::manifest::{ResourceRequest, RetryPolicy, TaskRuntime};
+    use crate::ui_api::{SecurityLevel, TaskComplexity, TaskPriority, TaskType, VerificationMethod};
+
+    pub(crate) fn manifest(id: &str) -> TaskManifest {
+        TaskManifest {
+            id: id.to_string(),
+            name: format!("Task {}", id),
+            aibox_id: "AIbox #1".to_string(),
+            task_type: TaskType::DataProcessing,
+            priority: TaskPriority::Normal,
+            reward_tokens: 10,
+            model: None,
+            data_size_gb: 0.1,
+            inputs: Vec::new(),
+            complexity: TaskComplexity::Low,
+            verification: VerificationMethod::CryptographicSignature,
+            security: SecurityLevel::Standard,
+            runtime: TaskRuntime::Process {
+                program: "true".to_string(),
+                args: vec![],
+                env: HashMap::new(),
+            },
+            resources: ResourceRequest { cpu_percent: 10, ram_gb: 0.5, gpu_percent: 0 },
+            deadline: None,
+            retry: RetryPolicy::default(),
+            cache: CacheMode::default(),
+            placement: TaskPlacement::Local,
+            submitted_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_store_replays_log_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = TaskStore::open(dir.path()).unwrap();
+            store.submit(manifest("a")).unwrap();
+            store.set_status("a", TaskStatus::Running, None).unwrap();
+            store.set_progress("a", 42.0).unwrap();
+        }
+
+        let store = TaskStore::open(dir.path()).unwrap();
+        let record = store.get("a").unwrap();
+        assert_eq!(record.status, TaskStatus::Running);
+        assert_eq!(record.progress, 42.0);
+        assert_eq!(record.status_history.len(), 2);
+    }
+
+    #[test]
+    fn test_replay_keeps_timestamps_and_final_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let (updated_at, history) = {
+            let mut store = TaskStore::open(dir.path()).unwrap();
+            store.submit(manifest("a")).unwrap();
+            store.set_status("a", TaskStatus::Running, None).unwrap();
+            store.add_worker("a", "worker".to_string()).unwrap();
+            store.set_progress("a", 42.0).unwrap();
+            let entries = store.wal_len;
+            store.finish("a", TaskStatus::Completed, TaskResult::failed(FailureKind::Other, "done".to_string())).unwrap();
+            assert_eq!(store.wal_len, entries + 1);
+            let record = store.get("a").unwrap();
+            let history: Vec<DateTime<Utc>> = record.status_history.iter().map(|change| change.at).collect();
+            (record.updated_at, history)
+        };
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let store = TaskStore::open(dir.path()).unwrap();
+        let record = store.get("a").unwrap();
+        assert_eq!(record.status, TaskStatus::Completed);
+        assert_eq!(record.updated_at, updated_at);
+        assert_eq!(record.status_history.iter().map(|change| change.at).collect::<Vec<_>>(), history);
+    }
+
+    #[test]
+    fn test_store_discards_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = TaskStore::open(dir.path()).unwrap();
+            store.submit(manifest("a")).unwrap();
+        }
+        let mut wal = OpenOptions::new().append(true).open(dir.path().join(WAL_FILE)).unwrap();
+        wal.write_all(b"{\"seq\":2,\"op\":\"Stat").unwrap();
+
+        let mut store = TaskStore::open(dir.path()).unwrap();
+        assert_eq!(store.get("a").unwrap().status, TaskStatus::Pending);
+        store.set_status("a", TaskStatus::Running, None).unwrap();
+
+        let store = TaskStore::open(dir.path()).unwrap();
+        assert_eq!(store.get("a").unwrap().status, TaskStatus::Running);
+    }
+
+    #[test]
+    fn test_recovery_reconciles_orphaned_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = TaskStore::open(dir.path()).unwrap();
+            for id in ["pending", "running", "checkpointed"] {
+                store.submit(manifest(id)).unwrap();
+            }
+            store.set_status("running", TaskStatus::Running, None).unwrap();
+            store.set_status("checkpointed", TaskStatus::Running, None).unwrap();
+            store.record_checkpoint("checkpointed", CheckpointRef {
+                sequence: 1,
+                path: dir.path().join("checkpoint"),
+                progress: 50.0,
+                content_id: ContentId::for_bytes(b"state"),
+                created_at: Utc::now(),
+            }).unwrap();
+            store.compact().unwrap();
+        }
+
+        let mut store = TaskStore::open(dir.path()).unwrap();
+        let report = store.recover().unwrap();
+        assert_eq!(report.requeued, vec!["pending".to_string()]);
+        assert_eq!(report.resumed, vec!["checkpointed".to_string()]);
+        assert_eq!(report.failed, vec!["running".to_string()]);
+        assert_eq!(store.get("checkpointed").unwrap().status, TaskStatus::Pending);
+        assert_eq!(store.get("running").unwrap().status, TaskStatus::Failed);
+    }
+}
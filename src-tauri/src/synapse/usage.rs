@@ -0,0 +1,284 @@
+//! Resource accounting of running tasks
+//!
+//! Every sampling pass refreshes the process table once and attributes
+//! each process to the task whose process it descends from. Per task the
+//! tracker keeps the latest and peak CPU and memory use, the disk I/O of
+//! every process seen in the tree (so that exited children still count)
+//! and the CPU time, integrated from the sampled CPU usage.
+//!
+//! The same pass measures the [`HostLoad`] caused by everything else on
+//! the host, which tells the engine when to give way to the user.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use sysinfo::{Pid, Process, System};
+
+use crate::ui_api::{DetailedResourceUsage, MeasuredResourceUsage, ResourceUsage};
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Measured resource use of one task's process tree
+pub struct UsageTracker {
+    root: Pid,
+    started: Instant,
+    last_sample: Option<Instant>,
+    /// Disk I/O totals (read, written) of every process seen in the tree
+    io: HashMap<Pid, (u64, u64)>,
+    usage: MeasuredResourceUsage,
+}
+
+impl UsageTracker {
+    /// Starts tracking the process tree rooted at process `root`
+    pub fn new(root: u32) -> Self {
+        Self {
+            root: Pid::from_u32(root),
+            started: Instant::now(),
+            last_sample: None,
+            io: HashMap::new(),
+            usage: MeasuredResourceUsage::default(),
+        }
+    }
+
+    /// Records a sample from a refreshed process table and returns the
+    /// processes of the tree
+    ///
+    /// `cores` is the number of logical CPUs, used to express CPU usage as
+    /// a share of the whole machine.
+    pub fn record(&mut self, processes: &HashMap<Pid, Process>, cores: usize, now: Instant) -> HashSet<Pid> {
+        let tree = process_tree(self.root, processes);
+        let mut core_percent = 0.0;
+        let mut ram_bytes = 0;
+        for pid in &tree {
+            let Some(process) = processes.get(pid) else { continue };
+            core_percent += process.cpu_usage();
+            ram_bytes += process.memory();
+            let disk = process.disk_usage();
+            self.io.insert(*pid, (disk.total_read_bytes, disk.total_written_bytes));
+        }
+
+        let since = self.last_sample.unwrap_or(self.started);
+        self.usage.cpu_time_secs += f64::from(core_percent) / 100.0 * now.duration_since(since).as_secs_f64();
+        self.last_sample = Some(now);
+
+        let usage = &mut self.usage;
+        usage.cpu_percent = core_percent / cores.max(1) as f32;
+        usage.peak_cpu_percent = usage.peak_cpu_percent.max(usage.cpu_percent);
+        usage.ram_bytes = ram_bytes;
+        usage.peak_ram_bytes = usage.peak_ram_bytes.max(ram_bytes);
+        usage.read_bytes = self.io.values().map(|(read, _)| read).sum();
+        usage.written_bytes = self.io.values().map(|(_, written)| written).sum();
+        tree
+    }
+
+    /// Gets the usage measured so far
+    pub fn usage(&self) -> MeasuredResourceUsage {
+        MeasuredResourceUsage { wall_time_secs: self.started.elapsed().as_secs_f64(), ..self.usage.clone() }
+    }
+}
+
+/// Load caused by processes other than tasks (and this application)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostLoad {
+    /// CPU usage in percent of the whole machine
+    pub cpu_percent: f32,
+    /// One-minute load average per CPU, less the share of the tasks
+    pub load_per_cpu: f32,
+    /// Name and CPU usage (percent of one CPU) of the busiest process
+    pub busiest: Option<(String, f32)>,
+}
+
+/// Shared process table used to sample all running tasks at once
+pub struct UsageSampler {
+    system: System,
+    cores: usize,
+}
+
+impl Default for UsageSampler {
+    fn default() -> Self {
+        Self {
+            system: System::new(),
+            cores: std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+        }
+    }
+}
+
+impl UsageSampler {
+    /// Refreshes the process table, records a sample for every tracker and
+    /// measures the load of the rest of the host
+    pub fn sample<'a>(&mut self, trackers: impl IntoIterator<Item = &'a mut UsageTracker>) -> HostLoad {
+        self.system.refresh_processes();
+        let processes = self.system.processes();
+        let now = Instant::now();
+        let mut tasks = HashSet::new();
+        for tracker in trackers {
+            tasks.extend(tracker.record(processes, self.cores, now));
+        }
+        tasks.insert(Pid::from_u32(std::process::id()));
+
+        let mut load = HostLoad::default();
+        let mut task_cpus = 0.0;
+        for (pid, process) in processes {
+            if process.thread_kind().is_some() {
+                continue;
+            }
+            let cpu = process.cpu_usage();
+            if tasks.contains(pid) {
+                task_cpus += cpu / 100.0;
+                continue;
+            }
+            load.cpu_percent += cpu;
+            if load.busiest.as_ref().is_none_or(|(_, busiest)| cpu > *busiest) {
+                load.busiest = Some((process.name().to_string(), cpu));
+            }
+        }
+        let cores = self.cores.max(1) as f32;
+        load.cpu_percent /= cores;
+        load.load_per_cpu = (System::load_average().one as f32 - task_cpus).max(0.0) / cores;
+        load
+    }
+}
+
+impl From<&MeasuredResourceUsage> for ResourceUsage {
+    fn from(usage: &MeasuredResourceUsage) -> Self {
+        ResourceUsage {
+            cpu_percent: usage.cpu_percent.round().clamp(0.0, 100.0) as u8,
+            ram_gb: (usage.ram_bytes as f64 / BYTES_PER_GB) as f32,
+            gpu_percent: 0,
+        }
+    }
+}
+
+impl From<&MeasuredResourceUsage> for DetailedResourceUsage {
+    fn from(usage: &MeasuredResourceUsage) -> Self {
+        let summary = ResourceUsage::from(usage);
+        DetailedResourceUsage {
+            cpu_percent: summary.cpu_percent,
+            ram_gb: summary.ram_gb,
+            gpu_percent: 0,
+            gpu_memory_gb: 0.0,
+        }
+    }
+}
+
+/// Finds `root` and all processes descending from it
+///
+/// Threads are listed as processes on Linux but their usage is already
+/// included in that of their process, so they are left out.
+fn process_tree(root: Pid, processes: &HashMap<Pid, Process>) -> HashSet<Pid> {
+    let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+    for (pid, process) in processes {
+        if process.thread_kind().is_some() {
+            continue;
+        }
+        if let Some(parent) = process.parent() {
+            children.entry(parent).or_default().push(*pid);
+        }
+    }
+    let mut tree = HashSet::new();
+    let mut pending = vec![root];
+    while let Some(pid) = pending.pop() {
+        if processes.contains_key(&pid) && tree.insert(pid) {
+            pending.extend(children.get(&pid).into_iter().flatten());
+        }
+    }
+    tree
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+    use std::time::Duration;
+
+    /// `sh` spinning on a CPU with a sleeping child of its own, killed on drop
+    struct BusyShell(Child);
+
+    impl BusyShell {
+        fn start() -> Self {
+            let shell = Command::new("sh").args(["-c", "sleep 5 & while :; do :; done"]).spawn().unwrap();
+            // Processes without CPU time yet get no usage on the next refresh
+            std::thread::sleep(Duration::from_millis(200));
+            Self(shell)
+        }
+
+        fn stop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    impl Drop for BusyShell {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    fn refreshed(system: &mut System) -> &HashMap<Pid, Process> {
+        system.refresh_processes();
+        system.processes()
+    }
+
+    #[test]
+    fn test_process_tree_includes_children() {
+        let shell = BusyShell::start();
+        let mut system = System::new();
+        let processes = refreshed(&mut system);
+
+        let root = Pid::from_u32(shell.0.id());
+        let tree = process_tree(root, processes);
+        assert_eq!(tree.len(), 2);
+        assert!(tree.contains(&root));
+        let child = tree.iter().find(|pid| **pid != root).unwrap();
+        assert_eq!(processes[child].parent(), Some(root));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_process_tree_excludes_threads() {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || stopped.recv());
+        let mut system = System::new();
+        let processes = refreshed(&mut system);
+
+        let root = Pid::from_u32(std::process::id());
+        let threads: Vec<&Pid> = processes
+            .iter()
+            .filter(|(_, process)| process.thread_kind().is_some() && process.parent() == Some(root))
+            .map(|(pid, _)| pid)
+            .collect();
+        assert!(!threads.is_empty());
+        let tree = process_tree(root, processes);
+        assert!(tree.contains(&root));
+        assert!(threads.iter().all(|thread| !tree.contains(thread)));
+        stop.send(()).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_cpu_time_is_integrated_and_peaks_are_kept() {
+        let mut shell = BusyShell::start();
+        let mut tracker = UsageTracker::new(shell.0.id());
+        let mut system = System::new();
+        system.refresh_processes();
+        std::thread::sleep(Duration::from_millis(500));
+        let processes = refreshed(&mut system);
+
+        // Two seconds pass for the tracker at the measured CPU usage
+        let tree = tracker.record(processes, 4, tracker.started + Duration::from_secs(2));
+        let core_percent: f32 = tree.iter().map(|pid| processes[pid].cpu_usage()).sum();
+        assert!(core_percent > 10.0, "busy shell used {}% of a core", core_percent);
+        let busy = tracker.usage();
+        assert!((busy.cpu_time_secs - f64::from(core_percent) / 100.0 * 2.0).abs() < 1e-6);
+        assert_eq!(busy.cpu_percent, core_percent / 4.0);
+        assert!(busy.ram_bytes > 0);
+
+        // Once the tree is gone, current usage drops and peaks remain
+        shell.stop();
+        let processes = refreshed(&mut system);
+        assert!(tracker.record(processes, 4, tracker.started + Duration::from_secs(3)).is_empty());
+        let idle = tracker.usage();
+        assert_eq!((idle.cpu_percent, idle.ram_bytes), (0.0, 0));
+        assert_eq!(idle.cpu_time_secs, busy.cpu_time_secs);
+        assert_eq!((idle.peak_cpu_percent, idle.peak_ram_bytes), (busy.cpu_percent, busy.ram_bytes));
+    }
+}
@@ -0,0 +1,23 @@
+//! Helpers shared by the Synapse modules
+
+/// Appends a length-prefixed optional field to a canonical encoding
+pub(super) fn push_field(buffer: &mut Vec<u8>, field: Option<&[u8]>) {
+    match field {
+        Some(bytes) => {
+            buffer.push(1);
+            buffer.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            buffer.extend_from_slice(bytes);
+        }
+        None => buffer.push(0),
+    }
+}
+
+/// Whether `name` can be used as the name of a file inside a task
+/// directory, without reaching outside of it
+pub(super) fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+}
@@ -0,0 +1,264 @@
+//! Result verification by redundant execution
+//!
+//! Tasks asking for `Consensus` or `ProofOfWork` verification run on
+//! several independent peers. The signed result hashes are compared and a
+//! result is only accepted once a quorum of peers agrees on it. Peers that
+//! returned a different result lose reputation; agreeing peers gain it.
+//!
+//! Redundant execution multiplies the cost of a task, so a task whose best
+//! bidder is a trusted peer runs on that peer alone, except for a random
+//! sample of spot checks that are still executed redundantly.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{SynapseError, SynapseResult};
+use crate::chronicle::ContentId;
+use crate::identity::PeerId;
+use crate::ui_api::VerificationMethod;
+use crate::util::write_atomic;
+
+/// Agreements a peer needs before it is trusted
+const TRUST_MIN_AGREEMENTS: u64 = 10;
+
+/// Reputation score from which a peer is trusted
+const TRUST_MIN_SCORE: f64 = 0.9;
+
+/// Disagreements after which a peer with a low score is no longer used
+const BAN_MIN_DISAGREEMENTS: u64 = 3;
+
+/// Reputation score below which a peer is no longer used
+const BAN_MAX_SCORE: f64 = 0.25;
+
+/// Weight of a disagreement against an agreement in the score
+const DISAGREEMENT_WEIGHT: f64 = 3.0;
+
+/// How many peers run a task and how many of them must agree
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationPolicy {
+    /// Number of independent executions
+    pub replicas: u32,
+    /// Number of matching results needed to accept one
+    pub quorum: u32,
+    /// Probability that a task assigned to a trusted peer is still
+    /// executed redundantly
+    pub spot_check_rate: f64,
+}
+
+impl VerificationPolicy {
+    /// Gets the policy implementing a verification method
+    ///
+    /// `ProofOfWork` is checked by re-execution: a second peer has to do
+    /// the same work and arrive at the same result.
+    pub fn for_method(method: &VerificationMethod) -> Self {
+        match method {
+            VerificationMethod::Consensus => Self { replicas: 3, quorum: 2, spot_check_rate: 0.2 },
+            VerificationMethod::ProofOfWork => Self { replicas: 2, quorum: 2, spot_check_rate: 0.2 },
+            VerificationMethod::CryptographicSignature | VerificationMethod::Manual => {
+                Self { replicas: 1, quorum: 1, spot_check_rate: 0.0 }
+            }
+        }
+    }
+
+    /// Decides how many peers run a task whose best bidder is (or is not)
+    /// `trusted`
+    pub fn replicas_for(&self, trusted: bool, rng: &mut impl Rng) -> u32 {
+        if self.replicas > 1 && trusted && !rng.gen_bool(self.spot_check_rate.clamp(0.0, 1.0)) {
+            1
+        } else {
+            self.replicas
+        }
+    }
+
+    /// Gets the quorum for a task running on `replicas` peers
+    pub fn quorum_for(&self, replicas: u32) -> u32 {
+        self.quorum.clamp(1, replicas.max(1))
+    }
+}
+
+/// Outcome of comparing the results returned so far
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// A quorum agrees on `result_hash`
+    Accepted {
+        result_hash: ContentId,
+        agreeing: Vec<PeerId>,
+        dissenting: Vec<PeerId>,
+    },
+    /// More results are needed
+    Undecided,
+    /// The outstanding results can no longer produce a quorum
+    NoQuorum,
+}
+
+/// Compares the result hashes returned by the peers running a task
+pub fn tally(results: &[(PeerId, ContentId)], replicas: u32, quorum: u32) -> Verdict {
+    let mut votes: HashMap<&ContentId, Vec<&PeerId>> = HashMap::new();
+    for (peer, hash) in results {
+        votes.entry(hash).or_default().push(peer);
+    }
+    let leader = votes
+        .iter()
+        .max_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| b.0.cmp(a.0)));
+
+    let leading_votes = leader.map_or(0, |(_, peers)| peers.len() as u32);
+    if let Some((hash, agreeing)) = leader.filter(|_| leading_votes >= quorum) {
+        let dissenting = results
+            .iter()
+            .filter(|(_, other)| other != *hash)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        return Verdict::Accepted {
+            result_hash: (*hash).clone(),
+            agreeing: agreeing.iter().map(|peer| (*peer).clone()).collect(),
+            dissenting,
+        };
+    }
+
+    let outstanding = replicas.saturating_sub(results.len() as u32);
+    if leading_votes + outstanding < quorum {
+        Verdict::NoQuorum
+    } else {
+        Verdict::Undecided
+    }
+}
+
+/// Verification track record of a peer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerReputation {
+    /// Results that matched the accepted result
+    pub agreements: u64,
+    /// Results that contradicted the accepted result or were forged
+    pub disagreements: u64,
+}
+
+impl PeerReputation {
+    /// Gets the reputation score between 0 and 1; unknown peers start at 0.5
+    pub fn score(&self) -> f64 {
+        (self.agreements as f64 + 1.0)
+            / (self.agreements as f64 + DISAGREEMENT_WEIGHT * self.disagreements as f64 + 2.0)
+    }
+
+    /// Whether results of the peer may skip redundant execution
+    pub fn is_trusted(&self) -> bool {
+        self.agreements >= TRUST_MIN_AGREEMENTS && self.score() >= TRUST_MIN_SCORE
+    }
+
+    /// Whether the peer is no longer given tasks
+    pub fn is_banned(&self) -> bool {
+        self.disagreements >= BAN_MIN_DISAGREEMENTS && self.score() < BAN_MAX_SCORE
+    }
+}
+
+/// Persistent reputation of the peers this node has worked with
+pub struct ReputationBook {
+    path: PathBuf,
+    peers: Mutex<HashMap<PeerId, PeerReputation>>,
+}
+
+impl ReputationBook {
+    /// Opens the reputation book stored at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed
+    pub fn open(path: impl AsRef<Path>) -> SynapseResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let peers = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| SynapseError::Corrupted(format!("reputation: {}", e)))?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self { path, peers: Mutex::new(peers) })
+    }
+
+    /// Gets the reputation of a peer
+    pub fn get(&self, peer: &str) -> PeerReputation {
+        self.peers
+            .lock()
+            .ok()
+            .and_then(|peers| peers.get(peer).copied())
+            .unwrap_or_default()
+    }
+
+    /// Records that a peer's result matched the accepted result
+    pub fn record_agreement(&self, peer: &PeerId) -> SynapseResult<()> {
+        self.update(peer, |reputation| reputation.agreements += 1)
+    }
+
+    /// Records that a peer's result was rejected
+    pub fn record_disagreement(&self, peer: &PeerId) -> SynapseResult<()> {
+        log::warn!("Penalizing peer {} for a rejected result", peer);
+        self.update(peer, |reputation| reputation.disagreements += 1)
+    }
+
+    fn update(&self, peer: &PeerId, f: impl FnOnce(&mut PeerReputation)) -> SynapseResult<()> {
+        let mut peers = self
+            .peers
+            .lock()
+            .map_err(|e| SynapseError::StateUnavailable(e.to_string()))?;
+        f(peers.entry(peer.clone()).or_default());
+        let bytes = serde_json::to_vec(&*peers).map_err(|e| SynapseError::Corrupted(e.to_string()))?;
+        write_atomic(&self.path, &bytes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    fn vote(peer: &str, result: &[u8]) -> (PeerId, ContentId) {
+        (peer.to_string(), ContentId::for_bytes(result))
+    }
+
+    #[test]
+    fn test_tally_requires_quorum() {
+        assert_eq!(tally(&[vote("a", b"42")], 3, 2), Verdict::Undecided);
+        assert_eq!(tally(&[vote("a", b"42"), vote("b", b"41")], 3, 2), Verdict::Undecided);
+        assert_eq!(
+            tally(&[vote("a", b"42"), vote("b", b"41"), vote("c", b"42")], 3, 2),
+            Verdict::Accepted {
+                result_hash: ContentId::for_bytes(b"42"),
+                agreeing: vec!["a".to_string(), "c".to_string()],
+                dissenting: vec!["b".to_string()],
+            }
+        );
+        assert_eq!(
+            tally(&[vote("a", b"1"), vote("b", b"2"), vote("c", b"3")], 3, 2),
+            Verdict::NoQuorum
+        );
+    }
+
+    #[test]
+    fn test_trusted_peers_are_spot_checked() {
+        let policy = VerificationPolicy::for_method(&VerificationMethod::Consensus);
+        // StepRng(0, 0) always samples, StepRng(u64::MAX, 0) never does
+        assert_eq!(policy.replicas_for(true, &mut StepRng::new(u64::MAX, 0)), 1);
+        assert_eq!(policy.replicas_for(true, &mut StepRng::new(0, 0)), 3);
+        assert_eq!(policy.replicas_for(false, &mut StepRng::new(u64::MAX, 0)), 3);
+    }
+
+    #[test]
+    fn test_reputation_persists_penalties() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reputation.json");
+        let book = ReputationBook::open(&path).unwrap();
+        let peer = "peer".to_string();
+        for _ in 0..BAN_MIN_DISAGREEMENTS {
+            book.record_disagreement(&peer).unwrap();
+        }
+
+        let book = ReputationBook::open(&path).unwrap();
+        let reputation = book.get(&peer);
+        assert_eq!(reputation.disagreements, BAN_MIN_DISAGREEMENTS);
+        assert!(reputation.is_banned());
+        assert!(!book.get("stranger").is_banned());
+    }
+}
@@ -0,0 +1,378 @@
+//! Task workflows
+//!
+//! A workflow is a DAG of tasks submitted together, such as "preprocess →
+//! train → evaluate". A task declares the tasks it depends on and the
+//! data edges feeding outputs of earlier tasks into its inputs; a data
+//! edge implies a dependency. Workflow tasks are only queued once all of
+//! their dependencies have completed, with the outputs staged into their
+//! input directory. A task whose dependency failed or was cancelled is
+//! never run and ends with the same status, so failures propagate down
+//! the graph.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::manifest::{is_safe_id, TaskManifest};
+use super::store::TaskStore;
+use super::util::is_plain_file_name;
+use super::{SynapseError, SynapseResult};
+use crate::ui_api::{TaskStatus, WorkflowProgress, WorkflowStatus, WorkflowTaskProgress};
+
+/// Immutable description of a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowManifest {
+    /// Workflow ID
+    pub id: String,
+    /// Human readable workflow name
+    pub name: String,
+    /// Tasks of the workflow
+    pub tasks: Vec<WorkflowTask>,
+    /// Submission timestamp
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// Task within a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTask {
+    /// Manifest of the task
+    pub manifest: TaskManifest,
+    /// IDs of the tasks that must complete before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Outputs of other tasks staged as inputs of this one
+    #[serde(default)]
+    pub inputs: Vec<DataEdge>,
+}
+
+/// Output file of one task used as input file of another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataEdge {
+    /// ID of the task producing the file
+    pub from_task: String,
+    /// Name of the file in the output directory of `from_task`
+    pub output: String,
+    /// Name of the file in the input directory of the consuming task
+    pub input: String,
+}
+
+impl WorkflowTask {
+    /// Gets the IDs of all tasks this task waits for, explicit or implied
+    /// by a data edge, without duplicates
+    pub fn dependencies(&self) -> Vec<&str> {
+        let mut dependencies: Vec<&str> = Vec::new();
+        let implied = self.inputs.iter().map(|edge| edge.from_task.as_str());
+        for id in self.depends_on.iter().map(String::as_str).chain(implied) {
+            if !dependencies.contains(&id) {
+                dependencies.push(id);
+            }
+        }
+        dependencies
+    }
+}
+
+impl WorkflowManifest {
+    /// Checks that the workflow can be accepted
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a task manifest is invalid, task IDs are not
+    /// unique, a dependency is unknown or the dependencies form a cycle
+    pub fn validate(&self) -> SynapseResult<()> {
+        let invalid = |reason: String| Err(SynapseError::InvalidManifest(format!("workflow {}: {}", self.id, reason)));
+        if !is_safe_id(&self.id) {
+            return invalid("workflow ID must be 1-128 characters of [A-Za-z0-9-_.]".to_string());
+        }
+        if self.tasks.is_empty() {
+            return invalid("workflow has no tasks".to_string());
+        }
+
+        let mut ids = HashSet::new();
+        for task in &self.tasks {
+            task.manifest.validate()?;
+            if !ids.insert(task.manifest.id.as_str()) {
+                return invalid(format!("task {} appears twice", task.manifest.id));
+            }
+        }
+
+        for task in &self.tasks {
+            let id = &task.manifest.id;
+            for dependency in task.dependencies() {
+                if dependency == id {
+                    return invalid(format!("task {} depends on itself", id));
+                }
+                if !ids.contains(dependency) {
+                    return invalid(format!("task {} depends on unknown task {}", id, dependency));
+                }
+            }
+            let mut inputs = HashSet::new();
+            for edge in &task.inputs {
+                if !is_plain_file_name(&edge.output) || !is_plain_file_name(&edge.input) {
+                    return invalid(format!("task {} has a data edge with an invalid file name", id));
+                }
+                if !inputs.insert(edge.input.as_str()) {
+                    return invalid(format!("task {} receives input {} twice", id, edge.input));
+                }
+            }
+        }
+
+        if let Some(id) = self.cycle_member() {
+            return invalid(format!("task {} is part of a dependency cycle", id));
+        }
+        Ok(())
+    }
+
+    /// Gets a task of the workflow by ID
+    pub fn task(&self, task_id: &str) -> Option<&WorkflowTask> {
+        self.tasks.iter().find(|task| task.manifest.id == task_id)
+    }
+
+    /// Finds a task that cannot be ordered because it is part of (or
+    /// depends on) a cycle, using Kahn's algorithm
+    fn cycle_member(&self) -> Option<&str> {
+        let mut waiting: HashMap<&str, usize> = self
+            .tasks
+            .iter()
+            .map(|task| (task.manifest.id.as_str(), task.dependencies().len()))
+            .collect();
+        let mut ready: Vec<&str> = waiting.iter().filter(|(_, n)| **n == 0).map(|(id, _)| *id).collect();
+
+        while let Some(done) = ready.pop() {
+            waiting.remove(done);
+            for task in &self.tasks {
+                if task.dependencies().contains(&done) {
+                    if let Some(n) = waiting.get_mut(task.manifest.id.as_str()) {
+                        *n -= 1;
+                        if *n == 0 {
+                            ready.push(task.manifest.id.as_str());
+                        }
+                    }
+                }
+            }
+        }
+        waiting.keys().min().copied()
+    }
+}
+
+/// What to do with a workflow task whose dependencies have all finished
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum StepAction {
+    /// Stage its inputs and queue it
+    Submit,
+    /// Record it as finished with `status` without running it
+    Skip { status: TaskStatus, reason: String },
+}
+
+/// Workflow task ready to leave the waiting state
+#[derive(Debug, Clone)]
+pub(super) struct WorkflowStep {
+    pub task: WorkflowTask,
+    pub action: StepAction,
+}
+
+/// Finds the workflow tasks that can be queued or skipped now
+///
+/// A task is skipped as `Failed` if any dependency failed, otherwise as
+/// `Cancelled` if any dependency was cancelled.
+pub(super) fn next_steps(store: &TaskStore) -> Vec<WorkflowStep> {
+    let mut steps = Vec::new();
+    for workflow in store.workflows() {
+        for task in &workflow.tasks {
+            if store.get(&task.manifest.id).is_some() {
+                continue;
+            }
+            let statuses: Option<Vec<(&str, TaskStatus)>> = task
+                .dependencies()
+                .into_iter()
+                .map(|id| store.get(id).map(|record| (id, record.status)))
+                .collect();
+            let Some(statuses) = statuses else { continue };
+
+            let find = |wanted: TaskStatus| statuses.iter().find(|(_, status)| *status == wanted);
+            let action = if let Some((id, _)) = find(TaskStatus::Failed) {
+                StepAction::Skip { status: TaskStatus::Failed, reason: format!("Dependency {} failed", id) }
+            } else if let Some((id, _)) = find(TaskStatus::Cancelled) {
+                StepAction::Skip { status: TaskStatus::Cancelled, reason: format!("Dependency {} was cancelled", id) }
+            } else if statuses.iter().all(|(_, status)| *status == TaskStatus::Completed) {
+                StepAction::Submit
+            } else {
+                continue;
+            };
+            steps.push(WorkflowStep { task: task.clone(), action });
+        }
+    }
+    steps
+}
+
+/// Computes the progress of a workflow from the records of its tasks
+///
+/// Tasks still waiting for their dependencies count as pending with no
+/// progress.
+pub(super) fn progress(workflow: &WorkflowManifest, store: &TaskStore) -> WorkflowProgress {
+    let status_of = |id: &str| store.get(id).map_or(TaskStatus::Pending, |record| record.status);
+
+    let tasks: Vec<WorkflowTaskProgress> = workflow
+        .tasks
+        .iter()
+        .map(|task| {
+            let record = store.get(&task.manifest.id);
+            let progress = match record {
+                Some(record) if record.status == TaskStatus::Completed => 100,
+                Some(record) => record.progress.clamp(0.0, 100.0) as u8,
+                None => 0,
+            };
+            let dependencies = task.dependencies();
+            WorkflowTaskProgress {
+                task_id: task.manifest.id.clone(),
+                name: task.manifest.name.clone(),
+                status: status_of(&task.manifest.id),
+                progress,
+                waiting_for: dependencies
+                    .iter()
+                    .filter(|id| status_of(id) != TaskStatus::Completed)
+                    .map(|id| id.to_string())
+                    .collect(),
+                depends_on: dependencies.into_iter().map(str::to_string).collect(),
+            }
+        })
+        .collect();
+
+    let count = |status: TaskStatus| tasks.iter().filter(|task| task.status == status).count();
+    let finished = count(TaskStatus::Completed) + count(TaskStatus::Failed) + count(TaskStatus::Cancelled);
+    let status = if finished < tasks.len() {
+        WorkflowStatus::Running
+    } else if count(TaskStatus::Failed) > 0 {
+        WorkflowStatus::Failed
+    } else if count(TaskStatus::Cancelled) > 0 {
+        WorkflowStatus::Cancelled
+    } else {
+        WorkflowStatus::Completed
+    };
+    let total_progress: u32 = tasks.iter().map(|task| task.progress as u32).sum();
+
+    WorkflowProgress {
+        id: workflow.id.clone(),
+        name: workflow.name.clone(),
+        status,
+        progress: (total_progress / tasks.len().max(1) as u32) as u8,
+        completed_tasks: count(TaskStatus::Completed) as u32,
+        total_tasks: tasks.len() as u32,
+        tasks,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::synapse::store::tests::manifest;
+    use crate::ui_api::IdleSettings;
+
+    pub(crate) fn task(id: &str, depends_on: &[&str], inputs: &[(&str, &str, &str)]) -> WorkflowTask {
+        WorkflowTask {
+            manifest: manifest(id),
+            depends_on: depends_on.iter().map(|id| id.to_string()).collect(),
+            inputs: inputs
+                .iter()
+                .map(|(from_task, output, input)| DataEdge {
+                    from_task: from_task.to_string(),
+                    output: output.to_string(),
+                    input: input.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn workflow(id: &str, tasks: Vec<WorkflowTask>) -> WorkflowManifest {
+        WorkflowManifest { id: id.to_string(), name: format!("Workflow {}", id), tasks, submitted_at: Utc::now() }
+    }
+
+    #[test]
+    fn test_validation_rejects_broken_graphs() {
+        let pipeline = workflow("ok", vec![
+            task("prep", &[], &[]),
+            task("train", &[], &[("prep", "data.csv", "train.csv")]),
+            task("eval", &["train"], &[("prep", "data.csv", "test.csv")]),
+        ]);
+        assert!(pipeline.validate().is_ok());
+        assert_eq!(pipeline.task("eval").unwrap().dependencies(), vec!["train", "prep"]);
+
+        let unknown = workflow("unknown", vec![task("a", &["missing"], &[])]);
+        assert!(matches!(unknown.validate(), Err(SynapseError::InvalidManifest(_))));
+
+        let cycle = workflow("cycle", vec![
+            task("a", &["c"], &[]),
+            task("b", &["a"], &[]),
+            task("c", &[], &[("b", "out", "in")]),
+            task("d", &[], &[]),
+        ]);
+        assert!(matches!(cycle.validate(), Err(SynapseError::InvalidManifest(e)) if e.contains("cycle")));
+
+        let escape = workflow("escape", vec![task("a", &[], &[]), task("b", &[], &[("a", "../secret", "in")])]);
+        assert!(escape.validate().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pipeline_passes_data_and_propagates_failures() {
+        use crate::synapse::manifest::TaskRuntime;
+        use crate::synapse::Synapse;
+        use std::time::Duration;
+
+        fn shell(mut task: WorkflowTask, script: &str) -> WorkflowTask {
+            task.manifest.runtime = TaskRuntime::Process {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                env: HashMap::new(),
+            };
+            task
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let (synapse, _) = Synapse::open(dir.path()).unwrap();
+        synapse.set_idle_settings(IdleSettings { enabled: false, ..IdleSettings::default() }).unwrap();
+        tokio::spawn(synapse.clone().run());
+
+        synapse.submit_workflow(workflow("pipeline", vec![
+            shell(task("prep", &[], &[]), r#"echo 20 > "$MYCELIUM_OUTPUT_DIR/data""#),
+            shell(
+                task("train", &[], &[("prep", "data", "train")]),
+                r#"echo $(($(cat "$MYCELIUM_INPUT_DIR/train") + 1)) > "$MYCELIUM_OUTPUT_DIR/model""#,
+            ),
+            shell(
+                task("eval", &[], &[("train", "model", "model"), ("prep", "data", "data")]),
+                r#"echo $(($(cat "$MYCELIUM_INPUT_DIR/model") * 2)) > "$MYCELIUM_OUTPUT_DIR/score""#,
+            ),
+        ])).unwrap();
+        synapse.submit_workflow(workflow("broken", vec![
+            shell(task("fail", &[], &[]), "exit 3"),
+            task("after-fail", &["fail"], &[]),
+            task("after-after-fail", &["after-fail"], &[]),
+        ])).unwrap();
+
+        let progress = synapse.workflow_progress("pipeline").unwrap();
+        assert_eq!(progress.tasks[2].waiting_for, vec!["train".to_string(), "prep".to_string()]);
+
+        let finished = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                let pipeline = synapse.workflow_progress("pipeline").unwrap();
+                let broken = synapse.workflow_progress("broken").unwrap();
+                if pipeline.status != WorkflowStatus::Running && broken.status != WorkflowStatus::Running {
+                    return (pipeline, broken);
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        let (pipeline, broken) = finished.expect("workflows did not finish in time");
+
+        assert_eq!(pipeline.status, WorkflowStatus::Completed);
+        assert_eq!((pipeline.progress, pipeline.completed_tasks), (100, 3));
+        let score = std::fs::read_to_string(synapse.output_dir("eval").join("score")).unwrap();
+        assert_eq!(score.trim(), "42");
+
+        assert_eq!(broken.status, WorkflowStatus::Failed);
+        assert!(broken.tasks.iter().all(|task| task.status == TaskStatus::Failed));
+        let skipped = synapse.record("after-after-fail").unwrap();
+        assert_eq!(skipped.result.unwrap().error.as_deref(), Some("Dependency after-fail failed"));
+    }
+}
@@ -130,6 +130,7 @@ pub enum ActivityType {
     PermissionChanged,
     ResourceAllocated,
     StorageFragmentStored,
+    StorageFragmentDeleted,
 }
 
 /// Activity status
@@ -199,8 +200,10 @@ pub struct ActiveTask {
     pub aibox_id: String,
     /// Task progress percentage
     pub progress: u8,
-    /// Time remaining
-    pub time_remaining: Option<String>,
+    /// Estimated time remaining in seconds (formatted by the frontend)
+    pub time_remaining_secs: Option<u64>,
+    /// Whether the task stopped reporting progress
+    pub is_stalled: bool,
     /// Task priority
     pub priority: TaskPriority,
     /// Reward in VOID tokens
@@ -212,7 +215,7 @@ pub struct ActiveTask {
 }
 
 /// Task priority levels
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum TaskPriority {
     Low,
     Normal,
@@ -221,7 +224,7 @@ pub enum TaskPriority {
 }
 
 /// Task status
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TaskStatus {
     Pending,
     Running,
@@ -250,6 +253,136 @@ pub struct TaskDetails {
     pub security: SecurityLevel,
     /// Detailed resource usage
     pub detailed_resource_usage: DetailedResourceUsage,
+    /// Scheduler decisions taken for this task, oldest first
+    pub scheduling_decisions: Vec<SchedulingDecision>,
+    /// Cause of the failure, for failed tasks and tasks waiting for a retry
+    pub failure_kind: Option<FailureKind>,
+    /// Resources measured while the task runs, or the summary of its last
+    /// run once it finished (local tasks only)
+    pub measured_usage: Option<MeasuredResourceUsage>,
+}
+
+/// Cause of a task failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureKind {
+    /// The process was killed for using too much memory
+    OutOfMemory,
+    /// The task did not finish before its deadline
+    Timeout,
+    /// The process exited with a non-zero exit code
+    NonZeroExit,
+    /// The process was killed for violating its sandbox
+    SandboxViolation,
+    /// Workers running the task disagreed on its result
+    VerificationMismatch,
+    /// A task the task depends on did not complete
+    DependencyFailed,
+    /// Any other failure (e.g. the process could not be started)
+    Other,
+}
+
+/// Scheduler decision recorded for a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulingDecision {
+    /// Decision timestamp
+    pub timestamp: DateTime<Utc>,
+    /// What the scheduler decided
+    pub action: SchedulingAction,
+    /// Effective priority (base priority plus aging) at decision time
+    pub effective_priority: f32,
+    /// Task that caused the decision (e.g. the Critical task that preempted this one)
+    pub related_task_id: Option<String>,
+    /// Human readable explanation
+    pub reason: String,
+}
+
+/// Scheduler actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchedulingAction {
+    Started,
+    Preempted,
+    Deferred,
+}
+
+/// Progress of a task workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowProgress {
+    /// Workflow ID
+    pub id: String,
+    /// Workflow name
+    pub name: String,
+    /// Overall status
+    pub status: WorkflowStatus,
+    /// Progress percentage over all tasks (0-100)
+    pub progress: u8,
+    /// Number of completed tasks
+    pub completed_tasks: u32,
+    /// Total number of tasks
+    pub total_tasks: u32,
+    /// Per-task progress in workflow order
+    pub tasks: Vec<WorkflowTaskProgress>,
+}
+
+/// Progress of a single task within a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTaskProgress {
+    /// Task ID
+    pub task_id: String,
+    /// Task name
+    pub name: String,
+    /// Task status (Pending while waiting for dependencies)
+    pub status: TaskStatus,
+    /// Progress percentage (0-100)
+    pub progress: u8,
+    /// Tasks this task depends on
+    pub depends_on: Vec<String>,
+    /// Dependencies that have not completed yet
+    pub waiting_for: Vec<String>,
+}
+
+/// Overall workflow status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorkflowStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Line of task output, as emitted in `task_log` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskLogLine {
+    /// Task ID
+    pub task_id: String,
+    /// Sequence number of the line within the task's log
+    pub seq: u64,
+    /// Stream the line was written to
+    pub stream: LogStream,
+    /// Line content (without the line break)
+    pub line: String,
+    /// When the line was captured
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Output stream of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Page of a task's log history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskLogPage {
+    /// Task ID
+    pub task_id: String,
+    /// Lines in order
+    pub lines: Vec<TaskLogLine>,
+    /// Offset to request the next page with
+    pub next_offset: u64,
+    /// Sequence number of the oldest line still kept (older lines were
+    /// dropped by log rotation)
+    pub first_available: u64,
 }
 
 /// Task types
@@ -303,6 +436,27 @@ pub struct DetailedResourceUsage {
     pub gpu_memory_gb: f32,
 }
 
+/// Resources measured for the process tree of a task
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeasuredResourceUsage {
+    /// CPU usage at the latest sample, as a percentage of the whole machine
+    pub cpu_percent: f32,
+    /// Highest CPU usage sampled
+    pub peak_cpu_percent: f32,
+    /// Resident memory at the latest sample in bytes
+    pub ram_bytes: u64,
+    /// Highest resident memory sampled in bytes
+    pub peak_ram_bytes: u64,
+    /// Bytes read from disk
+    pub read_bytes: u64,
+    /// Bytes written to disk
+    pub written_bytes: u64,
+    /// Time the task ran in seconds
+    pub wall_time_secs: f64,
+    /// CPU time used in seconds, summed over all cores
+    pub cpu_time_secs: f64,
+}
+
 // ============================================================================
 // CHRONICLE PROTOCOL API STRUCTURES
 // ============================================================================
@@ -349,8 +503,8 @@ pub struct RegionInfo {
 pub struct StorageSecurity {
     /// Encryption algorithm
     pub encryption_algorithm: String,
-    /// Redundancy factor
-    pub redundancy_factor: u8,
+    /// Redundancy factor: stored bytes per byte of data
+    pub redundancy_factor: f32,
 }
 
 /// Active fragment information
@@ -369,7 +523,7 @@ pub struct ActiveFragment {
 }
 
 /// Fragment status
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FragmentStatus {
     Active,
     Recovering,
@@ -415,6 +569,53 @@ pub struct NodeFragmentInfo {
     pub fragment_count: u32,
 }
 
+/// Progress of the Chronicle integrity scrub
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrubProgress {
+    /// Whether a scrub is running
+    pub running: bool,
+    /// Local fragments re-hashed so far
+    pub checked_fragments: u32,
+    /// Local fragments to re-hash
+    pub total_fragments: u32,
+    /// Local fragments found damaged
+    pub damaged_fragments: u32,
+    /// Objects whose lost shards were rebuilt
+    pub repaired_objects: u32,
+    /// Objects with too few shards left to rebuild them
+    pub lost_objects: u32,
+    /// Share of all shards with an intact copy, in percent
+    pub data_integrity: u8,
+    /// When the last scrub finished
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Progress of a streamed Chronicle upload or download
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// Transfer ID (the file path for file transfers)
+    pub transfer_id: String,
+    /// Direction of the transfer
+    pub direction: TransferDirection,
+    /// Bytes transferred so far, including resumed ones
+    pub transferred_bytes: u64,
+    /// Size of the object (`None` while uploading a stream of unknown size)
+    pub total_bytes: Option<u64>,
+    /// Bytes skipped because an earlier attempt already transferred them
+    pub resumed_bytes: u64,
+    /// Chunks transferred so far, including resumed ones
+    pub chunks: u32,
+    /// Content ID of the object once the transfer completed
+    pub object_id: Option<String>,
+}
+
+/// Direction of a streamed transfer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
 // ============================================================================
 // CONTACT PROTOCOL API STRUCTURES
 // ============================================================================
@@ -625,6 +826,65 @@ pub struct PermissionSettings {
     pub token: TokenSettings,
     /// Time restrictions
     pub time_restrictions: TimeRestrictions,
+    /// When tasks give way to the user's own work
+    #[serde(default)]
+    pub idle: IdleSettings,
+    /// Whether Chronicle refuses to store or serve unencrypted objects
+    /// (`None` keeps the current setting)
+    #[serde(default)]
+    pub encryption_required: Option<bool>,
+    /// Retention period of Chronicle objects in days (`None` keeps the
+    /// current period)
+    #[serde(default)]
+    pub data_retention_days: Option<u32>,
+    /// Which tasks peers may run on this node
+    #[serde(default)]
+    pub remote_tasks: RemoteTaskSettings,
+}
+
+/// Tasks that peers may run on this node
+///
+/// Tasks assigned by peers run as native processes with the user's
+/// privileges, so none are accepted unless the user enables them, and then
+/// only allowlisted ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteTaskSettings {
+    /// Whether tasks assigned by peers are accepted at all
+    pub enabled: bool,
+    /// Programs peers may start, exactly as named in the task runtime, with
+    /// any arguments
+    pub allowed_programs: Vec<String>,
+    /// Content IDs of task manifests peers may run whatever their program
+    pub allowed_manifests: Vec<String>,
+    /// Whether results of tasks that opted into shared caching are
+    /// exchanged through Chronicle: published with the results of tasks run
+    /// for peers, and imported from the workers of this node's tasks
+    pub share_results: bool,
+}
+
+/// Host activity thresholds above which tasks give way to the user
+///
+/// CPU usage and load only count processes other than Mycelium tasks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdleSettings {
+    /// Whether tasks yield to other work at all
+    pub enabled: bool,
+    /// Host CPU usage (percent of the machine) above which running tasks
+    /// are throttled and no new tasks start
+    pub throttle_cpu_percent: f32,
+    /// Host CPU usage (percent of the machine) above which running tasks
+    /// are paused
+    pub pause_cpu_percent: f32,
+    /// One-minute load average per CPU above which running tasks are paused
+    pub pause_load_per_cpu: f32,
+    /// CPU usage (percent of one CPU) at which another process counts as
+    /// heavy; a heavy process throttles tasks
+    pub heavy_process_cpu_percent: f32,
+    /// How long the host must stay below the thresholds before tasks speed
+    /// up or resume again
+    pub resume_after_secs: u64,
 }
 
 /// Communication settings
@@ -768,6 +1028,36 @@ pub struct DevelopmentForecasts {
     pub predicted_token_value_6m: f64,
 }
 
+// ============================================================================
+// NODE PROFILE API STRUCTURES
+// ============================================================================
+
+/// Capabilities of this node as advertised to peers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodeProfile {
+    /// Peer ID of the node
+    pub peer_id: String,
+    /// Latest compute benchmark (none until the first run finished)
+    pub compute: Option<ComputeBenchmark>,
+}
+
+/// Result of the local compute benchmark
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComputeBenchmark {
+    /// Integer throughput in million operations per second
+    pub integer_mops: f64,
+    /// Floating-point throughput in GFLOPS
+    pub float_gflops: f64,
+    /// Memory copy bandwidth in GB/s
+    pub memory_bandwidth_gbps: f64,
+    /// Number of threads the benchmark ran on
+    pub threads: usize,
+    /// Normalized compute score; the reference machine scores 1000
+    pub compute_score: f64,
+    /// When the benchmark ran
+    pub measured_at: DateTime<Utc>,
+}
+
 // ============================================================================
 // ERROR TYPES
 // ============================================================================